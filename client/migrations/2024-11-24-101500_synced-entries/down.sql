@@ -0,0 +1 @@
+DROP TABLE synced_entries;
@@ -0,0 +1,11 @@
+-- what each path looked like on both sides after it was last synced, directories have no file columns
+CREATE TABLE synced_entries (
+	path Text PRIMARY KEY NOT NULL,
+	-- hash of the local content, which differs from the hash on the server for encrypted volumes
+	content_hash Text,
+	remote_hash Text,
+	size BigInt,
+	-- modification time of the local file in nanoseconds since the unix epoch
+	modified BigInt,
+	CHECK ((content_hash IS NULL) = (remote_hash IS NULL) AND (content_hash IS NULL) = (size IS NULL) AND (content_hash IS NULL) = (modified IS NULL))
+);
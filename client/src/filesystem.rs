@@ -1,67 +1,216 @@
-use std::{cmp, ffi::OsStr, time::{Duration, UNIX_EPOCH}};
+use std::{ffi::OsStr, time::{Duration, UNIX_EPOCH}};
 
-use fuser::{FileAttr, FileType, Filesystem, ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory, ReplyEntry, ReplyWrite, Request};
-use futures_util::{stream::FuturesOrdered, StreamExt};
-use fye_shared::{DirectoryInfo, NodeID, NodeInfo};
+use bytes::Bytes;
+use fuser::{consts::{FUSE_ATOMIC_O_TRUNC, FUSE_DO_READDIRPLUS, FUSE_FLOCK_LOCKS, FUSE_POSIX_LOCKS}, FileAttr, FileType, Filesystem, KernelConfig, ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory, ReplyDirectoryPlus, ReplyEmpty, ReplyEntry, ReplyLock, ReplyOpen, ReplyStatfs, ReplyWrite, Request};
+use fye_shared::{DelegationKind, DirectoryInfo, DirectoryListing, EntryAttributes, Hash, LockKind, NodeID, NodeInfo};
+use tokio::signal::unix::{signal, SignalKind};
 
-use crate::{local_file_cache::LocalFileCache, remote_data_service::{CreateNodeError, DeleteDirectoryError, DeleteFileError, FetchDirectoryError, FetchFileError, FetchNodeError, NetworkError, WriteFileError}};
+use crate::{local_file_cache::{LocalFileCache, StageFileError}, mount_options::MountOptions, remote_data_service::{CreateNodeError, DelegationError, DeleteDirectoryError, DeleteFileError, FetchDirectoryError, FetchFileError, FetchNodeError, FetchStatsError, LockFileError, NetworkError, RenameError, WriteFileError}};
 
 mod reply;
 use reply::*;
+mod dir_handle;
+use dir_handle::*;
+mod file_handle;
+use file_handle::*;
 
-const TTL: Duration = Duration::from_secs(1);
+const BLOCK_SIZE: u32 = 512;
+const MAX_NAME_LENGTH: u32 = 255;
 
 const DIR_PERMISSIONS: u16 = 0o700;
 const FILE_PERMISSIONS: u16 = 0o600;
 
+/// Used by the kernel as the inclusive end of locks that extend to the end of the file
+const LOCK_OFFSET_MAX: u64 = i64::MAX as u64;
+const MIN_LOCK_RETRY_DELAY: Duration = Duration::from_millis(50);
+const MAX_LOCK_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Files at least this large aren't downloaded when opened read-only, only the ranges that are read
+const MIN_RANGED_READ_SIZE: u64 = 4 * 1024 * 1024;
+
 #[derive(Debug)]
 pub struct FyeFilesystem {
 	inner: &'static FyeFilesystemInner,
 }
 
 impl FyeFilesystem {
-	pub fn new(local_file_cache: LocalFileCache) -> Self {
+	pub fn new(local_file_cache: LocalFileCache, options: &MountOptions) -> Self {
 		let inner = FyeFilesystemInner {
 			local_file_cache,
+			dir_handles: Default::default(),
+			file_handles: Default::default(),
+			attr_ttl: options.attr_ttl,
+			entry_ttl: options.entry_ttl,
+			negative_ttl: options.negative_ttl,
+			max_readahead: options.max_readahead,
+			read_ahead: options.read_ahead,
 		};
+		let inner: &'static _ = Box::leak(Box::new(inner));
+		
+		tokio::spawn(inner.local_file_cache.upload_staged_files());
+		tokio::spawn(inner.local_file_cache.send_batches());
+		tokio::spawn(inner.local_file_cache.renew_lock_lease());
+		tokio::spawn(inner.local_file_cache.handle_recalls());
+		tokio::spawn(inner.print_stats_on_signal());
 		
 		Self {
-			inner: Box::leak(Box::new(inner)),
+			inner,
 		}
 	}
+}
+
+fn file_attr(id: NodeID, kind: FileType, size: u64) -> FileAttr {
+	let perm = match kind {
+		FileType::Directory => DIR_PERMISSIONS,
+		_ => FILE_PERMISSIONS,
+	};
 	
+	FileAttr {
+		ino: id.0,
+		size,
+		blocks: 1,
+		atime: UNIX_EPOCH,
+		mtime: UNIX_EPOCH,
+		ctime: UNIX_EPOCH,
+		crtime: UNIX_EPOCH,
+		kind,
+		perm,
+		nlink: 1,
+		uid: 0,
+		gid: 0,
+		rdev: 0,
+		flags: 0,
+		blksize: BLOCK_SIZE,
+	}
+}
+
+/// `None` for `F_UNLCK`
+fn lock_kind(typ: i32) -> Result<Option<LockKind>, Error> {
+	match typ {
+		libc::F_RDLCK => Ok(Some(LockKind::Shared)),
+		libc::F_WRLCK => Ok(Some(LockKind::Exclusive)),
+		libc::F_UNLCK => Ok(None),
+		_ => Err(Error::Inval),
+	}
+}
+
+/// Converts the inclusive end used by the kernel to the exclusive one used by the server
+fn lock_end(end: u64) -> Option<u64> {
+	match end >= LOCK_OFFSET_MAX {
+		true => None,
+		false => Some(end + 1),
+	}
+}
+
+fn lock_error(err: LockFileError) -> Error {
+	match err {
+		LockFileError::NetworkFailure(NetworkError::Timeout) => Error::TimedOut,
+		LockFileError::NetworkFailure(NetworkError::Other) => Error::NoLink,
+		LockFileError::ServerError | LockFileError::ProtocolMismatch => Error::IO,
+		LockFileError::NotFound => Error::NoEnt,
+		LockFileError::NotAFile => Error::IsDir,
+		LockFileError::Locked => Error::Again,
+		// like NFS does for lost locks
+		LockFileError::LeaseExpired => Error::IO,
+	}
+}
+
+/// Whether the process has a signal pending that it doesn't block, or already exited
+/// 
+/// Interrupt requests of the kernel aren't passed on by fuser, so requests that wait indefinitely check this instead.
+fn signal_pending(pid: u32) -> bool {
+	match std::fs::read_to_string(format!("/proc/{pid}/status")) {
+		Ok(status) => has_unblocked_signal(&status),
+		Err(_) => true,
+	}
+}
+
+fn has_unblocked_signal(status: &str) -> bool {
+	let mask = |field: &str| status.lines()
+		.find_map(|line| line.strip_prefix(field))
+		.and_then(|mask| u64::from_str_radix(mask.trim(), 16).ok())
+		.unwrap_or(0);
+	
+	// thread and process wide signals
+	(mask("SigPnd:") | mask("ShdPnd:")) & !mask("SigBlk:") != 0
+}
+
+fn fetch_file_error(err: FetchFileError) -> Error {
+	match err {
+		FetchFileError::NetworkFailure(NetworkError::Timeout) => Error::TimedOut,
+		FetchFileError::NetworkFailure(NetworkError::Other) => Error::NoLink,
+		FetchFileError::ServerError | FetchFileError::ProtocolMismatch => Error::IO,
+		FetchFileError::DecryptionFailed | FetchFileError::HashMismatch => Error::IO,
+		FetchFileError::NotFound => Error::NoEnt,
+		FetchFileError::NotAFile => Error::IsDir,
+		FetchFileError::Modified => Error::Stale,
+	}
+}
+
+fn stage_file_error(err: StageFileError) -> Error {
+	match err {
+		StageFileError::Modified => Error::Stale,
+		StageFileError::SpoolFailed => Error::IO,
+	}
+}
+
+fn write_file_error(err: WriteFileError) -> Error {
+	match err {
+		WriteFileError::NetworkFailure(NetworkError::Timeout) => Error::TimedOut,
+		WriteFileError::NetworkFailure(NetworkError::Other) => Error::NoLink,
+		WriteFileError::ServerError | WriteFileError::ProtocolMismatch | WriteFileError::ContentMismatch => Error::IO,
+		WriteFileError::NotFound => Error::NoEnt,
+		WriteFileError::NotAFile => Error::IsDir,
+		WriteFileError::Modified => Error::Stale,
+		WriteFileError::QuotaExceeded => Error::DQuot,
+		WriteFileError::Locked => Error::Again,
+	}
+}
+
+fn delegation_error(err: DelegationError) -> Error {
+	match err {
+		DelegationError::NetworkFailure(NetworkError::Timeout) => Error::TimedOut,
+		DelegationError::NetworkFailure(NetworkError::Other) => Error::NoLink,
+		DelegationError::ServerError | DelegationError::ProtocolMismatch | DelegationError::Conflicting => Error::IO,
+		DelegationError::NotFound => Error::NoEnt,
+		DelegationError::NotAFile => Error::IsDir,
+	}
 }
 
 #[derive(Debug)]
 struct FyeFilesystemInner {
 	local_file_cache: LocalFileCache,
+	dir_handles: DirHandles,
+	file_handles: FileHandles,
+	attr_ttl: Duration,
+	entry_ttl: Duration,
+	negative_ttl: Duration,
+	max_readahead: Option<u32>,
+	read_ahead: u64,
 }
 
 impl FyeFilesystemInner {
-	async fn attr_for(&self, id: NodeID) -> Result<FileAttr, Error> {
-		let info = self.local_file_cache.get_node_info(id).await.map_err(|_| Error::NoEnt)?; // TODO: handle errors besides missing
-		
-		let (size, kind, perm) = match info {
-			NodeInfo::Directory(_) => (0, FileType::Directory, DIR_PERMISSIONS),
-			NodeInfo::File(file_info) => (file_info.size, FileType::RegularFile, FILE_PERMISSIONS),
+	/// Prints the statistics of the node cache whenever the process receives `SIGUSR1`, they are also printed when unmounting
+	async fn print_stats_on_signal(&self) {
+		let mut signals = match signal(SignalKind::user_defined1()) {
+			Ok(signals) => signals,
+			Err(err) => {
+				eprintln!("could not listen for SIGUSR1: {err}");
+				return;
+			},
 		};
 		
-		Ok(FileAttr {
-			ino: id.0,
-			size,
-			blocks: 1,
-			atime: UNIX_EPOCH,
-			mtime: UNIX_EPOCH,
-			ctime: UNIX_EPOCH,
-			crtime: UNIX_EPOCH,
-			kind,
-			perm,
-			nlink: 1,
-			uid: 0,
-			gid: 0,
-			rdev: 0,
-			flags: 0,
-			blksize: 512,
+		while signals.recv().await.is_some() {
+			println!("node cache: {}", self.local_file_cache.stats());
+		}
+	}
+	
+	async fn attr_for(&self, id: NodeID) -> Result<FileAttr, Error> {
+		let info = self.get_node(id).await?;
+		
+		Ok(match info {
+			NodeInfo::Directory(_) => file_attr(id, FileType::Directory, 0),
+			NodeInfo::File(file_info) => file_attr(id, FileType::RegularFile, file_info.size),
 		})
 	}
 	
@@ -85,9 +234,239 @@ impl FyeFilesystemInner {
 			Ok(dir_info) => Ok(dir_info),
 		}
 	}
+	
+	async fn get_dir_listing(&self, id: NodeID, after: Option<&str>) -> Result<DirectoryListing, Error> {
+		match self.local_file_cache.get_dir_listing(id, after).await {
+			Err(FetchDirectoryError::NetworkFailure(NetworkError::Timeout)) => Err(Error::TimedOut),
+			Err(FetchDirectoryError::NetworkFailure(NetworkError::Other)) => Err(Error::NoLink),
+			Err(FetchDirectoryError::ServerError | FetchDirectoryError::ProtocolMismatch) => Err(Error::IO),
+			Err(FetchDirectoryError::NotFound) => Err(Error::NoEnt),
+			Err(FetchDirectoryError::NotADirectory) => Err(Error::NotDir),
+			Ok(listing) => Ok(listing),
+		}
+	}
+	
+	/// Returns the entries of an open directory following `offset`, fetching the next page if necessary
+	/// 
+	/// Child directories within fetched pages are prefetched in the background.
+	async fn read_dir_handle(&'static self, fh: u64, offset: i64) -> Result<Vec<ListingEntry>, Error> {
+		let handle = self.dir_handles.get(fh).ok_or(Error::BadF)?;
+		let mut handle = handle.lock().await;
+		
+		handle.seek(offset);
+		
+		while handle.needs_page() {
+			let listing = self.get_dir_listing(handle.id(), handle.continuation()).await?;
+			
+			let child_dirs: Vec<_> = listing.children.values()
+				.filter(|entry| matches!(entry.attributes, EntryAttributes::Directory))
+				.map(|entry| entry.id)
+				.collect();
+			
+			if !child_dirs.is_empty() {
+				tokio::spawn(self.local_file_cache.prefetch_dirs(child_dirs));
+			}
+			
+			handle.push_page(listing);
+			handle.seek(offset);
+		}
+		
+		Ok(handle.entries().cloned().collect())
+	}
+	
+	async fn get_file_data(&self, id: NodeID) -> Result<(Hash, Bytes), Error> {
+		self.local_file_cache.get_file_data(id).await.map_err(fetch_file_error)
+	}
+	
+	/// Fails with [`Error::Stale`] if the file was changed by someone else since it was opened.
+	async fn get_file_range(&self, id: NodeID, hash: &Hash, start: u64, end: u64) -> Result<Bytes, Error> {
+		self.local_file_cache.get_file_range(id, hash, start, end).await.map_err(fetch_file_error)
+	}
+	
+	/// Pins the current content of the file to a new handle
+	/// 
+	/// The content isn't fetched if the file is truncated anyway, or if a large file is only read.
+	async fn open_file(&self, id: NodeID, mut flags: i32) -> Result<u64, Error> {
+		let delegation_kind = match flags & libc::O_ACCMODE {
+			libc::O_RDONLY => DelegationKind::Read,
+			_ => DelegationKind::Write,
+		};
+		self.local_file_cache.acquire_delegation(id, delegation_kind).await.map_err(delegation_error)?;
+		
+		if flags & libc::O_ACCMODE == libc::O_RDONLY {
+			let NodeInfo::File(file_info) = self.get_node(id).await? else {
+				return Err(Error::IsDir);
+			};
+			
+			if file_info.size >= MIN_RANGED_READ_SIZE {
+				return Ok(self.file_handles.open_remote(id, file_info.hash, file_info.size));
+			}
+		}
+		
+		let is_truncated = flags & libc::O_TRUNC != 0 && flags & libc::O_ACCMODE != libc::O_RDONLY;
+		
+		let (hash, content) = if is_truncated {
+			let NodeInfo::File(file_info) = self.get_node(id).await? else {
+				return Err(Error::IsDir);
+			};
+			
+			if file_info.size == 0 {
+				flags &= !libc::O_TRUNC; // nothing to truncate
+			}
+			
+			(file_info.hash, Bytes::new())
+		} else {
+			self.get_file_data(id).await?
+		};
+		
+		Ok(self.file_handles.open(id, hash, content, flags))
+	}
+	
+	/// Stages buffered changes of an open file to be uploaded in the background
+	/// 
+	/// Fails with [`Error::Stale`] if the file was changed by someone else since it was opened.
+	async fn commit(&self, handle: &mut FileHandle) -> Result<(), Error> {
+		let Some(pending) = handle.pending() else {
+			return Ok(());
+		};
+		
+		let hash = self.local_file_cache.write_file_data(handle.id(), handle.hash(), pending.to_vec()).await
+			.map_err(stage_file_error)?;
+		
+		handle.committed(hash);
+		
+		Ok(())
+	}
+	
+	async fn commit_handle(&self, fh: u64) -> Result<(), Error> {
+		let handle = self.file_handles.get(fh).ok_or(Error::BadF)?;
+		let mut handle = handle.lock().await;
+		self.commit(&mut handle).await
+	}
+	
+	/// Cuts off the content of the file or pads it with zeros, through the handle if `ftruncate` was used
+	async fn set_size(&self, id: NodeID, fh: Option<u64>, size: u64) -> Result<(), Error> {
+		if let Some(fh) = fh {
+			let handle = self.file_handles.get(fh).ok_or(Error::BadF)?;
+			let mut handle = handle.lock().await;
+			
+			if handle.is_read_only() {
+				return Err(Error::BadF);
+			}
+			
+			handle.set_size(size);
+			return self.commit(&mut handle).await;
+		}
+		
+		self.local_file_cache.acquire_delegation(id, DelegationKind::Write).await.map_err(delegation_error)?;
+		
+		if !self.local_file_cache.can_set_file_size(id) {
+			// resized like it is written instead, as the server can't resize encrypted or staged content
+			let (hash, content) = self.get_file_data(id).await?;
+			let mut content = content.to_vec();
+			content.resize(size as usize, 0);
+			
+			self.local_file_cache.write_file_data(id, &hash, content).await.map_err(stage_file_error)?;
+			return Ok(());
+		}
+		
+		let NodeInfo::File(file_info) = self.get_node(id).await? else {
+			return Err(Error::IsDir);
+		};
+		
+		if file_info.size != size {
+			self.local_file_cache.set_file_size(id, &file_info.hash, size).await.map_err(write_file_error)?;
+		}
+		
+		Ok(())
+	}
+	
+	/// Waits until conflicting locks are released if `sleep` is set, by polling the server until the `requester` is interrupted
+	#[expect(clippy::too_many_arguments)]
+	async fn set_lock(&self, id: NodeID, owner: u64, typ: i32, start: u64, end: u64, pid: u32, sleep: bool, requester: u32) -> Result<(), Error> {
+		let end = lock_end(end);
+		let Some(kind) = lock_kind(typ)? else {
+			return self.local_file_cache.unlock_file(id, owner, start, end).await.map_err(lock_error);
+		};
+		
+		let mut delay = MIN_LOCK_RETRY_DELAY;
+		
+		loop {
+			match self.local_file_cache.lock_file(id, owner, kind, start, end, pid).await {
+				Err(LockFileError::Locked) if sleep => {
+					tokio::time::sleep(delay).await;
+					delay = (delay * 2).min(MAX_LOCK_RETRY_DELAY);
+					
+					if signal_pending(requester) {
+						return Err(Error::Intr);
+					}
+				},
+				result => return result.map_err(lock_error),
+			}
+		}
+	}
+	
+	/// Releases all locks of the owner on the file, only contacting the server if it might hold any
+	async fn release_locks(&self, id: NodeID, owner: u64) -> Result<(), Error> {
+		if !self.local_file_cache.holds_locks(id, owner) {
+			return Ok(());
+		}
+		
+		self.local_file_cache.unlock_file(id, owner, 0, None).await.map_err(lock_error)
+	}
 }
 
 impl Filesystem for FyeFilesystem {
+	fn init(&mut self, _req: &Request<'_>, config: &mut KernelConfig) -> Result<(), libc::c_int> {
+		// without readdirplus the kernel instead looks up every entry separately,
+		// without atomic truncation it truncates with a separate setattr instead of passing O_TRUNC to open
+		// without lock capabilities the kernel only coordinates locks between processes on this machine
+		if let Err(unsupported) = config.add_capabilities(FUSE_DO_READDIRPLUS | FUSE_ATOMIC_O_TRUNC | FUSE_POSIX_LOCKS | FUSE_FLOCK_LOCKS) {
+			eprintln!("kernel does not support capabilities: {unsupported:#x}");
+		}
+		
+		// small files are fetched whole on open and large ones read ahead by the client itself,
+		// so reading ahead in the kernel mostly saves round trips between the kernel and the client
+		if let Some(max_readahead) = self.inner.max_readahead {
+			if let Err(nearest) = config.set_max_readahead(max_readahead) {
+				eprintln!("kernel does not support a read ahead of {max_readahead} bytes, using {nearest} instead");
+				let _ = config.set_max_readahead(nearest);
+			}
+		}
+		
+		Ok(())
+	}
+	
+	fn destroy(&mut self) {
+		println!("node cache: {}", self.inner.local_file_cache.stats());
+	}
+	
+	fn statfs(&mut self, _req: &Request<'_>, _ino: u64, reply: ReplyStatfs) {
+		let this = self.inner;
+		respond(reply, async move || {
+			let stats = this.local_file_cache.get_storage_stats().await
+				.map_err(|err| match err {
+					FetchStatsError::NetworkFailure(NetworkError::Timeout) => Error::TimedOut,
+					FetchStatsError::NetworkFailure(NetworkError::Other) => Error::NoLink,
+					FetchStatsError::ServerError | FetchStatsError::ProtocolMismatch => Error::IO,
+				})?;
+			
+			let block_size = BLOCK_SIZE as u64;
+			let files = stats.file_count + stats.directory_count;
+			
+			Ok(StatfsReply {
+				blocks: stats.total_bytes / block_size,
+				bfree: stats.available_bytes / block_size,
+				bavail: stats.available_bytes / block_size,
+				files,
+				ffree: u64::MAX - files, // node ids are the only limit on the number of files
+				bsize: BLOCK_SIZE,
+				namelen: MAX_NAME_LENGTH,
+				frsize: BLOCK_SIZE,
+			})
+		})
+	}
+	
 	fn getattr(&mut self, _req: &Request, ino: u64, reply: ReplyAttr) {
 		println!("getattr");
 		let this = self.inner;
@@ -96,7 +475,7 @@ impl Filesystem for FyeFilesystem {
 			
 			Ok(AttrReply {
 				attr,
-				ttl: TTL,
+				ttl: this.attr_ttl,
 			})
 		})
 	}
@@ -111,58 +490,73 @@ impl Filesystem for FyeFilesystem {
 			let dir_info = this.get_directory(NodeID(parent)).await?;
 			
 			let Some(&entry) = dir_info.children.get(&name) else {
-				return Err(Error::NoEnt);
+				if this.negative_ttl.is_zero() {
+					return Err(Error::NoEnt);
+				}
+				
+				// the kernel remembers that the name doesn't exist if the entry has no node
+				return Ok(EntryReply {
+					attr: file_attr(NodeID(0), FileType::RegularFile, 0),
+					ttl: this.negative_ttl,
+					generation: 0,
+				});
 			};
 			
-			let attr = this.attr_for(entry).await;
+			// may fail if the cached directory is outdated
+			let attr = this.attr_for(entry).await?;
 			
 			Ok(EntryReply {
-				attr: attr.expect("child of directory should always exist"),
-				ttl: TTL,
+				attr,
+				ttl: this.entry_ttl,
 				generation: 0,
 			})
 		})
 	}
 	
-	fn readdir(&mut self, _req: &Request, ino: u64, _fh: u64, offset: i64, reply: ReplyDirectory) {
+	fn opendir(&mut self, _req: &Request<'_>, ino: u64, _flags: i32, reply: ReplyOpen) {
+		let this = self.inner;
+		respond(reply, async move || {
+			Ok(OpenReply {
+				fh: this.dir_handles.open(NodeID(ino)),
+				flags: 0,
+			})
+		})
+	}
+	
+	fn releasedir(&mut self, _req: &Request<'_>, _ino: u64, fh: u64, _flags: i32, reply: ReplyEmpty) {
+		self.inner.dir_handles.release(fh);
+		reply.ok();
+	}
+	
+	fn readdir(&mut self, _req: &Request, _ino: u64, fh: u64, offset: i64, reply: ReplyDirectory) {
 		println!("readdir");
 		let this = self.inner;
 		respond(reply, async move || {
-			let dir_info = this.get_directory(NodeID(ino)).await?;
-			
-			let mut futures: FuturesOrdered<_> = [
-				// TODO: avoid unnecessary allocation
-				(".".to_owned(), ino),
-				("..".to_owned(), dir_info.parent.0),
-			].into_iter()
-				.chain(dir_info.children.iter().map(|(name, entry)| {
-					// TODO: avoid unnecessary allocation
-					(name.to_owned(), entry.0)
-				}))
-				.enumerate()
-				.skip(offset as usize)
-				.map(|(i, (name, entry))| async move {
-					let node = this.get_node(NodeID(ino)).await?;
-					
-					let kind = match node {
-						NodeInfo::Directory(_) => FileType::Directory,
-						NodeInfo::File(_) => FileType::RegularFile,
-					};
-					
-					Ok(DirectoryReplyEntry {
-						ino: entry,
-						name,
-						offset: i as i64 + 1,
-						kind,
-					})
-				})
-				.collect();
-			
-			let mut entries = Vec::with_capacity(futures.len());
+			let entries = this.read_dir_handle(fh, offset).await?
+				.into_iter()
+				.map(|entry| DirectoryReplyEntry {
+					ino: entry.id.0,
+					name: entry.name,
+					offset: entry.offset,
+					kind: entry.kind,
+				});
 			
-			while let Some(entry) = futures.next().await {
-				entries.push(entry?);
-			}
+			Ok(entries)
+		})
+	}
+	
+	fn readdirplus(&mut self, _req: &Request<'_>, _ino: u64, fh: u64, offset: i64, reply: ReplyDirectoryPlus) {
+		let this = self.inner;
+		respond(reply, async move || {
+			let entries = this.read_dir_handle(fh, offset).await?
+				.into_iter()
+				.map(|entry| DirectoryPlusReplyEntry {
+					attr: file_attr(entry.id, entry.kind, entry.size),
+					name: entry.name,
+					offset: entry.offset,
+					ttl: this.entry_ttl,
+					generation: 0,
+				});
 			
 			Ok(entries)
 		})
@@ -183,6 +577,7 @@ impl Filesystem for FyeFilesystem {
 					CreateNodeError::ParentNotFound => Error::NoEnt,
 					CreateNodeError::ParentNotADirectory => Error::NotDir,
 					CreateNodeError::AlreadyExists => Error::Exist,
+					CreateNodeError::QuotaExceeded => Error::DQuot,
 				})?;
 			
 			let attr = FileAttr {
@@ -200,18 +595,18 @@ impl Filesystem for FyeFilesystem {
 				gid: 0,
 				rdev: 0,
 				flags: 0,
-				blksize: 512,
+				blksize: BLOCK_SIZE,
 			};
 			
 			Ok(EntryReply {
 				attr,
-				ttl: TTL,
+				ttl: this.entry_ttl,
 				generation: 0,
 			})
 		})
 	}
 	
-	fn create(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, mode: u32, _umask: u32, _flags: i32, reply: ReplyCreate) {
+	fn create(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, mode: u32, _umask: u32, flags: i32, reply: ReplyCreate) {
 		println!("create");
 		let this = self.inner;
 		let name = name.to_str().map(ToOwned::to_owned);
@@ -228,20 +623,26 @@ impl Filesystem for FyeFilesystem {
 			};
 			
 			let result = if is_directory {
-				this.local_file_cache.create_dir(NodeID(parent), name).await
+				this.local_file_cache.create_dir(NodeID(parent), name).await.map(|id| (id, None))
 			} else {
-				this.local_file_cache.create_file(NodeID(parent), name).await
+				this.local_file_cache.create_file(NodeID(parent), name).await.map(|(id, hash)| (id, Some(hash)))
 			};
 			
-			let id = result.map_err(|err| match err {
+			let (id, hash) = result.map_err(|err| match err {
 				CreateNodeError::NetworkFailure(NetworkError::Timeout) => Error::TimedOut,
 				CreateNodeError::NetworkFailure(NetworkError::Other) => Error::NoLink,
 				CreateNodeError::ServerError | CreateNodeError::ProtocolMismatch => Error::IO,
 				CreateNodeError::ParentNotFound => Error::NoEnt,
 				CreateNodeError::ParentNotADirectory => Error::NotDir,
 				CreateNodeError::AlreadyExists => Error::Exist,
+				CreateNodeError::QuotaExceeded => Error::DQuot,
 			})?;
 			
+			let fh = match hash {
+				Some(hash) => this.file_handles.open(id, hash, Bytes::new(), flags & !libc::O_TRUNC), // new files are empty already
+				None => 0,
+			};
+			
 			let (kind, perm) = if is_directory {
 				(FileType::Directory, DIR_PERMISSIONS)
 			} else {
@@ -264,11 +665,11 @@ impl Filesystem for FyeFilesystem {
 					gid: 0,
 					rdev: 0,
 					flags: 0,
-					blksize: 512,
+					blksize: BLOCK_SIZE,
 				},
-				ttl: TTL,
+				ttl: this.entry_ttl,
 				generation: 0,
-				fh: 0,
+				fh,
 				flags: 0,
 			})
 		})
@@ -281,11 +682,11 @@ impl Filesystem for FyeFilesystem {
 		_mode: Option<u32>,
 		_uid: Option<u32>,
 		_gid: Option<u32>,
-		_size: Option<u64>,
+		size: Option<u64>,
 		_atime: Option<fuser::TimeOrNow>,
 		_mtime: Option<fuser::TimeOrNow>,
 		_ctime: Option<std::time::SystemTime>,
-		_fh: Option<u64>,
+		fh: Option<u64>,
 		_crtime: Option<std::time::SystemTime>,
 		_chgtime: Option<std::time::SystemTime>,
 		_bkuptime: Option<std::time::SystemTime>,
@@ -294,34 +695,22 @@ impl Filesystem for FyeFilesystem {
 	) {
 		println!("setattr");
 		let this = self.inner;
-		// TODO: implement setattr
+		// only the size can be set, as nodes have no other attributes
 		respond(reply, async move || {
+			if let Some(size) = size {
+				this.set_size(NodeID(ino), fh, size).await?;
+			}
+			
 			let attr = this.attr_for(NodeID(ino)).await?;
 			
 			Ok(AttrReply {
 				attr,
-				ttl: TTL,
+				ttl: this.attr_ttl,
 			})
 		})
-		// respond(reply, || -> MaybeAsync<_> {
-			// if let Some(size) = size {
-			// 	if size > MAX_FILE_SIZE {
-			// 		Err(Error::FBig)?;
-			// 	}
-				
-			// 	let file = get_file_mut(&mut self.node_infos, ino)?;
-				
-			// 	file.content.resize(size as usize, 0);
-			// }
-			
-			// Sync(Ok(AttrReply {
-			// 	attr: self.attr_for(ino)?,
-			// 	ttl: TTL,
-			// }))
-		// })
 	}
 	
-	fn unlink(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: fuser::ReplyEmpty) {
+	fn unlink(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
 		println!("unlink");
 		let this = self.inner;
 		let name = name.to_str().map(ToOwned::to_owned);
@@ -336,13 +725,14 @@ impl Filesystem for FyeFilesystem {
 					DeleteFileError::NotFound => Error::NoEnt,
 					DeleteFileError::ParentNotADirectory => Error::NotDir,
 					DeleteFileError::NotAFile => Error::IsDir,
+					DeleteFileError::Locked => Error::Again,
 				})?;
 			
 			Ok(())
 		})
 	}
 	
-	fn rmdir(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: fuser::ReplyEmpty) {
+	fn rmdir(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
 		println!("rmdir");
 		let this = self.inner;
 		let name = name.to_str().map(ToOwned::to_owned);
@@ -363,11 +753,53 @@ impl Filesystem for FyeFilesystem {
 		})
 	}
 	
+	/// Exchanging both entries isn't supported
+	fn rename(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, newparent: u64, newname: &OsStr, flags: u32, reply: ReplyEmpty) {
+		let this = self.inner;
+		let name = name.to_str().map(ToOwned::to_owned);
+		let new_name = newname.to_str().map(ToOwned::to_owned);
+		respond(reply, async move || {
+			let name = name.ok_or(Error::IlSeq)?;
+			let new_name = new_name.ok_or(Error::IlSeq)?;
+			
+			if flags & !libc::RENAME_NOREPLACE != 0 {
+				return Err(Error::Inval);
+			}
+			
+			let replace = flags & libc::RENAME_NOREPLACE == 0;
+			
+			this.local_file_cache.rename(NodeID(parent), name, NodeID(newparent), new_name, replace).await
+				.map_err(|err| match err {
+					RenameError::NetworkFailure(NetworkError::Timeout) => Error::TimedOut,
+					RenameError::NetworkFailure(NetworkError::Other) => Error::NoLink,
+					RenameError::ServerError | RenameError::ProtocolMismatch => Error::IO,
+					RenameError::NotFound => Error::NoEnt,
+					RenameError::NotADirectory => Error::NotDir,
+					RenameError::NotAFile => Error::IsDir,
+					RenameError::AlreadyExists => Error::Exist,
+					RenameError::NotEmpty => Error::NotEmpty,
+					RenameError::QuotaExceeded => Error::DQuot,
+					RenameError::InvalidMove => Error::Inval,
+					RenameError::Locked => Error::Again,
+				})
+		})
+	}
+	
+	fn open(&mut self, _req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
+		let this = self.inner;
+		respond(reply, async move || {
+			Ok(OpenReply {
+				fh: this.open_file(NodeID(ino), flags).await?,
+				flags: 0,
+			})
+		})
+	}
+	
 	fn read(
 		&mut self,
 		_req: &Request,
-		ino: u64,
-		_fh: u64,
+		_ino: u64,
+		fh: u64,
 		offset: i64,
 		size: u32,
 		_flags: i32,
@@ -377,27 +809,28 @@ impl Filesystem for FyeFilesystem {
 		println!("read");
 		let this = self.inner;
 		respond(reply, async move || {
-			let data = this.local_file_cache.get_file_data(NodeID(ino)).await
-				.map_err(|err| match err {
-					FetchFileError::NetworkFailure(NetworkError::Timeout) => Error::TimedOut,
-					FetchFileError::NetworkFailure(NetworkError::Other) => Error::NoLink,
-					FetchFileError::ServerError | FetchFileError::ProtocolMismatch => Error::IO,
-					FetchFileError::NotFound => Error::NoEnt,
-					FetchFileError::NotAFile => Error::IsDir,
-				})?;
+			let handle = this.file_handles.get(fh).ok_or(Error::BadF)?;
+			let mut handle = handle.lock().await;
 			
-			let start = cmp::min(offset as usize, data.len());
-			let end = cmp::min(offset as usize + size as usize, data.len());
+			if let Some((start, end)) = handle.remote_range(offset as u64, size) {
+				if let Some(data) = handle.read_fetched(start, end) {
+					return Ok(data);
+				}
+				
+				let fetch_end = handle.read_ahead_end(start, end, this.read_ahead);
+				let data = this.get_file_range(handle.id(), handle.hash(), start, fetch_end).await?;
+				return Ok(handle.fetched(start, end, data));
+			}
 			
-			Ok(data.slice(start..end))
+			Ok(handle.read(offset as u64, size))
 		})
 	}
 	
 	fn write(
 		&mut self,
 		_req: &Request<'_>,
-		ino: u64,
-		_fh: u64,
+		_ino: u64,
+		fh: u64,
 		offset: i64,
 		data: &[u8],
 		_write_flags: u32,
@@ -409,15 +842,138 @@ impl Filesystem for FyeFilesystem {
 		let this = self.inner;
 		let data = data.to_owned(); // TODO: can this (potentially large) allocation be avoided?
 		respond(reply, async move || {
-			this.local_file_cache.write_file_data(NodeID(ino), offset.try_into().unwrap(), data).await
-				.map_err(|err| match err {
-					WriteFileError::NetworkFailure(NetworkError::Timeout) => Error::TimedOut,
-					WriteFileError::NetworkFailure(NetworkError::Other) => Error::NoLink,
-					WriteFileError::ServerError | WriteFileError::ProtocolMismatch => Error::IO,
-					WriteFileError::NotFound => Error::NoEnt,
-					WriteFileError::NotAFile => Error::IsDir,
-					WriteFileError::Modified => todo!("What to do?"),
-				})
+			let handle = this.file_handles.get(fh).ok_or(Error::BadF)?;
+			let mut handle = handle.lock().await;
+			
+			if handle.is_read_only() {
+				return Err(Error::BadF);
+			}
+			
+			Ok(handle.write(offset as u64, &data))
 		})
 	}
+	
+	/// Called on every `close` of a descriptor, so errors committing the writes are reported to the application
+	/// 
+	/// Also releases the POSIX locks of the closing process, which are lost on closing any descriptor of the file.
+	fn flush(&mut self, _req: &Request<'_>, ino: u64, fh: u64, lock_owner: u64, reply: ReplyEmpty) {
+		let this = self.inner;
+		respond(reply, async move || {
+			let result = this.commit_handle(fh).await;
+			this.release_locks(NodeID(ino), lock_owner).await?;
+			
+			result
+		})
+	}
+	
+	/// Waits until the changes are uploaded, unlike `flush`
+	fn fsync(&mut self, _req: &Request<'_>, ino: u64, fh: u64, _datasync: bool, reply: ReplyEmpty) {
+		let this = self.inner;
+		respond(reply, async move || {
+			this.commit_handle(fh).await?;
+			
+			this.local_file_cache.sync_file(NodeID(ino)).await
+				.map_err(write_file_error)
+		})
+	}
+	
+	/// Writes are normally committed by `flush` already, this only catches handles that were never flushed
+	fn release(&mut self, _req: &Request<'_>, ino: u64, fh: u64, _flags: i32, lock_owner: Option<u64>, _flush: bool, reply: ReplyEmpty) {
+		let this = self.inner;
+		respond(reply, async move || {
+			// only set if the file was locked with flock, those locks belong to the open file instead of a process
+			if let Some(lock_owner) = lock_owner {
+				this.release_locks(NodeID(ino), lock_owner).await?;
+			}
+			
+			let Some(handle) = this.file_handles.release(fh) else {
+				return Ok(());
+			};
+			
+			let mut handle = handle.lock().await;
+			this.commit(&mut handle).await
+		})
+	}
+	
+	fn getlk(
+		&mut self,
+		_req: &Request<'_>,
+		ino: u64,
+		_fh: u64,
+		lock_owner: u64,
+		start: u64,
+		end: u64,
+		typ: i32,
+		pid: u32,
+		reply: ReplyLock,
+	) {
+		let this = self.inner;
+		respond(reply, async move || {
+			let kind = lock_kind(typ)?.ok_or(Error::Inval)?;
+			let conflicting = this.local_file_cache.test_lock(NodeID(ino), lock_owner, kind, start, lock_end(end)).await
+				.map_err(lock_error)?;
+			
+			Ok(match conflicting {
+				Some(lock) => LockReply {
+					start: lock.start,
+					end: lock.end.map_or(LOCK_OFFSET_MAX, |end| end - 1),
+					typ: match lock.kind {
+						LockKind::Shared => libc::F_RDLCK,
+						LockKind::Exclusive => libc::F_WRLCK,
+					},
+					pid: lock.pid,
+				},
+				None => LockReply {
+					start,
+					end,
+					typ: libc::F_UNLCK,
+					pid,
+				},
+			})
+		})
+	}
+	
+	/// Locks are coordinated through the server, so they also exclude processes on other machines
+	/// 
+	/// `flock` locks arrive here as well, as locks on the whole file owned by the open file.
+	/// Unlike on local filesystems, they therefore conflict with POSIX locks of other owners on the same file.
+	fn setlk(
+		&mut self,
+		req: &Request<'_>,
+		ino: u64,
+		_fh: u64,
+		lock_owner: u64,
+		start: u64,
+		end: u64,
+		typ: i32,
+		pid: u32,
+		sleep: bool,
+		reply: ReplyEmpty,
+	) {
+		let this = self.inner;
+		let requester = req.pid();
+		respond(reply, async move || {
+			this.set_lock(NodeID(ino), lock_owner, typ, start, end, pid, sleep, requester).await
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	#[test]
+	fn detects_pending_signals() {
+		let status = |pending: &str, shared: &str, blocked: &str| format!("Name:\tcat\nSigPnd:\t{pending}\nShdPnd:\t{shared}\nSigBlk:\t{blocked}\nSigIgn:\t0000000000000000\n");
+		
+		assert!(!has_unblocked_signal(&status("0000000000000000", "0000000000000000", "0000000000000000")));
+		assert!(has_unblocked_signal(&status("0000000000000002", "0000000000000000", "0000000000000000")));
+		assert!(has_unblocked_signal(&status("0000000000000000", "0000000000004000", "0000000000000002")));
+		// blocked signals stay pending without interrupting
+		assert!(!has_unblocked_signal(&status("0000000000000000", "0000000000004000", "0000000000004000")));
+		
+		assert!(!signal_pending(std::process::id()));
+		// processes that exited don't wait anymore either
+		assert!(signal_pending(u32::MAX));
+	}
 }
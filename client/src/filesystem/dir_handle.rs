@@ -0,0 +1,124 @@
+use std::{collections::{HashMap, VecDeque}, sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex}};
+
+use fuser::FileType;
+use fye_shared::{DirectoryListing, EntryAttributes, NodeID};
+use tokio::sync::Mutex as AsyncMutex;
+
+#[derive(Clone, Debug)]
+pub struct ListingEntry {
+	pub offset: i64,
+	pub name: String,
+	pub id: NodeID,
+	pub kind: FileType,
+	pub size: u64,
+}
+
+/// State of a directory opened with `opendir`
+/// 
+/// Pages of the listing are fetched as the kernel reads further and entries that were already returned are discarded,
+/// so only a single page is held in memory at a time.
+#[derive(Debug)]
+pub struct DirHandle {
+	id: NodeID,
+	entries: VecDeque<ListingEntry>,
+	next_offset: i64,
+	continuation: Option<String>,
+	is_done: bool,
+}
+
+impl DirHandle {
+	fn new(id: NodeID) -> Self {
+		Self {
+			id,
+			entries: VecDeque::new(),
+			next_offset: 0,
+			continuation: None,
+			is_done: false,
+		}
+	}
+	
+	pub fn id(&self) -> NodeID {
+		self.id
+	}
+	
+	pub fn continuation(&self) -> Option<&str> {
+		self.continuation.as_deref()
+	}
+	
+	/// Discards all entries up to and including `offset`
+	/// 
+	/// Starts over from the beginning if entries before `offset` were already discarded, e.g. after `rewinddir`
+	pub fn seek(&mut self, offset: i64) {
+		let first_offset = self.next_offset - self.entries.len() as i64;
+		
+		if offset < first_offset {
+			*self = Self::new(self.id);
+		}
+		
+		while self.entries.front().is_some_and(|entry| entry.offset <= offset) {
+			self.entries.pop_front();
+		}
+	}
+	
+	pub fn needs_page(&self) -> bool {
+		self.entries.is_empty() && !self.is_done
+	}
+	
+	pub fn push_page(&mut self, listing: DirectoryListing) {
+		let is_first_page = self.next_offset == 0;
+		
+		let mut push = |name: String, id: NodeID, kind: FileType, size: u64| {
+			self.next_offset += 1;
+			self.entries.push_back(ListingEntry {
+				offset: self.next_offset,
+				name,
+				id,
+				kind,
+				size,
+			});
+		};
+		
+		if is_first_page {
+			push(".".to_owned(), self.id, FileType::Directory, 0);
+			push("..".to_owned(), listing.parent, FileType::Directory, 0);
+		}
+		
+		for (name, entry) in listing.children {
+			match entry.attributes {
+				EntryAttributes::Directory => push(name, entry.id, FileType::Directory, 0),
+				EntryAttributes::File(file_info) => push(name, entry.id, FileType::RegularFile, file_info.size),
+			}
+		}
+		
+		self.is_done = listing.continuation.is_none();
+		self.continuation = listing.continuation;
+	}
+	
+	pub fn entries(&self) -> impl Iterator<Item = &ListingEntry> {
+		self.entries.iter()
+	}
+}
+
+#[derive(Default, Debug)]
+pub struct DirHandles {
+	last_fh: AtomicU64,
+	handles: Mutex<HashMap<u64, Arc<AsyncMutex<DirHandle>>>>,
+}
+
+impl DirHandles {
+	pub fn open(&self, id: NodeID) -> u64 {
+		// 0 is never handed out
+		let fh = self.last_fh.fetch_add(1, Ordering::Relaxed) + 1;
+		let handle = Arc::new(AsyncMutex::new(DirHandle::new(id)));
+		self.handles.lock().expect("poison").insert(fh, handle);
+		fh
+	}
+	
+	pub fn get(&self, fh: u64) -> Option<Arc<AsyncMutex<DirHandle>>> {
+		self.handles.lock().expect("poison").get(&fh).cloned()
+	}
+	
+	pub fn release(&self, fh: u64) {
+		self.handles.lock().expect("poison").remove(&fh);
+	}
+}
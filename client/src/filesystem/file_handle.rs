@@ -0,0 +1,245 @@
+use std::{cmp, collections::HashMap, sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex}};
+
+use bytes::Bytes;
+use fye_shared::{Hash, NodeID};
+use tokio::sync::Mutex as AsyncMutex;
+
+/// State of a file opened with `open` or `create`
+/// 
+/// The content is pinned to the version that was current when the file was opened,
+/// so reads through the handle stay consistent even if the file is changed by someone else in the meantime.
+/// Writes are buffered and only sent to the server when the handle is flushed.
+/// Large files opened read-only aren't loaded at all, their ranges are fetched as they are read instead.
+#[derive(Debug)]
+pub struct FileHandle {
+	id: NodeID,
+	/// Hash of the pinned version on the server, writes fail if the file was changed since
+	hash: Hash,
+	content: Bytes,
+	/// Size of the pinned version if its content wasn't loaded
+	remote_size: Option<u64>,
+	/// Offset of the last range fetched from the server and its data, including what was read ahead
+	fetched: Option<(u64, Bytes)>,
+	/// Where the last read ended, so sequential reads can be told apart from random ones
+	next_offset: u64,
+	/// Content including all writes that weren't committed yet
+	pending: Option<Vec<u8>>,
+	is_read_only: bool,
+	is_append: bool,
+}
+
+impl FileHandle {
+	fn new(id: NodeID, hash: Hash, content: Bytes, flags: i32) -> Self {
+		let is_read_only = flags & libc::O_ACCMODE == libc::O_RDONLY;
+		let is_truncated = flags & libc::O_TRUNC != 0 && !is_read_only;
+		
+		Self {
+			id,
+			hash,
+			content,
+			remote_size: None,
+			fetched: None,
+			next_offset: 0,
+			pending: is_truncated.then(Vec::new),
+			is_read_only,
+			is_append: flags & libc::O_APPEND != 0,
+		}
+	}
+	
+	pub fn id(&self) -> NodeID {
+		self.id
+	}
+	
+	pub fn hash(&self) -> &Hash {
+		&self.hash
+	}
+	
+	pub fn is_read_only(&self) -> bool {
+		self.is_read_only
+	}
+	
+	/// Returns the range that has to be fetched from the server instead of calling [`Self::read`], if the content wasn't loaded
+	pub fn remote_range(&self, offset: u64, size: u32) -> Option<(u64, u64)> {
+		let remote_size = self.remote_size?;
+		let start = cmp::min(offset, remote_size);
+		
+		Some((start, cmp::min(start + size as u64, remote_size)))
+	}
+	
+	/// Returns a remote range without fetching it if it was already fetched by reading ahead
+	pub fn read_fetched(&mut self, start: u64, end: u64) -> Option<Bytes> {
+		let (fetched_start, data) = self.fetched.as_ref()?;
+		
+		if start < *fetched_start || end > fetched_start + data.len() as u64 {
+			return None;
+		}
+		
+		self.next_offset = end;
+		Some(data.slice((start - fetched_start) as usize..(end - fetched_start) as usize))
+	}
+	
+	/// Where fetching a remote range should end, extended by `read_ahead` bytes if it continues where the last read ended
+	pub fn read_ahead_end(&self, start: u64, end: u64, read_ahead: u64) -> u64 {
+		let remote_size = self.remote_size.unwrap_or(end);
+		
+		match start == self.next_offset {
+			true => cmp::max(end, cmp::min(end.saturating_add(read_ahead), remote_size)),
+			false => end,
+		}
+	}
+	
+	/// Remembers a fetched remote range for later reads and returns the part that was read
+	pub fn fetched(&mut self, start: u64, end: u64, data: Bytes) -> Bytes {
+		let read = data.slice(..cmp::min((end - start) as usize, data.len()));
+		
+		self.next_offset = start + read.len() as u64;
+		self.fetched = Some((start, data));
+		
+		read
+	}
+	
+	pub fn read(&self, offset: u64, size: u32) -> Bytes {
+		let len = match &self.pending {
+			Some(pending) => pending.len(),
+			None => self.content.len(),
+		};
+		
+		let start = cmp::min(offset as usize, len);
+		let end = cmp::min(start + size as usize, len);
+		
+		// pending content can still change, so only unchanged content can be shared without copying
+		match &self.pending {
+			Some(pending) => Bytes::copy_from_slice(&pending[start..end]),
+			None => self.content.slice(start..end),
+		}
+	}
+	
+	/// Writes at the end of the file instead of `offset` if the file was opened with `O_APPEND`
+	pub fn write(&mut self, offset: u64, data: &[u8]) -> u32 {
+		let pending = self.pending.get_or_insert_with(|| self.content.to_vec());
+		
+		let start = if self.is_append { pending.len() } else { offset as usize };
+		let end = start + data.len();
+		
+		if pending.len() < end {
+			pending.resize(end, 0);
+		}
+		
+		pending[start..end].copy_from_slice(data);
+		
+		data.len() as u32
+	}
+	
+	/// Cuts off the content or pads it with zeros, which is only sent to the server when the handle is flushed like writes
+	pub fn set_size(&mut self, size: u64) {
+		self.pending.get_or_insert_with(|| self.content.to_vec()).resize(size as usize, 0);
+	}
+	
+	/// Returns the content that has to be written to the server, if any
+	pub fn pending(&self) -> Option<&[u8]> {
+		self.pending.as_deref()
+	}
+	
+	/// Pins the version that was written to the server
+	pub fn committed(&mut self, hash: Hash) {
+		if let Some(pending) = self.pending.take() {
+			self.content = pending.into();
+		}
+		
+		self.hash = hash;
+	}
+}
+
+#[derive(Default, Debug)]
+pub struct FileHandles {
+	last_fh: AtomicU64,
+	handles: Mutex<HashMap<u64, Arc<AsyncMutex<FileHandle>>>>,
+}
+
+impl FileHandles {
+	pub fn open(&self, id: NodeID, hash: Hash, content: Bytes, flags: i32) -> u64 {
+		self.insert(FileHandle::new(id, hash, content, flags))
+	}
+	
+	/// Opens a read-only handle without loading the content, see [`FileHandle::remote_range`]
+	pub fn open_remote(&self, id: NodeID, hash: Hash, size: u64) -> u64 {
+		self.insert(FileHandle {
+			remote_size: Some(size),
+			..FileHandle::new(id, hash, Bytes::new(), libc::O_RDONLY)
+		})
+	}
+	
+	fn insert(&self, handle: FileHandle) -> u64 {
+		// 0 is never handed out
+		let fh = self.last_fh.fetch_add(1, Ordering::Relaxed) + 1;
+		self.handles.lock().expect("poison").insert(fh, Arc::new(AsyncMutex::new(handle)));
+		fh
+	}
+	
+	pub fn get(&self, fh: u64) -> Option<Arc<AsyncMutex<FileHandle>>> {
+		self.handles.lock().expect("poison").get(&fh).cloned()
+	}
+	
+	pub fn release(&self, fh: u64) -> Option<Arc<AsyncMutex<FileHandle>>> {
+		self.handles.lock().expect("poison").remove(&fh)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	#[test]
+	fn reads_ahead_sequentially() {
+		let content = Bytes::from_static(b"0123456789");
+		let mut handle = FileHandle {
+			remote_size: Some(content.len() as u64),
+			..FileHandle::new(NodeID(1), Hash(String::new()), Bytes::new(), libc::O_RDONLY)
+		};
+		
+		let (start, end) = handle.remote_range(0, 2).unwrap();
+		assert!(handle.read_fetched(start, end).is_none());
+		assert_eq!(handle.read_ahead_end(start, end, 4), 6);
+		assert_eq!(handle.fetched(start, end, content.slice(0..6)), "01");
+		
+		// the following reads are served from what was read ahead until it runs out
+		assert_eq!(handle.read_fetched(2, 4).unwrap(), "23");
+		assert_eq!(handle.read_fetched(4, 6).unwrap(), "45");
+		assert!(handle.read_fetched(6, 8).is_none());
+		assert_eq!(handle.read_ahead_end(6, 8, 4), 10);
+		
+		// random reads aren't extended
+		assert!(handle.read_fetched(8, 9).is_none());
+		assert_eq!(handle.read_ahead_end(8, 9, 4), 9);
+	}
+	
+	#[test]
+	fn reads_without_copying() {
+		let content = Bytes::from_static(b"0123456789");
+		let mut handle = FileHandle::new(NodeID(1), Hash(String::new()), content.clone(), libc::O_RDWR);
+		
+		let read = handle.read(2, 4);
+		assert_eq!(read, "2345");
+		assert_eq!(read.as_ptr(), content[2..].as_ptr());
+		assert_eq!(handle.read(8, 4), "89");
+		
+		handle.write(3, b"ab");
+		assert_eq!(handle.read(2, 4), "2ab5");
+		assert_eq!(handle.read(12, 4), "");
+	}
+	
+	#[test]
+	fn sets_size_until_committed() {
+		let mut handle = FileHandle::new(NodeID(1), Hash(String::new()), Bytes::from_static(b"0123456789"), libc::O_RDWR);
+		
+		handle.set_size(4);
+		assert_eq!(handle.pending(), Some(&b"0123"[..]));
+		
+		handle.set_size(6);
+		assert_eq!(handle.read(0, 10), "0123\0\0");
+		
+		handle.committed(Hash("resized".to_owned()));
+		assert_eq!(handle.pending(), None);
+		assert_eq!(handle.read(0, 10), "0123\0\0");
+	}
+}
@@ -1,6 +1,6 @@
 use std::{future::Future, time::Duration};
 
-use fuser::{FileAttr, FileType, ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory, ReplyEmpty, ReplyEntry, ReplyWrite};
+use fuser::{FileAttr, FileType, ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory, ReplyDirectoryPlus, ReplyEmpty, ReplyEntry, ReplyLock, ReplyOpen, ReplyStatfs, ReplyWrite};
 
 #[derive(Debug)]
 pub enum Error {
@@ -11,9 +11,18 @@ pub enum Error {
 	NotEmpty,
 	FBig,
 	IlSeq,
+	Inval,
 	NotSup,
 	TimedOut,
 	NoLink,
+	BadF,
+	DQuot,
+	/// Another owner holds a conflicting lock
+	Again,
+	/// The file was changed by someone else since it was opened
+	Stale,
+	/// The process waiting for the request received a signal
+	Intr,
 	IO,
 }
 
@@ -30,9 +39,15 @@ impl From<Error> for i32 {
 			NotEmpty => ENOTEMPTY,
 			FBig => EFBIG,
 			IlSeq => EILSEQ,
+			Inval => EINVAL,
 			NotSup => ENOTSUP,
 			TimedOut => ETIMEDOUT,
 			NoLink => ENOLINK,
+			BadF => EBADF,
+			DQuot => EDQUOT,
+			Again => EAGAIN,
+			Stale => ESTALE,
+			Intr => EINTR,
 			IO => EIO,
 		}
 	}
@@ -117,6 +132,34 @@ where
 	}
 }
 
+#[derive(Debug)]
+pub struct DirectoryPlusReplyEntry {
+	pub name: String,
+	pub offset: i64,
+	pub attr: FileAttr,
+	pub ttl: Duration,
+	pub generation: u64,
+}
+
+impl<I> Reply<I> for ReplyDirectoryPlus
+where
+	I: IntoIterator<Item = DirectoryPlusReplyEntry>,
+{
+	fn ok(mut self, iter: I) {
+		for entry in iter {
+			if self.add(entry.attr.ino, entry.offset, entry.name, &entry.ttl, &entry.attr, entry.generation) {
+				break;
+			}
+		}
+		
+		self.ok();
+	}
+	
+	fn error(self, err: Error) {
+		self.error(err.into());
+	}
+}
+
 #[derive(Debug)]
 pub struct CreateReply {
 	pub attr: FileAttr,
@@ -136,6 +179,64 @@ impl Reply<CreateReply> for ReplyCreate {
 	}
 }
 
+#[derive(Debug)]
+pub struct OpenReply {
+	pub fh: u64,
+	pub flags: u32,
+}
+
+impl Reply<OpenReply> for ReplyOpen {
+	fn ok(self, val: OpenReply) {
+		self.opened(val.fh, val.flags);
+	}
+	
+	fn error(self, err: Error) {
+		self.error(err.into());
+	}
+}
+
+#[derive(Debug)]
+pub struct StatfsReply {
+	pub blocks: u64,
+	pub bfree: u64,
+	pub bavail: u64,
+	pub files: u64,
+	pub ffree: u64,
+	pub bsize: u32,
+	pub namelen: u32,
+	pub frsize: u32,
+}
+
+impl Reply<StatfsReply> for ReplyStatfs {
+	fn ok(self, val: StatfsReply) {
+		self.statfs(val.blocks, val.bfree, val.bavail, val.files, val.ffree, val.bsize, val.namelen, val.frsize);
+	}
+	
+	fn error(self, err: Error) {
+		self.error(err.into());
+	}
+}
+
+/// A conflicting lock, or the requested one with a type of `F_UNLCK` if there is none
+#[derive(Debug)]
+pub struct LockReply {
+	pub start: u64,
+	/// Inclusive
+	pub end: u64,
+	pub typ: i32,
+	pub pid: u32,
+}
+
+impl Reply<LockReply> for ReplyLock {
+	fn ok(self, val: LockReply) {
+		self.locked(val.start, val.end, val.typ, val.pid);
+	}
+	
+	fn error(self, err: Error) {
+		self.error(err.into());
+	}
+}
+
 impl<T> Reply<T> for ReplyData
 where
 	T: AsRef<[u8]>,
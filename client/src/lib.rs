@@ -1,28 +1,147 @@
-#![feature(async_closure)]
 #![forbid(unsafe_code)]
 #![deny(non_snake_case)]
 
-use std::{io, path::Path};
+use std::{io, path::{Path, PathBuf}};
 use reqwest::Url;
 use tokio::runtime::Runtime;
 
 mod remote_data_service;
 mod local_file_cache;
 mod filesystem;
+mod transfer;
+mod sync;
+mod mount_options;
+mod testing;
 
-use remote_data_service::RemoteDataService;
-use local_file_cache::LocalFileCache;
+use fye_shared::NodeID;
+use remote_data_service::{parse_fingerprint, NetworkError, RemoteDataService, ResolvePathError, TlsOptions, UnlockVolumeError};
+use local_file_cache::{CachePolicy, LocalFileCache};
 use filesystem::FyeFilesystem;
 
-pub fn mount(mountpoint: impl AsRef<Path>) -> Result<(), io::Error> {
+pub use transfer::TransferReport;
+pub use mount_options::MountOptions;
+
+fn read_env_file(name: &str) -> Option<Vec<u8>> {
+	let path = std::env::var_os(name)?;
+	Some(std::fs::read(&path).unwrap_or_else(|err| panic!("{name} should be a readable file: {err}")))
+}
+
+/// Configured through `FYE_SERVER_URL` and the optional `FYE_TLS_CA`, `FYE_TLS_PIN` (SHA-256 fingerprint in hex),
+/// `FYE_TLS_CLIENT_CERT` and `FYE_TLS_CLIENT_KEY`
+/// 
+/// The volume is encrypted if `FYE_PASSPHRASE` is set.
+fn remote_data_service(runtime: &Runtime) -> Result<RemoteDataService, io::Error> {
+	let base_url = std::env::var("FYE_SERVER_URL").unwrap_or_else(|_| "http://localhost:3000/api/".to_owned());
+	let base_url = Url::parse(&base_url).expect("FYE_SERVER_URL should be a valid url");
+	
+	let identity = read_env_file("FYE_TLS_CLIENT_CERT").map(|mut identity| {
+		identity.extend(read_env_file("FYE_TLS_CLIENT_KEY").expect("FYE_TLS_CLIENT_KEY should be set when FYE_TLS_CLIENT_CERT is set"));
+		identity
+	});
+	
+	let options = TlsOptions {
+		ca_certificate: read_env_file("FYE_TLS_CA"),
+		pinned_fingerprint: std::env::var("FYE_TLS_PIN").ok()
+			.map(|pin| parse_fingerprint(&pin).expect("FYE_TLS_PIN should be a SHA-256 fingerprint")),
+		identity,
+	};
+	
+	let mut remote_data_service = RemoteDataService::with_tls(base_url, options).expect("TLS options should be valid");
+	
+	if let Ok(passphrase) = std::env::var("FYE_PASSPHRASE") {
+		runtime.block_on(remote_data_service.unlock_volume(&passphrase))
+			.map_err(|err| match err {
+				UnlockVolumeError::NetworkFailure(err) => network_error(err),
+				UnlockVolumeError::WrongPassphrase => io::Error::new(io::ErrorKind::PermissionDenied, "wrong passphrase"),
+				err => io::Error::other(format!("{err:?}")),
+			})?;
+	}
+	
+	Ok(remote_data_service)
+}
+
+/// Configured through `FYE_SPOOL_DIR`, by default `fye/spool` in the user's cache directory
+/// 
+/// Each volume gets its own directory within it, so changes are never uploaded to a different server or volume.
+fn spool_dir(remote_data_service: &RemoteDataService) -> PathBuf {
+	let spool_dir = std::env::var_os("FYE_SPOOL_DIR").map(PathBuf::from).unwrap_or_else(|| {
+		let cache_dir = std::env::var_os("XDG_CACHE_HOME").map(PathBuf::from)
+			.or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+			.expect("FYE_SPOOL_DIR, XDG_CACHE_HOME or HOME should be set");
+		
+		cache_dir.join("fye/spool")
+	});
+	
+	spool_dir.join(remote_data_service.volume_name())
+}
+
+/// Written files are uploaded in the background, changes that weren't uploaded before the last unmount are uploaded first
+pub fn mount(mountpoint: impl AsRef<Path>, options: &MountOptions) -> Result<(), io::Error> {
 	let runtime = Runtime::new().unwrap();
 	let _guard = runtime.enter();
 	
-	let remote_data_service = RemoteDataService::new(Url::parse("http://localhost:3000/api/").unwrap());
-	let local_file_cache = LocalFileCache::new(remote_data_service);
-	let filesystem = FyeFilesystem::new(local_file_cache);
+	let remote_data_service = remote_data_service(&runtime)?;
+	let spool_dir = spool_dir(&remote_data_service);
+	let local_file_cache = LocalFileCache::with_spool(remote_data_service, options.cache.clone(), spool_dir)?;
+	let filesystem = FyeFilesystem::new(local_file_cache, options);
 	
 	fuser::mount2(filesystem, &mountpoint, &[])?;
 	
 	Ok(())
 }
+
+/// Returns the id of the node at `path` on the server
+pub fn resolve(path: &str) -> Result<NodeID, io::Error> {
+	let runtime = Runtime::new().unwrap();
+	let _guard = runtime.enter();
+	
+	let local_file_cache = LocalFileCache::new(remote_data_service(&runtime)?, CachePolicy::default());
+	
+	runtime.block_on(local_file_cache.resolve_path(path)).map_err(resolve_error)
+}
+
+/// Copies the local directory tree at `local_path` into the directory at `remote_path` on the server
+pub fn push(local_path: &Path, remote_path: &str) -> Result<TransferReport, io::Error> {
+	let runtime = Runtime::new().unwrap();
+	let _guard = runtime.enter();
+	
+	let remote_data_service = remote_data_service(&runtime)?;
+	
+	runtime.block_on(transfer::push(&remote_data_service, local_path, remote_path))
+}
+
+/// Copies the directory tree at `remote_path` on the server into the local directory at `local_path`
+pub fn pull(remote_path: &str, local_path: &Path) -> Result<TransferReport, io::Error> {
+	let runtime = Runtime::new().unwrap();
+	let _guard = runtime.enter();
+	
+	let remote_data_service = remote_data_service(&runtime)?;
+	
+	runtime.block_on(transfer::pull(&remote_data_service, remote_path, local_path))
+}
+
+/// Keeps the local directory at `local_path` in sync with the directory at `remote_path` on the server, only returns on errors
+pub fn sync(local_path: &Path, remote_path: &str) -> Result<(), io::Error> {
+	let runtime = Runtime::new().unwrap();
+	let _guard = runtime.enter();
+	
+	let remote_data_service = remote_data_service(&runtime)?;
+	
+	runtime.block_on(sync::run(&remote_data_service, local_path, remote_path))
+}
+
+fn resolve_error(err: ResolvePathError) -> io::Error {
+	match err {
+		ResolvePathError::NotFound => io::Error::new(io::ErrorKind::NotFound, "no such file or directory"),
+		ResolvePathError::NotADirectory => io::Error::new(io::ErrorKind::NotADirectory, "not a directory"),
+		ResolvePathError::NetworkFailure(err) => network_error(err),
+		err => io::Error::other(format!("{err:?}")),
+	}
+}
+
+fn network_error(err: NetworkError) -> io::Error {
+	match err {
+		NetworkError::Timeout => io::Error::new(io::ErrorKind::TimedOut, "the server didn't respond in time"),
+		NetworkError::Other => io::Error::other("could not reach the server"),
+	}
+}
@@ -1,110 +1,495 @@
-use std::{collections::HashMap, sync::RwLock};
+use std::{collections::HashMap, io, path::PathBuf, sync::Mutex, time::Duration};
 
-use crate::remote_data_service::{CreateNodeError, DeleteDirectoryError, DeleteFileError, FetchDirectoryError, FetchFileError, WriteFileError};
+use crate::remote_data_service::{CreateNodeError, DelegationError, DeleteDirectoryError, DeleteFileError, FetchDirectoryError, FetchFileError, FetchStatsError, LockFileError, RenameError, ResolvePathError, WriteFileError};
 use bytes::Bytes;
-use fye_shared::{DirectoryInfo, FileInfo, NodeID, NodeInfo};
+use futures_util::{stream, StreamExt as _};
+use fye_shared::{DelegationKind, DirectoryInfo, DirectoryListing, EntryAttributes, FileInfo, FileLock, Hash, LockKind, NodeID, NodeInfo, StorageStats};
 
-use crate::remote_data_service::{FetchNodeError, RemoteDataService};
+use crate::remote_data_service::{FetchNodeError, RemoteDataService, Revalidated};
+
+mod node_cache;
+pub use node_cache::{CachePolicy, CacheStats};
+use node_cache::{Lookup, NodeCache};
+mod single_flight;
+use single_flight::SingleFlight;
+mod write_back;
+pub use write_back::StageFileError;
+use write_back::{StagedContent, WriteBack};
+
+const PARALLEL_PREFETCHES: usize = 4;
+
+const MIN_DELEGATION_RETRY_DELAY: Duration = Duration::from_millis(50);
+const MAX_DELEGATION_RETRY_DELAY: Duration = Duration::from_secs(1);
+/// How long to wait before asking for recalls again after the server couldn't be reached
+const RECALL_RETRY_DELAY: Duration = Duration::from_secs(5);
 
 #[derive(Debug)]
 pub struct LocalFileCache {
 	remote_data_service: RemoteDataService,
-	local_cache: RwLock<HashMap<NodeID, NodeInfo>>,
+	local_cache: Mutex<NodeCache>,
+	/// Fetches that are in flight, so concurrent lookups of the same node only fetch it once
+	node_fetches: SingleFlight<NodeID, Result<NodeInfo, FetchNodeError>>,
+	dir_fetches: SingleFlight<NodeID, Result<DirectoryInfo, FetchDirectoryError>>,
+	prefetch_dirs: usize,
+	/// Whether files are delegated to this client when they are opened
+	use_delegations: bool,
+	/// Files delegated to this client, until the server recalls them
+	delegations: Mutex<HashMap<NodeID, DelegationKind>>,
+	/// Only set if files are written through this cache
+	write_back: Option<WriteBack>,
 }
 
 impl LocalFileCache {
-	pub fn new(remote_data_service: RemoteDataService) -> Self {
+	pub fn new(remote_data_service: RemoteDataService, policy: CachePolicy) -> Self {
 		Self {
 			remote_data_service,
-			local_cache: Default::default(),
+			node_fetches: Default::default(),
+			dir_fetches: Default::default(),
+			prefetch_dirs: policy.prefetch_dirs,
+			use_delegations: policy.delegations,
+			delegations: Default::default(),
+			local_cache: Mutex::new(NodeCache::new(policy)),
+			write_back: None,
 		}
 	}
 	
-	pub async fn get_node_info(&self, id: NodeID) -> Result<NodeInfo, FetchNodeError> {
-		if let Some(info) = self.local_cache.read().expect("poison").get(&id) {
-			return Ok(info.clone());
+	/// Written contents are staged in `spool_dir` and uploaded by [`Self::upload_staged_files`]
+	/// 
+	/// Contents that were staged but not uploaded when the client was last stopped are uploaded again.
+	pub fn with_spool(remote_data_service: RemoteDataService, policy: CachePolicy, spool_dir: PathBuf) -> Result<Self, io::Error> {
+		Ok(Self {
+			write_back: Some(WriteBack::open(spool_dir)?),
+			..Self::new(remote_data_service, policy)
+		})
+	}
+	
+	pub fn stats(&self) -> CacheStats {
+		self.local_cache.lock().expect("poison").stats()
+	}
+	
+	fn staged(&self, id: NodeID) -> Option<StagedContent> {
+		self.write_back.as_ref()?.get(id)
+	}
+	
+	/// Staged contents replace the contents on the server until they are uploaded
+	fn with_staged(&self, id: NodeID, file_info: FileInfo) -> FileInfo {
+		match self.staged(id) {
+			Some(content) => FileInfo {
+				size: content.size,
+				hash: content.hash,
+			},
+			None => file_info,
 		}
+	}
+	
+	pub async fn get_node_info(&self, id: NodeID) -> Result<NodeInfo, FetchNodeError> {
+		let cached = self.local_cache.lock().expect("poison").get(id);
+		
+		let info = match cached {
+			Lookup::Hit(Some(info)) => info,
+			Lookup::Hit(None) => return Err(FetchNodeError::NotFound),
+			cached => self.node_fetches.run(id, || self.fetch_node_info(id, cached)).await?,
+		};
 		
-		let info = self.remote_data_service.fetch_node_info(id).await?;
-		self.local_cache.write().expect("poison").insert(id, info.clone());
-		Ok(info)
+		Ok(match info {
+			NodeInfo::File(file_info) => NodeInfo::File(self.with_staged(id, file_info)),
+			info => info,
+		})
+	}
+	
+	/// Revalidates the cached node if it is stale instead of fetching it completely
+	async fn fetch_node_info(&self, id: NodeID, mut cached: Lookup) -> Result<NodeInfo, FetchNodeError> {
+		loop {
+			let etag = match cached {
+				Lookup::Hit(Some(info)) => return Ok(info),
+				Lookup::Hit(None) => return Err(FetchNodeError::NotFound),
+				Lookup::Stale(etag) => Some(etag),
+				Lookup::Miss => None,
+			};
+			
+			match self.remote_data_service.revalidate_node_info(id, etag.as_ref()).await {
+				Ok(Revalidated::NotModified) => {
+					let etag = etag.expect("only revalidated with an etag");
+					
+					// fetched again without the etag if the cached node is gone
+					cached = match self.local_cache.lock().expect("poison").refresh(id, &etag) {
+						Some(info) => Lookup::Hit(Some(info)),
+						None => Lookup::Miss,
+					};
+				},
+				Ok(Revalidated::Modified(etag, info)) => {
+					self.local_cache.lock().expect("poison").insert_tagged(id, info.clone(), etag);
+					return Ok(info);
+				},
+				Err(FetchNodeError::NotFound) => {
+					self.local_cache.lock().expect("poison").insert_not_found(id);
+					return Err(FetchNodeError::NotFound);
+				},
+				Err(err) => return Err(err),
+			}
+		}
 	}
 	
 	pub async fn get_dir_info(&self, id: NodeID) -> Result<DirectoryInfo, FetchDirectoryError> {
-		// ignore NodeInfo::Directory and fetch fresh data
-		if let Some(NodeInfo::Directory(dir_info)) = self.local_cache.read().expect("poison").get(&id) {
-			return Ok(dir_info.clone());
+		let cached = self.local_cache.lock().expect("poison").get(id);
+		
+		match cached {
+			Lookup::Hit(Some(NodeInfo::Directory(dir_info))) => Ok(dir_info),
+			Lookup::Hit(Some(NodeInfo::File(_))) => Err(FetchDirectoryError::NotADirectory),
+			Lookup::Hit(None) => Err(FetchDirectoryError::NotFound),
+			cached => self.dir_fetches.run(id, || self.fetch_dir_info(id, cached)).await,
 		}
+	}
+	
+	/// Large directories that expired are revalidated, so they are only transferred again if an entry changed
+	async fn fetch_dir_info(&self, id: NodeID, mut cached: Lookup) -> Result<DirectoryInfo, FetchDirectoryError> {
+		loop {
+			let etag = match cached {
+				Lookup::Hit(Some(NodeInfo::Directory(dir_info))) => return Ok(dir_info),
+				Lookup::Hit(Some(NodeInfo::File(_))) => return Err(FetchDirectoryError::NotADirectory),
+				Lookup::Hit(None) => return Err(FetchDirectoryError::NotFound),
+				Lookup::Stale(etag) => Some(etag),
+				Lookup::Miss => None,
+			};
+			
+			match self.remote_data_service.revalidate_dir_info(id, etag.as_ref()).await {
+				Ok(Revalidated::NotModified) => {
+					let etag = etag.expect("only revalidated with an etag");
+					
+					cached = match self.local_cache.lock().expect("poison").refresh(id, &etag) {
+						Some(info) => Lookup::Hit(Some(info)),
+						None => Lookup::Miss,
+					};
+				},
+				Ok(Revalidated::Modified(etag, info)) => {
+					self.local_cache.lock().expect("poison").insert_tagged(id, NodeInfo::Directory(info.clone()), etag);
+					return Ok(info);
+				},
+				Err(FetchDirectoryError::NotFound) => {
+					self.local_cache.lock().expect("poison").insert_not_found(id);
+					return Err(FetchDirectoryError::NotFound);
+				},
+				Err(err) => return Err(err),
+			}
+		}
+	}
+	
+	/// Fetches the directories that aren't cached yet in the background, up to the limit set by the [`CachePolicy`]
+	/// 
+	/// Looking up entries of directories that were just listed, e.g. when walking a tree, then doesn't have to wait for the server.
+	pub async fn prefetch_dirs(&self, ids: Vec<NodeID>) {
+		let ids: Vec<_> = {
+			let local_cache = self.local_cache.lock().expect("poison");
+			ids.into_iter()
+				.filter(|&id| !local_cache.is_fresh(id))
+				.take(self.prefetch_dirs)
+				.collect()
+		};
 		
-		let info = self.remote_data_service.fetch_dir_info(id).await?;
-		self.local_cache.write().expect("poison").insert(id, NodeInfo::Directory(info.clone()));
-		Ok(info)
+		// errors are reported once the directory is actually used
+		stream::iter(ids)
+			.for_each_concurrent(PARALLEL_PREFETCHES, |id| async move {
+				let _ = self.get_dir_info(id).await;
+			})
+			.await;
 	}
 	
-	pub async fn get_file_data(&self, id: NodeID) -> Result<Bytes, FetchFileError> {
+	/// Always fetches fresh data, but updates the cache with all files within the page,
+	/// as well as the directory itself if the listing fit into a single page
+	pub async fn get_dir_listing(&self, id: NodeID, after: Option<&str>) -> Result<DirectoryListing, FetchDirectoryError> {
+		let mut listing = self.remote_data_service.fetch_dir_listing(id, after).await?;
+		
+		let mut local_cache = self.local_cache.lock().expect("poison");
+		
+		if after.is_none() && listing.is_complete() {
+			local_cache.insert(id, NodeInfo::Directory(listing.to_dir_info()));
+		}
+		
+		for entry in listing.children.values_mut() {
+			// child directories aren't included as their children are unknown
+			if let EntryAttributes::File(file_info) = &mut entry.attributes {
+				local_cache.insert(entry.id, NodeInfo::File(file_info.clone()));
+				*file_info = self.with_staged(entry.id, file_info.clone());
+			}
+		}
+		
+		Ok(listing)
+	}
+	
+	pub async fn get_storage_stats(&self) -> Result<StorageStats, FetchStatsError> {
+		self.remote_data_service.fetch_storage_stats().await
+	}
+	
+	/// Always fetches fresh data and updates the cache with every node along the path
+	pub async fn resolve_path(&self, path: &str) -> Result<NodeID, ResolvePathError> {
+		let nodes = self.remote_data_service.resolve_path(path).await?;
+		
+		let mut local_cache = self.local_cache.lock().expect("poison");
+		let mut id = NodeID::ROOT;
+		
+		for node in nodes {
+			id = node.id;
+			local_cache.insert(node.id, node.info);
+		}
+		
+		Ok(id)
+	}
+	
+	pub async fn get_file_data(&self, id: NodeID) -> Result<(Hash, Bytes), FetchFileError> {
+		if let Some(content) = self.staged(id) {
+			return Ok((content.hash, self.remote_data_service.decode_file_data(id, content.data)?));
+		}
+		
 		let (hash, data) = self.remote_data_service.fetch_file_data(id).await?;
-		self.local_cache.write().expect("poison").insert(id, NodeInfo::File(FileInfo {
+		self.local_cache.lock().expect("poison").insert(id, NodeInfo::File(FileInfo {
 			size: data.len() as u64,
-			hash,
+			hash: hash.clone(),
 		}));
 		
-		Ok(data)
+		Ok((hash, data))
 	}
 	
-	pub async fn write_file_data(&self, id: NodeID, offset: u64, data: Vec<u8>) -> Result<u32, WriteFileError> {
-		// TODO: implement offsets
-		assert_eq!(offset, 0);
+	/// Fetches only the range `start..end` of the content with `hash`, without caching it
+	pub async fn get_file_range(&self, id: NodeID, hash: &Hash, start: u64, end: u64) -> Result<Bytes, FetchFileError> {
+		if let Some(content) = self.staged(id).filter(|content| &content.hash == hash) {
+			let data = self.remote_data_service.decode_file_data(id, content.data)?;
+			let start = (start as usize).min(data.len());
+			
+			return Ok(data.slice(start..(end as usize).clamp(start, data.len())));
+		}
 		
-		let len = data.len();
+		self.remote_data_service.fetch_file_range(id, hash, start, end).await
+	}
+	
+	/// Stages the content to be uploaded in the background if the file still has `expected_hash` and returns the new hash
+	/// 
+	/// Requires a cache created with [`Self::with_spool`].
+	pub async fn write_file_data(&self, id: NodeID, expected_hash: &Hash, data: Vec<u8>) -> Result<Hash, StageFileError> {
+		let write_back = self.write_back.as_ref().expect("cache should have a spool directory to write files");
 		
-		let hash = {
-			let cache = self.local_cache.read().expect("poison");
-			cache.get(&id).and_then(|node| match node {
-				NodeInfo::File(file_info) => Some(file_info.hash.clone()), // TODO: is clone necessary?
-				NodeInfo::Directory(_) => None,
-			})
+		let size = data.len() as u64;
+		let (hash, data) = self.remote_data_service.encode_file_data(id, data);
+		
+		write_back.stage(id, expected_hash, StagedContent {
+			hash: hash.clone(),
+			size,
+			data: data.into(),
+		}).await?;
+		
+		Ok(hash)
+	}
+	
+	/// Whether [`Self::set_file_size`] can be used, which isn't the case for encrypted volumes or while content is staged for the file
+	pub fn can_set_file_size(&self, id: NodeID) -> bool {
+		!self.remote_data_service.is_encrypted() && self.staged(id).is_none()
+	}
+	
+	/// Lets the server cut off the content of the file or pad it with zeros if it still has `expected_hash` and returns the new hash
+	/// 
+	/// Unlike [`Self::write_file_data`] nothing is staged, so the content doesn't have to be fetched first.
+	pub async fn set_file_size(&self, id: NodeID, expected_hash: &Hash, size: u64) -> Result<Hash, WriteFileError> {
+		let result = self.remote_data_service.set_size_batched(id, expected_hash, size).await;
+		let mut local_cache = self.local_cache.lock().expect("poison");
+		
+		match &result {
+			Ok(hash) => local_cache.insert(id, NodeInfo::File(FileInfo {
+				size,
+				hash: hash.clone(),
+			})),
+			Err(WriteFileError::Modified | WriteFileError::NotFound | WriteFileError::NotAFile) => {
+				local_cache.remove(id);
+			},
+			Err(_) => {},
+		}
+		
+		result
+	}
+	
+	/// Waits until the content that is currently staged for the file is uploaded
+	pub async fn sync_file(&self, id: NodeID) -> Result<(), WriteFileError> {
+		match &self.write_back {
+			Some(write_back) => write_back.sync(id).await,
+			None => Ok(()),
+		}
+	}
+	
+	/// Uploads staged contents one at a time as they become due, never returns
+	pub async fn upload_staged_files(&self) {
+		let Some(write_back) = &self.write_back else {
+			return;
+		};
+		
+		loop {
+			let upload = write_back.next_upload().await;
+			let content = &upload.content;
+			
+			let result = match self.remote_data_service.write_encoded_file_data(upload.id, &upload.base_hash, &content.hash, content.data.clone()).await {
+				// the content was already uploaded before the client was restarted
+				Err(WriteFileError::Modified) if self.has_hash(upload.id, &content.hash).await => Ok(()),
+				result => result,
+			};
+			
+			if let Err(WriteFileError::Modified | WriteFileError::NotFound | WriteFileError::NotAFile) = result {
+				self.local_cache.lock().expect("poison").remove(upload.id);
+			}
+			
+			write_back.finish_upload(upload, result).await;
+		}
+	}
+	
+	/// Sends the creations and deletions of concurrent callers in batches, never returns
+	/// 
+	/// Has to be running for [`Self::create_dir`], [`Self::create_file`], [`Self::delete_dir`], [`Self::delete_file`] and [`Self::rename`] to return.
+	pub async fn send_batches(&self) {
+		self.remote_data_service.send_batches().await
+	}
+	
+	/// Locks aren't cached, as they have to be coordinated with other clients
+	pub async fn lock_file(&self, id: NodeID, owner: u64, kind: LockKind, start: u64, end: Option<u64>, pid: u32) -> Result<(), LockFileError> {
+		self.remote_data_service.lock_file(id, owner, kind, start, end, pid).await
+	}
+	
+	pub async fn test_lock(&self, id: NodeID, owner: u64, kind: LockKind, start: u64, end: Option<u64>) -> Result<Option<FileLock>, LockFileError> {
+		self.remote_data_service.test_lock(id, owner, kind, start, end).await
+	}
+	
+	pub async fn unlock_file(&self, id: NodeID, owner: u64, start: u64, end: Option<u64>) -> Result<(), LockFileError> {
+		self.remote_data_service.unlock_file(id, owner, start, end).await
+	}
+	
+	pub fn holds_locks(&self, id: NodeID, owner: u64) -> bool {
+		self.remote_data_service.holds_locks(id, owner)
+	}
+	
+	/// Keeps locks from expiring on the server, never returns
+	pub async fn renew_lock_lease(&self) {
+		self.remote_data_service.renew_lock_lease().await
+	}
+	
+	/// Makes sure the file is delegated to this client if delegations are enabled, waiting while conflicting ones are recalled
+	/// 
+	/// A write delegation also covers reading, so it is kept when asking for a read delegation.
+	pub async fn acquire_delegation(&self, id: NodeID, kind: DelegationKind) -> Result<(), DelegationError> {
+		if !self.use_delegations {
+			return Ok(());
+		}
+		
+		let is_delegated = match self.delegations.lock().expect("poison").get(&id) {
+			Some(DelegationKind::Write) => true,
+			Some(DelegationKind::Read) => kind == DelegationKind::Read,
+			None => false,
 		};
 		
-		// TODO: what to do when hash isn't available?
-		let hash = hash.unwrap();
+		if is_delegated {
+			return Ok(());
+		}
 		
-		self.remote_data_service.write_file_data(id, &hash, data).await?;
-		self.local_cache.write().expect("poison").remove(&id);
+		let mut delay = MIN_DELEGATION_RETRY_DELAY;
 		
-		Ok(len as u32)
+		// the server revokes delegations of clients that don't return them, so this doesn't wait forever
+		loop {
+			match self.remote_data_service.request_delegation(id, kind).await {
+				Err(DelegationError::Conflicting) => {
+					tokio::time::sleep(delay).await;
+					delay = (delay * 2).min(MAX_DELEGATION_RETRY_DELAY);
+				},
+				result => break result?,
+			}
+		}
+		
+		self.delegations.lock().expect("poison").insert(id, kind);
+		self.local_cache.lock().expect("poison").set_delegated(id, true);
+		
+		Ok(())
+	}
+	
+	/// Uploads staged content of the file before returning its delegation, so other clients see all changes made under it
+	async fn return_delegation(&self, id: NodeID) -> Result<(), DelegationError> {
+		if let Err(err) = self.sync_file(id).await {
+			eprintln!("could not upload file {id} before returning its delegation: {err:?}");
+		}
+		
+		self.delegations.lock().expect("poison").remove(&id);
+		
+		{
+			let mut local_cache = self.local_cache.lock().expect("poison");
+			local_cache.set_delegated(id, false);
+			local_cache.remove(id);
+		}
+		
+		self.remote_data_service.return_delegation(id).await
+	}
+	
+	/// Returns the delegations the server recalls, never returns
+	pub async fn handle_recalls(&self) {
+		if !self.use_delegations {
+			return;
+		}
+		
+		loop {
+			let recalled = match self.remote_data_service.wait_for_recalls().await {
+				Ok(recalled) => recalled,
+				Err(err) => {
+					eprintln!("could not wait for recalled delegations: {err:?}");
+					tokio::time::sleep(RECALL_RETRY_DELAY).await;
+					continue;
+				},
+			};
+			
+			let mut has_failed = false;
+			
+			for id in recalled {
+				if let Err(err) = self.return_delegation(id).await {
+					eprintln!("could not return delegation of file {id}: {err:?}");
+					has_failed = true;
+				}
+			}
+			
+			// the server keeps asking for delegations that weren't returned
+			if has_failed {
+				tokio::time::sleep(RECALL_RETRY_DELAY).await;
+			}
+		}
+	}
+	
+	async fn has_hash(&self, id: NodeID, hash: &Hash) -> bool {
+		match self.remote_data_service.fetch_node_info(id).await {
+			Ok(NodeInfo::File(file_info)) => file_info.hash == *hash,
+			_ => false,
+		}
 	}
 	
 	pub async fn create_dir(&self, parent_id: NodeID, name: String) -> Result<NodeID, CreateNodeError> {
-		let id = self.remote_data_service.create_dir(parent_id, &name).await?;
+		let id = self.remote_data_service.create_dir_batched(parent_id, &name).await?;
 		
-		let mut local_cache = self.local_cache.write().expect("poison");
+		let mut local_cache = self.local_cache.lock().expect("poison");
 		local_cache.insert(id, NodeInfo::Directory(DirectoryInfo::with_parent(parent_id)));
 		
-		if let Some(NodeInfo::Directory(parent_info)) = local_cache.get_mut(&parent_id) {
+		if let Some(NodeInfo::Directory(parent_info)) = local_cache.get_mut(parent_id) {
 			parent_info.children.insert(name, id);
 		}
 		
 		Ok(id)
 	}
 	
-	pub async fn create_file(&self, parent_id: NodeID, name: String) -> Result<NodeID, CreateNodeError> {
-		let (id, hash) = self.remote_data_service.create_file(parent_id, &name).await?;
+	pub async fn create_file(&self, parent_id: NodeID, name: String) -> Result<(NodeID, Hash), CreateNodeError> {
+		let (id, hash) = self.remote_data_service.create_file_batched(parent_id, &name).await?;
 		
-		let mut local_cache = self.local_cache.write().expect("poison");
+		let mut local_cache = self.local_cache.lock().expect("poison");
 		local_cache.insert(id, NodeInfo::File(FileInfo {
 			size: 0,
-			hash,
+			hash: hash.clone(),
 		}));
 		
-		if let Some(NodeInfo::Directory(parent_info)) = local_cache.get_mut(&parent_id) {
+		if let Some(NodeInfo::Directory(parent_info)) = local_cache.get_mut(parent_id) {
 			parent_info.children.insert(name, id);
 		}
 		
-		Ok(id)
+		Ok((id, hash))
 	}
 	
-	fn delete_node_from_local_cache(local_cache: &mut HashMap<NodeID, NodeInfo>, parent_id: NodeID, name: &str) {
-		let Some(NodeInfo::Directory(parent_info)) = local_cache.get_mut(&parent_id) else {
+	fn delete_node_from_local_cache(local_cache: &mut NodeCache, parent_id: NodeID, name: &str) {
+		let Some(NodeInfo::Directory(parent_info)) = local_cache.get_mut(parent_id) else {
 			return;
 		};
 		
@@ -115,26 +500,39 @@ impl LocalFileCache {
 		let mut to_be_deleted = vec![removed];
 		
 		while let Some(node) = to_be_deleted.pop() {
-			if let Some(NodeInfo::Directory(dir_info)) = local_cache.remove(&node) {
+			if let Some(NodeInfo::Directory(dir_info)) = local_cache.remove(node) {
 				to_be_deleted.extend(dir_info.children.values());
 			}
 		}
 	}
 	
 	pub async fn delete_dir(&self, parent_id: NodeID, name: String) -> Result<(), DeleteDirectoryError> {
-		self.remote_data_service.delete_dir(parent_id, &name).await?;
+		self.remote_data_service.delete_dir_batched(parent_id, &name).await?;
 		
-		let mut local_cache = self.local_cache.write().expect("poison");
+		let mut local_cache = self.local_cache.lock().expect("poison");
 		Self::delete_node_from_local_cache(&mut local_cache, parent_id, &name);
 		
 		Ok(())
 	}
 	
 	pub async fn delete_file(&self, parent_id: NodeID, name: String) -> Result<(), DeleteFileError> {
-		self.remote_data_service.delete_file(parent_id, &name).await?;
+		self.remote_data_service.delete_file_batched(parent_id, &name).await?;
+		
+		let mut local_cache = self.local_cache.lock().expect("poison");
+		Self::delete_node_from_local_cache(&mut local_cache, parent_id, &name);
+		
+		Ok(())
+	}
+	
+	/// Both parents and the moved node are fetched again afterwards, as a moved directory has a new parent
+	pub async fn rename(&self, parent_id: NodeID, name: String, new_parent_id: NodeID, new_name: String, replace: bool) -> Result<(), RenameError> {
+		self.remote_data_service.rename_batched(parent_id, &name, new_parent_id, &new_name, replace).await?;
 		
-		let mut local_cache = self.local_cache.write().expect("poison");
+		let mut local_cache = self.local_cache.lock().expect("poison");
+		Self::delete_node_from_local_cache(&mut local_cache, new_parent_id, &new_name);
 		Self::delete_node_from_local_cache(&mut local_cache, parent_id, &name);
+		local_cache.remove(parent_id);
+		local_cache.remove(new_parent_id);
 		
 		Ok(())
 	}
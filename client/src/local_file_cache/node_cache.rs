@@ -0,0 +1,319 @@
+use std::{collections::HashSet, fmt::{self, Display, Formatter}, num::NonZeroUsize, time::{Duration, Instant}};
+
+use fye_shared::{Hash, NodeID, NodeInfo};
+use lru::LruCache;
+
+#[derive(Clone, Debug)]
+pub struct CachePolicy {
+	/// Maximum number of nodes that are cached, the least recently used ones are evicted first
+	pub capacity: NonZeroUsize,
+	/// How long cached nodes are used before they are fetched again
+	pub max_age: Duration,
+	/// How long nodes that don't exist are remembered
+	pub negative_max_age: Duration,
+	/// Maximum number of child directories that are fetched in the background whenever a page of a directory is listed,
+	/// none by default since every one of them is a separate request
+	pub prefetch_dirs: usize,
+	/// Whether opened files are delegated to this client, so they are used without fetching them again until recalled
+	pub delegations: bool,
+}
+
+impl Default for CachePolicy {
+	fn default() -> Self {
+		Self {
+			capacity: NonZeroUsize::new(100_000).unwrap(),
+			max_age: Duration::from_secs(5),
+			negative_max_age: Duration::from_secs(1),
+			prefetch_dirs: 0,
+			delegations: false,
+		}
+	}
+}
+
+#[derive(Default, Clone, Copy, Debug)]
+pub struct CacheStats {
+	pub hits: u64,
+	/// Hits of nodes that are known not to exist
+	pub negative_hits: u64,
+	pub misses: u64,
+	/// Misses of nodes that were cached for too long
+	pub expired: u64,
+	/// Expired nodes that the server confirmed to be unchanged
+	pub revalidated: u64,
+	pub evictions: u64,
+}
+
+impl CacheStats {
+	pub fn hit_rate(&self) -> f64 {
+		let lookups = self.hits + self.misses;
+		
+		match lookups {
+			0 => 0.0,
+			_ => self.hits as f64 / lookups as f64,
+		}
+	}
+}
+
+impl Display for CacheStats {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"{} hits ({} negative), {} misses ({} expired, {} revalidated), {} evictions, hit rate {:.1}%",
+			self.hits,
+			self.negative_hits,
+			self.misses,
+			self.expired,
+			self.revalidated,
+			self.evictions,
+			self.hit_rate() * 100.0,
+		)
+	}
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+	/// `None` if the node doesn't exist
+	info: Option<NodeInfo>,
+	/// Set if the node was fetched along with its ETag, so it can be revalidated once expired
+	etag: Option<Hash>,
+	fetched_at: Instant,
+}
+
+impl CacheEntry {
+	fn is_fresh(&self, policy: &CachePolicy) -> bool {
+		let max_age = match self.info {
+			Some(_) => policy.max_age,
+			None => policy.negative_max_age,
+		};
+		
+		self.fetched_at.elapsed() <= max_age
+	}
+}
+
+#[derive(Debug)]
+pub enum Lookup {
+	/// Fresh node info, `None` if the node is known not to exist
+	Hit(Option<NodeInfo>),
+	/// The node expired and has to be revalidated with its ETag, see [`NodeCache::refresh`]
+	Stale(Hash),
+	Miss,
+}
+
+/// Node infos that are only used while they are fresh according to the [`CachePolicy`]
+/// 
+/// Expired nodes with an ETag are kept until they are either refreshed or replaced.
+#[derive(Debug)]
+pub struct NodeCache {
+	policy: CachePolicy,
+	entries: LruCache<NodeID, CacheEntry>,
+	/// Nodes delegated to this client, which can't be changed by other clients and so stay fresh
+	delegated: HashSet<NodeID>,
+	stats: CacheStats,
+}
+
+impl NodeCache {
+	pub fn new(policy: CachePolicy) -> Self {
+		Self {
+			entries: LruCache::new(policy.capacity),
+			policy,
+			delegated: HashSet::new(),
+			stats: CacheStats::default(),
+		}
+	}
+	
+	pub fn stats(&self) -> CacheStats {
+		self.stats
+	}
+	
+	pub fn get(&mut self, id: NodeID) -> Lookup {
+		let Some(entry) = self.entries.get(&id) else {
+			self.stats.misses += 1;
+			return Lookup::Miss;
+		};
+		
+		if !self.delegated.contains(&id) && !entry.is_fresh(&self.policy) {
+			self.stats.misses += 1;
+			self.stats.expired += 1;
+			
+			if let Some(etag) = &entry.etag {
+				return Lookup::Stale(etag.clone());
+			}
+			
+			self.entries.pop(&id);
+			return Lookup::Miss;
+		}
+		
+		self.stats.hits += 1;
+		
+		if entry.info.is_none() {
+			self.stats.negative_hits += 1;
+		}
+		
+		Lookup::Hit(entry.info.clone())
+	}
+	
+	/// Unlike [`Self::get`] this doesn't count as a lookup
+	pub fn is_fresh(&self, id: NodeID) -> bool {
+		self.entries.peek(&id).is_some_and(|entry| self.delegated.contains(&id) || entry.is_fresh(&self.policy))
+	}
+	
+	/// Delegated nodes are fresh regardless of their age, until they are no longer delegated
+	pub fn set_delegated(&mut self, id: NodeID, is_delegated: bool) {
+		match is_delegated {
+			true => self.delegated.insert(id),
+			false => self.delegated.remove(&id),
+		};
+	}
+	
+	/// Makes an expired node fresh again after the server confirmed that it still has the ETag it was cached with
+	/// 
+	/// Returns `None` if the node was removed in the meantime.
+	pub fn refresh(&mut self, id: NodeID, etag: &Hash) -> Option<NodeInfo> {
+		let entry = self.entries.get_mut(&id)?;
+		
+		// replaced by a different version while revalidating
+		if entry.etag.as_ref() != Some(etag) {
+			return None;
+		}
+		
+		entry.fetched_at = Instant::now();
+		self.stats.revalidated += 1;
+		
+		entry.info.clone()
+	}
+	
+	/// Allows changing a node in place after changing it on the server, without affecting its freshness
+	pub fn get_mut(&mut self, id: NodeID) -> Option<&mut NodeInfo> {
+		self.entries.peek_mut(&id)?.info.as_mut()
+	}
+	
+	pub fn insert(&mut self, id: NodeID, info: NodeInfo) {
+		self.push(id, Some(info), None);
+	}
+	
+	pub fn insert_tagged(&mut self, id: NodeID, info: NodeInfo, etag: Hash) {
+		self.push(id, Some(info), Some(etag));
+	}
+	
+	pub fn insert_not_found(&mut self, id: NodeID) {
+		self.push(id, None, None);
+	}
+	
+	pub fn remove(&mut self, id: NodeID) -> Option<NodeInfo> {
+		self.entries.pop(&id)?.info
+	}
+	
+	fn push(&mut self, id: NodeID, info: Option<NodeInfo>, etag: Option<Hash>) {
+		let entry = CacheEntry {
+			info,
+			etag,
+			fetched_at: Instant::now(),
+		};
+		
+		// also returns the previous entry of the same node
+		if self.entries.push(id, entry).is_some_and(|(evicted, _)| evicted != id) {
+			self.stats.evictions += 1;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::thread;
+	
+	use fye_shared::FileInfo;
+	
+	use super::*;
+	
+	fn file(size: u64) -> NodeInfo {
+		NodeInfo::File(FileInfo {
+			size,
+			hash: Hash(format!("hash {size}")),
+		})
+	}
+	
+	fn policy(max_age: Duration, negative_max_age: Duration) -> CachePolicy {
+		CachePolicy {
+			max_age,
+			negative_max_age,
+			..CachePolicy::default()
+		}
+	}
+	
+	/// Outlasts a max age of zero
+	fn expire() {
+		thread::sleep(Duration::from_millis(2));
+	}
+	
+	#[test]
+	fn expires() {
+		let mut cache = NodeCache::new(policy(Duration::ZERO, Duration::from_secs(3600)));
+		let etag = Hash("etag".to_owned());
+		
+		cache.insert(NodeID(1), file(1));
+		cache.insert_tagged(NodeID(2), file(2), etag.clone());
+		cache.insert(NodeID(3), file(3));
+		cache.set_delegated(NodeID(3), true);
+		expire();
+		
+		// untagged nodes can't be revalidated, so they are dropped
+		assert!(!cache.is_fresh(NodeID(1)));
+		assert!(matches!(cache.get(NodeID(1)), Lookup::Miss));
+		assert!(cache.remove(NodeID(1)).is_none());
+		
+		assert!(matches!(cache.get(NodeID(2)), Lookup::Stale(stale) if stale == etag));
+		assert_eq!(cache.refresh(NodeID(2), &Hash("other".to_owned())), None);
+		assert_eq!(cache.refresh(NodeID(2), &etag), Some(file(2)));
+		
+		// delegated nodes don't expire until they are no longer delegated
+		assert!(matches!(cache.get(NodeID(3)), Lookup::Hit(Some(info)) if info == file(3)));
+		cache.set_delegated(NodeID(3), false);
+		assert!(!cache.is_fresh(NodeID(3)));
+		
+		let stats = cache.stats();
+		assert_eq!((stats.hits, stats.misses, stats.expired, stats.revalidated), (1, 2, 2, 1));
+	}
+	
+	#[test]
+	fn remembers_missing_nodes() {
+		let mut cache = NodeCache::new(policy(Duration::from_secs(3600), Duration::from_millis(50)));
+		
+		cache.insert_not_found(NodeID(1));
+		assert!(matches!(cache.get(NodeID(1)), Lookup::Hit(None)));
+		
+		// for a shorter time than existing ones
+		cache.insert(NodeID(2), file(2));
+		thread::sleep(Duration::from_millis(60));
+		assert!(matches!(cache.get(NodeID(1)), Lookup::Miss));
+		assert!(matches!(cache.get(NodeID(2)), Lookup::Hit(Some(_))));
+		
+		let stats = cache.stats();
+		assert_eq!((stats.hits, stats.negative_hits, stats.misses, stats.expired), (2, 1, 1, 1));
+	}
+	
+	#[test]
+	fn evicts_least_recently_used() {
+		let mut cache = NodeCache::new(CachePolicy {
+			capacity: NonZeroUsize::new(2).unwrap(),
+			..CachePolicy::default()
+		});
+		
+		cache.insert(NodeID(1), file(1));
+		cache.insert(NodeID(2), file(2));
+		assert!(matches!(cache.get(NodeID(1)), Lookup::Hit(_)));
+		cache.insert(NodeID(3), file(3));
+		
+		assert!(matches!(cache.get(NodeID(2)), Lookup::Miss));
+		assert!(matches!(cache.get(NodeID(1)), Lookup::Hit(_)));
+		assert!(matches!(cache.get(NodeID(3)), Lookup::Hit(_)));
+		
+		// replacing a node doesn't evict it
+		cache.insert(NodeID(3), file(4));
+		assert_eq!(cache.get_mut(NodeID(3)), Some(&mut file(4)));
+		
+		let stats = cache.stats();
+		assert_eq!((stats.hits, stats.misses, stats.evictions), (3, 1, 1));
+		assert_eq!(stats.hit_rate(), 0.75);
+		assert_eq!(stats.to_string(), "3 hits (0 negative), 1 misses (0 expired, 0 revalidated), 1 evictions, hit rate 75.0%");
+	}
+}
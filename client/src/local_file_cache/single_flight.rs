@@ -0,0 +1,170 @@
+use std::{collections::HashMap, future::Future, hash::Hash, sync::Mutex};
+
+use tokio::sync::broadcast;
+
+/// Deduplicates concurrent fetches of the same key
+/// 
+/// Only the first caller actually fetches, everyone arriving while it is in flight waits for its result instead.
+#[derive(Debug)]
+pub struct SingleFlight<K, V> {
+	in_flight: Mutex<HashMap<K, broadcast::Sender<V>>>,
+}
+
+impl<K, V> Default for SingleFlight<K, V> {
+	fn default() -> Self {
+		Self {
+			in_flight: Mutex::new(HashMap::new()),
+		}
+	}
+}
+
+impl<K: Hash + Eq + Copy, V: Clone> SingleFlight<K, V> {
+	pub async fn run<F: Future<Output = V>>(&self, key: K, fetch: impl FnOnce() -> F) -> V {
+		loop {
+			let mut receiver = {
+				let mut in_flight = self.in_flight.lock().expect("poison");
+				
+				match in_flight.get(&key) {
+					Some(sender) => sender.subscribe(),
+					None => {
+						in_flight.insert(key, broadcast::channel(1).0);
+						break;
+					},
+				}
+			};
+			
+			match receiver.recv().await {
+				Ok(value) => return value,
+				// the fetching caller was cancelled, so someone else has to fetch
+				Err(_) => continue,
+			}
+		}
+		
+		let flight = Flight {
+			single_flight: self,
+			key: Some(key),
+		};
+		
+		let value = fetch().await;
+		flight.land(value.clone());
+		
+		value
+	}
+}
+
+/// Removes the fetch from the in flight ones even if the fetching caller is cancelled
+struct Flight<'a, K: Hash + Eq + Copy, V> {
+	single_flight: &'a SingleFlight<K, V>,
+	/// Taken once the result was sent
+	key: Option<K>,
+}
+
+impl<K: Hash + Eq + Copy, V> Flight<'_, K, V> {
+	fn land(mut self, value: V) {
+		let key = self.key.take().expect("only landed once");
+		let sender = self.single_flight.in_flight.lock().expect("poison").remove(&key);
+		
+		// fails if nobody is waiting
+		let _ = sender.expect("fetch should be in flight").send(value);
+	}
+}
+
+impl<K: Hash + Eq + Copy, V> Drop for Flight<'_, K, V> {
+	fn drop(&mut self) {
+		if let Some(key) = self.key {
+			// waiting callers are woken up by the sender being dropped
+			self.single_flight.in_flight.lock().expect("poison").remove(&key);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{sync::atomic::{AtomicUsize, Ordering}, time::Duration};
+	
+	use tokio::time;
+	
+	use super::*;
+	
+	/// Fetches after a second, counting how often it was called
+	async fn fetch<V>(fetches: &AtomicUsize, value: V) -> V {
+		fetches.fetch_add(1, Ordering::Relaxed);
+		time::sleep(Duration::from_secs(1)).await;
+		value
+	}
+	
+	#[tokio::test(start_paused = true)]
+	async fn coalesces() {
+		let single_flight = SingleFlight::default();
+		let fetches = AtomicUsize::new(0);
+		
+		let results = tokio::join!(
+			single_flight.run(1, || fetch(&fetches, 'a')),
+			single_flight.run(1, || fetch(&fetches, 'b')),
+			single_flight.run(1, || fetch(&fetches, 'c')),
+		);
+		
+		assert_eq!(results, ('a', 'a', 'a'));
+		assert_eq!(fetches.load(Ordering::Relaxed), 1);
+		assert!(single_flight.in_flight.lock().unwrap().is_empty());
+		
+		// once landed, the next caller fetches again
+		assert_eq!(single_flight.run(1, || fetch(&fetches, 'd')).await, 'd');
+		assert_eq!(fetches.load(Ordering::Relaxed), 2);
+	}
+	
+	#[tokio::test(start_paused = true)]
+	async fn separates_keys() {
+		let single_flight = SingleFlight::default();
+		let fetches = AtomicUsize::new(0);
+		
+		let results = tokio::join!(
+			single_flight.run(1, || fetch(&fetches, 'a')),
+			single_flight.run(2, || fetch(&fetches, 'b')),
+		);
+		
+		assert_eq!(results, ('a', 'b'));
+		assert_eq!(fetches.load(Ordering::Relaxed), 2);
+	}
+	
+	#[tokio::test(start_paused = true)]
+	async fn reelects_when_cancelled() {
+		let single_flight = SingleFlight::default();
+		let fetches = AtomicUsize::new(0);
+		
+		let leader = time::timeout(Duration::from_millis(500), single_flight.run(1, || fetch(&fetches, 'a')));
+		let waiters = async {
+			// joins the flight of the leader
+			tokio::task::yield_now().await;
+			tokio::join!(
+				single_flight.run(1, || fetch(&fetches, 'b')),
+				single_flight.run(1, || fetch(&fetches, 'c')),
+			)
+		};
+		
+		let (leader, waiters) = tokio::join!(leader, waiters);
+		
+		assert!(leader.is_err());
+		// one of the waiters took over and the other one waited for it
+		assert!(waiters == ('b', 'b') || waiters == ('c', 'c'));
+		assert_eq!(fetches.load(Ordering::Relaxed), 2);
+		assert!(single_flight.in_flight.lock().unwrap().is_empty());
+	}
+	
+	#[tokio::test(start_paused = true)]
+	async fn fans_out_errors() {
+		let single_flight = SingleFlight::<u32, Result<u32, &str>>::default();
+		let fetches = AtomicUsize::new(0);
+		
+		let results = tokio::join!(
+			single_flight.run(1, || fetch(&fetches, Err("failed"))),
+			single_flight.run(1, || fetch(&fetches, Ok(1))),
+		);
+		
+		assert_eq!(results, (Err("failed"), Err("failed")));
+		assert_eq!(fetches.load(Ordering::Relaxed), 1);
+		
+		// errors aren't remembered
+		assert_eq!(single_flight.run(1, || fetch(&fetches, Ok(1))).await, Ok(1));
+	}
+}
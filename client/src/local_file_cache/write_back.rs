@@ -0,0 +1,505 @@
+use std::{collections::HashMap, io::{self, Write as _}, path::PathBuf, pin::pin, sync::Mutex, time::Duration};
+
+use bytes::Bytes;
+use fye_shared::{Hash, NodeID};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::{Mutex as AsyncMutex, Notify}, time::Instant};
+
+use crate::remote_data_service::WriteFileError;
+
+/// Time to wait for further writes before uploading, so files that are written repeatedly are only uploaded once
+const UPLOAD_DELAY: Duration = Duration::from_secs(1);
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+const TEMP_SUFFIX: &str = ".tmp";
+/// Contents that couldn't be uploaded because the file was changed on the server are kept with this suffix,
+/// after the id and the start of the content hash, so later conflicts of the same file don't replace them
+const CONFLICT_SUFFIX: &str = ".conflict";
+
+#[derive(Debug)]
+pub enum StageFileError {
+	/// The staged content doesn't have the expected hash
+	Modified,
+	SpoolFailed,
+}
+
+/// Content of a spooled file, named after the id of the node
+#[derive(Serialize, Deserialize)]
+struct SpoolEntry {
+	/// Hash of the content on the server that is replaced
+	base_hash: Hash,
+	/// Size of the plaintext content
+	size: u64,
+	/// Content as it is uploaded, i.e. encrypted for encrypted volumes
+	data: Vec<u8>,
+}
+
+#[derive(Clone, Debug)]
+pub struct StagedContent {
+	/// Hash the content will have on the server
+	pub hash: Hash,
+	pub size: u64,
+	pub data: Bytes,
+}
+
+#[derive(Debug)]
+struct DirtyFile {
+	base_hash: Hash,
+	content: StagedContent,
+	upload_at: Instant,
+	is_uploading: bool,
+	/// Number of failed uploads, along with the last error
+	failures: (u64, Option<WriteFileError>),
+}
+
+/// Upload that was handed out by [`WriteBack::next_upload`]
+#[derive(Debug)]
+pub struct Upload {
+	pub id: NodeID,
+	pub base_hash: Hash,
+	pub content: StagedContent,
+}
+
+/// Contents that were written locally but not yet uploaded
+/// 
+/// Every staged content is also written to a file in the spool directory,
+/// so it can still be uploaded after the client is restarted.
+#[derive(Debug)]
+pub struct WriteBack {
+	spool_dir: PathBuf,
+	files: Mutex<HashMap<NodeID, DirtyFile>>,
+	/// Hashes of contents that were dropped because the file was changed on the server in the meantime
+	conflicts: Mutex<HashMap<NodeID, Hash>>,
+	/// Held while changing the spool directory, so files are changed in the same order as `files`
+	spool_lock: AsyncMutex<()>,
+	/// Wakes the uploader when an upload is due earlier than before
+	staged: Notify,
+	/// Wakes everyone waiting for an upload after each attempt
+	uploaded: Notify,
+}
+
+impl WriteBack {
+	/// Loads all contents that weren't uploaded before the client was stopped, which are then uploaded right away
+	pub fn open(spool_dir: PathBuf) -> Result<Self, io::Error> {
+		std::fs::create_dir_all(&spool_dir)?;
+		
+		let mut files = HashMap::new();
+		let now = Instant::now();
+		
+		for dir_entry in std::fs::read_dir(&spool_dir)? {
+			let path = dir_entry?.path();
+			
+			// skips temporary files and conflicts
+			let Some(id) = path.file_name().and_then(|name| name.to_str()?.parse().ok()).map(NodeID) else {
+				continue;
+			};
+			
+			let entry: SpoolEntry = match postcard::from_bytes(&std::fs::read(&path)?) {
+				Ok(entry) => entry,
+				Err(err) => {
+					eprintln!("ignoring invalid spool file {}: {err}", path.display());
+					continue;
+				},
+			};
+			
+			files.insert(id, DirtyFile {
+				base_hash: entry.base_hash,
+				content: StagedContent {
+					hash: Hash(blake3::hash(&entry.data).to_hex().to_string()),
+					size: entry.size,
+					data: entry.data.into(),
+				},
+				upload_at: now,
+				is_uploading: false,
+				failures: (0, None),
+			});
+		}
+		
+		Ok(Self {
+			spool_dir,
+			files: Mutex::new(files),
+			conflicts: Default::default(),
+			spool_lock: AsyncMutex::new(()),
+			staged: Notify::new(),
+			uploaded: Notify::new(),
+		})
+	}
+	
+	pub fn get(&self, id: NodeID) -> Option<StagedContent> {
+		self.files.lock().expect("poison").get(&id).map(|file| file.content.clone())
+	}
+	
+	/// Replaces the content of the file, which has to currently have `expected_hash`
+	/// 
+	/// Files that aren't staged yet are assumed to have `expected_hash` on the server, which is checked once uploaded.
+	pub async fn stage(&self, id: NodeID, expected_hash: &Hash, content: StagedContent) -> Result<(), StageFileError> {
+		let _spool_lock = self.spool_lock.lock().await;
+		
+		let base_hash = match self.files.lock().expect("poison").get(&id) {
+			Some(file) if file.content.hash != *expected_hash => return Err(StageFileError::Modified),
+			Some(file) => file.base_hash.clone(),
+			None => expected_hash.clone(),
+		};
+		
+		let entry = SpoolEntry {
+			base_hash: base_hash.clone(),
+			size: content.size,
+			data: content.data.to_vec(),
+		};
+		
+		self.write_spool_file(id, &entry).await.map_err(|err| {
+			eprintln!("could not write spool file for {id}: {err}");
+			StageFileError::SpoolFailed
+		})?;
+		
+		let upload_at = Instant::now() + UPLOAD_DELAY;
+		let mut files = self.files.lock().expect("poison");
+		
+		match files.get_mut(&id) {
+			// an upload that is in progress finishes with the previous content
+			Some(file) => {
+				file.content = content;
+				file.upload_at = upload_at;
+			},
+			None => {
+				files.insert(id, DirtyFile {
+					base_hash,
+					content,
+					upload_at,
+					is_uploading: false,
+					failures: (0, None),
+				});
+			},
+		}
+		
+		self.conflicts.lock().expect("poison").remove(&id);
+		self.staged.notify_one();
+		
+		Ok(())
+	}
+	
+	/// Waits until an upload is due, which has to be passed to [`Self::finish_upload`] afterwards
+	pub async fn next_upload(&self) -> Upload {
+		loop {
+			let staged = self.staged.notified();
+			
+			let upload_at = {
+				let mut files = self.files.lock().expect("poison");
+				let next = files.iter_mut()
+					.filter(|(_, file)| !file.is_uploading)
+					.min_by_key(|(_, file)| file.upload_at);
+				
+				match next {
+					Some((&id, file)) if file.upload_at <= Instant::now() => {
+						file.is_uploading = true;
+						
+						return Upload {
+							id,
+							base_hash: file.base_hash.clone(),
+							content: file.content.clone(),
+						};
+					},
+					Some((_, file)) => Some(file.upload_at),
+					None => None,
+				}
+			};
+			
+			match upload_at {
+				Some(upload_at) => {
+					// woken early if another file is due before
+					let _ = tokio::time::timeout_at(upload_at, staged).await;
+				},
+				None => staged.await,
+			}
+		}
+	}
+	
+	/// Removes the content once it is uploaded, unless it was replaced in the meantime
+	/// 
+	/// Contents are dropped if the file was changed or deleted on the server, after other errors the upload is retried later.
+	pub async fn finish_upload(&self, upload: Upload, result: Result<(), WriteFileError>) {
+		let _spool_lock = self.spool_lock.lock().await;
+		let id = upload.id;
+		
+		let replaced = {
+			let mut files = self.files.lock().expect("poison");
+			let file = files.get_mut(&id).expect("file should be staged while uploading");
+			file.is_uploading = false;
+			
+			match result {
+				Ok(()) if file.content.hash == upload.content.hash => {
+					files.remove(&id);
+					None
+				},
+				Ok(()) => {
+					file.base_hash = upload.content.hash;
+					
+					Some(SpoolEntry {
+						base_hash: file.base_hash.clone(),
+						size: file.content.size,
+						data: file.content.data.to_vec(),
+					})
+				},
+				Err(WriteFileError::Modified) => {
+					let file = files.remove(&id).expect("file should be staged while uploading");
+					self.conflicts.lock().expect("poison").insert(id, file.content.hash);
+					None
+				},
+				Err(WriteFileError::NotFound | WriteFileError::NotAFile) => {
+					files.remove(&id);
+					None
+				},
+				Err(err) => {
+					eprintln!("could not upload {id}, retrying later: {err:?}");
+					file.upload_at = Instant::now() + RETRY_DELAY;
+					file.failures = (file.failures.0 + 1, Some(err));
+					self.uploaded.notify_waiters();
+					return;
+				},
+			}
+		};
+		
+		let path = self.spool_dir.join(id.to_string());
+		let conflict = self.conflicts.lock().expect("poison").get(&id).cloned();
+		
+		let result = match (replaced, conflict) {
+			// the spool file has to be based on the uploaded content now, in case the client is restarted before the next upload
+			(Some(entry), _) => self.write_spool_file(id, &entry).await,
+			(None, Some(hash)) => {
+				let conflict_path = self.spool_dir.join(format!("{id}.{}{CONFLICT_SUFFIX}", &hash.0[..8]));
+				eprintln!("{id} was changed on the server, keeping the local changes in {}", conflict_path.display());
+				tokio::fs::rename(&path, conflict_path).await
+			},
+			(None, None) => tokio::fs::remove_file(&path).await,
+		};
+		
+		if let Err(err) = result {
+			eprintln!("could not update spool file for {id}: {err}");
+		}
+		
+		self.uploaded.notify_waiters();
+	}
+	
+	/// Uploads the file right away and waits until the content that is currently staged is stored on the server
+	/// 
+	/// Returns the error of the next failed upload attempt, the upload is still retried afterwards.
+	pub async fn sync(&self, id: NodeID) -> Result<(), WriteFileError> {
+		let (hash, failure_count) = {
+			let mut files = self.files.lock().expect("poison");
+			let Some(file) = files.get_mut(&id) else {
+				return Ok(());
+			};
+			
+			file.upload_at = Instant::now();
+			(file.content.hash.clone(), file.failures.0)
+		};
+		
+		self.staged.notify_one();
+		
+		loop {
+			let mut uploaded = pin!(self.uploaded.notified());
+			uploaded.as_mut().enable();
+			
+			match self.files.lock().expect("poison").get(&id) {
+				None if self.conflicts.lock().expect("poison").get(&id) == Some(&hash) => return Err(WriteFileError::Modified),
+				None => return Ok(()),
+				Some(file) if file.base_hash == hash => return Ok(()),
+				Some(DirtyFile { failures: (count, Some(err)), .. }) if *count > failure_count => return Err(err.clone()),
+				Some(_) => (),
+			}
+			
+			uploaded.await;
+		}
+	}
+	
+	/// Replaces the spool file atomically, so it is never left truncated
+	async fn write_spool_file(&self, id: NodeID, entry: &SpoolEntry) -> Result<(), io::Error> {
+		let path = self.spool_dir.join(id.to_string());
+		let temp_path = self.spool_dir.join(format!("{id}{TEMP_SUFFIX}"));
+		let data = postcard::to_stdvec(entry).expect("spool entry should be serializable");
+		
+		tokio::task::spawn_blocking(move || {
+			let mut file = std::fs::File::create(&temp_path)?;
+			file.write_all(&data)?;
+			file.sync_all()?;
+			
+			std::fs::rename(&temp_path, &path)
+		}).await.expect("writing spool file should not panic")
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+	
+	use super::*;
+	
+	fn content(data: &[u8]) -> StagedContent {
+		StagedContent {
+			hash: hash_of(data),
+			size: data.len() as u64,
+			data: Bytes::copy_from_slice(data),
+		}
+	}
+	
+	fn hash_of(data: &[u8]) -> Hash {
+		Hash(blake3::hash(data).to_hex().to_string())
+	}
+	
+	fn spool_files(spool_dir: &std::path::Path) -> Vec<String> {
+		let mut names: Vec<String> = std::fs::read_dir(spool_dir).unwrap()
+			.map(|entry| entry.unwrap().file_name().into_string().unwrap())
+			.collect();
+		names.sort();
+		names
+	}
+	
+	#[tokio::test(start_paused = true)]
+	async fn stage() {
+		let spool_dir = tempfile::tempdir().unwrap();
+		let write_back = WriteBack::open(spool_dir.path().into()).unwrap();
+		let id = NodeID(5);
+		
+		write_back.stage(id, &hash_of(b""), content(b"Hello")).await.unwrap();
+		assert_eq!(write_back.get(id).unwrap().data, &b"Hello"[..]);
+		assert_eq!(spool_files(spool_dir.path()), ["5"]);
+		
+		// has to be based on the staged content
+		let err = write_back.stage(id, &hash_of(b""), content(b"World")).await.unwrap_err();
+		assert!(matches!(err, StageFileError::Modified));
+		
+		write_back.stage(id, &hash_of(b"Hello"), content(b"World")).await.unwrap();
+		
+		// the base stays the content on the server
+		let upload = write_back.next_upload().await;
+		assert_eq!(upload.id, id);
+		assert_eq!(upload.base_hash, hash_of(b""));
+		assert_eq!(upload.content.data, &b"World"[..]);
+	}
+	
+	#[tokio::test(start_paused = true)]
+	async fn finish_upload() {
+		let spool_dir = tempfile::tempdir().unwrap();
+		let write_back = WriteBack::open(spool_dir.path().into()).unwrap();
+		
+		write_back.stage(NodeID(5), &hash_of(b""), content(b"Hello")).await.unwrap();
+		let upload = write_back.next_upload().await;
+		
+		// staged again while uploading, so only the base changes
+		write_back.stage(NodeID(5), &hash_of(b"Hello"), content(b"Hello World")).await.unwrap();
+		write_back.finish_upload(upload, Ok(())).await;
+		
+		let upload = write_back.next_upload().await;
+		assert_eq!(upload.base_hash, hash_of(b"Hello"));
+		assert_eq!(upload.content.data, &b"Hello World"[..]);
+		write_back.finish_upload(upload, Ok(())).await;
+		
+		assert!(write_back.get(NodeID(5)).is_none());
+		assert!(spool_files(spool_dir.path()).is_empty());
+		
+		// changed on the server in the meantime
+		write_back.stage(NodeID(6), &hash_of(b""), content(b"Mine")).await.unwrap();
+		let upload = write_back.next_upload().await;
+		write_back.finish_upload(upload, Err(WriteFileError::Modified)).await;
+		
+		assert!(write_back.get(NodeID(6)).is_none());
+		let first_conflict = format!("6.{}{CONFLICT_SUFFIX}", &hash_of(b"Mine").0[..8]);
+		assert_eq!(spool_files(spool_dir.path()), [first_conflict.as_str()]);
+		
+		// a second conflict of the same file is kept as well
+		write_back.stage(NodeID(6), &hash_of(b"Theirs"), content(b"Mine again")).await.unwrap();
+		let upload = write_back.next_upload().await;
+		write_back.finish_upload(upload, Err(WriteFileError::Modified)).await;
+		
+		let second_conflict = format!("6.{}{CONFLICT_SUFFIX}", &hash_of(b"Mine again").0[..8]);
+		let mut conflicts = [first_conflict.clone(), second_conflict.clone()];
+		conflicts.sort();
+		assert_eq!(spool_files(spool_dir.path()), conflicts);
+		
+		for (name, data) in [(first_conflict, &b"Mine"[..]), (second_conflict, b"Mine again")] {
+			let entry: SpoolEntry = postcard::from_bytes(&std::fs::read(spool_dir.path().join(name)).unwrap()).unwrap();
+			assert_eq!(entry.data, data);
+		}
+		
+		// other failures are retried
+		write_back.stage(NodeID(7), &hash_of(b""), content(b"Retried")).await.unwrap();
+		let upload = write_back.next_upload().await;
+		write_back.finish_upload(upload, Err(WriteFileError::ServerError)).await;
+		
+		let upload = write_back.next_upload().await;
+		assert_eq!(upload.id, NodeID(7));
+		assert_eq!(upload.content.data, &b"Retried"[..]);
+	}
+	
+	#[tokio::test(start_paused = true)]
+	async fn sync() {
+		let spool_dir = tempfile::tempdir().unwrap();
+		let write_back = Arc::new(WriteBack::open(spool_dir.path().into()).unwrap());
+		
+		// nothing to wait for
+		write_back.sync(NodeID(5)).await.unwrap();
+		
+		write_back.stage(NodeID(5), &hash_of(b""), content(b"Hello")).await.unwrap();
+		
+		let syncing = tokio::spawn({
+			let write_back = write_back.clone();
+			async move { write_back.sync(NodeID(5)).await }
+		});
+		
+		// the first attempt fails, which is reported
+		let upload = write_back.next_upload().await;
+		write_back.finish_upload(upload, Err(WriteFileError::ServerError)).await;
+		assert!(matches!(syncing.await.unwrap(), Err(WriteFileError::ServerError)));
+		
+		let syncing = tokio::spawn({
+			let write_back = write_back.clone();
+			async move { write_back.sync(NodeID(5)).await }
+		});
+		
+		// uploaded right away instead of after the retry delay
+		let upload = write_back.next_upload().await;
+		assert!(!syncing.is_finished());
+		write_back.finish_upload(upload, Ok(())).await;
+		syncing.await.unwrap().unwrap();
+		
+		write_back.stage(NodeID(6), &hash_of(b""), content(b"Mine")).await.unwrap();
+		
+		let syncing = tokio::spawn({
+			let write_back = write_back.clone();
+			async move { write_back.sync(NodeID(6)).await }
+		});
+		
+		let upload = write_back.next_upload().await;
+		write_back.finish_upload(upload, Err(WriteFileError::Modified)).await;
+		assert!(matches!(syncing.await.unwrap(), Err(WriteFileError::Modified)));
+	}
+	
+	#[tokio::test(start_paused = true)]
+	async fn replay() {
+		let spool_dir = tempfile::tempdir().unwrap();
+		
+		{
+			let write_back = WriteBack::open(spool_dir.path().into()).unwrap();
+			write_back.stage(NodeID(5), &hash_of(b""), content(b"Hello")).await.unwrap();
+			write_back.stage(NodeID(6), &hash_of(b"Old"), content(b"World")).await.unwrap();
+		}
+		
+		// leftovers of interrupted writes and conflicts are ignored
+		std::fs::write(spool_dir.path().join(format!("7{TEMP_SUFFIX}")), b"partial").unwrap();
+		std::fs::write(spool_dir.path().join(format!("8.01234567{CONFLICT_SUFFIX}")), b"").unwrap();
+		std::fs::write(spool_dir.path().join("9"), b"").unwrap();
+		
+		let write_back = WriteBack::open(spool_dir.path().into()).unwrap();
+		assert!(write_back.get(NodeID(7)).is_none());
+		assert!(write_back.get(NodeID(8)).is_none());
+		assert!(write_back.get(NodeID(9)).is_none());
+		
+		// uploaded right away, with the same base as before
+		let mut uploads = [write_back.next_upload().await, write_back.next_upload().await];
+		uploads.sort_by_key(|upload| upload.id.0);
+		
+		assert_eq!((uploads[0].id, &uploads[0].base_hash, &uploads[0].content.hash), (NodeID(5), &hash_of(b""), &hash_of(b"Hello")));
+		assert_eq!((uploads[1].id, &uploads[1].base_hash, &uploads[1].content.hash), (NodeID(6), &hash_of(b"Old"), &hash_of(b"World")));
+		assert_eq!(uploads[1].content.size, 5);
+	}
+}
@@ -1,6 +1,83 @@
 #![forbid(unsafe_code)]
 #![deny(non_snake_case)]
 
-fn main() {
-	fye_client::mount("mnt").unwrap();
+use std::{io, path::Path, process::ExitCode};
+
+use fye_client::{MountOptions, TransferReport};
+
+fn main() -> ExitCode {
+	let args: Vec<String> = std::env::args().skip(1).collect();
+	let args: Vec<&str> = args.iter().map(String::as_str).collect();
+	
+	match args[..] {
+		[] => return mount("mnt", ""),
+		["mount", mountpoint] => return mount(mountpoint, ""),
+		["mount", mountpoint, "-o", options] => return mount(mountpoint, options),
+		["resolve", path] => match fye_client::resolve(path) {
+			Ok(id) => println!("{id}"),
+			Err(err) => {
+				eprintln!("fye: {path}: {err}");
+				return ExitCode::FAILURE;
+			},
+		},
+		["push", local_path, remote_path] => return report_transfer(fye_client::push(Path::new(local_path), remote_path)),
+		["pull", remote_path, local_path] => return report_transfer(fye_client::pull(remote_path, Path::new(local_path))),
+		["sync", local_path] => return sync(Path::new(local_path), "/"),
+		["sync", local_path, remote_path] => return sync(Path::new(local_path), remote_path),
+		_ => {
+			eprintln!("usage: fye [mount <mountpoint> [-o <options>] | resolve <path> | push <local-dir> <remote-path> | pull <remote-path> <local-dir> | sync <local-dir> [<remote-path>]]");
+			return ExitCode::FAILURE;
+		},
+	}
+	
+	ExitCode::SUCCESS
+}
+
+fn mount(mountpoint: &str, options: &str) -> ExitCode {
+	let options: MountOptions = match options.parse() {
+		Ok(options) => options,
+		Err(err) => {
+			eprintln!("fye: {err}");
+			return ExitCode::FAILURE;
+		},
+	};
+	
+	match fye_client::mount(mountpoint, &options) {
+		Ok(()) => ExitCode::SUCCESS,
+		Err(err) => {
+			eprintln!("fye: {mountpoint}: {err}");
+			ExitCode::FAILURE
+		},
+	}
+}
+
+fn sync(local_path: &Path, remote_path: &str) -> ExitCode {
+	// only returns on errors
+	let Err(err) = fye_client::sync(local_path, remote_path) else {
+		return ExitCode::SUCCESS;
+	};
+	
+	eprintln!("fye: {err}");
+	ExitCode::FAILURE
+}
+
+fn report_transfer(result: Result<TransferReport, io::Error>) -> ExitCode {
+	let report = match result {
+		Ok(report) => report,
+		Err(err) => {
+			eprintln!("fye: {err}");
+			return ExitCode::FAILURE;
+		},
+	};
+	
+	for (path, reason) in &report.failed {
+		eprintln!("fye: {}: {reason}", path.display());
+	}
+	
+	println!("{} copied, {} unchanged, {} failed", report.transferred, report.skipped, report.failed.len());
+	
+	match report.failed.is_empty() {
+		true => ExitCode::SUCCESS,
+		false => ExitCode::FAILURE,
+	}
 }
@@ -0,0 +1,70 @@
+use std::{num::NonZeroUsize, str::FromStr, time::Duration};
+
+use crate::local_file_cache::CachePolicy;
+
+#[derive(Clone, Debug)]
+pub struct MountOptions {
+	pub cache: CachePolicy,
+	/// How long the kernel caches attributes of nodes
+	pub attr_ttl: Duration,
+	/// How long the kernel caches the node a name refers to
+	pub entry_ttl: Duration,
+	/// How long the kernel remembers names that don't exist, not at all if zero
+	pub negative_ttl: Duration,
+	/// How many bytes the kernel reads ahead when a file is read sequentially, the kernel's default if unset
+	pub max_readahead: Option<u32>,
+	/// How many bytes beyond a sequential read of a large file are fetched from the server along with it,
+	/// so reading it through doesn't take a round trip to the server for every read of the kernel
+	pub read_ahead: u64,
+}
+
+impl Default for MountOptions {
+	fn default() -> Self {
+		Self {
+			cache: CachePolicy::default(),
+			attr_ttl: Duration::from_secs(1),
+			entry_ttl: Duration::from_secs(1),
+			negative_ttl: Duration::ZERO,
+			max_readahead: None,
+			read_ahead: 1024 * 1024,
+		}
+	}
+}
+
+/// Parses comma separated options like `attr_timeout=5,cache_size=10000`, with all timeouts in seconds
+impl FromStr for MountOptions {
+	type Err = String;
+	
+	fn from_str(options: &str) -> Result<Self, Self::Err> {
+		let mut result = Self::default();
+		
+		for option in options.split(',').filter(|option| !option.is_empty()) {
+			let (key, value) = option.split_once('=').ok_or_else(|| format!("option {option} should have a value"))?;
+			
+			let seconds = || value.parse::<f64>().ok()
+				.and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+				.ok_or_else(|| format!("{key} should be a number of seconds"));
+			
+			match key {
+				"attr_timeout" => result.attr_ttl = seconds()?,
+				"entry_timeout" => result.entry_ttl = seconds()?,
+				"negative_timeout" => result.negative_ttl = seconds()?,
+				"cache_timeout" => result.cache.max_age = seconds()?,
+				"cache_negative_timeout" => result.cache.negative_max_age = seconds()?,
+				"cache_size" => result.cache.capacity = value.parse::<NonZeroUsize>()
+					.map_err(|_| format!("{key} should be a positive number of nodes"))?,
+				"prefetch_dirs" => result.cache.prefetch_dirs = value.parse()
+					.map_err(|_| format!("{key} should be a number of directories"))?,
+				"delegations" => result.cache.delegations = value.parse()
+					.map_err(|_| format!("{key} should be true or false"))?,
+				"max_readahead" => result.max_readahead = Some(value.parse()
+					.map_err(|_| format!("{key} should be a number of bytes"))?),
+				"read_ahead" => result.read_ahead = value.parse()
+					.map_err(|_| format!("{key} should be a number of bytes"))?,
+				_ => return Err(format!("unknown option {key}")),
+			}
+		}
+		
+		Ok(result)
+	}
+}
@@ -1,6 +1,15 @@
-use bytes::Bytes;
-use fye_shared::{DirectoryInfo, Hash, NodeID, NodeInfo};
-use reqwest::{header, Client, StatusCode, Url};
+use std::{borrow::Cow, collections::HashSet, io, path::{Path, PathBuf}, sync::Mutex, time::Duration};
+
+use bytes::{Bytes, BytesMut};
+use chacha20poly1305::aead::{rand_core::RngCore as _, OsRng};
+use futures_util::{future::Either, stream, Stream, StreamExt as _, TryStreamExt as _};
+use fye_shared::{DirectoryInfo, DirectoryListing, EntryAttributes, FileInfo, Hash, NodeID, NodeInfo, ResolvedNode, StorageStats, CLIENT_ID_HEADER, CONTENT_HASH_HEADER, CONTENT_SIZE_HEADER};
+use fye_shared::{BatchOperation, BatchOutcome, BatchRequest, BatchResult};
+use fye_shared::{FileLock, LockKind, LockOwner, UnlockRequest, LOCK_LEASE_SECS};
+use fye_shared::{DelegationKind, DelegationRequest};
+use reqwest::{header, Body, Client, StatusCode, Url};
+use serde::de::DeserializeOwned;
+use tokio::io::AsyncReadExt as _;
 
 mod error;
 pub use error::*;
@@ -8,26 +17,281 @@ pub use error::*;
 mod reqwest_postcard;
 use reqwest_postcard::*;
 
+mod tls;
+pub use tls::{parse_fingerprint, TlsConfigError, TlsOptions};
+
+mod encryption;
+use encryption::{encrypted_size, ChunkRange, VolumeKey, CHUNK_SIZE, HEADER_SIZE};
+
+mod batch;
+use batch::BatchQueue;
+
+/// Contents at least this large are first uploaded by reference, in case the server already has them
+const MIN_REFERENCE_UPLOAD_SIZE: usize = 64 * 1024;
+
+/// A local file that was hashed to be written by [`RemoteDataService::write_local_file`]
+#[derive(Debug)]
+pub struct LocalContent {
+	/// Hash the content will have on the server
+	pub hash: Hash,
+	path: PathBuf,
+	/// Size of the local file, which is smaller than the content stored on the server for encrypted volumes
+	size: u64,
+	plaintext_hash: blake3::Hash,
+}
+
+impl LocalContent {
+	/// Hash of the local file itself, which is the same as [`Self::hash`] unless the volume is encrypted
+	pub fn plaintext_hash(&self) -> blake3::Hash {
+		self.plaintext_hash
+	}
+}
+
+/// Result of a conditional request with the ETag of a cached copy
+#[derive(Debug)]
+pub enum Revalidated<T> {
+	/// The cached copy is still up to date
+	NotModified,
+	/// The current data along with its ETag
+	Modified(Hash, T),
+}
+
 #[derive(Debug)]
 pub struct RemoteDataService {
 	base_url: Url,
 	client: Client,
+	/// Set for encrypted volumes, names and contents are then encrypted before being sent to the server
+	volume_key: Option<VolumeKey>,
+	/// Hash of the wrapped volume key, which tells apart volumes that are served at the same url one after another
+	wrapped_key_hash: Option<blake3::Hash>,
+	batch_queue: BatchQueue,
+	/// Identifies this client as the owner of its locks and delegations
+	client_id: u64,
+	/// Files and owners that might still hold locks, the lease is only renewed while there are any
+	held_locks: Mutex<HashSet<(NodeID, u64)>>,
+	/// Files and owners whose locks the server dropped when the lease expired, the next lock operation of each reports it
+	lost_locks: Mutex<HashSet<(NodeID, u64)>>,
 }
 
 impl RemoteDataService {
-	pub fn new(base_url: Url) -> Self {
-		let client = Client::builder()
+	/// A pinned fingerprint replaces all other certificate validation, including the CA certificate
+	pub fn with_tls(base_url: Url, options: TlsOptions) -> Result<Self, TlsConfigError> {
+		let client_id = OsRng.next_u64();
+		
+		// lets the server tell changes of this client apart, so they don't recall its own delegations
+		let mut builder = Client::builder()
 			.user_agent(concat!("FyeClient/", env!("CARGO_PKG_VERSION")))
-			.build().expect("creating reqwest client should not fail");
+			.default_headers(header::HeaderMap::from_iter([(header::HeaderName::from_static(CLIENT_ID_HEADER), client_id.into())]))
+			.use_rustls_tls();
+		
+		if let Some(fingerprint) = options.pinned_fingerprint {
+			builder = builder.use_preconfigured_tls(tls::pinned_config(fingerprint, options.identity.as_deref())?);
+		} else {
+			if let Some(ca_certificate) = &options.ca_certificate {
+				let certificate = reqwest::Certificate::from_pem(ca_certificate)
+					.map_err(|_| TlsConfigError::InvalidCertificate)?;
+				builder = builder.add_root_certificate(certificate);
+			}
+			
+			if let Some(identity) = &options.identity {
+				let identity = reqwest::Identity::from_pem(identity)
+					.map_err(|_| TlsConfigError::InvalidIdentity)?;
+				builder = builder.identity(identity);
+			}
+		}
 		
-		Self {
+		let client = builder.build().map_err(|_| TlsConfigError::Other)?;
+		
+		Ok(Self {
 			base_url,
 			client,
+			volume_key: None,
+			wrapped_key_hash: None,
+			batch_queue: BatchQueue::default(),
+			client_id,
+			held_locks: Default::default(),
+			lost_locks: Default::default(),
+		})
+	}
+	
+	/// Enables encryption with the key stored on the server, creating a new one if the volume doesn't have one yet
+	pub async fn unlock_volume(&mut self, passphrase: &str) -> Result<(), UnlockVolumeError> {
+		let url = self.base_url.join("volume-key").expect("url should be valid");
+		
+		let wrapped_key: Vec<u8> = match decode_errors(self.client.get(url.clone()), StatusCode::OK).await {
+			Ok(response) => response.postcard().await?,
+			Err(Error::NotFound) => {
+				let (volume_key, wrapped_key) = VolumeKey::generate(passphrase);
+				let request = self.client.post(url.clone())
+					.postcard(&wrapped_key);
+				
+				match decode_errors(request, StatusCode::CREATED).await {
+					Ok(_) => {
+						self.volume_key = Some(volume_key);
+						self.wrapped_key_hash = Some(blake3::hash(&wrapped_key));
+						return Ok(());
+					},
+					// another client created a key in the meantime
+					Err(Error::AlreadyExists) => decode_errors(self.client.get(url), StatusCode::OK).await?
+						.postcard().await?,
+					Err(err) => return Err(err.into()),
+				}
+			},
+			Err(err) => return Err(err.into()),
+		};
+		
+		let volume_key = VolumeKey::unwrap(passphrase, &wrapped_key).map_err(|_| UnlockVolumeError::WrongPassphrase)?;
+		self.volume_key = Some(volume_key);
+		self.wrapped_key_hash = Some(blake3::hash(&wrapped_key));
+		
+		Ok(())
+	}
+	
+	/// Name for local state that only applies to this volume, derived from the server url and the volume key if there is one
+	/// 
+	/// Has to be called after [`Self::unlock_volume`] for encrypted volumes.
+	pub fn volume_name(&self) -> String {
+		let mut hasher = blake3::Hasher::new();
+		hasher.update(self.base_url.as_str().as_bytes());
+		
+		if let Some(wrapped_key_hash) = &self.wrapped_key_hash {
+			hasher.update(wrapped_key_hash.as_bytes());
+		}
+		
+		hasher.finalize().to_hex()[..32].to_owned()
+	}
+	
+	fn encrypt_name<'a>(&self, name: &'a str) -> Cow<'a, str> {
+		match &self.volume_key {
+			Some(volume_key) => Cow::Owned(volume_key.encrypt_name(name)),
+			None => Cow::Borrowed(name),
+		}
+	}
+	
+	fn decrypt_name(&self, name: String) -> Result<String, Error> {
+		match &self.volume_key {
+			Some(volume_key) => volume_key.decrypt_name(&name).map_err(|_| Error::DecryptionFailed),
+			None => Ok(name),
 		}
 	}
 	
+	fn decrypt_file_info(&self, file_info: FileInfo) -> FileInfo {
+		match &self.volume_key {
+			Some(_) => FileInfo {
+				size: encryption::plaintext_size(file_info.size),
+				..file_info
+			},
+			None => file_info,
+		}
+	}
+	
+	fn decrypt_dir_info(&self, dir_info: DirectoryInfo) -> Result<DirectoryInfo, Error> {
+		if self.volume_key.is_none() {
+			return Ok(dir_info);
+		}
+		
+		let children = dir_info.children.into_iter()
+			.map(|(name, id)| Ok((self.decrypt_name(name)?, id)))
+			.collect::<Result<_, Error>>()?;
+		
+		Ok(DirectoryInfo {
+			children,
+			..dir_info
+		})
+	}
+	
+	fn decrypt_node_info(&self, info: NodeInfo) -> Result<NodeInfo, Error> {
+		Ok(match info {
+			NodeInfo::Directory(dir_info) => NodeInfo::Directory(self.decrypt_dir_info(dir_info)?),
+			NodeInfo::File(file_info) => NodeInfo::File(self.decrypt_file_info(file_info)),
+		})
+	}
+	
+	/// Sends the request with `If-None-Match` if there is a cached copy with the ETag `etag`
+	async fn fetch_revalidated<T: DeserializeOwned>(&self, path: &str, etag: Option<&Hash>) -> Result<Revalidated<T>, Error> {
+		let url = self.base_url.join(path).expect("url should be valid");
+		let mut request = self.client.get(url);
+		
+		if let Some(etag) = etag {
+			request = request.header(header::IF_NONE_MATCH, etag.to_header());
+		}
+		
+		let response = match decode_errors(request, StatusCode::OK).await {
+			Err(Error::NotModified) if etag.is_some() => return Ok(Revalidated::NotModified),
+			result => result?,
+		};
+		
+		let etag = Hash::from_header(
+			response.headers().get(header::ETAG).ok_or(Error::ProtocolMismatch)?
+		).ok_or(Error::ProtocolMismatch)?;
+		
+		Ok(Revalidated::Modified(etag, response.postcard().await?))
+	}
+	
 	pub async fn fetch_node_info(&self, id: NodeID) -> Result<NodeInfo, FetchNodeError> {
-		let url = self.base_url.join(&format!("node/{id}")).expect("url should be valid");
+		match self.revalidate_node_info(id, None).await? {
+			Revalidated::Modified(_, info) => Ok(info),
+			Revalidated::NotModified => Err(FetchNodeError::ProtocolMismatch),
+		}
+	}
+	
+	/// The ETag of files is their hash, the ETag of directories changes whenever an entry is added or removed
+	pub async fn revalidate_node_info(&self, id: NodeID, etag: Option<&Hash>) -> Result<Revalidated<NodeInfo>, FetchNodeError> {
+		Ok(match self.fetch_revalidated(&format!("node/{id}"), etag).await? {
+			Revalidated::Modified(etag, data) => Revalidated::Modified(etag, self.decrypt_node_info(data)?),
+			Revalidated::NotModified => Revalidated::NotModified,
+		})
+	}
+	
+	pub async fn fetch_dir_info(&self, id: NodeID) -> Result<DirectoryInfo, FetchDirectoryError> {
+		match self.revalidate_dir_info(id, None).await? {
+			Revalidated::Modified(_, info) => Ok(info),
+			Revalidated::NotModified => Err(FetchDirectoryError::ProtocolMismatch),
+		}
+	}
+	
+	pub async fn revalidate_dir_info(&self, id: NodeID, etag: Option<&Hash>) -> Result<Revalidated<DirectoryInfo>, FetchDirectoryError> {
+		Ok(match self.fetch_revalidated(&format!("dir/{id}"), etag).await? {
+			Revalidated::Modified(etag, data) => Revalidated::Modified(etag, self.decrypt_dir_info(data)?),
+			Revalidated::NotModified => Revalidated::NotModified,
+		})
+	}
+	
+	/// Fetches a single page of the listing, starting after the given continuation token
+	pub async fn fetch_dir_listing(&self, id: NodeID, after: Option<&str>) -> Result<DirectoryListing, FetchDirectoryError> {
+		let url = self.base_url.join(&format!("dir/{id}/listing")).expect("url should be valid");
+		let mut request = self.client.get(url);
+		
+		if let Some(after) = after {
+			request = request.query(&[("after", after)]);
+		}
+		
+		let data: DirectoryListing = decode_errors(request, StatusCode::OK).await?
+			.postcard().await?;
+		
+		if self.volume_key.is_none() {
+			return Ok(data);
+		}
+		
+		// the continuation token is kept encrypted as the server compares it to the encrypted names
+		let children = data.children.into_iter()
+			.map(|(name, mut entry)| {
+				if let EntryAttributes::File(file_info) = entry.attributes {
+					entry.attributes = EntryAttributes::File(self.decrypt_file_info(file_info));
+				}
+				
+				Ok((self.decrypt_name(name)?, entry))
+			})
+			.collect::<Result<_, Error>>()?;
+		
+		Ok(DirectoryListing {
+			children,
+			..data
+		})
+	}
+	
+	pub async fn fetch_storage_stats(&self) -> Result<StorageStats, FetchStatsError> {
+		let url = self.base_url.join("stats").expect("url should be valid");
 		let request = self.client.get(url);
 		
 		let data = decode_errors(request, StatusCode::OK).await?
@@ -36,34 +300,278 @@ impl RemoteDataService {
 		Ok(data)
 	}
 	
-	pub async fn fetch_dir_info(&self, id: NodeID) -> Result<DirectoryInfo, FetchDirectoryError> {
-		let url = self.base_url.join(&format!("dir/{id}")).expect("url should be valid");
-		let request = self.client.get(url);
+	/// Returns every node along `path`, starting with the root directory
+	pub async fn resolve_path(&self, path: &str) -> Result<Vec<ResolvedNode>, ResolvePathError> {
+		let url = self.base_url.join("resolve").expect("url should be valid");
+		let path = match self.volume_key {
+			Some(_) => path.split('/')
+				.map(|component| match component {
+					"" | "." | ".." => Cow::Borrowed(component),
+					name => self.encrypt_name(name),
+				})
+				.collect::<Vec<_>>().join("/"),
+			None => path.to_owned(),
+		};
 		
-		let data = decode_errors(request, StatusCode::OK).await?
+		let request = self.client.get(url)
+			.query(&[("path", path)]);
+		
+		let data: Vec<ResolvedNode> = decode_errors(request, StatusCode::OK).await?
 			.postcard().await?;
 		
+		let data = data.into_iter()
+			.map(|node| Ok(ResolvedNode {
+				info: self.decrypt_node_info(node.info)?,
+				..node
+			}))
+			.collect::<Result<_, Error>>()?;
+		
 		Ok(data)
 	}
 	
+	/// Content that doesn't match its hash is fetched a second time before failing with [`FetchFileError::HashMismatch`]
 	pub async fn fetch_file_data(&self, id: NodeID) -> Result<(Hash, Bytes), FetchFileError> {
+		let (hash, data) = match self.download_file_data(id).await {
+			Err(Error::HashMismatch) => self.download_file_data(id).await?, // corruption along the way may be transient
+			result => result?,
+		};
+		
+		Ok((hash, self.decode_file_data(id, data)?))
+	}
+	
+	/// Reverses [`Self::encode_file_data`]
+	pub fn decode_file_data(&self, id: NodeID, data: Bytes) -> Result<Bytes, FetchFileError> {
+		match &self.volume_key {
+			Some(volume_key) => Ok(volume_key.decrypt_content(id, &data).map_err(|_| Error::DecryptionFailed)?.into()),
+			None => Ok(data),
+		}
+	}
+	
+	/// Downloads the content as stored on the server, hashing it while it is received
+	async fn download_file_data(&self, id: NodeID) -> Result<(Hash, Bytes), Error> {
+		let url = self.base_url.join(&format!("file/{id}/data")).expect("url should be valid");
+		let request = self.client.get(url);
+		
+		let mut response = decode_errors(request, StatusCode::OK).await?;
+		let hash = Hash::from_header(
+			response.headers().get(header::ETAG).ok_or(Error::ProtocolMismatch)?
+		).ok_or(Error::ProtocolMismatch)?;
+		
+		let mut hasher = blake3::Hasher::new();
+		let mut data = BytesMut::new();
+		
+		while let Some(chunk) = response.chunk().await.map_err(Error::network_error)? {
+			hasher.update(&chunk);
+			data.extend_from_slice(&chunk);
+		}
+		
+		if hasher.finalize().to_hex().as_str() != hash.0 {
+			return Err(Error::HashMismatch);
+		}
+		
+		Ok((hash, data.freeze()))
+	}
+	
+	/// Streams the content instead of keeping it in memory, decrypting it for encrypted volumes
+	/// 
+	/// The stream fails at its end with [`FetchFileError::HashMismatch`] if the content doesn't match its hash,
+	/// so nothing it returned should be used before it ended. Unlike [`Self::fetch_file_data`] this isn't retried.
+	pub async fn stream_file_data(&self, id: NodeID) -> Result<(Hash, impl Stream<Item = Result<Bytes, FetchFileError>>), FetchFileError> {
 		let url = self.base_url.join(&format!("file/{id}/data")).expect("url should be valid");
 		let request = self.client.get(url);
 		
 		let response = decode_errors(request, StatusCode::OK).await?;
 		let hash = Hash::from_header(
-			response.headers().get(header::ETAG).ok_or(FetchFileError::ProtocolMismatch)?
-		).ok_or(FetchFileError::ProtocolMismatch)?;
-		let data = response.bytes().await.map_err(Error::network_error)?;
+			response.headers().get(header::ETAG).ok_or(Error::ProtocolMismatch)?
+		).ok_or(Error::ProtocolMismatch)?;
+		
+		let decryptor = self.volume_key.as_ref().map(|volume_key| volume_key.content_decryptor(id));
+		let expected_hash = hash.clone();
+		
+		// the state is gone once the end was reached
+		let data = stream::try_unfold(Some((response, blake3::Hasher::new(), decryptor)), move |state| {
+			let expected_hash = expected_hash.clone();
+			
+			async move {
+				let Some((mut response, mut hasher, mut decryptor)) = state else {
+					return Ok(None);
+				};
+				
+				let Some(chunk) = response.chunk().await.map_err(Error::network_error)? else {
+					if hasher.finalize().to_hex().as_str() != expected_hash.0 {
+						return Err(Error::HashMismatch.into());
+					}
+					
+					let rest = match decryptor {
+						Some(decryptor) => decryptor.finish().map_err(|_| Error::DecryptionFailed)?.into(),
+						None => Bytes::new(),
+					};
+					return Ok(Some((rest, None)));
+				};
+				
+				hasher.update(&chunk);
+				let chunk = match &mut decryptor {
+					Some(decryptor) => decryptor.update(&chunk).map_err(|_| Error::DecryptionFailed)?.into(),
+					None => chunk,
+				};
+				
+				Ok(Some((chunk, Some((response, hasher, decryptor)))))
+			}
+		});
 		
 		Ok((hash, data))
 	}
 	
-	pub async fn write_file_data(&self, id: NodeID, expected_hash: &Hash, data: Vec<u8>) -> Result<(), WriteFileError> {
+	/// Fetches the range `start..end` of the content with `hash`, which is clamped to the content's size
+	/// 
+	/// For encrypted volumes only the header and the chunks containing the range are downloaded and decrypted.
+	/// Fails with [`FetchFileError::Modified`] if the file doesn't have that content anymore.
+	pub async fn fetch_file_range(&self, id: NodeID, hash: &Hash, start: u64, end: u64) -> Result<Bytes, FetchFileError> {
+		let Some(volume_key) = &self.volume_key else {
+			return Ok(self.download_file_range(id, hash, start, end).await?.0);
+		};
+		
+		let (header, encrypted_size) = self.download_file_range(id, hash, 0, HEADER_SIZE as u64).await?;
+		
+		if encrypted_size == 0 {
+			return Ok(Bytes::new()); // not written yet
+		}
+		
+		let chunks = ChunkRange::covering(start, end, encrypted_size);
+		let (chunks_start, chunks_end) = chunks.encrypted_range(encrypted_size);
+		let (data, _) = self.download_file_range(id, hash, chunks_start, chunks_end).await?;
+		
+		let plaintext = Bytes::from(volume_key.decrypt_chunks(id, &header, chunks, &data).map_err(|_| Error::DecryptionFailed)?);
+		let offset = |position: u64| (position.saturating_sub(chunks.plaintext_start()) as usize).min(plaintext.len());
+		
+		Ok(plaintext.slice(offset(start)..offset(end).max(offset(start))))
+	}
+	
+	/// Downloads the range `start..end` of the content as stored on the server if it still has `hash`,
+	/// and returns it along with the size of the whole content
+	async fn download_file_range(&self, id: NodeID, hash: &Hash, start: u64, end: u64) -> Result<(Bytes, u64), Error> {
+		if start >= end {
+			return Ok((Bytes::new(), 0));
+		}
+		
 		let url = self.base_url.join(&format!("file/{id}/data")).expect("url should be valid");
+		let request = self.client.get(url)
+			.header(header::IF_MATCH, hash.to_header())
+			.header(header::RANGE, format!("bytes={start}-{}", end - 1));
+		
+		let response = match decode_errors(request, StatusCode::PARTIAL_CONTENT).await {
+			Err(Error::RangeNotSatisfiable) => return Ok((Bytes::new(), 0)), // the range starts after the end of the content
+			result => result?,
+		};
+		
+		let size = response.headers().get(header::CONTENT_RANGE)
+			.and_then(|value| value.to_str().ok())
+			.and_then(|value| value.rsplit_once('/'))
+			.and_then(|(_, size)| size.parse().ok())
+			.ok_or(Error::ProtocolMismatch)?;
+		
+		Ok((response.bytes().await.map_err(Error::network_error)?, size))
+	}
+	
+	/// Hashes on the server are then of the encrypted content
+	pub fn is_encrypted(&self) -> bool {
+		self.volume_key.is_some()
+	}
+	
+	/// Encrypts the content for encrypted volumes and returns it along with the hash it will have on the server
+	pub fn encode_file_data(&self, id: NodeID, data: Vec<u8>) -> (Hash, Vec<u8>) {
+		let data = match &self.volume_key {
+			Some(volume_key) => volume_key.encrypt_content(id, &data),
+			None => data,
+		};
+		
+		(Hash(blake3::hash(&data).to_hex().to_string()), data)
+	}
+	
+	/// Writes content returned by [`Self::encode_file_data`]
+	/// 
+	/// Larger contents are first uploaded by reference, which succeeds without sending them if the server already has them.
+	pub async fn write_encoded_file_data(&self, id: NodeID, expected_hash: &Hash, hash: &Hash, data: Bytes) -> Result<(), WriteFileError> {
+		let size = data.len() as u64;
+		
+		// encrypted contents practically never repeat, so uploading them by reference would only add a round trip
+		if self.volume_key.is_none() && data.len() >= MIN_REFERENCE_UPLOAD_SIZE {
+			match self.upload_file_data(id, expected_hash, hash, size, Bytes::new()).await {
+				Err(Error::ContentMismatch) => (), // not stored on the server yet
+				result => return Ok(result?),
+			}
+		}
+		
+		self.upload_file_data(id, expected_hash, hash, size, data).await?;
+		
+		Ok(())
+	}
+	
+	/// Hashes a local file the way it would be stored as the content of the file with `id`, without loading it into memory at once
+	/// 
+	/// For encrypted volumes the file is read twice, as the nonce is derived from the plaintext.
+	pub async fn hash_local_file(&self, id: NodeID, path: &Path) -> Result<LocalContent, io::Error> {
+		let size = tokio::fs::metadata(path).await?.len();
+		let plaintext_hash = hash_chunks(read_chunks(path.to_owned(), size)).await?;
+		
+		let hash = match self.volume_key {
+			Some(_) => hash_chunks(self.read_local_file(id, path.to_owned(), size, &plaintext_hash)).await?,
+			None => plaintext_hash,
+		};
+		
+		Ok(LocalContent {
+			hash: Hash(hash.to_hex().to_string()),
+			path: path.to_owned(),
+			size,
+			plaintext_hash,
+		})
+	}
+	
+	/// Writes a local file as the content while reading it, like [`Self::write_encoded_file_data`]
+	/// 
+	/// Fails with [`WriteFileError::ContentMismatch`] if the local file was changed since it was hashed.
+	pub async fn write_local_file(&self, id: NodeID, expected_hash: &Hash, content: &LocalContent) -> Result<(), WriteFileError> {
+		let size = match self.volume_key {
+			Some(_) => encrypted_size(content.size),
+			None => content.size,
+		};
+		
+		if self.volume_key.is_none() && size >= MIN_REFERENCE_UPLOAD_SIZE as u64 {
+			match self.upload_file_data(id, expected_hash, &content.hash, size, Bytes::new()).await {
+				Err(Error::ContentMismatch) => (), // not stored on the server yet
+				result => return Ok(result?),
+			}
+		}
+		
+		let body = Body::wrap_stream(self.read_local_file(id, content.path.clone(), content.size, &content.plaintext_hash));
+		self.upload_file_data(id, expected_hash, &content.hash, size, body).await?;
+		
+		Ok(())
+	}
+	
+	/// Reads a local file as it is stored on the server, encrypting it for encrypted volumes
+	fn read_local_file(&self, id: NodeID, path: PathBuf, size: u64, plaintext_hash: &blake3::Hash) -> impl Stream<Item = Result<Bytes, io::Error>> + Send + 'static {
+		let chunks = read_chunks(path, size);
+		
+		let Some(volume_key) = &self.volume_key else {
+			return Either::Left(chunks);
+		};
+		
+		let mut encryptor = volume_key.content_encryptor(id, plaintext_hash, size);
+		let header = Bytes::copy_from_slice(&encryptor.header());
+		
+		Either::Right(stream::once(async { Ok(header) }).chain(chunks.map_ok(move |chunk| encryptor.encrypt_chunk(&chunk).into())))
+	}
+	
+	/// The server verifies the body against `hash` and `size`, unless it already has that content in which case the body is ignored
+	async fn upload_file_data(&self, id: NodeID, expected_hash: &Hash, hash: &Hash, size: u64, body: impl Into<Body>) -> Result<(), Error> {
+		let url = self.base_url.join(&format!("file/{id}/data")).expect("url should be valid");
+		
 		let request = self.client.put(url)
 			.header(header::IF_MATCH, expected_hash.to_header())
-			.body(data);
+			.header(CONTENT_HASH_HEADER, hash.to_header())
+			.header(CONTENT_SIZE_HEADER, size)
+			.body(body);
 		
 		decode_errors(request, StatusCode::NO_CONTENT).await?;
 		
@@ -73,7 +581,7 @@ impl RemoteDataService {
 	pub async fn create_dir(&self, parent_id: NodeID, name: &str) -> Result<NodeID, CreateNodeError> {
 		let url = self.base_url.join(&format!("dir/{parent_id}/new-dir")).expect("url should be valid");
 		let request = self.client.post(url)
-			.postcard(name); // &str and String are serialized the same
+			.postcard(&*self.encrypt_name(name)); // &str and String are serialized the same
 		
 		let response = decode_errors(request, StatusCode::CREATED).await?;
 		let location = response.headers().get(header::LOCATION).ok_or(CreateNodeError::ProtocolMismatch)?
@@ -88,7 +596,7 @@ impl RemoteDataService {
 	pub async fn create_file(&self, parent_id: NodeID, name: &str) -> Result<(NodeID, Hash), CreateNodeError> {
 		let url = self.base_url.join(&format!("dir/{parent_id}/new-file")).expect("url should be valid");
 		let request = self.client.post(url)
-			.postcard(name); // &str and String are serialized the same
+			.postcard(&*self.encrypt_name(name)); // &str and String are serialized the same
 		
 		let response = decode_errors(request, StatusCode::CREATED).await?;
 		let headers = response.headers();
@@ -108,7 +616,7 @@ impl RemoteDataService {
 	pub async fn delete_dir(&self, parent_id: NodeID, name: &str) -> Result<(), DeleteDirectoryError> {
 		let url = self.base_url.join(&format!("dir/{parent_id}/delete-dir")).expect("url should be valid");
 		let request = self.client.post(url)
-			.postcard(name); // &str and String are serialized the same
+			.postcard(&*self.encrypt_name(name)); // &str and String are serialized the same
 		
 		decode_errors(request, StatusCode::NO_CONTENT).await?;
 		
@@ -118,10 +626,372 @@ impl RemoteDataService {
 	pub async fn delete_file(&self, parent_id: NodeID, name: &str) -> Result<(), DeleteFileError> {
 		let url = self.base_url.join(&format!("dir/{parent_id}/delete-file")).expect("url should be valid");
 		let request = self.client.post(url)
-			.postcard(name); // &str and String are serialized the same
+			.postcard(&*self.encrypt_name(name)); // &str and String are serialized the same
+		
+		decode_errors(request, StatusCode::NO_CONTENT).await?;
+		
+		Ok(())
+	}
+	
+	fn encrypt_operation(&self, operation: BatchOperation) -> BatchOperation {
+		if self.volume_key.is_none() {
+			return operation;
+		}
+		
+		let encrypt = |name: String| self.encrypt_name(&name).into_owned();
+		
+		match operation {
+			BatchOperation::CreateDir { parent, name } => BatchOperation::CreateDir { parent, name: encrypt(name) },
+			BatchOperation::CreateFile { parent, name } => BatchOperation::CreateFile { parent, name: encrypt(name) },
+			BatchOperation::DeleteDir { parent, name } => BatchOperation::DeleteDir { parent, name: encrypt(name) },
+			BatchOperation::DeleteFile { parent, name } => BatchOperation::DeleteFile { parent, name: encrypt(name) },
+			BatchOperation::Rename { parent, name, new_parent, new_name, replace } => BatchOperation::Rename {
+				parent,
+				name: encrypt(name),
+				new_parent,
+				new_name: encrypt(new_name),
+				replace,
+			},
+			// only sent for unencrypted volumes
+			operation @ BatchOperation::SetSize { .. } => operation,
+		}
+	}
+	
+	/// Applies all operations in a single request and returns a result for each of them, see [`BatchRequest`]
+	pub async fn run_batch(&self, operations: Vec<BatchOperation>, atomic: bool) -> Result<Vec<BatchResult>, RunBatchError> {
+		let count = operations.len();
+		let operations = operations.into_iter()
+			.map(|operation| self.encrypt_operation(operation))
+			.collect();
+		
+		let url = self.base_url.join("batch").expect("url should be valid");
+		let request = self.client.post(url)
+			.postcard(&BatchRequest { atomic, operations });
+		
+		let results: Vec<BatchResult> = decode_errors(request, StatusCode::OK).await?
+			.postcard().await?;
+		
+		if results.len() != count {
+			return Err(RunBatchError::ProtocolMismatch);
+		}
+		
+		Ok(results)
+	}
+	
+	/// Sends the operations queued by the `*_batched` methods, never returns
+	/// 
+	/// Operations that are queued while a batch is in flight are sent together as the next batch,
+	/// so a burst of operations only takes a few round trips while a single operation isn't delayed.
+	pub async fn send_batches(&self) {
+		loop {
+			let (operations, replies): (Vec<_>, Vec<_>) = self.batch_queue.next_batch().await
+				.into_iter()
+				.unzip();
+			
+			match self.run_batch(operations, false).await {
+				Ok(results) => {
+					for (reply, result) in replies.into_iter().zip(results) {
+						// the caller may have given up waiting
+						let _ = reply.send(Ok(result));
+					}
+				},
+				Err(err) => {
+					for reply in replies {
+						let _ = reply.send(Err(err.clone()));
+					}
+				},
+			}
+		}
+	}
+	
+	async fn run_batched(&self, operation: BatchOperation) -> Result<BatchOutcome, Error> {
+		Ok(self.batch_queue.run(operation).await??)
+	}
+	
+	/// Like [`Self::create_dir`], but sent along with concurrent operations, only returns while [`Self::send_batches`] is running
+	pub async fn create_dir_batched(&self, parent_id: NodeID, name: &str) -> Result<NodeID, CreateNodeError> {
+		match self.run_batched(BatchOperation::CreateDir { parent: parent_id, name: name.to_owned() }).await? {
+			BatchOutcome::CreatedDir(id) => Ok(id),
+			_ => Err(CreateNodeError::ProtocolMismatch),
+		}
+	}
+	
+	/// Like [`Self::create_file`], but sent along with concurrent operations, only returns while [`Self::send_batches`] is running
+	pub async fn create_file_batched(&self, parent_id: NodeID, name: &str) -> Result<(NodeID, Hash), CreateNodeError> {
+		match self.run_batched(BatchOperation::CreateFile { parent: parent_id, name: name.to_owned() }).await? {
+			BatchOutcome::CreatedFile(id, hash) => Ok((id, hash)),
+			_ => Err(CreateNodeError::ProtocolMismatch),
+		}
+	}
+	
+	/// Like [`Self::delete_dir`], but sent along with concurrent operations, only returns while [`Self::send_batches`] is running
+	pub async fn delete_dir_batched(&self, parent_id: NodeID, name: &str) -> Result<(), DeleteDirectoryError> {
+		match self.run_batched(BatchOperation::DeleteDir { parent: parent_id, name: name.to_owned() }).await? {
+			BatchOutcome::Deleted => Ok(()),
+			_ => Err(DeleteDirectoryError::ProtocolMismatch),
+		}
+	}
+	
+	/// Like [`Self::delete_file`], but sent along with concurrent operations, only returns while [`Self::send_batches`] is running
+	pub async fn delete_file_batched(&self, parent_id: NodeID, name: &str) -> Result<(), DeleteFileError> {
+		match self.run_batched(BatchOperation::DeleteFile { parent: parent_id, name: name.to_owned() }).await? {
+			BatchOutcome::Deleted => Ok(()),
+			_ => Err(DeleteFileError::ProtocolMismatch),
+		}
+	}
+	
+	/// Moves the entry to `new_parent_id` as `new_name`, replacing an existing entry with that name if `replace` is set
+	/// 
+	/// Only available batched, as there is no separate endpoint for it. Only returns while [`Self::send_batches`] is running.
+	pub async fn rename_batched(&self, parent_id: NodeID, name: &str, new_parent_id: NodeID, new_name: &str, replace: bool) -> Result<(), RenameError> {
+		let operation = BatchOperation::Rename {
+			parent: parent_id,
+			name: name.to_owned(),
+			new_parent: new_parent_id,
+			new_name: new_name.to_owned(),
+			replace,
+		};
+		
+		match self.run_batched(operation).await? {
+			BatchOutcome::Renamed => Ok(()),
+			_ => Err(RenameError::ProtocolMismatch),
+		}
+	}
+	
+	/// Cuts off the content of the file or pads it with zeros and returns the new hash, see [`BatchOperation::SetSize`]
+	/// 
+	/// Only available batched and for unencrypted volumes. Only returns while [`Self::send_batches`] is running.
+	pub async fn set_size_batched(&self, id: NodeID, expected_hash: &Hash, size: u64) -> Result<Hash, WriteFileError> {
+		assert!(self.volume_key.is_none(), "encrypted content can't be resized by the server");
+		
+		match self.run_batched(BatchOperation::SetSize { file: id, expected_hash: expected_hash.clone(), size }).await? {
+			BatchOutcome::Resized(hash) => Ok(hash),
+			_ => Err(WriteFileError::ProtocolMismatch),
+		}
+	}
+	
+	fn lock_owner(&self, owner: u64) -> LockOwner {
+		LockOwner {
+			client: self.client_id,
+			owner,
+		}
+	}
+	
+	/// Fails with [`LockFileError::LeaseExpired`] once if the owner's locks on the file were lost, so it doesn't go unnoticed
+	fn check_lost_locks(&self, id: NodeID, owner: u64) -> Result<(), LockFileError> {
+		match self.lost_locks.lock().expect("poison").remove(&(id, owner)) {
+			true => Err(LockFileError::LeaseExpired),
+			false => Ok(()),
+		}
+	}
+	
+	/// Fails with [`LockFileError::Locked`] instead of waiting if another owner holds a conflicting lock
+	/// 
+	/// Replaces the owner's own locks in the range, so it can be used to convert between shared and exclusive locks.
+	pub async fn lock_file(&self, id: NodeID, owner: u64, kind: LockKind, start: u64, end: Option<u64>, pid: u32) -> Result<(), LockFileError> {
+		self.check_lost_locks(id, owner)?;
+		
+		let url = self.base_url.join(&format!("file/{id}/lock")).expect("url should be valid");
+		let request = self.client.post(url)
+			.postcard(&FileLock {
+				owner: self.lock_owner(owner),
+				kind,
+				start,
+				end,
+				pid,
+			});
 		
 		decode_errors(request, StatusCode::NO_CONTENT).await?;
+		self.held_locks.lock().expect("poison").insert((id, owner));
 		
 		Ok(())
 	}
+	
+	/// Returns a lock of another owner which conflicts with the described one, if there is any
+	pub async fn test_lock(&self, id: NodeID, owner: u64, kind: LockKind, start: u64, end: Option<u64>) -> Result<Option<FileLock>, LockFileError> {
+		self.check_lost_locks(id, owner)?;
+		
+		let url = self.base_url.join(&format!("file/{id}/lock/test")).expect("url should be valid");
+		let request = self.client.post(url)
+			.postcard(&FileLock {
+				owner: self.lock_owner(owner),
+				kind,
+				start,
+				end,
+				pid: 0,
+			});
+		
+		let conflicting = decode_errors(request, StatusCode::OK).await?
+			.postcard().await?;
+		
+		Ok(conflicting)
+	}
+	
+	pub async fn unlock_file(&self, id: NodeID, owner: u64, start: u64, end: Option<u64>) -> Result<(), LockFileError> {
+		self.check_lost_locks(id, owner)?;
+		
+		let url = self.base_url.join(&format!("file/{id}/unlock")).expect("url should be valid");
+		let request = self.client.post(url)
+			.postcard(&UnlockRequest {
+				owner: self.lock_owner(owner),
+				start,
+				end,
+			});
+		
+		decode_errors(request, StatusCode::NO_CONTENT).await?;
+		
+		if start == 0 && end.is_none() {
+			self.held_locks.lock().expect("poison").remove(&(id, owner));
+		}
+		
+		Ok(())
+	}
+	
+	/// Whether the owner might hold locks on the file, so closing it only sends an unlock request if necessary
+	/// 
+	/// Also true if its locks were lost, so closing the file reports that.
+	pub fn holds_locks(&self, id: NodeID, owner: u64) -> bool {
+		self.held_locks.lock().expect("poison").contains(&(id, owner))
+			|| self.lost_locks.lock().expect("poison").contains(&(id, owner))
+	}
+	
+	/// Renews the lease of this client's locks while it holds any, never returns
+	pub async fn renew_lock_lease(&self) {
+		loop {
+			tokio::time::sleep(Duration::from_secs(LOCK_LEASE_SECS / 3)).await;
+			self.renew_lock_lease_once().await;
+		}
+	}
+	
+	/// Moves the locks to the lost ones if the lease already expired
+	async fn renew_lock_lease_once(&self) {
+		// locks acquired while renewing also renew the lease, so they are never lost
+		let renewed = self.held_locks.lock().expect("poison").clone();
+		if renewed.is_empty() {
+			return;
+		}
+		
+		let url = self.base_url.join(&format!("lock-lease/{}", self.client_id)).expect("url should be valid");
+		
+		// the lease is renewed either way, but locks of an expired one were already dropped by the server
+		match decode_errors(self.client.put(url), StatusCode::NO_CONTENT).await {
+			Ok(_) => (),
+			Err(Error::NotFound) => {
+				eprintln!("lock lease expired, locks held until now were lost");
+				self.held_locks.lock().expect("poison").retain(|held| !renewed.contains(held));
+				self.lost_locks.lock().expect("poison").extend(renewed);
+			},
+			Err(err) => eprintln!("could not renew lock lease: {err:?}"),
+		}
+	}
+	
+	/// Fails with [`DelegationError::Conflicting`] while conflicting delegations of other clients are being recalled
+	pub async fn request_delegation(&self, id: NodeID, kind: DelegationKind) -> Result<(), DelegationError> {
+		let url = self.base_url.join(&format!("file/{id}/delegation")).expect("url should be valid");
+		let request = self.client.post(url)
+			.postcard(&DelegationRequest {
+				client: self.client_id,
+				kind,
+			});
+		
+		decode_errors(request, StatusCode::NO_CONTENT).await?;
+		
+		Ok(())
+	}
+	
+	pub async fn return_delegation(&self, id: NodeID) -> Result<(), DelegationError> {
+		let url = self.base_url.join(&format!("file/{id}/delegation/{}", self.client_id)).expect("url should be valid");
+		
+		decode_errors(self.client.delete(url), StatusCode::NO_CONTENT).await?;
+		
+		Ok(())
+	}
+	
+	/// Waits until the server recalls delegations of this client, returning the files they are for
+	/// 
+	/// Might return no files if nothing was recalled for a while.
+	pub async fn wait_for_recalls(&self) -> Result<Vec<NodeID>, DelegationError> {
+		let url = self.base_url.join(&format!("delegation-recalls/{}", self.client_id)).expect("url should be valid");
+		
+		let recalled = decode_errors(self.client.get(url), StatusCode::OK).await?
+			.postcard().await?;
+		
+		Ok(recalled)
+	}
+}
+
+/// Hashes a local file as it is, like the plaintext hash of [`RemoteDataService::hash_local_file`]
+pub async fn hash_plaintext_file(path: &Path) -> Result<blake3::Hash, io::Error> {
+	let size = tokio::fs::metadata(path).await?.len();
+	hash_chunks(read_chunks(path.to_owned(), size)).await
+}
+
+async fn hash_chunks(chunks: impl Stream<Item = Result<Bytes, io::Error>>) -> Result<blake3::Hash, io::Error> {
+	let mut hasher = blake3::Hasher::new();
+	let mut chunks = std::pin::pin!(chunks);
+	
+	while let Some(chunk) = chunks.try_next().await? {
+		hasher.update(&chunk);
+	}
+	
+	Ok(hasher.finalize())
+}
+
+/// Reads the first `size` bytes of a local file in the chunks it is encrypted in, failing if the file got shorter in the meantime
+fn read_chunks(path: PathBuf, size: u64) -> impl Stream<Item = Result<Bytes, io::Error>> + Send + 'static {
+	let chunk_count = size.div_ceil(CHUNK_SIZE as u64).max(1); // empty files still have a (last) chunk
+	
+	stream::try_unfold((None, 0), move |(file, index)| {
+		let path = path.clone();
+		
+		async move {
+			if index == chunk_count {
+				return Ok(None);
+			}
+			
+			let mut file = match file {
+				Some(file) => file,
+				None => tokio::fs::File::open(path).await?,
+			};
+			
+			let mut chunk = vec![0; (size - index * CHUNK_SIZE as u64).min(CHUNK_SIZE as u64) as usize];
+			file.read_exact(&mut chunk).await?;
+			
+			Ok(Some((Bytes::from(chunk), (Some(file), index + 1))))
+		}
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::MockServer;
+	
+	#[tokio::test]
+	async fn reports_lost_locks() {
+		let (service, mock) = MockServer::default().serve().await;
+		let (file, other) = {
+			let mut state = mock.state();
+			(state.add_file(NodeID::ROOT, "file", b""), state.add_file(NodeID::ROOT, "other", b""))
+		};
+		
+		service.lock_file(file, 1, LockKind::Exclusive, 0, None, 0).await.unwrap();
+		service.lock_file(other, 1, LockKind::Shared, 0, None, 0).await.unwrap();
+		
+		// the mock server never keeps leases, as if this one expired
+		service.renew_lock_lease_once().await;
+		assert!(service.held_locks.lock().unwrap().is_empty());
+		assert!(service.holds_locks(file, 1));
+		assert!(!service.holds_locks(file, 2));
+		
+		// each loss is only reported once
+		assert!(matches!(service.test_lock(file, 1, LockKind::Exclusive, 0, None).await, Err(LockFileError::LeaseExpired)));
+		assert!(matches!(service.test_lock(file, 1, LockKind::Exclusive, 0, None).await, Ok(None)));
+		assert!(matches!(service.unlock_file(other, 1, 0, None).await, Err(LockFileError::LeaseExpired)));
+		assert!(!service.holds_locks(other, 1));
+		
+		// locks are held again once acquired again
+		service.lock_file(file, 1, LockKind::Exclusive, 0, None, 0).await.unwrap();
+		assert!(service.holds_locks(file, 1));
+	}
 }
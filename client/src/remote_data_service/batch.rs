@@ -0,0 +1,47 @@
+use std::sync::Mutex;
+
+use fye_shared::{BatchOperation, BatchResult, MAX_BATCH_SIZE};
+use tokio::sync::{oneshot, Notify};
+
+use super::RunBatchError;
+
+type Reply = oneshot::Sender<Result<BatchResult, RunBatchError>>;
+
+/// Operations of concurrent callers waiting to be sent together by [`super::RemoteDataService::send_batches`]
+#[derive(Default, Debug)]
+pub(super) struct BatchQueue {
+	queued: Mutex<Vec<(BatchOperation, Reply)>>,
+	/// Wakes the sender once an operation is queued
+	pushed: Notify,
+}
+
+impl BatchQueue {
+	/// Waits until the operation was sent as part of a batch
+	/// 
+	/// Fails with [`RunBatchError::NotSent`] if the batch containing it is dropped without being sent.
+	pub async fn run(&self, operation: BatchOperation) -> Result<BatchResult, RunBatchError> {
+		let (reply, result) = oneshot::channel();
+		
+		self.queued.lock().expect("poison").push((operation, reply));
+		self.pushed.notify_one();
+		
+		result.await.unwrap_or(Err(RunBatchError::NotSent))
+	}
+	
+	/// Waits until at least one operation is queued and takes as many as fit into a single batch
+	pub async fn next_batch(&self) -> Vec<(BatchOperation, Reply)> {
+		loop {
+			{
+				let mut queued = self.queued.lock().expect("poison");
+				
+				if !queued.is_empty() {
+					let count = queued.len().min(MAX_BATCH_SIZE);
+					return queued.drain(..count).collect();
+				}
+			}
+			
+			// doesn't miss operations queued in the meantime as the notification is stored until then
+			self.pushed.notified().await;
+		}
+	}
+}
@@ -0,0 +1,620 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chacha20poly1305::{aead::{rand_core::RngCore, Aead, KeyInit, OsRng, Payload}, XChaCha20Poly1305, XNonce};
+use fye_shared::NodeID;
+use serde::{Deserialize, Serialize};
+
+const FORMAT_VERSION: u8 = 1;
+
+/// Size of the plaintext within each chunk, only the last chunk may be smaller
+pub const CHUNK_SIZE: usize = 64 * 1024;
+const TAG_SIZE: usize = 16;
+const NONCE_PREFIX_SIZE: usize = 19;
+/// Version byte followed by the random nonce prefix of the file
+pub const HEADER_SIZE: usize = 1 + NONCE_PREFIX_SIZE;
+
+/// Key wrapped with a key derived from the passphrase, as stored on the server
+#[derive(Serialize, Deserialize, Debug)]
+struct WrappedKey {
+	version: u8,
+	salt: [u8; 16],
+	memory_cost: u32,
+	time_cost: u32,
+	parallelism: u32,
+	nonce: [u8; 24],
+	ciphertext: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct DecryptionFailed;
+
+/// Keys of an encrypted volume, derived from a single random key which is stored wrapped on the server
+/// 
+/// Contents are split into chunks that are encrypted separately, so any range can be decrypted on its own.
+/// The nonce of each chunk contains its index and whether it is the last one, so chunks can't be reordered or cut off,
+/// and the id of the file is authenticated, so contents can't be swapped between files.
+/// 
+/// Names are encrypted deterministically with a nonce derived from the name itself,
+/// so the server can still look up entries by name and detect duplicates.
+/// This reveals which entries share the same name, but nothing else.
+/// 
+/// Contents are encrypted deterministically as well, with a nonce derived from the id of the file and the content,
+/// so unchanged files can be recognized by their hash on the server.
+/// This reveals when a file is changed back to a content it had before, but nothing else.
+pub struct VolumeKey {
+	content_cipher: XChaCha20Poly1305,
+	content_nonce_key: [u8; 32],
+	name_cipher: XChaCha20Poly1305,
+	name_nonce_key: [u8; 32],
+}
+
+impl std::fmt::Debug for VolumeKey {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("VolumeKey(..)")
+	}
+}
+
+fn derive_wrapping_key(passphrase: &str, wrapped: &WrappedKey) -> Result<[u8; 32], DecryptionFailed> {
+	let params = Params::new(wrapped.memory_cost, wrapped.time_cost, wrapped.parallelism, Some(32))
+		.map_err(|_| DecryptionFailed)?;
+	
+	let mut key = [0; 32];
+	Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+		.hash_password_into(passphrase.as_bytes(), &wrapped.salt, &mut key)
+		.map_err(|_| DecryptionFailed)?;
+	
+	Ok(key)
+}
+
+impl VolumeKey {
+	fn from_master_key(master_key: &[u8; 32]) -> Self {
+		Self {
+			content_cipher: XChaCha20Poly1305::new(&blake3::derive_key("fye 2024-10-29 content key", master_key).into()),
+			content_nonce_key: blake3::derive_key("fye 2024-11-24 content nonce key", master_key),
+			name_cipher: XChaCha20Poly1305::new(&blake3::derive_key("fye 2024-10-29 name key", master_key).into()),
+			name_nonce_key: blake3::derive_key("fye 2024-10-29 name nonce key", master_key),
+		}
+	}
+	
+	/// Generates a new random key, returning it together with its wrapped form to be stored on the server
+	pub fn generate(passphrase: &str) -> (Self, Vec<u8>) {
+		let mut master_key = [0; 32];
+		OsRng.fill_bytes(&mut master_key);
+		
+		let params = Params::default();
+		let mut wrapped = WrappedKey {
+			version: FORMAT_VERSION,
+			salt: [0; 16],
+			memory_cost: params.m_cost(),
+			time_cost: params.t_cost(),
+			parallelism: params.p_cost(),
+			nonce: [0; 24],
+			ciphertext: Vec::new(),
+		};
+		OsRng.fill_bytes(&mut wrapped.salt);
+		OsRng.fill_bytes(&mut wrapped.nonce);
+		
+		let wrapping_key = derive_wrapping_key(passphrase, &wrapped).expect("default parameters should be valid");
+		wrapped.ciphertext = XChaCha20Poly1305::new(&wrapping_key.into())
+			.encrypt(XNonce::from_slice(&wrapped.nonce), &master_key[..])
+			.expect("encryption should not fail");
+		
+		let wrapped = postcard::to_stdvec(&wrapped).expect("serialization should not fail");
+		(Self::from_master_key(&master_key), wrapped)
+	}
+	
+	/// Fails if the passphrase is wrong or the wrapped key is malformed
+	pub fn unwrap(passphrase: &str, wrapped: &[u8]) -> Result<Self, DecryptionFailed> {
+		let wrapped: WrappedKey = postcard::from_bytes(wrapped).map_err(|_| DecryptionFailed)?;
+		
+		if wrapped.version != FORMAT_VERSION {
+			return Err(DecryptionFailed);
+		}
+		
+		let wrapping_key = derive_wrapping_key(passphrase, &wrapped)?;
+		let master_key = XChaCha20Poly1305::new(&wrapping_key.into())
+			.decrypt(XNonce::from_slice(&wrapped.nonce), &wrapped.ciphertext[..])
+			.map_err(|_| DecryptionFailed)?;
+		
+		let master_key = master_key.try_into().map_err(|_| DecryptionFailed)?;
+		Ok(Self::from_master_key(&master_key))
+	}
+	
+	pub fn encrypt_name(&self, name: &str) -> String {
+		let nonce = blake3::keyed_hash(&self.name_nonce_key, name.as_bytes());
+		let nonce = XNonce::from_slice(&nonce.as_bytes()[..24]);
+		
+		let mut data = nonce.to_vec();
+		data.extend(self.name_cipher.encrypt(nonce, name.as_bytes()).expect("encryption should not fail"));
+		
+		URL_SAFE_NO_PAD.encode(data)
+	}
+	
+	pub fn decrypt_name(&self, name: &str) -> Result<String, DecryptionFailed> {
+		let data = URL_SAFE_NO_PAD.decode(name).map_err(|_| DecryptionFailed)?;
+		
+		if data.len() < 24 {
+			return Err(DecryptionFailed);
+		}
+		
+		let (nonce, ciphertext) = data.split_at(24);
+		let name = self.name_cipher.decrypt(XNonce::from_slice(nonce), ciphertext).map_err(|_| DecryptionFailed)?;
+		
+		String::from_utf8(name).map_err(|_| DecryptionFailed)
+	}
+	
+	fn chunk_nonce(prefix: &[u8], index: u32, is_last: bool) -> XNonce {
+		let mut nonce = XNonce::default();
+		nonce[..NONCE_PREFIX_SIZE].copy_from_slice(prefix);
+		nonce[NONCE_PREFIX_SIZE..NONCE_PREFIX_SIZE + 4].copy_from_slice(&index.to_be_bytes());
+		nonce[NONCE_PREFIX_SIZE + 4] = is_last as u8;
+		nonce
+	}
+	
+	pub fn encrypt_content(&self, id: NodeID, data: &[u8]) -> Vec<u8> {
+		let mut encryptor = self.content_encryptor(id, &blake3::hash(data), data.len() as u64);
+		let mut encrypted = Vec::with_capacity(encrypted_size(data.len() as u64) as usize);
+		encrypted.extend_from_slice(&encryptor.header());
+		
+		for chunk in chunks(data) {
+			encrypted.extend(encryptor.encrypt_chunk(chunk));
+		}
+		
+		encrypted
+	}
+	
+	/// Encrypts content of `size` bytes whose blake3 hash is `plaintext_hash` chunk by chunk,
+	/// resulting in the same as [`Self::encrypt_content`]
+	pub fn content_encryptor(&self, id: NodeID, plaintext_hash: &blake3::Hash, size: u64) -> ContentEncryptor {
+		let mut nonce_hasher = blake3::Hasher::new_keyed(&self.content_nonce_key);
+		nonce_hasher.update(&id.0.to_le_bytes());
+		nonce_hasher.update(plaintext_hash.as_bytes());
+		
+		ContentEncryptor {
+			cipher: self.content_cipher.clone(),
+			aad: id.0.to_le_bytes(),
+			prefix: nonce_hasher.finalize().as_bytes()[..NONCE_PREFIX_SIZE].try_into().expect("hash should be long enough"),
+			next_index: 0,
+			total: size.div_ceil(CHUNK_SIZE as u64).max(1), // empty content still has a (last) chunk
+		}
+	}
+	
+	/// Decrypts the content as it is received, see [`ContentDecryptor`]
+	pub fn content_decryptor(&self, id: NodeID) -> ContentDecryptor {
+		ContentDecryptor {
+			cipher: self.content_cipher.clone(),
+			aad: id.0.to_le_bytes(),
+			prefix: None,
+			next_index: 0,
+			buffer: Vec::new(),
+			is_empty: true,
+		}
+	}
+	
+	pub fn decrypt_content(&self, id: NodeID, data: &[u8]) -> Result<Vec<u8>, DecryptionFailed> {
+		// newly created files are empty on the server until they are written for the first time
+		if data.is_empty() {
+			return Ok(Vec::new());
+		}
+		
+		if data.len() < HEADER_SIZE + TAG_SIZE {
+			return Err(DecryptionFailed);
+		}
+		
+		let (header, body) = data.split_at(HEADER_SIZE);
+		let chunks = ChunkRange::covering(0, plaintext_size(data.len() as u64), data.len() as u64);
+		
+		self.decrypt_chunks(id, header, chunks, body)
+	}
+	
+	/// Decrypts the consecutive `chunks` in `data`, given the header at the start of the stored content
+	/// 
+	/// Returns the plaintext of the whole chunks, starting at [`ChunkRange::plaintext_start`].
+	pub fn decrypt_chunks(&self, id: NodeID, header: &[u8], chunks: ChunkRange, data: &[u8]) -> Result<Vec<u8>, DecryptionFailed> {
+		if header.len() != HEADER_SIZE || header[0] != FORMAT_VERSION {
+			return Err(DecryptionFailed);
+		}
+		
+		let prefix = &header[1..];
+		let encrypted_chunks: Vec<&[u8]> = data.chunks(CHUNK_SIZE + TAG_SIZE).collect();
+		
+		if encrypted_chunks.len() as u64 != chunks.count {
+			return Err(DecryptionFailed);
+		}
+		
+		let aad = id.0.to_le_bytes();
+		let mut decrypted = Vec::with_capacity(data.len());
+		
+		for (index, chunk) in (chunks.first..).zip(encrypted_chunks) {
+			decrypted.extend(decrypt_chunk(&self.content_cipher, prefix, &aad, index, index + 1 == chunks.total, chunk)?);
+		}
+		
+		Ok(decrypted)
+	}
+}
+
+fn decrypt_chunk(cipher: &XChaCha20Poly1305, prefix: &[u8], aad: &[u8], index: u64, is_last: bool, chunk: &[u8]) -> Result<Vec<u8>, DecryptionFailed> {
+	let nonce = VolumeKey::chunk_nonce(prefix, index as u32, is_last);
+	
+	cipher.decrypt(&nonce, Payload {
+		msg: chunk,
+		aad,
+	}).map_err(|_| DecryptionFailed)
+}
+
+/// Splits a plaintext into the chunks it is encrypted in, which is a single empty one for empty content
+pub fn chunks(data: &[u8]) -> impl Iterator<Item = &[u8]> {
+	let count = data.len().div_ceil(CHUNK_SIZE).max(1);
+	(0..count).map(move |index| &data[(index * CHUNK_SIZE).min(data.len())..((index + 1) * CHUNK_SIZE).min(data.len())])
+}
+
+/// Encrypts a content one chunk after another, so it doesn't have to be in memory at once
+pub struct ContentEncryptor {
+	cipher: XChaCha20Poly1305,
+	aad: [u8; 8],
+	prefix: [u8; NONCE_PREFIX_SIZE],
+	next_index: u64,
+	/// Number of chunks of the whole content, as the last one is encrypted differently
+	total: u64,
+}
+
+impl ContentEncryptor {
+	/// Has to be stored before the encrypted chunks
+	pub fn header(&self) -> [u8; HEADER_SIZE] {
+		let mut header = [FORMAT_VERSION; HEADER_SIZE];
+		header[1..].copy_from_slice(&self.prefix);
+		header
+	}
+	
+	/// Encrypts the next chunk, which has to be [`CHUNK_SIZE`] bytes long unless it is the last one
+	pub fn encrypt_chunk(&mut self, chunk: &[u8]) -> Vec<u8> {
+		assert!(self.next_index < self.total, "content should have as many chunks as its size implies");
+		
+		let index = self.next_index;
+		self.next_index += 1;
+		
+		let nonce = VolumeKey::chunk_nonce(&self.prefix, index as u32, self.next_index == self.total);
+		self.cipher.encrypt(&nonce, Payload {
+			msg: chunk,
+			aad: &self.aad,
+		}).expect("encryption should not fail")
+	}
+}
+
+/// Decrypts a content while it is received, without knowing its size up front
+/// 
+/// The chunk received last is only decrypted once it is known whether it is the last chunk of the content.
+pub struct ContentDecryptor {
+	cipher: XChaCha20Poly1305,
+	aad: [u8; 8],
+	/// Known once the header was received
+	prefix: Option<[u8; NONCE_PREFIX_SIZE]>,
+	next_index: u64,
+	buffer: Vec<u8>,
+	/// Newly created files are empty on the server until they are written for the first time
+	is_empty: bool,
+}
+
+impl ContentDecryptor {
+	/// Returns the plaintext of the chunks that are complete now
+	pub fn update(&mut self, data: &[u8]) -> Result<Vec<u8>, DecryptionFailed> {
+		self.is_empty &= data.is_empty();
+		self.buffer.extend_from_slice(data);
+		
+		if self.prefix.is_none() {
+			if self.buffer.len() < HEADER_SIZE {
+				return Ok(Vec::new());
+			}
+			
+			if self.buffer[0] != FORMAT_VERSION {
+				return Err(DecryptionFailed);
+			}
+			
+			self.prefix = Some(self.buffer[1..HEADER_SIZE].try_into().expect("header should have the size of the prefix after the version"));
+			self.buffer.drain(..HEADER_SIZE);
+		}
+		
+		let prefix = self.prefix.expect("should be known by now");
+		let chunk_size = CHUNK_SIZE + TAG_SIZE;
+		let mut decrypted = Vec::new();
+		let mut start = 0;
+		
+		// a chunk that isn't followed by anything yet might be the last one
+		while self.buffer.len() - start > chunk_size {
+			decrypted.extend(decrypt_chunk(&self.cipher, &prefix, &self.aad, self.next_index, false, &self.buffer[start..start + chunk_size])?);
+			self.next_index += 1;
+			start += chunk_size;
+		}
+		
+		self.buffer.drain(..start);
+		Ok(decrypted)
+	}
+	
+	/// Returns the plaintext of the last chunk, fails if the content was cut off
+	pub fn finish(self) -> Result<Vec<u8>, DecryptionFailed> {
+		if self.is_empty {
+			return Ok(Vec::new());
+		}
+		
+		let prefix = self.prefix.ok_or(DecryptionFailed)?;
+		decrypt_chunk(&self.cipher, &prefix, &self.aad, self.next_index, true, &self.buffer)
+	}
+}
+
+/// Consecutive chunks of an encrypted content
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ChunkRange {
+	pub first: u64,
+	pub count: u64,
+	/// Number of chunks of the whole content, as the last one is encrypted differently
+	pub total: u64,
+}
+
+impl ChunkRange {
+	/// The chunks containing the plaintext range `start..end` of content stored with `encrypted_size` bytes
+	pub fn covering(start: u64, end: u64, encrypted_size: u64) -> Self {
+		let body = encrypted_size.saturating_sub(HEADER_SIZE as u64);
+		let total = body.div_ceil((CHUNK_SIZE + TAG_SIZE) as u64).max(1);
+		
+		let first = (start / CHUNK_SIZE as u64).min(total - 1);
+		let last = (end.saturating_sub(1) / CHUNK_SIZE as u64).clamp(first, total - 1);
+		
+		Self {
+			first,
+			count: last - first + 1,
+			total,
+		}
+	}
+	
+	/// Start and exclusive end of the chunks within the stored content, excluding the header which is always needed as well
+	pub fn encrypted_range(&self, encrypted_size: u64) -> (u64, u64) {
+		let chunk_size = (CHUNK_SIZE + TAG_SIZE) as u64;
+		let start = HEADER_SIZE as u64 + self.first * chunk_size;
+		let end = (start + self.count * chunk_size).min(encrypted_size);
+		
+		(start, end)
+	}
+	
+	/// Offset of the first chunk within the plaintext
+	pub fn plaintext_start(&self) -> u64 {
+		self.first * CHUNK_SIZE as u64
+	}
+}
+
+/// Size of the content stored on the server given the size of the plaintext
+pub fn encrypted_size(plaintext_size: u64) -> u64 {
+	let chunk_count = plaintext_size.div_ceil(CHUNK_SIZE as u64).max(1);
+	HEADER_SIZE as u64 + plaintext_size + chunk_count * TAG_SIZE as u64
+}
+
+/// Size of the decrypted content given the size stored on the server
+pub fn plaintext_size(encrypted_size: u64) -> u64 {
+	if encrypted_size == 0 {
+		return 0;
+	}
+	
+	let body = encrypted_size.saturating_sub(HEADER_SIZE as u64);
+	let chunk_count = body.div_ceil((CHUNK_SIZE + TAG_SIZE) as u64);
+	body.saturating_sub(chunk_count * TAG_SIZE as u64)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	const ID: NodeID = NodeID(42);
+	
+	/// Sizes around the chunk boundaries
+	const SIZES: [usize; 7] = [0, 1, CHUNK_SIZE - 1, CHUNK_SIZE, CHUNK_SIZE + 1, 2 * CHUNK_SIZE, 3 * CHUNK_SIZE + 100];
+	
+	fn key() -> VolumeKey {
+		VolumeKey::from_master_key(&[7; 32])
+	}
+	
+	fn content(size: usize) -> Vec<u8> {
+		(0..size).map(|i| (i % 251) as u8).collect()
+	}
+	
+	/// Decrypts a range the way `RemoteDataService::fetch_file_range` does
+	fn decrypt_range(key: &VolumeKey, encrypted: &[u8], start: u64, end: u64) -> Result<Vec<u8>, DecryptionFailed> {
+		let chunks = ChunkRange::covering(start, end, encrypted.len() as u64);
+		let (chunks_start, chunks_end) = chunks.encrypted_range(encrypted.len() as u64);
+		
+		let plaintext = key.decrypt_chunks(ID, &encrypted[..HEADER_SIZE], chunks, &encrypted[chunks_start as usize..chunks_end as usize])?;
+		let offset = |position: u64| (position.saturating_sub(chunks.plaintext_start()) as usize).min(plaintext.len());
+		
+		Ok(plaintext[offset(start)..offset(end).max(offset(start))].to_vec())
+	}
+	
+	#[test]
+	fn round_trip() {
+		let key = key();
+		
+		for size in SIZES {
+			let data = content(size);
+			let encrypted = key.encrypt_content(ID, &data);
+			
+			assert_eq!(plaintext_size(encrypted.len() as u64), size as u64);
+			assert_eq!(key.decrypt_content(ID, &encrypted).unwrap(), data, "size {size}");
+		}
+		
+		// files that were never written are empty on the server
+		assert_eq!(key.decrypt_content(ID, &[]).unwrap(), Vec::<u8>::new());
+	}
+	
+	#[test]
+	fn tampered() {
+		let key = key();
+		let encrypted = key.encrypt_content(ID, &content(2 * CHUNK_SIZE + 1));
+		
+		for position in [0, 1, HEADER_SIZE, HEADER_SIZE + CHUNK_SIZE + TAG_SIZE + 5, encrypted.len() - 1] {
+			let mut tampered = encrypted.clone();
+			tampered[position] ^= 1;
+			
+			assert!(key.decrypt_content(ID, &tampered).is_err(), "position {position}");
+		}
+		
+		// the content of one file can't be passed off as another one's
+		assert!(key.decrypt_content(NodeID(43), &encrypted).is_err());
+		assert!(VolumeKey::from_master_key(&[8; 32]).decrypt_content(ID, &encrypted).is_err());
+	}
+	
+	#[test]
+	fn reordered() {
+		let key = key();
+		let encrypted = key.encrypt_content(ID, &content(3 * CHUNK_SIZE));
+		let chunk = CHUNK_SIZE + TAG_SIZE;
+		
+		let mut reordered = encrypted[..HEADER_SIZE].to_vec();
+		reordered.extend_from_slice(&encrypted[HEADER_SIZE + chunk..HEADER_SIZE + 2 * chunk]);
+		reordered.extend_from_slice(&encrypted[HEADER_SIZE..HEADER_SIZE + chunk]);
+		reordered.extend_from_slice(&encrypted[HEADER_SIZE + 2 * chunk..]);
+		
+		assert!(key.decrypt_content(ID, &reordered).is_err());
+		
+		// chunks can't be taken from another version of the same file either
+		let mut other_content = content(3 * CHUNK_SIZE);
+		other_content[0] ^= 1;
+		let other = key.encrypt_content(ID, &other_content);
+		let mut mixed = encrypted[..HEADER_SIZE + chunk].to_vec();
+		mixed.extend_from_slice(&other[HEADER_SIZE + chunk..]);
+		
+		assert!(key.decrypt_content(ID, &mixed).is_err());
+	}
+	
+	#[test]
+	fn truncated() {
+		let key = key();
+		
+		for size in [1, CHUNK_SIZE, CHUNK_SIZE + 1, 2 * CHUNK_SIZE] {
+			let encrypted = key.encrypt_content(ID, &content(size));
+			
+			// dropping whole chunks at the end is detected because the last chunk is marked
+			let whole_chunks = HEADER_SIZE + (size - 1) / CHUNK_SIZE * (CHUNK_SIZE + TAG_SIZE);
+			
+			for length in [1, HEADER_SIZE, HEADER_SIZE + TAG_SIZE, whole_chunks, encrypted.len() - 1] {
+				assert!(key.decrypt_content(ID, &encrypted[..length]).is_err(), "size {size}, length {length}");
+			}
+		}
+	}
+	
+	#[test]
+	fn ranges() {
+		let key = key();
+		
+		for size in SIZES {
+			let data = content(size);
+			let encrypted = key.encrypt_content(ID, &data);
+			
+			let boundaries = [0, 1, CHUNK_SIZE - 1, CHUNK_SIZE, CHUNK_SIZE + 1, 2 * CHUNK_SIZE, size.saturating_sub(1), size, size + 10];
+			
+			for start in boundaries {
+				for end in boundaries.into_iter().filter(|&end| end >= start) {
+					let expected = &data[start.min(size)..end.min(size)];
+					let decrypted = decrypt_range(&key, &encrypted, start as u64, end as u64).unwrap();
+					
+					assert_eq!(decrypted, expected, "size {size}, range {start}..{end}");
+				}
+			}
+		}
+	}
+	
+	#[test]
+	fn chunk_ranges() {
+		let encrypted_size = (HEADER_SIZE + 3 * CHUNK_SIZE + 100 + 4 * TAG_SIZE) as u64;
+		let chunk = (CHUNK_SIZE + TAG_SIZE) as u64;
+		
+		let range = ChunkRange::covering(CHUNK_SIZE as u64 - 1, CHUNK_SIZE as u64 + 1, encrypted_size);
+		assert_eq!(range, ChunkRange { first: 0, count: 2, total: 4 });
+		assert_eq!(range.encrypted_range(encrypted_size), (HEADER_SIZE as u64, HEADER_SIZE as u64 + 2 * chunk));
+		
+		let range = ChunkRange::covering(3 * CHUNK_SIZE as u64, 4 * CHUNK_SIZE as u64, encrypted_size);
+		assert_eq!(range, ChunkRange { first: 3, count: 1, total: 4 });
+		assert_eq!(range.encrypted_range(encrypted_size), (HEADER_SIZE as u64 + 3 * chunk, encrypted_size));
+		assert_eq!(range.plaintext_start(), 3 * CHUNK_SIZE as u64);
+		
+		// ranges past the end still include the last chunk, so its authenticity is verified
+		let range = ChunkRange::covering(10 * CHUNK_SIZE as u64, 11 * CHUNK_SIZE as u64, encrypted_size);
+		assert_eq!(range, ChunkRange { first: 3, count: 1, total: 4 });
+	}
+	
+	#[test]
+	fn deterministic() {
+		let key = key();
+		let encrypted = key.encrypt_content(ID, &content(CHUNK_SIZE + 1));
+		
+		// unchanged contents have to be encrypted the same to be recognized by their hash
+		assert_eq!(key.encrypt_content(ID, &content(CHUNK_SIZE + 1)), encrypted);
+		assert_eq!(encrypted.len() as u64, encrypted_size(CHUNK_SIZE as u64 + 1));
+		
+		// but nonces are never reused for anything else
+		let mut other_content = content(CHUNK_SIZE + 1);
+		other_content[CHUNK_SIZE] ^= 1;
+		
+		assert_ne!(key.encrypt_content(ID, &other_content)[..HEADER_SIZE], encrypted[..HEADER_SIZE]);
+		assert_ne!(key.encrypt_content(NodeID(43), &content(CHUNK_SIZE + 1))[..HEADER_SIZE], encrypted[..HEADER_SIZE]);
+		assert_ne!(VolumeKey::from_master_key(&[8; 32]).encrypt_content(ID, &content(CHUNK_SIZE + 1))[..HEADER_SIZE], encrypted[..HEADER_SIZE]);
+	}
+	
+	#[test]
+	fn streamed() {
+		let key = key();
+		
+		for size in SIZES {
+			let data = content(size);
+			
+			let mut encryptor = key.content_encryptor(ID, &blake3::hash(&data), size as u64);
+			let mut encrypted = encryptor.header().to_vec();
+			for chunk in chunks(&data) {
+				encrypted.extend(encryptor.encrypt_chunk(chunk));
+			}
+			
+			assert_eq!(encrypted, key.encrypt_content(ID, &data), "size {size}");
+			
+			// received in pieces that don't line up with the chunks
+			for piece_size in [1, 1000, CHUNK_SIZE + TAG_SIZE, encrypted.len().max(1)] {
+				let mut decryptor = key.content_decryptor(ID);
+				let mut decrypted = Vec::new();
+				
+				for piece in encrypted.chunks(piece_size) {
+					decrypted.extend(decryptor.update(piece).unwrap());
+				}
+				decrypted.extend(decryptor.finish().unwrap());
+				
+				assert_eq!(decrypted, data, "size {size}, pieces of {piece_size}");
+			}
+			
+			let mut decryptor = key.content_decryptor(ID);
+			let truncated = decryptor.update(&encrypted[..encrypted.len() - 1]).and_then(|_| decryptor.finish());
+			assert!(truncated.is_err(), "size {size}");
+		}
+		
+		// files that were never written are empty on the server
+		assert_eq!(key.content_decryptor(ID).finish().unwrap(), Vec::<u8>::new());
+	}
+	
+	#[test]
+	fn wrong_passphrase() {
+		let (key, wrapped) = VolumeKey::generate("correct horse");
+		let encrypted = key.encrypt_content(ID, b"secret");
+		
+		assert!(VolumeKey::unwrap("battery staple", &wrapped).is_err());
+		assert!(VolumeKey::unwrap("correct horse", &wrapped[..wrapped.len() - 1]).is_err());
+		
+		let unwrapped = VolumeKey::unwrap("correct horse", &wrapped).unwrap();
+		assert_eq!(unwrapped.decrypt_content(ID, &encrypted).unwrap(), b"secret");
+		assert_eq!(unwrapped.decrypt_name(&key.encrypt_name("name")).unwrap(), "name");
+	}
+	
+	#[test]
+	fn names() {
+		let key = key();
+		let encrypted = key.encrypt_name("file.txt");
+		
+		// names have to be encrypted deterministically to be looked up
+		assert_eq!(key.encrypt_name("file.txt"), encrypted);
+		assert_ne!(key.encrypt_name("file.txt2"), encrypted);
+		assert_eq!(key.decrypt_name(&encrypted).unwrap(), "file.txt");
+		assert!(VolumeKey::from_master_key(&[8; 32]).decrypt_name(&encrypted).is_err());
+	}
+}
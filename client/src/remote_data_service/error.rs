@@ -1,9 +1,10 @@
 #![allow(clippy::enum_variant_names)]
 #![allow(clippy::wildcard_in_or_patterns)]
 
+use fye_shared::BatchError;
 use reqwest::{RequestBuilder, Response, StatusCode};
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum NetworkError {
 	Timeout,
 	Other,
@@ -21,6 +22,13 @@ pub(super) enum Error {
 	DirectoryNotEmpty,
 	Modified,
 	NotModified,
+	QuotaExceeded,
+	DecryptionFailed,
+	HashMismatch,
+	ContentMismatch,
+	Locked,
+	RangeNotSatisfiable,
+	InvalidMove,
 }
 
 impl Error {
@@ -56,11 +64,15 @@ pub(super) async fn decode_errors(request: RequestBuilder, expected_status: Stat
 		},
 		StatusCode::PRECONDITION_FAILED => Error::Modified,
 		StatusCode::NOT_MODIFIED => Error::NotModified,
+		StatusCode::INSUFFICIENT_STORAGE => Error::QuotaExceeded,
+		StatusCode::UNPROCESSABLE_ENTITY => Error::ContentMismatch,
+		StatusCode::LOCKED => Error::Locked,
+		StatusCode::RANGE_NOT_SATISFIABLE => Error::RangeNotSatisfiable,
 		_ => Error::ProtocolMismatch,
 	})
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum FetchNodeError {
 	NetworkFailure(NetworkError),
 	ServerError,
@@ -81,7 +93,7 @@ impl From<Error> for FetchNodeError {
 	}
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum FetchDirectoryError {
 	NetworkFailure(NetworkError),
 	ServerError,
@@ -104,6 +116,118 @@ impl From<Error> for FetchDirectoryError {
 	}
 }
 
+/// Failure of a whole batch, failures of single operations are part of the results
+#[derive(Clone, Debug)]
+pub enum RunBatchError {
+	NetworkFailure(NetworkError),
+	ServerError,
+	ProtocolMismatch,
+	NotSent, // the operation was queued but the batches stopped being sent
+}
+
+impl From<Error> for RunBatchError {
+	fn from(value: Error) -> Self {
+		use Error::*;
+		
+		match value {
+			NetworkFailure(err) => Self::NetworkFailure(err),
+			ServerError => Self::ServerError,
+			ProtocolMismatch | _ => Self::ProtocolMismatch,
+		}
+	}
+}
+
+impl From<RunBatchError> for Error {
+	fn from(value: RunBatchError) -> Self {
+		match value {
+			RunBatchError::NetworkFailure(err) => Error::NetworkFailure(err),
+			RunBatchError::ServerError => Error::ServerError,
+			RunBatchError::ProtocolMismatch => Error::ProtocolMismatch,
+			RunBatchError::NotSent => Error::NetworkFailure(NetworkError::Other),
+		}
+	}
+}
+
+impl From<BatchError> for Error {
+	fn from(value: BatchError) -> Self {
+		match value {
+			BatchError::NotFound => Error::NotFound,
+			BatchError::NotAFile => Error::NotAFile,
+			BatchError::NotADirectory => Error::NotADirectory,
+			BatchError::AlreadyExists => Error::AlreadyExists,
+			BatchError::DirectoryNotEmpty => Error::DirectoryNotEmpty,
+			BatchError::QuotaExceeded => Error::QuotaExceeded,
+			BatchError::Locked => Error::Locked,
+			BatchError::InvalidMove => Error::InvalidMove,
+			BatchError::Modified => Error::Modified,
+			// operations are only aborted in atomic batches
+			BatchError::ServerError | BatchError::Aborted => Error::ServerError,
+		}
+	}
+}
+
+#[derive(Debug)]
+pub enum FetchStatsError {
+	NetworkFailure(NetworkError),
+	ServerError,
+	ProtocolMismatch,
+}
+
+impl From<Error> for FetchStatsError {
+	fn from(value: Error) -> Self {
+		use Error::*;
+		
+		match value {
+			NetworkFailure(err) => Self::NetworkFailure(err),
+			ServerError => Self::ServerError,
+			ProtocolMismatch | _ => Self::ProtocolMismatch,
+		}
+	}
+}
+
+#[derive(Debug)]
+pub enum ResolvePathError {
+	NetworkFailure(NetworkError),
+	ServerError,
+	ProtocolMismatch,
+	NotFound,
+	NotADirectory, // a component other than the last one is a file
+}
+
+impl From<Error> for ResolvePathError {
+	fn from(value: Error) -> Self {
+		use Error::*;
+		
+		match value {
+			NetworkFailure(err) => Self::NetworkFailure(err),
+			ServerError => Self::ServerError,
+			NotFound => Self::NotFound,
+			NotADirectory => Self::NotADirectory,
+			ProtocolMismatch | _ => Self::ProtocolMismatch,
+		}
+	}
+}
+
+#[derive(Debug)]
+pub enum UnlockVolumeError {
+	NetworkFailure(NetworkError),
+	ServerError,
+	ProtocolMismatch,
+	WrongPassphrase, // or a corrupted key
+}
+
+impl From<Error> for UnlockVolumeError {
+	fn from(value: Error) -> Self {
+		use Error::*;
+		
+		match value {
+			NetworkFailure(err) => Self::NetworkFailure(err),
+			ServerError => Self::ServerError,
+			ProtocolMismatch | _ => Self::ProtocolMismatch,
+		}
+	}
+}
+
 #[derive(Debug)]
 pub enum FetchFileError {
 	NetworkFailure(NetworkError),
@@ -111,6 +235,9 @@ pub enum FetchFileError {
 	ProtocolMismatch,
 	NotFound,
 	NotAFile,
+	Modified, // the file no longer has the requested content
+	DecryptionFailed, // the content was modified or encrypted with a different key
+	HashMismatch, // the content received doesn't match its hash, even after retrying
 }
 
 impl From<Error> for FetchFileError {
@@ -122,12 +249,15 @@ impl From<Error> for FetchFileError {
 			ServerError => Self::ServerError,
 			NotFound => Self::NotFound,
 			NotAFile => Self::NotAFile,
+			Modified => Self::Modified,
+			DecryptionFailed => Self::DecryptionFailed,
+			HashMismatch => Self::HashMismatch,
 			ProtocolMismatch | _ => Self::ProtocolMismatch,
 		}
 	}
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum WriteFileError {
 	NetworkFailure(NetworkError),
 	ServerError,
@@ -135,6 +265,10 @@ pub enum WriteFileError {
 	NotFound,
 	NotAFile,
 	Modified,
+	QuotaExceeded,
+	ContentMismatch, // the server received something other than what was sent
+	/// Another client holds a delegation of the file, which is being recalled
+	Locked,
 }
 
 impl From<Error> for WriteFileError {
@@ -147,6 +281,9 @@ impl From<Error> for WriteFileError {
 			NotFound => Self::NotFound,
 			NotAFile => Self::NotAFile,
 			Modified => Self::Modified,
+			QuotaExceeded => Self::QuotaExceeded,
+			ContentMismatch => Self::ContentMismatch,
+			Locked => Self::Locked,
 			ProtocolMismatch | _ => Self::ProtocolMismatch,
 		}
 	}
@@ -160,6 +297,7 @@ pub enum CreateNodeError {
 	ParentNotFound,
 	ParentNotADirectory,
 	AlreadyExists,
+	QuotaExceeded,
 }
 
 impl From<Error> for CreateNodeError {
@@ -172,6 +310,7 @@ impl From<Error> for CreateNodeError {
 			NotFound => Self::ParentNotFound,
 			NotADirectory => Self::ParentNotADirectory,
 			AlreadyExists => Self::AlreadyExists,
+			QuotaExceeded => Self::QuotaExceeded,
 			ProtocolMismatch | _ => Self::ProtocolMismatch,
 		}
 	}
@@ -202,6 +341,42 @@ impl From<Error> for DeleteDirectoryError {
 	}
 }
 
+#[derive(Debug)]
+pub enum RenameError {
+	NetworkFailure(NetworkError),
+	ServerError,
+	ProtocolMismatch,
+	NotFound, // could refer to either parent or the entry
+	NotADirectory, // could refer to either parent or a replaced entry
+	NotAFile, // a replaced entry is a directory while the renamed one is a file
+	AlreadyExists,
+	NotEmpty,
+	QuotaExceeded,
+	InvalidMove,
+	/// Another client holds a delegation of a replaced file, which is being recalled
+	Locked,
+}
+
+impl From<Error> for RenameError {
+	fn from(value: Error) -> Self {
+		use Error::*;
+		
+		match value {
+			NetworkFailure(err) => Self::NetworkFailure(err),
+			ServerError => Self::ServerError,
+			NotFound => Self::NotFound,
+			NotADirectory => Self::NotADirectory,
+			NotAFile => Self::NotAFile,
+			AlreadyExists => Self::AlreadyExists,
+			DirectoryNotEmpty => Self::NotEmpty,
+			QuotaExceeded => Self::QuotaExceeded,
+			InvalidMove => Self::InvalidMove,
+			Locked => Self::Locked,
+			ProtocolMismatch | _ => Self::ProtocolMismatch,
+		}
+	}
+}
+
 #[derive(Debug)]
 pub enum DeleteFileError {
 	NetworkFailure(NetworkError),
@@ -210,6 +385,8 @@ pub enum DeleteFileError {
 	NotFound, // could refer to parent or child
 	ParentNotADirectory,
 	NotAFile,
+	/// Another client holds a delegation of the file, which is being recalled
+	Locked,
 }
 
 impl From<Error> for DeleteFileError {
@@ -222,6 +399,61 @@ impl From<Error> for DeleteFileError {
 			NotFound => Self::NotFound,
 			NotADirectory => Self::ParentNotADirectory,
 			NotAFile => Self::NotAFile,
+			Locked => Self::Locked,
+			ProtocolMismatch | _ => Self::ProtocolMismatch,
+		}
+	}
+}
+
+#[derive(Debug)]
+pub enum LockFileError {
+	NetworkFailure(NetworkError),
+	ServerError,
+	ProtocolMismatch,
+	NotFound,
+	NotAFile,
+	/// Another owner holds a conflicting lock
+	Locked,
+	/// The lease expired, so the server dropped the locks the owner held on the file
+	LeaseExpired,
+}
+
+impl From<Error> for LockFileError {
+	fn from(value: Error) -> Self {
+		use Error::*;
+		
+		match value {
+			NetworkFailure(err) => Self::NetworkFailure(err),
+			ServerError => Self::ServerError,
+			NotFound => Self::NotFound,
+			NotAFile => Self::NotAFile,
+			Locked => Self::Locked,
+			ProtocolMismatch | _ => Self::ProtocolMismatch,
+		}
+	}
+}
+
+#[derive(Debug)]
+pub enum DelegationError {
+	NetworkFailure(NetworkError),
+	ServerError,
+	ProtocolMismatch,
+	NotFound,
+	NotAFile,
+	/// Other clients hold conflicting delegations, which are being recalled
+	Conflicting,
+}
+
+impl From<Error> for DelegationError {
+	fn from(value: Error) -> Self {
+		use Error::*;
+		
+		match value {
+			NetworkFailure(err) => Self::NetworkFailure(err),
+			ServerError => Self::ServerError,
+			NotFound => Self::NotFound,
+			NotAFile => Self::NotAFile,
+			Locked => Self::Conflicting,
 			ProtocolMismatch | _ => Self::ProtocolMismatch,
 		}
 	}
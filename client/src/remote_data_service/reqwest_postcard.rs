@@ -3,7 +3,7 @@ use serde::{de::DeserializeOwned, Serialize};
 
 use super::Error;
 
-pub trait RequestBuilderPostcard {
+pub(super) trait RequestBuilderPostcard {
 	fn postcard<T: Serialize + ?Sized>(self, value: &T) -> Self;
 }
 
@@ -14,7 +14,7 @@ impl RequestBuilderPostcard for RequestBuilder {
 	}
 }
 
-pub trait ResponsePostcard {
+pub(super) trait ResponsePostcard {
 	async fn postcard<T: DeserializeOwned>(self) -> Result<T, Error>;
 }
 
@@ -0,0 +1,213 @@
+use std::sync::Arc;
+
+use rustls::{
+	client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+	crypto::{self, ring, CryptoProvider},
+	pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+	ClientConfig, DigitallySignedStruct, SignatureScheme,
+};
+use sha2::{Digest, Sha256};
+
+/// TLS settings for connecting to the server, all of them are optional
+/// 
+/// Certificates and keys are PEM encoded.
+#[derive(Clone, Default, Debug)]
+pub struct TlsOptions {
+	/// Additional CA to trust, e.g. for a self-signed server certificate
+	pub ca_certificate: Option<Vec<u8>>,
+	/// SHA-256 fingerprint of the server certificate, only this certificate is accepted if set
+	pub pinned_fingerprint: Option<[u8; 32]>,
+	/// Client certificate chain and private key
+	pub identity: Option<Vec<u8>>,
+}
+
+#[derive(Debug)]
+pub enum TlsConfigError {
+	InvalidCertificate,
+	InvalidIdentity,
+	Other,
+}
+
+/// Accepts only the server certificate with the pinned fingerprint, regardless of who issued it
+#[derive(Debug)]
+struct PinnedCertVerifier {
+	fingerprint: [u8; 32],
+	provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+	fn verify_server_cert(
+		&self,
+		end_entity: &CertificateDer<'_>,
+		_intermediates: &[CertificateDer<'_>],
+		_server_name: &ServerName<'_>,
+		_ocsp_response: &[u8],
+		_now: UnixTime,
+	) -> Result<ServerCertVerified, rustls::Error> {
+		let fingerprint: [u8; 32] = Sha256::digest(end_entity).into();
+		
+		match fingerprint == self.fingerprint {
+			true => Ok(ServerCertVerified::assertion()),
+			false => Err(rustls::Error::InvalidCertificate(rustls::CertificateError::ApplicationVerificationFailure)),
+		}
+	}
+	
+	fn verify_tls12_signature(
+		&self,
+		message: &[u8],
+		cert: &CertificateDer<'_>,
+		dss: &DigitallySignedStruct,
+	) -> Result<HandshakeSignatureValid, rustls::Error> {
+		crypto::verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+	}
+	
+	fn verify_tls13_signature(
+		&self,
+		message: &[u8],
+		cert: &CertificateDer<'_>,
+		dss: &DigitallySignedStruct,
+	) -> Result<HandshakeSignatureValid, rustls::Error> {
+		crypto::verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+	}
+	
+	fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+		self.provider.signature_verification_algorithms.supported_schemes()
+	}
+}
+
+fn parse_identity(pem: &[u8]) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>), TlsConfigError> {
+	let certs = rustls_pemfile::certs(&mut &pem[..])
+		.collect::<Result<Vec<_>, _>>()
+		.map_err(|_| TlsConfigError::InvalidIdentity)?;
+	
+	let key = rustls_pemfile::private_key(&mut &pem[..])
+		.map_err(|_| TlsConfigError::InvalidIdentity)?
+		.ok_or(TlsConfigError::InvalidIdentity)?;
+	
+	if certs.is_empty() {
+		return Err(TlsConfigError::InvalidIdentity);
+	}
+	
+	Ok((certs, key))
+}
+
+/// Builds a rustls configuration that only trusts the server certificate with the given fingerprint
+pub(super) fn pinned_config(fingerprint: [u8; 32], identity: Option<&[u8]>) -> Result<ClientConfig, TlsConfigError> {
+	let provider = Arc::new(ring::default_provider());
+	let verifier = Arc::new(PinnedCertVerifier {
+		fingerprint,
+		provider: provider.clone(),
+	});
+	
+	let builder = ClientConfig::builder_with_provider(provider)
+		.with_safe_default_protocol_versions().map_err(|_| TlsConfigError::Other)?
+		.dangerous()
+		.with_custom_certificate_verifier(verifier);
+	
+	let config = match identity {
+		Some(identity) => {
+			let (certs, key) = parse_identity(identity)?;
+			builder.with_client_auth_cert(certs, key).map_err(|_| TlsConfigError::InvalidIdentity)?
+		},
+		None => builder.with_no_client_auth(),
+	};
+	
+	Ok(config)
+}
+
+/// Parses a fingerprint as printed by e.g. `openssl x509 -fingerprint -sha256`, colons are optional
+pub fn parse_fingerprint(fingerprint: &str) -> Option<[u8; 32]> {
+	let hex: Vec<u8> = fingerprint.bytes().filter(|&char| char != b':').collect();
+	
+	// from_str_radix would also accept signs
+	if hex.len() != 64 || !hex.iter().all(u8::is_ascii_hexdigit) {
+		return None;
+	}
+	
+	let mut bytes = [0; 32];
+	for (byte, digits) in bytes.iter_mut().zip(hex.chunks(2)) {
+		*byte = u8::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()?;
+	}
+	
+	Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	use axum::Router;
+	use axum_server::{tls_rustls::RustlsConfig, Handle};
+	use rcgen::{CertificateParams, KeyPair};
+	use reqwest::Url;
+	use rustls::{pki_types::PrivatePkcs8KeyDer, ServerConfig};
+	
+	use crate::remote_data_service::{RemoteDataService, ResolvePathError, TlsOptions};
+	
+	/// Serves a self-signed certificate, returning the port and the fingerprint of the certificate
+	async fn serve() -> (u16, [u8; 32]) {
+		let key = KeyPair::generate().unwrap();
+		let cert = CertificateParams::new(vec!["localhost".to_owned()]).unwrap()
+			.self_signed(&key).unwrap();
+		
+		let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+			.with_safe_default_protocol_versions().unwrap()
+			.with_no_client_auth()
+			.with_single_cert(vec![cert.der().clone()], PrivatePkcs8KeyDer::from(key.serialize_der()).into()).unwrap();
+		
+		let handle = Handle::new();
+		let server = axum_server::bind_rustls("127.0.0.1:0".parse().unwrap(), RustlsConfig::from_config(Arc::new(config)))
+			.handle(handle.clone())
+			.serve(Router::new().into_make_service());
+		tokio::spawn(server);
+		
+		(handle.listening().await.unwrap().port(), Sha256::digest(cert.der()).into())
+	}
+	
+	async fn connect(port: u16, pinned_fingerprint: [u8; 32]) -> Result<(), ResolvePathError> {
+		let url = Url::parse(&format!("https://localhost:{port}/")).unwrap();
+		let service = RemoteDataService::with_tls(url, TlsOptions {
+			pinned_fingerprint: Some(pinned_fingerprint),
+			..TlsOptions::default()
+		}).unwrap();
+		
+		// the server doesn't have any routes, so reaching it at all means the connection was accepted
+		match service.resolve_path("/").await {
+			Err(ResolvePathError::NotFound) => Ok(()),
+			Err(err) => Err(err),
+			Ok(_) => panic!("the server has no routes"),
+		}
+	}
+	
+	#[tokio::test]
+	async fn accepts_pinned_certificate() {
+		let (port, fingerprint) = serve().await;
+		
+		// neither the name nor the issuer is checked
+		connect(port, fingerprint).await.unwrap();
+		
+		let mut other = fingerprint;
+		other[0] ^= 1;
+		assert!(matches!(connect(port, other).await, Err(ResolvePathError::NetworkFailure(_))));
+	}
+	
+	#[test]
+	fn fingerprints() {
+		let hex = "AB".repeat(32);
+		let with_colons = vec!["ab"; 32].join(":");
+		
+		assert_eq!(parse_fingerprint(&hex), Some([0xab; 32]));
+		assert_eq!(parse_fingerprint(&with_colons), Some([0xab; 32]));
+		
+		for malformed in [
+			"",
+			&hex[..62],
+			&format!("{hex}ab"),
+			&format!("{}zz", &hex[..62]),
+			&"+1".repeat(32),
+			&format!("{}é", &hex[..62]),
+		] {
+			assert_eq!(parse_fingerprint(malformed), None, "{malformed}");
+		}
+	}
+}
@@ -0,0 +1,784 @@
+use std::{collections::{BTreeMap, BTreeSet, HashMap}, io, path::{Path, PathBuf}, time::{Duration, SystemTime}};
+
+use diesel::result::Error as DieselError;
+use fye_shared::{EntryAttributes, Hash, NodeID};
+use notify::{RecursiveMode, Watcher as _};
+use tokio::sync::mpsc;
+
+use crate::{remote_data_service::{hash_plaintext_file, DeleteDirectoryError, RemoteDataService}, transfer::{self, PartialDownload, PARTIAL_SUFFIX}};
+
+mod state;
+use state::{SyncState, SyncedEntry};
+
+/// Name of the SQLite database inside the synced directory which contains the [`SyncState`],
+/// SQLite's temporary files next to it start with the same name
+const STATE_FILE: &str = ".fye-sync-state";
+/// Remote changes are only noticed by polling
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+/// Local changes are synced once no further changes occurred for this long
+const SETTLE_DELAY: Duration = Duration::from_secs(2);
+
+#[derive(Debug)]
+enum LocalEntry {
+	Directory,
+	File {
+		content_hash: String,
+		size: u64,
+		modified: SystemTime,
+	},
+}
+
+#[derive(Debug)]
+enum RemoteEntry {
+	Directory(NodeID),
+	File(NodeID, Hash),
+}
+
+/// Keeps the local directory and the directory at `remote_path` in sync until an error occurs
+/// 
+/// Local changes are synced shortly after they happen, remote changes once they are noticed by polling.
+/// A path that changed on both sides keeps the version on the server, the local version is moved aside
+/// to a name derived from its content. A modification always wins over a deletion, so nothing is lost.
+pub async fn run(service: &RemoteDataService, local_root: &Path, remote_path: &str) -> Result<(), io::Error> {
+	tokio::fs::create_dir_all(local_root).await?;
+	
+	let mut syncer = Syncer {
+		service,
+		local_root: local_root.to_owned(),
+		remote_root: transfer::create_remote_dirs(service, remote_path).await?,
+		state: SyncState::open(&local_root.join(STATE_FILE))?,
+	};
+	
+	let (sender, mut changes) = mpsc::unbounded_channel();
+	let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+		if event.is_ok_and(|event| !event.paths.iter().all(|path| is_ignored(path))) {
+			let _ = sender.send(());
+		}
+	}).map_err(io::Error::other)?;
+	watcher.watch(local_root, RecursiveMode::Recursive).map_err(io::Error::other)?;
+	
+	loop {
+		syncer.sync().await;
+		
+		// the changes made by the sync itself can't be told apart from others, they are covered by the next pass anyway
+		while changes.try_recv().is_ok() {}
+		
+		if let Ok(Some(())) = tokio::time::timeout(POLL_INTERVAL, changes.recv()).await {
+			while let Ok(Some(())) = tokio::time::timeout(SETTLE_DELAY, changes.recv()).await {}
+		}
+	}
+}
+
+/// Files written by the sync itself which aren't synced
+fn is_ignored(path: &Path) -> bool {
+	path.file_name()
+		.and_then(|name| name.to_str())
+		.is_some_and(|name| name.starts_with(STATE_FILE) || name.ends_with(PARTIAL_SUFFIX))
+}
+
+struct Syncer<'a> {
+	service: &'a RemoteDataService,
+	local_root: PathBuf,
+	remote_root: NodeID,
+	state: SyncState,
+}
+
+impl<'a> Syncer<'a> {
+	/// Compares both sides with the state of the last sync and applies the changes of each side to the other one
+	/// 
+	/// Failures are reported and retried in the next pass.
+	async fn sync(&mut self) {
+		let mut local = match self.scan_local().await {
+			Ok(local) => local,
+			Err(err) => {
+				eprintln!("fye: scanning {} failed: {err}", self.local_root.display());
+				return;
+			},
+		};
+		
+		let mut remote = match self.scan_remote().await {
+			Ok(remote) => remote,
+			Err(err) => {
+				eprintln!("fye: scanning the server failed: {err}");
+				return;
+			},
+		};
+		
+		let mut remote_ids: HashMap<String, NodeID> = remote.iter()
+			.filter_map(|(path, entry)| match entry {
+				RemoteEntry::Directory(id) => Some((path.clone(), *id)),
+				RemoteEntry::File(..) => None,
+			})
+			.collect();
+		remote_ids.insert(String::new(), self.remote_root);
+		
+		let paths: BTreeSet<String> = local.keys()
+			.chain(remote.keys())
+			.chain(self.state.paths())
+			.cloned()
+			.collect();
+		
+		// deletions go first, as a node replaced with one of a different kind needs to be deleted before it can be created again
+		// children are deleted before their parents, the parent is only deleted if that leaves it empty
+		for path in paths.iter().rev() {
+			if let Err(err) = self.sync_deletion(path, &mut local, &mut remote, &mut remote_ids).await {
+				eprintln!("fye: {path}: {err}");
+			}
+		}
+		
+		// parents are created before their children
+		for path in &paths {
+			if let Err(err) = self.sync_path(path, &mut local, &remote, &mut remote_ids).await {
+				eprintln!("fye: {path}: {err}");
+			}
+		}
+	}
+	
+	async fn scan_local(&self) -> Result<BTreeMap<String, LocalEntry>, io::Error> {
+		let mut entries = BTreeMap::new();
+		let mut pending = vec![String::new()];
+		
+		while let Some(dir) = pending.pop() {
+			let mut read_dir = tokio::fs::read_dir(self.local_root.join(&dir)).await?;
+			
+			while let Some(entry) = read_dir.next_entry().await? {
+				if is_ignored(&entry.path()) {
+					continue;
+				}
+				
+				let Some(name) = entry.file_name().to_str().map(str::to_owned) else {
+					eprintln!("fye: {}: name is not valid UTF-8", entry.path().display());
+					continue;
+				};
+				
+				let path = join(&dir, &name);
+				let metadata = entry.metadata().await?;
+				
+				if metadata.is_dir() {
+					pending.push(path.clone());
+					entries.insert(path, LocalEntry::Directory);
+				} else if metadata.is_file() {
+					let size = metadata.len();
+					let modified = metadata.modified()?;
+					
+					let content_hash = match self.state.get(&path) {
+						Some(SyncedEntry::File { content_hash, size: synced_size, modified: synced_modified, .. })
+							if *synced_size == size && *synced_modified == modified => content_hash.clone(),
+						_ => hash_plaintext_file(&entry.path()).await?.to_hex().to_string(),
+					};
+					
+					entries.insert(path, LocalEntry::File { content_hash, size, modified });
+				}
+			}
+		}
+		
+		Ok(entries)
+	}
+	
+	async fn scan_remote(&self) -> Result<BTreeMap<String, RemoteEntry>, String> {
+		let mut entries = BTreeMap::new();
+		let mut pending = vec![(String::new(), self.remote_root)];
+		
+		while let Some((dir, id)) = pending.pop() {
+			let children = transfer::fetch_children(self.service, id).await.map_err(|err| format!("{err:?}"))?;
+			
+			for (name, entry) in children {
+				let path = join(&dir, &name);
+				
+				// the server accepts any name, which may not be usable as a local file name
+				if name.contains('/') || name == "." || name == ".." || is_ignored(Path::new(&name)) {
+					eprintln!("fye: {path}: name is not valid locally");
+					continue;
+				}
+				
+				match entry.attributes {
+					EntryAttributes::Directory => {
+						pending.push((path.clone(), entry.id));
+						entries.insert(path, RemoteEntry::Directory(entry.id));
+					},
+					EntryAttributes::File(file_info) => {
+						entries.insert(path, RemoteEntry::File(entry.id, file_info.hash));
+					},
+				}
+			}
+		}
+		
+		Ok(entries)
+	}
+	
+	/// Deletes the node on the side where it wasn't changed, if it was deleted or replaced on the other side
+	async fn sync_deletion(
+		&mut self,
+		path: &str,
+		local: &mut BTreeMap<String, LocalEntry>,
+		remote: &mut BTreeMap<String, RemoteEntry>,
+		remote_ids: &mut HashMap<String, NodeID>
+	) -> Result<(), String> {
+		let synced = self.state.get(path);
+		let local_changed = local_changed(local.get(path), synced);
+		let remote_changed = remote_changed(remote.get(path), synced);
+		
+		match (local_changed, remote_changed) {
+			(false, false) => Ok(()),
+			// a modification wins over a deletion, so the other side is simply synced as if it was new
+			(true, true) => self.state.remove(path).map_err(state_error),
+			(true, false) if !is_same_kind(local.get(path), remote.get(path)) => {
+				let Some(entry) = remote.get(path) else {
+					return Ok(());
+				};
+				
+				let (parent, name) = split(path);
+				let parent_id = *remote_ids.get(parent).ok_or("parent doesn't exist on the server")?;
+				
+				let is_deleted = match entry {
+					RemoteEntry::Directory(_) => match self.service.delete_dir(parent_id, name).await {
+						Ok(()) => true,
+						// something was added on the server, which is then synced as if the directory was new
+						Err(DeleteDirectoryError::NotEmpty) => false,
+						Err(err) => return Err(format!("{err:?}")),
+					},
+					RemoteEntry::File(..) => {
+						self.service.delete_file(parent_id, name).await.map_err(|err| format!("{err:?}"))?;
+						true
+					},
+				};
+				
+				if is_deleted {
+					eprintln!("deleted {path} on the server");
+					remote.remove(path);
+					remote_ids.remove(path);
+				}
+				
+				self.state.remove(path).map_err(state_error)
+			},
+			(false, true) if !is_same_kind(local.get(path), remote.get(path)) => {
+				let Some(entry) = local.get(path) else {
+					return Ok(());
+				};
+				
+				let local_path = self.local_root.join(path);
+				let result = match entry {
+					LocalEntry::Directory => tokio::fs::remove_dir(&local_path).await,
+					LocalEntry::File { .. } => tokio::fs::remove_file(&local_path).await,
+				};
+				
+				match result {
+					Ok(()) => {
+						eprintln!("deleted {path} locally");
+						local.remove(path);
+					},
+					// something was added locally, which is then synced as if the directory was new
+					Err(err) if err.kind() == io::ErrorKind::DirectoryNotEmpty => (),
+					Err(err) => return Err(err.to_string()),
+				}
+				
+				self.state.remove(path).map_err(state_error)
+			},
+			_ => Ok(()),
+		}
+	}
+	
+	/// Creates or updates the node on the side where it wasn't changed
+	async fn sync_path(
+		&mut self,
+		path: &str,
+		local: &mut BTreeMap<String, LocalEntry>,
+		remote: &BTreeMap<String, RemoteEntry>,
+		remote_ids: &mut HashMap<String, NodeID>
+	) -> Result<(), String> {
+		let synced = self.state.get(path);
+		let local_changed = local_changed(local.get(path), synced);
+		let remote_changed = remote_changed(remote.get(path), synced);
+		
+		if local_changed && remote_changed && local.contains_key(path) {
+			if let Some(remote_entry) = remote.get(path) {
+				return self.merge(path, local, remote_entry).await;
+			}
+		}
+		
+		match (local.get(path), remote.get(path)) {
+			(None, None) => self.state.remove(path).map_err(state_error),
+			// otherwise the deletion on the other side failed, and is retried in the next pass
+			(Some(entry), None) if local_changed => self.upload(path, entry, None, remote_ids).await,
+			(None, Some(entry)) if remote_changed => self.download(path, entry, None).await,
+			(Some(local_entry), Some(remote_entry)) if local_changed => self.upload(path, local_entry, Some(remote_entry), remote_ids).await,
+			(Some(local_entry), Some(remote_entry)) if remote_changed => self.download(path, remote_entry, Some(local_entry)).await,
+			_ => Ok(()),
+		}
+	}
+	
+	async fn upload(&mut self, path: &str, entry: &LocalEntry, existing: Option<&RemoteEntry>, remote_ids: &mut HashMap<String, NodeID>) -> Result<(), String> {
+		let (parent, name) = split(path);
+		
+		let LocalEntry::File { size, modified, .. } = entry else {
+			if existing.is_none() {
+				let parent_id = *remote_ids.get(parent).ok_or("parent doesn't exist on the server")?;
+				let id = self.service.create_dir(parent_id, name).await.map_err(|err| format!("{err:?}"))?;
+				remote_ids.insert(path.to_owned(), id);
+				eprintln!("created {path} on the server");
+			}
+			
+			return self.state.insert(path, SyncedEntry::Directory).map_err(state_error);
+		};
+		
+		let (id, expected_hash) = match existing {
+			Some(RemoteEntry::File(id, hash)) => (*id, hash.clone()),
+			Some(RemoteEntry::Directory(_)) => return Err("is a directory on the server".to_owned()),
+			None => {
+				let parent_id = *remote_ids.get(parent).ok_or("parent doesn't exist on the server")?;
+				self.service.create_file(parent_id, name).await.map_err(|err| format!("{err:?}"))?
+			},
+		};
+		
+		// hashed again, as the content may have changed since it was scanned
+		// and the content of encrypted files depends on their id
+		let content = self.service.hash_local_file(id, &self.local_root.join(path)).await.map_err(|err| err.to_string())?;
+		
+		if content.hash != expected_hash {
+			self.service.write_local_file(id, &expected_hash, &content).await.map_err(|err| format!("{err:?}"))?;
+		}
+		eprintln!("uploaded {path}");
+		
+		self.state.insert(path, SyncedEntry::File {
+			content_hash: content.plaintext_hash().to_hex().to_string(),
+			remote_hash: content.hash,
+			size: *size,
+			modified: *modified,
+		}).map_err(state_error)?;
+		
+		Ok(())
+	}
+	
+	/// Only replaces a local file that still is as `scanned`, so changes made while the sync is running aren't lost
+	async fn download(&mut self, path: &str, entry: &RemoteEntry, scanned: Option<&LocalEntry>) -> Result<(), String> {
+		let local_path = self.local_root.join(path);
+		
+		let RemoteEntry::File(id, _) = entry else {
+			match tokio::fs::create_dir(&local_path).await {
+				Ok(()) => eprintln!("created {path} locally"),
+				Err(err) if err.kind() == io::ErrorKind::AlreadyExists && local_path.is_dir() => (),
+				Err(err) => return Err(err.to_string()),
+			}
+			
+			return self.state.insert(path, SyncedEntry::Directory).map_err(state_error);
+		};
+		
+		let download = transfer::download_partial(self.service, *id, &local_path).await?;
+		
+		// the next pass then sees the changes on both sides
+		if !self.is_unchanged_locally(path, scanned).await? {
+			download.discard().await;
+			return Err("changed locally while downloading, retrying in the next pass".to_owned());
+		}
+		
+		self.finish_download(path, download).await?;
+		eprintln!("downloaded {path}");
+		
+		Ok(())
+	}
+	
+	/// Resolves a path that changed on both sides, the version on the server keeps the path
+	async fn merge(&mut self, path: &str, local: &mut BTreeMap<String, LocalEntry>, remote_entry: &RemoteEntry) -> Result<(), String> {
+		let local_entry = local.get(path).expect("should be called for paths that exist locally");
+		
+		match (local_entry, remote_entry) {
+			(LocalEntry::Directory, RemoteEntry::Directory(_)) => {
+				return self.state.insert(path, SyncedEntry::Directory).map_err(state_error);
+			},
+			(LocalEntry::File { content_hash, size, modified }, RemoteEntry::File(id, remote_hash)) => {
+				// hashes on the server can only be compared directly if they aren't of the encrypted content
+				if !self.service.is_encrypted() && remote_hash.0 == *content_hash {
+					self.state.insert(path, SyncedEntry::File {
+						content_hash: content_hash.clone(),
+						remote_hash: remote_hash.clone(),
+						size: *size,
+						modified: *modified,
+					}).map_err(state_error)?;
+					return Ok(());
+				}
+				
+				let download = transfer::download_partial(self.service, *id, &self.local_root.join(path)).await?;
+				
+				if download.plaintext_hash.to_hex().as_str() == content_hash {
+					self.state.insert(path, SyncedEntry::File {
+						content_hash: content_hash.clone(),
+						remote_hash: download.hash.clone(),
+						size: *size,
+						modified: *modified,
+					}).map_err(state_error)?;
+					download.discard().await;
+					return Ok(());
+				}
+				
+				if let Err(err) = self.move_aside(path, local).await {
+					download.discard().await;
+					return Err(err);
+				}
+				self.finish_download(path, download).await?;
+				eprintln!("downloaded {path}");
+				
+				return Ok(());
+			},
+			_ => (),
+		}
+		
+		// different kinds of nodes on both sides
+		self.move_aside(path, local).await?;
+		self.download(path, remote_entry, None).await
+	}
+	
+	/// Moves the local version of a conflicting path to a name derived from its content, where it is synced as a new node
+	async fn move_aside(&mut self, path: &str, local: &mut BTreeMap<String, LocalEntry>) -> Result<(), String> {
+		let conflict_path = match local.get(path) {
+			Some(LocalEntry::File { content_hash, .. }) => format!("{path}.conflict-{}", &content_hash[..8]),
+			Some(LocalEntry::Directory) | None => format!("{path}.conflict"),
+		};
+		
+		tokio::fs::rename(self.local_root.join(path), self.local_root.join(&conflict_path)).await.map_err(|err| err.to_string())?;
+		eprintln!("conflict: moved local version of {path} to {conflict_path}");
+		
+		// the children moved along and are synced under their new paths in the next pass
+		let prefix = format!("{path}/");
+		local.retain(|entry_path, _| entry_path != path && !entry_path.starts_with(&prefix));
+		
+		Ok(())
+	}
+	
+	/// Whether the local node at `path` still is as `scanned`, judged by size and modification time just like the scan does
+	async fn is_unchanged_locally(&self, path: &str, scanned: Option<&LocalEntry>) -> Result<bool, String> {
+		let metadata = match tokio::fs::symlink_metadata(self.local_root.join(path)).await {
+			Ok(metadata) => Some(metadata),
+			Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+			Err(err) => return Err(err.to_string()),
+		};
+		
+		Ok(match (scanned, metadata) {
+			(None, None) => true,
+			(Some(LocalEntry::File { size, modified, .. }), Some(metadata)) => {
+				metadata.is_file() && metadata.len() == *size && metadata.modified().ok() == Some(*modified)
+			},
+			_ => false,
+		})
+	}
+	
+	/// Moves the downloaded file into place and records it as synced
+	async fn finish_download(&mut self, path: &str, download: PartialDownload) -> Result<(), String> {
+		let local_path = self.local_root.join(path);
+		let (remote_hash, plaintext_hash) = (download.hash.clone(), download.plaintext_hash);
+		
+		download.finish(&local_path).await.map_err(|err| err.to_string())?;
+		let metadata = tokio::fs::metadata(&local_path).await.map_err(|err| err.to_string())?;
+		
+		self.state.insert(path, SyncedEntry::File {
+			content_hash: plaintext_hash.to_hex().to_string(),
+			remote_hash,
+			size: metadata.len(),
+			modified: metadata.modified().map_err(|err| err.to_string())?,
+		}).map_err(state_error)?;
+		
+		Ok(())
+	}
+}
+
+fn state_error(err: DieselError) -> String {
+	format!("failed updating the sync state: {err}")
+}
+
+fn local_changed(local: Option<&LocalEntry>, synced: Option<&SyncedEntry>) -> bool {
+	match (local, synced) {
+		(None, None) | (Some(LocalEntry::Directory), Some(SyncedEntry::Directory)) => false,
+		(Some(LocalEntry::File { content_hash, .. }), Some(SyncedEntry::File { content_hash: synced_hash, .. })) => content_hash != synced_hash,
+		_ => true,
+	}
+}
+
+fn remote_changed(remote: Option<&RemoteEntry>, synced: Option<&SyncedEntry>) -> bool {
+	match (remote, synced) {
+		(None, None) | (Some(RemoteEntry::Directory(_)), Some(SyncedEntry::Directory)) => false,
+		(Some(RemoteEntry::File(_, hash)), Some(SyncedEntry::File { remote_hash, .. })) => hash != remote_hash,
+		_ => true,
+	}
+}
+
+fn is_same_kind(local: Option<&LocalEntry>, remote: Option<&RemoteEntry>) -> bool {
+	matches!(
+		(local, remote),
+		(Some(LocalEntry::Directory), Some(RemoteEntry::Directory(_))) | (Some(LocalEntry::File { .. }), Some(RemoteEntry::File(..)))
+	)
+}
+
+/// Paths are relative to the synced directory and separated by `/`, the empty path is the synced directory itself
+fn join(dir: &str, name: &str) -> String {
+	match dir {
+		"" => name.to_owned(),
+		dir => format!("{dir}/{name}"),
+	}
+}
+
+/// Returns the parent and the name of a path
+fn split(path: &str) -> (&str, &str) {
+	path.rsplit_once('/').unwrap_or(("", path))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::{hash_of, MockServer};
+	
+	fn syncer<'a>(service: &'a RemoteDataService, local_root: &Path) -> Syncer<'a> {
+		Syncer {
+			service,
+			local_root: local_root.to_owned(),
+			remote_root: NodeID::ROOT,
+			state: SyncState::open(&local_root.join(STATE_FILE)).unwrap(),
+		}
+	}
+	
+	fn hash(data: &[u8]) -> String {
+		hash_of(data).0
+	}
+	
+	/// Writes a local file and returns it as it is scanned
+	fn local_file(local_root: &Path, path: &str, data: &[u8]) -> LocalEntry {
+		let local_path = local_root.join(path);
+		std::fs::write(&local_path, data).unwrap();
+		let metadata = std::fs::metadata(&local_path).unwrap();
+		
+		LocalEntry::File {
+			content_hash: hash(data),
+			size: metadata.len(),
+			modified: metadata.modified().unwrap(),
+		}
+	}
+	
+	/// The state after syncing the local entry to an unencrypted volume, where both hashes are the same
+	fn synced(entry: &LocalEntry) -> SyncedEntry {
+		match entry {
+			LocalEntry::Directory => SyncedEntry::Directory,
+			LocalEntry::File { content_hash, size, modified } => SyncedEntry::File {
+				content_hash: content_hash.clone(),
+				remote_hash: Hash(content_hash.clone()),
+				size: *size,
+				modified: *modified,
+			},
+		}
+	}
+	
+	#[test]
+	fn detects_changes() {
+		let file = |content_hash: &str, modified: u64| LocalEntry::File {
+			content_hash: content_hash.to_owned(),
+			size: 1,
+			modified: SystemTime::UNIX_EPOCH + Duration::from_secs(modified),
+		};
+		let synced_file = synced(&file("a", 0));
+		
+		assert!(!local_changed(None, None));
+		assert!(!local_changed(Some(&LocalEntry::Directory), Some(&SyncedEntry::Directory)));
+		assert!(!local_changed(Some(&file("a", 0)), Some(&synced_file)));
+		// only the content counts, not the modification time
+		assert!(!local_changed(Some(&file("a", 1)), Some(&synced_file)));
+		assert!(local_changed(Some(&file("b", 0)), Some(&synced_file)));
+		assert!(local_changed(Some(&file("a", 0)), None));
+		assert!(local_changed(None, Some(&synced_file)));
+		assert!(local_changed(Some(&LocalEntry::Directory), Some(&synced_file)));
+		assert!(local_changed(Some(&file("a", 0)), Some(&SyncedEntry::Directory)));
+		
+		let remote_file = |remote_hash: &str| RemoteEntry::File(NodeID(2), Hash(remote_hash.to_owned()));
+		
+		assert!(!remote_changed(None, None));
+		assert!(!remote_changed(Some(&RemoteEntry::Directory(NodeID(2))), Some(&SyncedEntry::Directory)));
+		assert!(!remote_changed(Some(&remote_file("a")), Some(&synced_file)));
+		assert!(remote_changed(Some(&remote_file("b")), Some(&synced_file)));
+		assert!(remote_changed(Some(&remote_file("a")), None));
+		assert!(remote_changed(None, Some(&synced_file)));
+		assert!(remote_changed(Some(&RemoteEntry::Directory(NodeID(2))), Some(&synced_file)));
+		assert!(remote_changed(Some(&remote_file("a")), Some(&SyncedEntry::Directory)));
+	}
+	
+	#[tokio::test]
+	async fn deletes_locally() {
+		let local_root = tempfile::tempdir().unwrap();
+		let (service, mock) = MockServer::default().serve().await;
+		let mut syncer = syncer(&service, local_root.path());
+		
+		let mut local = BTreeMap::new();
+		for (path, data) in [("file", &b"unchanged"[..]), ("edited", b"before")] {
+			let entry = local_file(local_root.path(), path, data);
+			syncer.state.insert(path, synced(&entry)).unwrap();
+			local.insert(path.to_owned(), entry);
+		}
+		local.insert("edited".to_owned(), local_file(local_root.path(), "edited", b"after"));
+		
+		for path in ["dir", "dir/new"] {
+			std::fs::create_dir(local_root.path().join(path)).unwrap();
+			local.insert(path.to_owned(), LocalEntry::Directory);
+		}
+		syncer.state.insert("dir", SyncedEntry::Directory).unwrap();
+		
+		// everything was deleted on the server
+		let mut remote = BTreeMap::new();
+		let mut remote_ids = HashMap::from([(String::new(), NodeID::ROOT)]);
+		
+		for path in ["file", "edited", "dir/new", "dir"] {
+			syncer.sync_deletion(path, &mut local, &mut remote, &mut remote_ids).await.unwrap();
+		}
+		
+		assert!(!local_root.path().join("file").exists());
+		// modifications win over deletions and non-empty directories are kept
+		assert_eq!(std::fs::read(local_root.path().join("edited")).unwrap(), b"after");
+		assert!(local_root.path().join("dir/new").is_dir());
+		
+		assert_eq!(local.keys().collect::<Vec<_>>(), ["dir", "dir/new", "edited"]);
+		assert_eq!(syncer.state.paths().count(), 0);
+		assert!(mock.state().deleted.is_empty());
+	}
+	
+	#[tokio::test]
+	async fn deletes_remotely() {
+		let local_root = tempfile::tempdir().unwrap();
+		let mock = MockServer::default();
+		let (dir, file, edited) = {
+			let mut state = mock.state();
+			let dir = state.add_dir(NodeID::ROOT, "dir");
+			(dir, state.add_file(dir, "file", b"unchanged"), state.add_file(NodeID::ROOT, "edited", b"after"))
+		};
+		let (service, mock) = mock.serve().await;
+		let mut syncer = syncer(&service, local_root.path());
+		
+		let mut remote = BTreeMap::from([
+			("dir".to_owned(), RemoteEntry::Directory(dir)),
+			("dir/file".to_owned(), RemoteEntry::File(file, hash_of(b"unchanged"))),
+			("edited".to_owned(), RemoteEntry::File(edited, hash_of(b"after"))),
+		]);
+		let mut remote_ids = HashMap::from([(String::new(), NodeID::ROOT), ("dir".to_owned(), dir)]);
+		
+		syncer.state.insert("dir", SyncedEntry::Directory).unwrap();
+		for (path, data) in [("dir/file", &b"unchanged"[..]), ("edited", b"before")] {
+			syncer.state.insert(path, SyncedEntry::File {
+				content_hash: hash(data),
+				remote_hash: Hash(hash(data)),
+				size: data.len() as u64,
+				modified: SystemTime::UNIX_EPOCH,
+			}).unwrap();
+		}
+		
+		// everything was deleted locally
+		let mut local = BTreeMap::new();
+		
+		for path in ["edited", "dir/file", "dir"] {
+			syncer.sync_deletion(path, &mut local, &mut remote, &mut remote_ids).await.unwrap();
+		}
+		
+		assert_eq!(mock.state().deleted, ["file", "dir"]);
+		assert!(mock.state().files.contains_key(&edited));
+		assert_eq!(remote.keys().collect::<Vec<_>>(), ["edited"]);
+		assert_eq!(remote_ids.keys().collect::<Vec<_>>(), [""]);
+		assert_eq!(syncer.state.paths().count(), 0);
+	}
+	
+	#[tokio::test]
+	async fn merges() {
+		let local_root = tempfile::tempdir().unwrap();
+		let mock = MockServer::default();
+		let (same, different) = {
+			let mut state = mock.state();
+			(state.add_file(NodeID::ROOT, "same", b"same"), state.add_file(NodeID::ROOT, "different", b"remote"))
+		};
+		let (service, mock) = mock.serve().await;
+		let mut syncer = syncer(&service, local_root.path());
+		
+		std::fs::create_dir(local_root.path().join("dir")).unwrap();
+		let mut local = BTreeMap::from([
+			("dir".to_owned(), LocalEntry::Directory),
+			("same".to_owned(), local_file(local_root.path(), "same", b"same")),
+			("different".to_owned(), local_file(local_root.path(), "different", b"local")),
+		]);
+		
+		syncer.merge("dir", &mut local, &RemoteEntry::Directory(NodeID(2))).await.unwrap();
+		syncer.merge("same", &mut local, &RemoteEntry::File(same, hash_of(b"same"))).await.unwrap();
+		// same contents aren't downloaded
+		assert_eq!(mock.state().downloads, 0);
+		syncer.merge("different", &mut local, &RemoteEntry::File(different, hash_of(b"remote"))).await.unwrap();
+		
+		assert_eq!(syncer.state.get("dir"), Some(&SyncedEntry::Directory));
+		assert_eq!(syncer.state.get("same"), local.get("same").map(synced).as_ref());
+		
+		// the server keeps the path and the local version is moved aside
+		let conflict_path = format!("different.conflict-{}", &hash(b"local")[..8]);
+		assert_eq!(std::fs::read(local_root.path().join("different")).unwrap(), b"remote");
+		assert_eq!(std::fs::read(local_root.path().join(conflict_path)).unwrap(), b"local");
+		assert!(!local.contains_key("different"));
+		assert!(matches!(
+			syncer.state.get("different"),
+			Some(SyncedEntry::File { content_hash, .. }) if *content_hash == hash(b"remote")
+		));
+	}
+	
+	#[tokio::test]
+	async fn keeps_local_changes_while_downloading() {
+		let local_root = tempfile::tempdir().unwrap();
+		let mock = MockServer::default();
+		let (unchanged, edited) = {
+			let mut state = mock.state();
+			let unchanged = state.add_file(NodeID::ROOT, "unchanged", b"remote");
+			let edited = state.add_file(NodeID::ROOT, "edited", b"remote");
+			state.edits_on_download.insert(edited, (local_root.path().join("edited"), b"edited locally"));
+			(unchanged, edited)
+		};
+		let (service, _mock) = mock.serve().await;
+		let mut syncer = syncer(&service, local_root.path());
+		
+		let mut local = BTreeMap::new();
+		for path in ["unchanged", "edited"] {
+			let entry = local_file(local_root.path(), path, b"local");
+			syncer.state.insert(path, synced(&entry)).unwrap();
+			local.insert(path.to_owned(), entry);
+		}
+		
+		let remote = BTreeMap::from([
+			("unchanged".to_owned(), RemoteEntry::File(unchanged, hash_of(b"remote"))),
+			("edited".to_owned(), RemoteEntry::File(edited, hash_of(b"remote"))),
+		]);
+		let mut remote_ids = HashMap::from([(String::new(), NodeID::ROOT)]);
+		
+		syncer.sync_path("unchanged", &mut local, &remote, &mut remote_ids).await.unwrap();
+		assert_eq!(std::fs::read(local_root.path().join("unchanged")).unwrap(), b"remote");
+		
+		syncer.sync_path("edited", &mut local, &remote, &mut remote_ids).await.unwrap_err();
+		assert_eq!(std::fs::read(local_root.path().join("edited")).unwrap(), b"edited locally");
+		// still the state before, so the next pass sees changes on both sides
+		assert_eq!(syncer.state.get("edited"), local.get("edited").map(synced).as_ref());
+		assert!(!local_root.path().join(format!(".edited{PARTIAL_SUFFIX}")).exists());
+	}
+	
+	#[tokio::test]
+	async fn syncs_large_files() {
+		// spans several chunks, which are read and written one at a time
+		let data: Vec<u8> = (0..200_000).map(|i| (i % 251) as u8).collect();
+		let local_root = tempfile::tempdir().unwrap();
+		let other_root = tempfile::tempdir().unwrap();
+		let (service, mock) = MockServer::default().serve().await;
+		
+		std::fs::write(local_root.path().join("large"), &data).unwrap();
+		let mut uploader = syncer(&service, local_root.path());
+		uploader.sync().await;
+		assert!(mock.state().files.values().any(|file| *file == data));
+		
+		let mut downloader = syncer(&service, other_root.path());
+		downloader.sync().await;
+		assert_eq!(std::fs::read(other_root.path().join("large")).unwrap(), data);
+		assert!(matches!(
+			downloader.state.get("large"),
+			Some(SyncedEntry::File { content_hash, remote_hash, .. }) if *content_hash == hash(&data) && *remote_hash == hash_of(&data)
+		));
+		
+		// nothing is left next to the downloaded file
+		let mut names: Vec<_> = std::fs::read_dir(other_root.path()).unwrap()
+			.map(|entry| entry.unwrap().file_name().into_string().unwrap())
+			.filter(|name| !name.starts_with(STATE_FILE))
+			.collect();
+		names.sort();
+		assert_eq!(names, ["large"]);
+	}
+}
@@ -0,0 +1,164 @@
+use std::{collections::BTreeMap, io, path::Path, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use diesel::{prelude::*, result::Error as DieselError, sqlite::{Sqlite, SqliteConnection}};
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use fye_shared::Hash;
+
+diesel::table! {
+	synced_entries (path) {
+		path -> Text,
+		content_hash -> Nullable<Text>,
+		remote_hash -> Nullable<Text>,
+		size -> Nullable<BigInt>,
+		modified -> Nullable<BigInt>,
+	}
+}
+
+#[derive(Queryable, Selectable, Insertable, Debug)]
+#[diesel(table_name = synced_entries)]
+#[diesel(check_for_backend(Sqlite))]
+struct SyncedEntryRow {
+	path: String,
+	content_hash: Option<String>,
+	remote_hash: Option<String>,
+	size: Option<i64>,
+	/// Nanoseconds since the unix epoch
+	modified: Option<i64>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum SyncedEntry {
+	Directory,
+	File {
+		/// Hash of the local content, which differs from the hash on the server for encrypted volumes
+		content_hash: String,
+		remote_hash: Hash,
+		/// Size and modification time of the local file, files with the same ones aren't hashed again
+		size: u64,
+		modified: SystemTime,
+	},
+}
+
+impl SyncedEntryRow {
+	fn new(path: &str, entry: &SyncedEntry) -> Self {
+		match entry {
+			SyncedEntry::Directory => Self {
+				path: path.to_owned(),
+				content_hash: None,
+				remote_hash: None,
+				size: None,
+				modified: None,
+			},
+			SyncedEntry::File { content_hash, remote_hash, size, modified } => Self {
+				path: path.to_owned(),
+				content_hash: Some(content_hash.clone()),
+				remote_hash: Some(remote_hash.0.clone()),
+				size: Some(*size as i64),
+				// times before the epoch only cause the file to be hashed again
+				modified: Some(modified.duration_since(UNIX_EPOCH).map_or(-1, |since_epoch| since_epoch.as_nanos() as i64)),
+			},
+		}
+	}
+	
+	fn into_entry(self) -> (String, SyncedEntry) {
+		let entry = match (self.content_hash, self.remote_hash, self.size, self.modified) {
+			(Some(content_hash), Some(remote_hash), Some(size), Some(modified)) => SyncedEntry::File {
+				content_hash,
+				remote_hash: Hash(remote_hash),
+				size: size as u64,
+				modified: UNIX_EPOCH + Duration::from_nanos(modified.max(0) as u64),
+			},
+			_ => SyncedEntry::Directory,
+		};
+		
+		(self.path, entry)
+	}
+}
+
+/// What each path looked like on both sides after it was last synced, used to tell which side changed it
+/// 
+/// All entries are kept in memory, but every change is written to the database right away,
+/// so an interrupted sync doesn't mistake the paths it already synced for conflicts.
+pub struct SyncState {
+	conn: SqliteConnection,
+	entries: BTreeMap<String, SyncedEntry>,
+}
+
+impl SyncState {
+	/// Opens the database at `path`, creating it if it doesn't exist yet
+	pub fn open(path: &Path) -> Result<Self, io::Error> {
+		const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+		
+		let url = path.to_str().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path is not valid UTF-8"))?;
+		let mut conn = SqliteConnection::establish(url).map_err(io::Error::other)?;
+		conn.run_pending_migrations(MIGRATIONS).map_err(io::Error::other)?;
+		
+		let entries = synced_entries::table
+			.select(SyncedEntryRow::as_select())
+			.load(&mut conn).map_err(io::Error::other)?
+			.into_iter()
+			.map(SyncedEntryRow::into_entry)
+			.collect();
+		
+		Ok(Self {
+			conn,
+			entries,
+		})
+	}
+	
+	pub fn get(&self, path: &str) -> Option<&SyncedEntry> {
+		self.entries.get(path)
+	}
+	
+	pub fn paths(&self) -> impl Iterator<Item = &String> {
+		self.entries.keys()
+	}
+	
+	pub fn insert(&mut self, path: &str, entry: SyncedEntry) -> Result<(), DieselError> {
+		diesel::replace_into(synced_entries::table)
+			.values(SyncedEntryRow::new(path, &entry))
+			.execute(&mut self.conn)?;
+		
+		self.entries.insert(path.to_owned(), entry);
+		Ok(())
+	}
+	
+	pub fn remove(&mut self, path: &str) -> Result<(), DieselError> {
+		if self.entries.remove(path).is_some() {
+			diesel::delete(synced_entries::table.find(path))
+				.execute(&mut self.conn)?;
+		}
+		
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	#[test]
+	fn persists() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("state");
+		
+		let file = SyncedEntry::File {
+			content_hash: "content".to_owned(),
+			remote_hash: Hash("remote".to_owned()),
+			size: 5,
+			modified: UNIX_EPOCH + Duration::from_nanos(1_234_567_891),
+		};
+		
+		let mut state = SyncState::open(&path).unwrap();
+		state.insert("dir", SyncedEntry::Directory).unwrap();
+		state.insert("dir/file", file.clone()).unwrap();
+		state.insert("gone", SyncedEntry::Directory).unwrap();
+		state.remove("gone").unwrap();
+		drop(state);
+		
+		let state = SyncState::open(&path).unwrap();
+		assert_eq!(state.paths().collect::<Vec<_>>(), ["dir", "dir/file"]);
+		assert_eq!(state.get("dir"), Some(&SyncedEntry::Directory));
+		assert_eq!(state.get("dir/file"), Some(&file));
+	}
+}
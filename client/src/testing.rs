@@ -0,0 +1,320 @@
+#![cfg(test)]
+
+use std::{collections::{BTreeMap, HashMap}, path::PathBuf, sync::{Arc, Mutex, MutexGuard}};
+
+use axum::{body::Bytes, extract::{Path, Query, State}, http::{header, HeaderMap, StatusCode}, response::{IntoResponse, Response}, routing::{get, post, put}, Router};
+use fye_shared::{DirectoryInfo, DirectoryListing, EntryAttributes, EntryInfo, FileInfo, FileLock, Hash, NodeID, NodeInfo, ResolvedNode, CONTENT_HASH_HEADER, CONTENT_SIZE_HEADER};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::remote_data_service::{RemoteDataService, TlsOptions};
+
+/// In-memory stand-in for the server, implementing just enough of its API for the tests of the client
+/// 
+/// Names and contents are stored as they are sent, so they are encrypted for encrypted volumes.
+#[derive(Default)]
+pub struct MockServer {
+	state: Mutex<MockState>,
+}
+
+pub struct MockState {
+	last_id: u64,
+	/// Parent and children of every directory
+	pub dirs: HashMap<NodeID, (NodeID, BTreeMap<String, NodeID>)>,
+	pub files: HashMap<NodeID, Vec<u8>>,
+	volume_key: Option<Vec<u8>>,
+	/// Names of all deleted nodes, in the order they were deleted
+	pub deleted: Vec<String>,
+	/// Number of contents that were actually sent, not just by reference
+	pub uploads: usize,
+	pub downloads: usize,
+	/// Local files that are changed whenever the file with the id is downloaded, like someone editing them at the same time
+	pub edits_on_download: HashMap<NodeID, (PathBuf, &'static [u8])>,
+}
+
+impl Default for MockState {
+	fn default() -> Self {
+		Self {
+			last_id: NodeID::ROOT.0,
+			dirs: HashMap::from([(NodeID::ROOT, (NodeID::ROOT, BTreeMap::new()))]),
+			files: HashMap::new(),
+			volume_key: None,
+			deleted: Vec::new(),
+			uploads: 0,
+			downloads: 0,
+			edits_on_download: HashMap::new(),
+		}
+	}
+}
+
+impl MockState {
+	pub fn add_dir(&mut self, parent: NodeID, name: &str) -> NodeID {
+		self.last_id += 1;
+		let id = NodeID(self.last_id);
+		
+		self.dirs.insert(id, (parent, BTreeMap::new()));
+		self.dirs.get_mut(&parent).expect("parent should exist").1.insert(name.to_owned(), id);
+		id
+	}
+	
+	pub fn add_file(&mut self, parent: NodeID, name: &str, data: &[u8]) -> NodeID {
+		self.last_id += 1;
+		let id = NodeID(self.last_id);
+		
+		self.files.insert(id, data.to_owned());
+		self.dirs.get_mut(&parent).expect("parent should exist").1.insert(name.to_owned(), id);
+		id
+	}
+	
+	fn file_info(&self, id: NodeID) -> FileInfo {
+		let data = &self.files[&id];
+		
+		FileInfo {
+			size: data.len() as u64,
+			hash: hash_of(data),
+		}
+	}
+	
+	fn node_info(&self, id: NodeID) -> NodeInfo {
+		match self.dirs.get(&id) {
+			Some((parent, children)) => NodeInfo::Directory(DirectoryInfo {
+				parent: *parent,
+				children: children.clone(),
+			}),
+			None => NodeInfo::File(self.file_info(id)),
+		}
+	}
+}
+
+pub fn hash_of(data: &[u8]) -> Hash {
+	Hash(blake3::hash(data).to_hex().to_string())
+}
+
+impl MockServer {
+	pub fn state(&self) -> MutexGuard<'_, MockState> {
+		self.state.lock().unwrap()
+	}
+	
+	/// Starts serving on a random local port, returning a client for it
+	pub async fn serve(self) -> (RemoteDataService, Arc<Self>) {
+		let mock = Arc::new(self);
+		let router = Router::new()
+			.route("/dir/:id", get(dir_info))
+			.route("/dir/:id/listing", get(dir_listing))
+			.route("/dir/:id/new-dir", post(new_dir))
+			.route("/dir/:id/new-file", post(new_file))
+			.route("/dir/:id/delete-dir", post(delete_dir))
+			.route("/dir/:id/delete-file", post(delete_file))
+			.route("/file/:id/data", get(file_data).put(write_file_data))
+			.route("/file/:id/lock", post(lock_file))
+			.route("/file/:id/lock/test", post(test_lock))
+			.route("/lock-lease/:client", put(renew_lock_lease))
+			.route("/resolve", get(resolve))
+			.route("/volume-key", get(volume_key).post(new_volume_key))
+			.with_state(mock.clone());
+		
+		let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+		tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+		
+		(RemoteDataService::with_tls(url, TlsOptions::default()).unwrap(), mock)
+	}
+}
+
+type Mock = State<Arc<MockServer>>;
+
+fn postcard(value: &impl Serialize) -> Response {
+	([(header::CONTENT_TYPE, "application/postcard")], postcard::to_stdvec(value).unwrap()).into_response()
+}
+
+fn conflict(reason: &'static str) -> Response {
+	(StatusCode::CONFLICT, reason).into_response()
+}
+
+async fn dir_info(State(mock): Mock, Path(id): Path<u64>) -> Response {
+	match mock.state().node_info(NodeID(id)) {
+		NodeInfo::Directory(dir_info) => ([(header::ETAG, "\"v\"")], postcard(&dir_info)).into_response(),
+		NodeInfo::File(_) => conflict("Not A Directory"),
+	}
+}
+
+async fn dir_listing(State(mock): Mock, Path(id): Path<u64>) -> Response {
+	let state = mock.state();
+	let Some((parent, children)) = state.dirs.get(&NodeID(id)) else {
+		return StatusCode::NOT_FOUND.into_response();
+	};
+	
+	let children = children.iter()
+		.map(|(name, &id)| {
+			let attributes = match state.dirs.contains_key(&id) {
+				true => EntryAttributes::Directory,
+				false => EntryAttributes::File(state.file_info(id)),
+			};
+			
+			(name.clone(), EntryInfo { id, attributes })
+		})
+		.collect();
+	
+	postcard(&DirectoryListing {
+		parent: *parent,
+		children,
+		continuation: None,
+	})
+}
+
+fn created(id: NodeID, kind: &str) -> [(header::HeaderName, String); 1] {
+	[(header::LOCATION, format!("/{kind}/{id}"))]
+}
+
+async fn new_dir(State(mock): Mock, Path(parent): Path<u64>, body: Bytes) -> Response {
+	let name: String = postcard::from_bytes(&body).unwrap();
+	let mut state = mock.state();
+	
+	if state.dirs[&NodeID(parent)].1.contains_key(&name) {
+		return conflict("Already Exists");
+	}
+	
+	let id = state.add_dir(NodeID(parent), &name);
+	(StatusCode::CREATED, created(id, "dir")).into_response()
+}
+
+async fn new_file(State(mock): Mock, Path(parent): Path<u64>, body: Bytes) -> Response {
+	let name: String = postcard::from_bytes(&body).unwrap();
+	let mut state = mock.state();
+	
+	if state.dirs[&NodeID(parent)].1.contains_key(&name) {
+		return conflict("Already Exists");
+	}
+	
+	let id = state.add_file(NodeID(parent), &name, b"");
+	(StatusCode::CREATED, created(id, "file"), [(header::ETAG, hash_of(b"").to_header())]).into_response()
+}
+
+async fn delete_dir(State(mock): Mock, Path(parent): Path<u64>, body: Bytes) -> Response {
+	let name: String = postcard::from_bytes(&body).unwrap();
+	let mut state = mock.state();
+	
+	let Some(&id) = state.dirs[&NodeID(parent)].1.get(&name) else {
+		return StatusCode::NOT_FOUND.into_response();
+	};
+	
+	if !state.dirs[&id].1.is_empty() {
+		return conflict("Directory Not Empty");
+	}
+	
+	state.dirs.remove(&id);
+	state.dirs.get_mut(&NodeID(parent)).unwrap().1.remove(&name);
+	state.deleted.push(name);
+	StatusCode::NO_CONTENT.into_response()
+}
+
+async fn delete_file(State(mock): Mock, Path(parent): Path<u64>, body: Bytes) -> Response {
+	let name: String = postcard::from_bytes(&body).unwrap();
+	let mut state = mock.state();
+	
+	let Some(id) = state.dirs.get_mut(&NodeID(parent)).unwrap().1.remove(&name) else {
+		return StatusCode::NOT_FOUND.into_response();
+	};
+	
+	state.files.remove(&id);
+	state.deleted.push(name);
+	StatusCode::NO_CONTENT.into_response()
+}
+
+async fn file_data(State(mock): Mock, Path(id): Path<u64>) -> Response {
+	let mut state = mock.state();
+	
+	if let Some((path, data)) = state.edits_on_download.get(&NodeID(id)) {
+		std::fs::write(path, data).unwrap();
+	}
+	
+	let Some(data) = state.files.get(&NodeID(id)).cloned() else {
+		return StatusCode::NOT_FOUND.into_response();
+	};
+	
+	state.downloads += 1;
+	([(header::ETAG, hash_of(&data).to_header())], data).into_response()
+}
+
+async fn write_file_data(State(mock): Mock, Path(id): Path<u64>, headers: HeaderMap, body: Bytes) -> Response {
+	let mut state = mock.state();
+	let Some(current) = state.files.get(&NodeID(id)) else {
+		return StatusCode::NOT_FOUND.into_response();
+	};
+	
+	if Hash::from_header(&headers[header::IF_MATCH]).as_ref() != Some(&hash_of(current)) {
+		return StatusCode::PRECONDITION_FAILED.into_response();
+	}
+	
+	let hash = Hash::from_header(&headers[CONTENT_HASH_HEADER]).unwrap();
+	let size: u64 = headers[CONTENT_SIZE_HEADER].to_str().unwrap().parse().unwrap();
+	
+	let data = match state.files.values().find(|data| hash_of(data) == hash) {
+		// uploaded by reference
+		Some(data) if body.is_empty() => data.clone(),
+		_ if hash_of(&body) != hash || body.len() as u64 != size => return StatusCode::UNPROCESSABLE_ENTITY.into_response(),
+		_ => {
+			state.uploads += 1;
+			body.to_vec()
+		},
+	};
+	
+	state.files.insert(NodeID(id), data);
+	StatusCode::NO_CONTENT.into_response()
+}
+
+/// Locks always succeed, without being kept
+async fn lock_file() -> StatusCode {
+	StatusCode::NO_CONTENT
+}
+
+async fn test_lock() -> Response {
+	postcard(&None::<FileLock>)
+}
+
+/// Leases aren't kept either, so they are always expired
+async fn renew_lock_lease() -> StatusCode {
+	StatusCode::NOT_FOUND
+}
+
+#[derive(Deserialize)]
+struct ResolveQuery {
+	path: String,
+}
+
+async fn resolve(State(mock): Mock, Query(ResolveQuery { path }): Query<ResolveQuery>) -> Response {
+	let state = mock.state();
+	let mut ids = vec![NodeID::ROOT];
+	
+	for name in path.split('/') {
+		let id = *ids.last().unwrap();
+		
+		match name {
+			"" | "." => (),
+			".." => if ids.len() > 1 {
+				ids.pop();
+			},
+			name => match state.dirs.get(&id).and_then(|(_, children)| children.get(name)) {
+				Some(&child) => ids.push(child),
+				None => return StatusCode::NOT_FOUND.into_response(),
+			},
+		}
+	}
+	
+	let nodes: Vec<_> = ids.into_iter()
+		.map(|id| ResolvedNode { id, info: state.node_info(id) })
+		.collect();
+	postcard(&nodes)
+}
+
+async fn volume_key(State(mock): Mock) -> Response {
+	match &mock.state().volume_key {
+		Some(wrapped_key) => postcard(wrapped_key),
+		None => StatusCode::NOT_FOUND.into_response(),
+	}
+}
+
+async fn new_volume_key(State(mock): Mock, body: Bytes) -> Response {
+	mock.state().volume_key = Some(postcard::from_bytes(&body).unwrap());
+	StatusCode::CREATED.into_response()
+}
@@ -0,0 +1,448 @@
+use std::{collections::BTreeMap, future::Future, io, path::{Path, PathBuf}};
+
+use futures_util::{stream, StreamExt as _, TryStreamExt as _};
+use fye_shared::{EntryAttributes, EntryInfo, Hash, NodeID, NodeInfo};
+use tokio::io::AsyncWriteExt as _;
+
+use crate::remote_data_service::{FetchDirectoryError, RemoteDataService};
+
+/// Number of files that are uploaded or downloaded at the same time
+const PARALLEL_TRANSFERS: usize = 8;
+/// Files being written are named after their destination with this suffix
+pub const PARTIAL_SUFFIX: &str = ".fye-partial";
+
+#[derive(Default, Debug)]
+pub struct TransferReport {
+	pub transferred: usize,
+	/// Files that already had the same content at the destination
+	pub skipped: usize,
+	/// Local paths that couldn't be transferred, along with the reason
+	pub failed: Vec<(PathBuf, String)>,
+}
+
+enum Outcome {
+	Transferred,
+	Skipped,
+}
+
+struct Upload {
+	local: PathBuf,
+	target: UploadTarget,
+}
+
+enum UploadTarget {
+	/// Id and hash of the file on the server
+	Existing(NodeID, Hash),
+	/// Parent directory and name of a file that doesn't exist on the server yet
+	New(NodeID, String),
+}
+
+struct Download {
+	local: PathBuf,
+	id: NodeID,
+	hash: Hash,
+}
+
+/// Copies everything inside `local` into the directory at `remote_path`, which is created if it doesn't exist yet
+/// 
+/// Files that already exist on the server are overwritten unless they have the same content,
+/// files that only exist on the server are left alone.
+pub async fn push(service: &RemoteDataService, local: &Path, remote_path: &str) -> Result<TransferReport, io::Error> {
+	let root = create_remote_dirs(service, remote_path).await?;
+	
+	let mut report = TransferReport::default();
+	let mut uploads = Vec::new();
+	let mut pending = vec![(local.to_owned(), root)];
+	
+	// directories are created while walking the tree, so the files can be uploaded in any order afterwards
+	while let Some((dir, id)) = pending.pop() {
+		if let Err(err) = walk_local_dir(service, &dir, id, &mut pending, &mut uploads, &mut report.failed).await {
+			report.failed.push((dir, err));
+		}
+	}
+	
+	let uploads = uploads.into_iter()
+		.map(|upload| async move {
+			let result = upload_file(service, upload.target, &upload.local).await;
+			(upload.local, result)
+		})
+		.collect();
+	run_transfers(uploads, &mut report).await;
+	
+	Ok(report)
+}
+
+/// Copies everything inside the directory at `remote_path` into `local`, which is created if it doesn't exist yet
+/// 
+/// Local files are overwritten unless they have the same content, files that only exist locally are left alone.
+pub async fn pull(service: &RemoteDataService, remote_path: &str, local: &Path) -> Result<TransferReport, io::Error> {
+	let root = match service.resolve_path(remote_path).await.map_err(crate::resolve_error)?.pop() {
+		Some(node) if matches!(node.info, NodeInfo::Directory(_)) => node.id,
+		_ => return Err(io::Error::new(io::ErrorKind::NotADirectory, "not a directory")),
+	};
+	
+	tokio::fs::create_dir_all(local).await?;
+	
+	let mut report = TransferReport::default();
+	let mut downloads = Vec::new();
+	let mut pending = vec![(root, local.to_owned())];
+	
+	while let Some((id, dir)) = pending.pop() {
+		if let Err(err) = walk_remote_dir(service, id, &dir, &mut pending, &mut downloads, &mut report.failed).await {
+			report.failed.push((dir, err));
+		}
+	}
+	
+	let downloads = downloads.into_iter()
+		.map(|download| async move {
+			let result = download_file(service, &download).await;
+			(download.local, result)
+		})
+		.collect();
+	run_transfers(downloads, &mut report).await;
+	
+	Ok(report)
+}
+
+/// Runs the transfers in parallel, printing the progress after each one
+async fn run_transfers<F>(transfers: Vec<F>, report: &mut TransferReport)
+where
+	F: Future<Output = (PathBuf, Result<Outcome, String>)>,
+{
+	let total = transfers.len();
+	let mut results = stream::iter(transfers)
+		.buffer_unordered(PARALLEL_TRANSFERS)
+		.enumerate();
+	
+	while let Some((index, (path, result))) = results.next().await {
+		let status = match result {
+			Ok(Outcome::Transferred) => {
+				report.transferred += 1;
+				"copied"
+			},
+			Ok(Outcome::Skipped) => {
+				report.skipped += 1;
+				"unchanged"
+			},
+			Err(err) => {
+				report.failed.push((path.clone(), err));
+				"failed"
+			},
+		};
+		
+		eprintln!("[{}/{total}] {status} {}", index + 1, path.display());
+	}
+}
+
+/// Returns the directory at `path`, creating it and any missing parents
+pub async fn create_remote_dirs(service: &RemoteDataService, path: &str) -> Result<NodeID, io::Error> {
+	let mut id = NodeID::ROOT;
+	
+	for name in path.split('/').filter(|name| !name.is_empty() && *name != ".") {
+		let dir_info = service.fetch_dir_info(id).await.map_err(remote_error)?;
+		
+		id = match dir_info.children.get(name) {
+			Some(child) => *child,
+			None => service.create_dir(id, name).await.map_err(remote_error)?,
+		};
+	}
+	
+	// fails if the last component is a file
+	service.fetch_dir_info(id).await.map_err(remote_error)?;
+	
+	Ok(id)
+}
+
+/// Creates the subdirectories of a local directory on the server and queues its files for uploading
+async fn walk_local_dir(
+	service: &RemoteDataService,
+	dir: &Path,
+	id: NodeID,
+	pending: &mut Vec<(PathBuf, NodeID)>,
+	uploads: &mut Vec<Upload>,
+	failed: &mut Vec<(PathBuf, String)>
+) -> Result<(), String> {
+	let mut remote_children = fetch_children(service, id).await.map_err(|err| format!("{err:?}"))?;
+	let mut entries = tokio::fs::read_dir(dir).await.map_err(|err| err.to_string())?;
+	
+	while let Some(entry) = entries.next_entry().await.map_err(|err| err.to_string())? {
+		let path = entry.path();
+		
+		let Some(name) = entry.file_name().to_str().map(str::to_owned) else {
+			failed.push((path, "name is not valid UTF-8".to_owned()));
+			continue;
+		};
+		
+		let file_type = match entry.file_type().await {
+			Ok(file_type) => file_type,
+			Err(err) => {
+				failed.push((path, err.to_string()));
+				continue;
+			},
+		};
+		
+		match remote_children.remove(&name) {
+			_ if !file_type.is_dir() && !file_type.is_file() => failed.push((path, "only directories and regular files are supported".to_owned())),
+			Some(EntryInfo { id: child_id, attributes: EntryAttributes::Directory }) if file_type.is_dir() => pending.push((path, child_id)),
+			Some(EntryInfo { id: child_id, attributes: EntryAttributes::File(file_info) }) if file_type.is_file() => uploads.push(Upload {
+				local: path,
+				target: UploadTarget::Existing(child_id, file_info.hash),
+			}),
+			Some(_) => failed.push((path, "exists as a different type on the server".to_owned())),
+			None if file_type.is_dir() => match service.create_dir(id, &name).await {
+				Ok(child_id) => pending.push((path, child_id)),
+				Err(err) => failed.push((path, format!("{err:?}"))),
+			},
+			None => uploads.push(Upload {
+				local: path,
+				target: UploadTarget::New(id, name),
+			}),
+		}
+	}
+	
+	Ok(())
+}
+
+/// Creates the subdirectories of a directory on the server locally and queues its files for downloading
+async fn walk_remote_dir(
+	service: &RemoteDataService,
+	id: NodeID,
+	dir: &Path,
+	pending: &mut Vec<(NodeID, PathBuf)>,
+	downloads: &mut Vec<Download>,
+	failed: &mut Vec<(PathBuf, String)>
+) -> Result<(), String> {
+	let children = fetch_children(service, id).await.map_err(|err| format!("{err:?}"))?;
+	
+	for (name, entry) in children {
+		let path = dir.join(&name);
+		
+		// the server accepts any name, which may not be usable as a local file name
+		if name.contains('/') || name == "." || name == ".." {
+			failed.push((path, "name is not valid locally".to_owned()));
+			continue;
+		}
+		
+		match entry.attributes {
+			EntryAttributes::Directory => match tokio::fs::create_dir(&path).await {
+				Ok(()) => pending.push((entry.id, path)),
+				Err(err) if err.kind() == io::ErrorKind::AlreadyExists && path.is_dir() => pending.push((entry.id, path)),
+				Err(err) => failed.push((path, err.to_string())),
+			},
+			EntryAttributes::File(file_info) => downloads.push(Download {
+				local: path,
+				id: entry.id,
+				hash: file_info.hash,
+			}),
+		}
+	}
+	
+	Ok(())
+}
+
+/// Fetches every page of the listing
+pub async fn fetch_children(service: &RemoteDataService, id: NodeID) -> Result<BTreeMap<String, EntryInfo>, FetchDirectoryError> {
+	let mut listing = service.fetch_dir_listing(id, None).await?;
+	let mut children = std::mem::take(&mut listing.children);
+	
+	while let Some(after) = listing.continuation.take() {
+		listing = service.fetch_dir_listing(id, Some(&after)).await?;
+		children.append(&mut listing.children);
+	}
+	
+	Ok(children)
+}
+
+/// Files are read and written chunk by chunk, so they don't have to fit into memory
+async fn upload_file(service: &RemoteDataService, target: UploadTarget, local: &Path) -> Result<Outcome, String> {
+	let (id, hash, is_new) = match target {
+		UploadTarget::Existing(id, hash) => (id, hash, false),
+		UploadTarget::New(parent_id, name) => {
+			let (id, hash) = service.create_file(parent_id, &name).await.map_err(|err| format!("{err:?}"))?;
+			(id, hash, true)
+		},
+	};
+	
+	// the content of encrypted files depends on their id, so new files can only be hashed once they were created
+	let content = service.hash_local_file(id, local).await.map_err(|err| err.to_string())?;
+	
+	if content.hash == hash {
+		// new files are only created with the same content if they are empty
+		return Ok(if is_new { Outcome::Transferred } else { Outcome::Skipped });
+	}
+	
+	service.write_local_file(id, &hash, &content).await.map_err(|err| format!("{err:?}"))?;
+	
+	Ok(Outcome::Transferred)
+}
+
+async fn download_file(service: &RemoteDataService, download: &Download) -> Result<Outcome, String> {
+	match service.hash_local_file(download.id, &download.local).await {
+		Ok(content) if content.hash == download.hash => return Ok(Outcome::Skipped),
+		Ok(_) => (),
+		Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+		Err(err) => return Err(err.to_string()),
+	}
+	
+	let partial = download_partial(service, download.id, &download.local).await?;
+	partial.finish(&download.local).await.map_err(|err| err.to_string())?;
+	
+	Ok(Outcome::Transferred)
+}
+
+/// Content of a file that was downloaded next to its destination, so an interrupted download doesn't leave a truncated file behind
+#[derive(Debug)]
+pub struct PartialDownload {
+	/// Hash of the content on the server
+	pub hash: Hash,
+	/// Hash of the downloaded file, which differs from [`Self::hash`] for encrypted volumes
+	pub plaintext_hash: blake3::Hash,
+	path: PathBuf,
+}
+
+impl PartialDownload {
+	/// Replaces the destination with the downloaded file
+	pub async fn finish(self, destination: &Path) -> Result<(), io::Error> {
+		tokio::fs::rename(&self.path, destination).await
+	}
+	
+	pub async fn discard(self) {
+		let _ = tokio::fs::remove_file(&self.path).await;
+	}
+}
+
+/// Streams the content of the file into a hidden file next to `destination`, which is removed again if that fails
+pub async fn download_partial(service: &RemoteDataService, id: NodeID, destination: &Path) -> Result<PartialDownload, String> {
+	let (hash, data) = service.stream_file_data(id).await.map_err(|err| format!("{err:?}"))?;
+	let partial = partial_path(destination);
+	
+	let result = async {
+		let mut data = std::pin::pin!(data);
+		let mut file = tokio::fs::File::create(&partial).await.map_err(|err| err.to_string())?;
+		let mut hasher = blake3::Hasher::new();
+		
+		while let Some(chunk) = data.try_next().await.map_err(|err| format!("{err:?}"))? {
+			hasher.update(&chunk);
+			file.write_all(&chunk).await.map_err(|err| err.to_string())?;
+		}
+		
+		file.flush().await.map_err(|err| err.to_string())?;
+		Ok(hasher.finalize())
+	}.await;
+	
+	match result {
+		Ok(plaintext_hash) => Ok(PartialDownload {
+			hash,
+			plaintext_hash,
+			path: partial,
+		}),
+		Err(err) => {
+			let _ = tokio::fs::remove_file(&partial).await;
+			Err(err)
+		},
+	}
+}
+
+/// Hidden file next to the destination that is written first
+fn partial_path(path: &Path) -> PathBuf {
+	let file_name = path.file_name().expect("should have a file name").to_string_lossy();
+	path.with_file_name(format!(".{file_name}{PARTIAL_SUFFIX}"))
+}
+
+fn remote_error(err: impl std::fmt::Debug) -> io::Error {
+	io::Error::other(format!("{err:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::MockServer;
+	
+	/// Spans several chunks of encrypted content, with a partial one at the end
+	const LARGE_SIZE: usize = 200_000;
+	
+	fn large_data() -> Vec<u8> {
+		(0..LARGE_SIZE).map(|i| (i % 251) as u8).collect()
+	}
+	
+	async fn write_tree(root: &Path) {
+		tokio::fs::create_dir_all(root.join("dir/nested")).await.unwrap();
+		tokio::fs::write(root.join("small"), b"small").await.unwrap();
+		tokio::fs::write(root.join("empty"), b"").await.unwrap();
+		tokio::fs::write(root.join("dir/nested/large"), large_data()).await.unwrap();
+	}
+	
+	async fn assert_same_tree(expected: &Path, actual: &Path) {
+		for path in ["small", "empty", "dir/nested/large"] {
+			assert_eq!(tokio::fs::read(expected.join(path)).await.unwrap(), tokio::fs::read(actual.join(path)).await.unwrap(), "{path}");
+		}
+	}
+	
+	#[tokio::test]
+	async fn round_trip() {
+		let source = tempfile::tempdir().unwrap();
+		let target = tempfile::tempdir().unwrap();
+		write_tree(source.path()).await;
+		let (service, mock) = MockServer::default().serve().await;
+		
+		let report = push(&service, source.path(), "/backup").await.unwrap();
+		assert_eq!((report.transferred, report.skipped, report.failed.len()), (3, 0, 0));
+		assert_eq!(mock.state().uploads, 2); // the empty file is created empty
+		
+		let report = pull(&service, "/backup", target.path()).await.unwrap();
+		assert_eq!((report.transferred, report.skipped, report.failed.len()), (3, 0, 0));
+		assert_same_tree(source.path(), target.path()).await;
+		
+		let large = mock.state().files.values().find(|data| data.len() == LARGE_SIZE).cloned();
+		assert_eq!(large, Some(large_data()));
+	}
+	
+	#[tokio::test]
+	async fn skips_unchanged() {
+		let source = tempfile::tempdir().unwrap();
+		write_tree(source.path()).await;
+		let (service, mock) = MockServer::default().serve().await;
+		
+		push(&service, source.path(), "/").await.unwrap();
+		let uploads = mock.state().uploads;
+		
+		tokio::fs::write(source.path().join("small"), b"changed").await.unwrap();
+		let report = push(&service, source.path(), "/").await.unwrap();
+		assert_eq!((report.transferred, report.skipped), (1, 2));
+		assert_eq!(mock.state().uploads, uploads + 1);
+		
+		// pulling into the source doesn't download anything either
+		let report = pull(&service, "/", source.path()).await.unwrap();
+		assert_eq!((report.transferred, report.skipped), (0, 3));
+		assert_eq!(mock.state().downloads, 0);
+	}
+	
+	#[tokio::test]
+	async fn encrypted() {
+		let source = tempfile::tempdir().unwrap();
+		let target = tempfile::tempdir().unwrap();
+		write_tree(source.path()).await;
+		let (mut service, mock) = MockServer::default().serve().await;
+		service.unlock_volume("passphrase").await.unwrap();
+		
+		let report = push(&service, source.path(), "/").await.unwrap();
+		assert_eq!((report.transferred, report.failed.len()), (3, 0));
+		
+		// neither names nor contents are stored in plain text
+		{
+			let state = mock.state();
+			assert!(!state.dirs[&NodeID::ROOT].1.contains_key("small"));
+			assert!(state.files.values().all(|data| data != b"small" && *data != large_data()));
+		}
+		
+		let report = pull(&service, "/", target.path()).await.unwrap();
+		assert_eq!((report.transferred, report.failed.len()), (3, 0));
+		assert_same_tree(source.path(), target.path()).await;
+		
+		// encrypting the same content again results in the same hash
+		let uploads = mock.state().uploads;
+		let report = push(&service, target.path(), "/").await.unwrap();
+		assert_eq!((report.transferred, report.skipped), (0, 3));
+		assert_eq!(mock.state().uploads, uploads);
+	}
+}
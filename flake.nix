@@ -35,6 +35,10 @@
 					client = prev.callPackage ./nix/client.nix {
 						craneLib = makeCraneLib final;
 					};
+					
+					server-postgres-tests = prev.callPackage ./nix/server-postgres-tests.nix {
+						craneLib = makeCraneLib final;
+					};
 				};
 			};
 			
@@ -52,6 +56,11 @@
 				inherit (pkgs.fye) server client;
 			};
 			
+			# the server package already runs its tests against SQLite
+			checks = {
+				inherit (pkgs.fye) server client server-postgres-tests;
+			};
+			
 			devShells.default = craneLib.devShell {
 				packages = with pkgs; [
 					rust-analyzer
@@ -61,6 +70,7 @@
 					llvmPackages.bintools # lld
 					openssl
 					sqlite
+					postgresql
 				];
 				
 				DATABASE_URL = "dev_data/fye.db";
@@ -5,6 +5,7 @@
 , llvmPackages
 , openssl
 , sqlite
+, postgresql
 }:
 
 let
@@ -28,6 +29,7 @@ let
 		buildInputs = [
 			openssl
 			sqlite
+			postgresql # libpq
 		];
 	};
 in {
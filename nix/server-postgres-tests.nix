@@ -0,0 +1,32 @@
+{ callPackage
+, craneLib
+, postgresql
+}:
+
+let
+	common = callPackage ./common.nix {
+		inherit craneLib;
+	};
+in craneLib.cargoTest (common.args // {
+	inherit (common) cargoArtifacts;
+	
+	pname = "${common.pname}-server-postgres-tests";
+	src = common.sourceFor ../server;
+	cargoTestExtraArgs = "--package fye_server";
+	
+	nativeBuildInputs = common.args.nativeBuildInputs ++ [
+		postgresql
+	];
+	
+	# the server tests run against a throwaway database instead of in-memory SQLite
+	preBuild = ''
+		export PGDATA="$TMPDIR/postgres"
+		initdb --auth=trust --username=fye > /dev/null
+		pg_ctl start --wait --log="$TMPDIR/postgres.log" --options="-c listen_addresses= -k $TMPDIR"
+		export FYE_TEST_POSTGRES_URL="postgresql://fye@/postgres?host=$TMPDIR"
+	'';
+	
+	postBuild = ''
+		pg_ctl stop --wait
+	'';
+})
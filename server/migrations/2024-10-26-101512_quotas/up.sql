@@ -0,0 +1,20 @@
+CREATE TABLE quotas (
+	directory BigInt PRIMARY KEY NOT NULL,
+	used_bytes BigInt NOT NULL,
+	used_nodes BigInt NOT NULL,
+	max_bytes BigInt,
+	max_nodes BigInt,
+	FOREIGN KEY(directory) REFERENCES directories ON DELETE CASCADE
+);
+
+-- every top level directory gets its own quota, including the nodes that already exist inside it
+WITH RECURSIVE tree(top, node) AS (
+	SELECT directory, directory FROM directory_entries WHERE parent = 1 AND directory IS NOT NULL
+	UNION ALL
+	SELECT tree.top, COALESCE(directory_entries.directory, directory_entries.file) FROM directory_entries
+		JOIN tree ON directory_entries.parent = tree.node
+)
+INSERT INTO quotas (directory, used_bytes, used_nodes)
+	SELECT top, COALESCE(SUM(files.size), 0), COUNT(*) FROM tree
+		LEFT JOIN files ON files.id = tree.node
+		GROUP BY top;
@@ -0,0 +1 @@
+DROP TABLE volume_key;
@@ -0,0 +1,5 @@
+-- at most a single row, the key is only ever read and written by clients
+CREATE TABLE volume_key (
+	id Integer PRIMARY KEY NOT NULL CHECK (id = 0),
+	wrapped_key Binary NOT NULL
+);
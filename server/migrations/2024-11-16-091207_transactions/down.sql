@@ -0,0 +1,4 @@
+DROP TABLE staged_operations;
+DROP TABLE staged_contents;
+DROP TABLE transactions;
+DROP TABLE transaction_id;
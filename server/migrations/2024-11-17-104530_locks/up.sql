@@ -0,0 +1,20 @@
+-- clients hold their locks as long as they keep renewing their lease
+CREATE TABLE lock_leases (
+	client BigInt PRIMARY KEY NOT NULL,
+	-- unix time in seconds
+	expires_at BigInt NOT NULL
+);
+
+-- advisory locks on byte ranges of files, locks of the same owner never overlap
+CREATE TABLE file_locks (
+	file BigInt NOT NULL,
+	client BigInt NOT NULL,
+	owner BigInt NOT NULL,
+	exclusive Boolean NOT NULL,
+	range_start BigInt NOT NULL,
+	-- the lock extends to the end of the file without it
+	range_end BigInt,
+	pid BigInt NOT NULL,
+	PRIMARY KEY (file, client, owner, range_start),
+	FOREIGN KEY(file) REFERENCES files ON DELETE CASCADE
+);
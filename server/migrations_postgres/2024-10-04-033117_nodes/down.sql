@@ -0,0 +1,4 @@
+DROP TABLE directory_entries;
+DROP TABLE directories;
+DROP TABLE files;
+DROP TABLE node_id;
@@ -0,0 +1,2 @@
+ALTER TABLE files
+	ADD COLUMN hash Text NOT NULL;
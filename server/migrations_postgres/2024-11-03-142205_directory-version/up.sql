@@ -0,0 +1,3 @@
+-- incremented whenever an entry is added to or removed from the directory
+ALTER TABLE directories
+	ADD COLUMN version BigInt NOT NULL DEFAULT 0;
@@ -0,0 +1,10 @@
+-- files that clients cache exclusively or without revalidating them, until the server recalls them
+CREATE TABLE delegations (
+	file BigInt NOT NULL,
+	client BigInt NOT NULL,
+	writable Boolean NOT NULL,
+	-- unix time in seconds, delegations that aren't returned in time are revoked
+	recalled_at BigInt,
+	PRIMARY KEY (file, client),
+	FOREIGN KEY(file) REFERENCES files ON DELETE CASCADE
+);
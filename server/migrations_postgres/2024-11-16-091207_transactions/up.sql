@@ -0,0 +1,35 @@
+-- hands out transaction ids like node_id does for nodes
+CREATE TABLE transaction_id (
+	current_id BigInt PRIMARY KEY NOT NULL
+);
+
+INSERT INTO transaction_id (current_id) VALUES (0);
+
+-- transactions which are currently being staged, they are dropped along with everything staged once they time out
+CREATE TABLE transactions (
+	id BigInt PRIMARY KEY NOT NULL,
+	-- unix time in seconds
+	opened_at BigInt NOT NULL
+);
+
+-- contents which are already stored, but only replace the contents of their files once the transaction is committed
+CREATE TABLE staged_contents (
+	transaction_id BigInt NOT NULL,
+	file BigInt NOT NULL,
+	prev_hash Text NOT NULL,
+	hash Text NOT NULL,
+	size BigInt NOT NULL,
+	PRIMARY KEY (transaction_id, file),
+	FOREIGN KEY(transaction_id) REFERENCES transactions ON DELETE CASCADE,
+	FOREIGN KEY(file) REFERENCES files ON DELETE CASCADE
+);
+
+-- directory changes, applied in order of their position after all contents
+CREATE TABLE staged_operations (
+	transaction_id BigInt NOT NULL,
+	position BigInt NOT NULL,
+	-- encoded with postcard
+	operation Bytea NOT NULL,
+	PRIMARY KEY (transaction_id, position),
+	FOREIGN KEY(transaction_id) REFERENCES transactions ON DELETE CASCADE
+);
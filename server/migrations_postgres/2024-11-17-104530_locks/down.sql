@@ -0,0 +1,2 @@
+DROP TABLE file_locks;
+DROP TABLE lock_leases;
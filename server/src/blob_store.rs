@@ -0,0 +1,197 @@
+use std::{fmt::Debug, io, pin::Pin};
+
+use bytes::Bytes;
+use futures::{future::BoxFuture, FutureExt, Stream};
+use tokio::fs::File;
+use tokio_util::io::ReaderStream;
+
+use crate::routes::upload_file::UploadFile;
+
+mod local;
+pub use local::LocalBlobStore;
+mod memory;
+pub use memory::MemoryBlobStore;
+mod s3;
+pub use s3::S3BlobStore;
+
+pub type BlobStream = Pin<Box<dyn Stream<Item = Result<Bytes, io::Error>> + Send>>;
+
+pub struct Blob {
+	/// The bytes as stored, which are compressed with zstd if `is_compressed` is set
+	pub stream: BlobStream,
+	pub is_compressed: bool,
+	/// Size of the stored bytes, which is the compressed size for compressed blobs
+	pub size: u64,
+}
+
+/// Storage for file contents, addressed by the hash of their uncompressed content
+/// 
+/// A blob may be stored compressed or uncompressed, but never both. Missing blobs result in [`io::ErrorKind::NotFound`].
+pub trait BlobStore: Debug + Send + Sync {
+	/// Stores the content of `stream` under `hash`, the caller is responsible for the hash matching the content
+	/// 
+	/// Nothing is stored if the stream fails. A blob that already exists is kept as is, as the content is the same.
+	fn put_stream<'a>(&'a self, hash: &'a str, is_compressed: bool, stream: BlobStream) -> BoxFuture<'a, Result<(), io::Error>>;
+	
+	/// Stores an uploaded file, which is removed afterwards
+	fn put_file<'a>(&'a self, hash: &'a str, is_compressed: bool, file: UploadFile) -> BoxFuture<'a, Result<(), io::Error>> {
+		async move {
+			let stream = ReaderStream::new(File::open(file.path()).await?);
+			self.put_stream(hash, is_compressed, Box::pin(stream)).await
+		}.boxed()
+	}
+	
+	fn get<'a>(&'a self, hash: &'a str) -> BoxFuture<'a, Result<Blob, io::Error>>;
+	
+	/// Returns the uncompressed bytes from `start` up to `end` (exclusive), which need to be within the blob
+	fn get_range<'a>(&'a self, hash: &'a str, start: u64, end: u64) -> BoxFuture<'a, Result<BlobStream, io::Error>>;
+	
+	fn exists<'a>(&'a self, hash: &'a str) -> BoxFuture<'a, Result<bool, io::Error>>;
+	
+	/// Deleting a blob that doesn't exist is not an error
+	#[allow(dead_code)] // blobs aren't garbage collected yet, fsck only quarantines them
+	fn delete<'a>(&'a self, hash: &'a str) -> BoxFuture<'a, Result<(), io::Error>>;
+	
+	/// Moves a blob out of the way so it is no longer found, but is kept around for inspection
+	fn quarantine<'a>(&'a self, hash: &'a str) -> BoxFuture<'a, Result<(), io::Error>>;
+	
+	/// Returns the hashes of all stored blobs
+	fn list(&self) -> BoxFuture<'_, Result<Vec<String>, io::Error>>;
+	
+	/// Returns the total and available space in bytes, or [`None`] if the store isn't limited by a disk
+	fn capacity(&self) -> Result<Option<(u64, u64)>, io::Error>;
+}
+
+/// Blob names are the hex encoded hash, optionally followed by `.zst` for compressed blobs
+pub fn parse_blob_name(name: &str) -> Option<(&str, bool)> {
+	let (hash, is_compressed) = match name.strip_suffix(".zst") {
+		Some(hash) => (hash, true),
+		None => (name, false),
+	};
+	
+	let is_hash = hash.len() == 64 && hash.bytes().all(|char| char.is_ascii_digit() || (b'a'..=b'f').contains(&char));
+	is_hash.then_some((hash, is_compressed))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	use futures::TryStreamExt as _;
+	
+	async fn collect(stream: BlobStream) -> Vec<u8> {
+		stream.map_ok(|chunk| chunk.to_vec()).try_concat().await.unwrap()
+	}
+	
+	fn stream_of(data: &[u8]) -> BlobStream {
+		Box::pin(futures::stream::iter([Ok(Bytes::copy_from_slice(data))]))
+	}
+	
+	async fn check_store(store: &dyn BlobStore) {
+		let content = b"compressible ".repeat(100);
+		let hash = blake3::hash(&content).to_hex();
+		let small_hash = blake3::hash(b"small").to_hex();
+		
+		assert!(!store.exists(&hash).await.unwrap());
+		assert_eq!(store.get(&hash).await.err().unwrap().kind(), io::ErrorKind::NotFound);
+		
+		// a failing stream doesn't leave anything behind
+		let failing = futures::stream::iter([Ok(Bytes::from_static(b"partial")), Err(io::Error::other("failed"))]);
+		store.put_stream(&small_hash, false, Box::pin(failing)).await.unwrap_err();
+		assert!(!store.exists(&small_hash).await.unwrap());
+		
+		let mut compressed = Vec::new();
+		let mut encoder = async_compression::tokio::write::ZstdEncoder::new(&mut compressed);
+		tokio::io::AsyncWriteExt::write_all(&mut encoder, &content).await.unwrap();
+		tokio::io::AsyncWriteExt::shutdown(&mut encoder).await.unwrap();
+		
+		store.put_stream(&hash, true, stream_of(&compressed)).await.unwrap();
+		store.put_stream(&small_hash, false, stream_of(b"small")).await.unwrap();
+		assert!(store.exists(&hash).await.unwrap());
+		
+		let blob = store.get(&hash).await.unwrap();
+		assert!(blob.is_compressed);
+		assert_eq!(collect(blob.stream).await, compressed);
+		
+		let blob = store.get(&small_hash).await.unwrap();
+		assert!(!blob.is_compressed);
+		assert_eq!(collect(blob.stream).await, b"small");
+		
+		assert_eq!(collect(store.get_range(&hash, 600, 613).await.unwrap()).await, &content[600..613]);
+		assert_eq!(collect(store.get_range(&small_hash, 1, 4).await.unwrap()).await, b"mal");
+		
+		let mut list = store.list().await.unwrap();
+		list.sort();
+		let mut expected = vec![hash.to_string(), small_hash.to_string()];
+		expected.sort();
+		assert_eq!(list, expected);
+		
+		store.delete(&hash).await.unwrap();
+		store.delete(&hash).await.unwrap();
+		assert!(!store.exists(&hash).await.unwrap());
+		assert_eq!(store.list().await.unwrap(), vec![small_hash.to_string()]);
+		
+		store.delete(&small_hash).await.unwrap();
+	}
+	
+	async fn check_quarantine(store: &dyn BlobStore) {
+		let hash = blake3::hash(b"quarantined").to_hex();
+		
+		store.put_stream(&hash, false, stream_of(b"quarantined")).await.unwrap();
+		store.quarantine(&hash).await.unwrap();
+		
+		assert!(!store.exists(&hash).await.unwrap());
+		assert_eq!(store.get(&hash).await.err().unwrap().kind(), io::ErrorKind::NotFound);
+		assert!(store.list().await.unwrap().is_empty());
+		
+		// the same content can be stored again
+		store.put_stream(&hash, false, stream_of(b"quarantined")).await.unwrap();
+		assert!(store.exists(&hash).await.unwrap());
+		store.delete(&hash).await.unwrap();
+	}
+	
+	#[test]
+	fn blob_names() {
+		let hash = blake3::hash(b"").to_hex();
+		
+		assert_eq!(parse_blob_name(&hash), Some((hash.as_str(), false)));
+		assert_eq!(parse_blob_name(&format!("{hash}.zst")), Some((hash.as_str(), true)));
+		assert_eq!(parse_blob_name(&format!("{hash}.upload-1")), None);
+		assert_eq!(parse_blob_name("abc"), None);
+	}
+	
+	#[tokio::test]
+	async fn memory_store() {
+		check_store(&MemoryBlobStore::default()).await;
+	}
+	
+	#[tokio::test]
+	async fn local_store() {
+		let temp_dir = tempfile::tempdir().unwrap();
+		check_store(&LocalBlobStore::new(temp_dir.path().into())).await;
+		
+		// nothing is left behind
+		assert!(temp_dir.path().read_dir().unwrap().next().is_none());
+	}
+	
+	#[tokio::test]
+	async fn memory_store_quarantine() {
+		check_quarantine(&MemoryBlobStore::default()).await;
+	}
+	
+	#[tokio::test]
+	async fn local_store_quarantine() {
+		let temp_dir = tempfile::tempdir().unwrap();
+		check_quarantine(&LocalBlobStore::new(temp_dir.path().into())).await;
+		
+		// the quarantined blob is kept aside
+		let hash = blake3::hash(b"quarantined").to_hex();
+		assert_eq!(std::fs::read(temp_dir.path().join("quarantine").join(hash.as_str())).unwrap(), b"quarantined");
+	}
+	
+	#[tokio::test]
+	#[ignore = "needs an S3 compatible store such as MinIO, with an empty bucket configured through FYE_S3_BUCKET and the AWS_* variables"]
+	async fn s3_store() {
+		check_store(&S3BlobStore::from_env().unwrap()).await;
+	}
+}
@@ -0,0 +1,154 @@
+use std::{io, path::{Path, PathBuf}, sync::{atomic::{AtomicU64, Ordering}, Arc}};
+
+use futures::{future::BoxFuture, FutureExt};
+use tokio::{fs::File, io::{AsyncReadExt, AsyncSeekExt, SeekFrom}};
+use tokio_util::io::ReaderStream;
+
+use crate::{compression::decompress_range, routes::upload_file::UploadFile, stream::stream_to_file};
+
+use super::{parse_blob_name, Blob, BlobStore, BlobStream};
+
+/// Stores blobs as files in a single directory
+#[derive(Debug)]
+pub struct LocalBlobStore {
+	directory: Arc<Path>,
+	last_upload: AtomicU64,
+}
+
+impl LocalBlobStore {
+	pub fn new(directory: Arc<Path>) -> Self {
+		Self {
+			directory,
+			last_upload: AtomicU64::new(0),
+		}
+	}
+	
+	fn path(&self, hash: &str, is_compressed: bool) -> PathBuf {
+		match is_compressed {
+			true => self.directory.join(format!("{hash}.zst")),
+			false => self.directory.join(hash),
+		}
+	}
+	
+	async fn open(&self, hash: &str) -> Result<(File, bool), io::Error> {
+		match File::open(self.path(hash, true)).await {
+			Ok(file) => Ok((file, true)),
+			Err(err) if err.kind() == io::ErrorKind::NotFound => Ok((File::open(self.path(hash, false)).await?, false)),
+			Err(err) => Err(err),
+		}
+	}
+	
+	async fn is_stored(&self, hash: &str) -> Result<bool, io::Error> {
+		Ok(tokio::fs::try_exists(self.path(hash, true)).await?
+			|| tokio::fs::try_exists(self.path(hash, false)).await?)
+	}
+}
+
+impl BlobStore for LocalBlobStore {
+	fn put_stream<'a>(&'a self, hash: &'a str, is_compressed: bool, mut stream: BlobStream) -> BoxFuture<'a, Result<(), io::Error>> {
+		async move {
+			if self.is_stored(hash).await? {
+				return Ok(());
+			}
+			
+			// written next to the final location first, so a failing stream doesn't leave a partial blob
+			let upload = self.last_upload.fetch_add(1, Ordering::Relaxed);
+			let mut file = UploadFile::new(self.directory.join(format!("{hash}.upload-{upload}"))).await?;
+			
+			stream_to_file(&mut stream, &mut file).await?;
+			file.sync_all().await?;
+			
+			file.move_to(self.path(hash, is_compressed)).await
+		}.boxed()
+	}
+	
+	/// Moves the file instead of copying it
+	fn put_file<'a>(&'a self, hash: &'a str, is_compressed: bool, file: UploadFile) -> BoxFuture<'a, Result<(), io::Error>> {
+		async move {
+			if self.is_stored(hash).await? {
+				return Ok(());
+			}
+			
+			file.move_to(self.path(hash, is_compressed)).await
+		}.boxed()
+	}
+	
+	fn get<'a>(&'a self, hash: &'a str) -> BoxFuture<'a, Result<Blob, io::Error>> {
+		async move {
+			let (file, is_compressed) = self.open(hash).await?;
+			let size = file.metadata().await?.len();
+			
+			Ok(Blob {
+				stream: Box::pin(ReaderStream::new(file)),
+				is_compressed,
+				size,
+			})
+		}.boxed()
+	}
+	
+	fn get_range<'a>(&'a self, hash: &'a str, start: u64, end: u64) -> BoxFuture<'a, Result<BlobStream, io::Error>> {
+		async move {
+			match self.open(hash).await? {
+				(file, true) => decompress_range(Box::pin(ReaderStream::new(file)), start, end).await,
+				(mut file, false) => {
+					file.seek(SeekFrom::Start(start)).await?;
+					Ok(Box::pin(ReaderStream::new(file.take(end - start))) as BlobStream)
+				},
+			}
+		}.boxed()
+	}
+	
+	fn exists<'a>(&'a self, hash: &'a str) -> BoxFuture<'a, Result<bool, io::Error>> {
+		self.is_stored(hash).boxed()
+	}
+	
+	fn delete<'a>(&'a self, hash: &'a str) -> BoxFuture<'a, Result<(), io::Error>> {
+		async move {
+			for is_compressed in [true, false] {
+				match tokio::fs::remove_file(self.path(hash, is_compressed)).await {
+					Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+					_ => (),
+				}
+			}
+			
+			Ok(())
+		}.boxed()
+	}
+	
+	/// Moves the blob into the `quarantine` directory
+	fn quarantine<'a>(&'a self, hash: &'a str) -> BoxFuture<'a, Result<(), io::Error>> {
+		async move {
+			let quarantine = self.directory.join("quarantine");
+			tokio::fs::create_dir_all(&quarantine).await?;
+			
+			let (_, is_compressed) = self.open(hash).await?;
+			let path = self.path(hash, is_compressed);
+			let name = path.file_name().expect("blob paths should have a file name");
+			
+			tokio::fs::rename(&path, quarantine.join(name)).await
+		}.boxed()
+	}
+	
+	fn list(&self) -> BoxFuture<'_, Result<Vec<String>, io::Error>> {
+		async move {
+			let mut entries = tokio::fs::read_dir(&self.directory).await?;
+			let mut hashes = Vec::new();
+			
+			while let Some(entry) = entries.next_entry().await? {
+				let name = entry.file_name();
+				
+				// skips uploads in progress
+				if let Some((hash, _)) = name.to_str().and_then(parse_blob_name) {
+					hashes.push(hash.to_owned());
+				}
+			}
+			
+			Ok(hashes)
+		}.boxed()
+	}
+	
+	fn capacity(&self) -> Result<Option<(u64, u64)>, io::Error> {
+		let stats = fs4::statvfs(&self.directory)?;
+		Ok(Some((stats.total_space(), stats.available_space())))
+	}
+}
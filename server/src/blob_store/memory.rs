@@ -0,0 +1,93 @@
+use std::{collections::HashMap, io, sync::RwLock};
+
+use bytes::{Bytes, BytesMut};
+use futures::{future::BoxFuture, FutureExt, TryStreamExt};
+
+use crate::compression::decompress_range;
+
+use super::{Blob, BlobStore, BlobStream};
+
+/// Keeps all blobs in memory, mostly useful for tests
+#[derive(Default, Debug)]
+pub struct MemoryBlobStore {
+	blobs: RwLock<HashMap<String, (Bytes, bool)>>,
+	quarantined: RwLock<HashMap<String, (Bytes, bool)>>,
+}
+
+impl MemoryBlobStore {
+	fn find(&self, hash: &str) -> Result<(Bytes, bool), io::Error> {
+		self.blobs.read().expect("poison").get(hash).cloned()
+			.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("blob {hash} not found")))
+	}
+}
+
+fn stream_of(data: Bytes) -> BlobStream {
+	Box::pin(futures::stream::once(async { Ok(data) }))
+}
+
+impl BlobStore for MemoryBlobStore {
+	fn put_stream<'a>(&'a self, hash: &'a str, is_compressed: bool, stream: BlobStream) -> BoxFuture<'a, Result<(), io::Error>> {
+		async move {
+			let data: BytesMut = stream.try_collect().await?;
+			
+			self.blobs.write().expect("poison")
+				.entry(hash.to_owned())
+				.or_insert((data.freeze(), is_compressed));
+			
+			Ok(())
+		}.boxed()
+	}
+	
+	fn get<'a>(&'a self, hash: &'a str) -> BoxFuture<'a, Result<Blob, io::Error>> {
+		async move {
+			let (data, is_compressed) = self.find(hash)?;
+			
+			Ok(Blob {
+				size: data.len() as u64,
+				stream: stream_of(data),
+				is_compressed,
+			})
+		}.boxed()
+	}
+	
+	fn get_range<'a>(&'a self, hash: &'a str, start: u64, end: u64) -> BoxFuture<'a, Result<BlobStream, io::Error>> {
+		async move {
+			match self.find(hash)? {
+				(data, true) => decompress_range(stream_of(data), start, end).await,
+				(data, false) => {
+					let end = (end as usize).min(data.len());
+					let start = (start as usize).min(end);
+					Ok(stream_of(data.slice(start..end)))
+				},
+			}
+		}.boxed()
+	}
+	
+	fn exists<'a>(&'a self, hash: &'a str) -> BoxFuture<'a, Result<bool, io::Error>> {
+		let exists = self.blobs.read().expect("poison").contains_key(hash);
+		futures::future::ready(Ok(exists)).boxed()
+	}
+	
+	fn delete<'a>(&'a self, hash: &'a str) -> BoxFuture<'a, Result<(), io::Error>> {
+		self.blobs.write().expect("poison").remove(hash);
+		futures::future::ready(Ok(())).boxed()
+	}
+	
+	fn quarantine<'a>(&'a self, hash: &'a str) -> BoxFuture<'a, Result<(), io::Error>> {
+		let result = self.find(hash).map(|blob| {
+			self.blobs.write().expect("poison").remove(hash);
+			self.quarantined.write().expect("poison").insert(hash.to_owned(), blob);
+		});
+		
+		futures::future::ready(result).boxed()
+	}
+	
+	fn list(&self) -> BoxFuture<'_, Result<Vec<String>, io::Error>> {
+		let hashes = self.blobs.read().expect("poison").keys().cloned().collect();
+		futures::future::ready(Ok(hashes)).boxed()
+	}
+	
+	fn capacity(&self) -> Result<Option<(u64, u64)>, io::Error> {
+		Ok(None)
+	}
+}
@@ -0,0 +1,180 @@
+use std::io;
+
+use futures::{future::BoxFuture, FutureExt, StreamExt, TryStreamExt};
+use object_store::{aws::{AmazonS3, AmazonS3Builder}, path::Path, GetOptions, GetRange, ObjectStore, WriteMultipart};
+
+use crate::compression::decompress_range;
+
+use super::{parse_blob_name, Blob, BlobStore, BlobStream};
+
+const PREFIX: &str = "blobs";
+/// Kept outside of [`PREFIX`] so quarantined blobs aren't listed
+const QUARANTINE_PREFIX: &str = "quarantine";
+/// Number of parts uploaded in parallel
+const MAX_CONCURRENCY: usize = 8;
+
+/// Stores blobs in an S3 compatible object store
+#[derive(Debug)]
+pub struct S3BlobStore {
+	store: AmazonS3,
+}
+
+impl S3BlobStore {
+	/// Configured through `FYE_S3_BUCKET` and the usual `AWS_*` variables,
+	/// S3 compatible stores like MinIO can be used by setting `AWS_ENDPOINT` (and `AWS_ALLOW_HTTP`)
+	pub fn from_env() -> Result<Self, io::Error> {
+		let bucket = std::env::var("FYE_S3_BUCKET")
+			.map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "FYE_S3_BUCKET should be set"))?;
+		
+		let store = AmazonS3Builder::from_env()
+			.with_bucket_name(bucket)
+			.build()?;
+		
+		Ok(Self {
+			store,
+		})
+	}
+	
+	fn path(hash: &str, is_compressed: bool) -> Path {
+		match is_compressed {
+			true => Path::from(format!("{PREFIX}/{hash}.zst")),
+			false => Path::from(format!("{PREFIX}/{hash}")),
+		}
+	}
+	
+	async fn object_exists(&self, path: &Path) -> Result<bool, io::Error> {
+		match self.store.head(path).await {
+			Ok(_) => Ok(true),
+			Err(object_store::Error::NotFound { .. }) => Ok(false),
+			Err(err) => Err(err.into()),
+		}
+	}
+	
+	async fn is_compressed(&self, hash: &str) -> Result<bool, io::Error> {
+		if self.object_exists(&Self::path(hash, true)).await? {
+			return Ok(true);
+		}
+		
+		match self.object_exists(&Self::path(hash, false)).await? {
+			true => Ok(false),
+			false => Err(io::Error::new(io::ErrorKind::NotFound, format!("blob {hash} not found"))),
+		}
+	}
+}
+
+impl BlobStore for S3BlobStore {
+	fn put_stream<'a>(&'a self, hash: &'a str, is_compressed: bool, mut stream: BlobStream) -> BoxFuture<'a, Result<(), io::Error>> {
+		async move {
+			if self.exists(hash).await? {
+				return Ok(());
+			}
+			
+			// objects only become visible once the upload is completed
+			let upload = self.store.put_multipart(&Self::path(hash, is_compressed)).await?;
+			let mut upload = WriteMultipart::new(upload);
+			
+			while let Some(bytes) = stream.next().await {
+				let bytes = match bytes {
+					Ok(bytes) => bytes,
+					Err(err) => {
+						if let Err(abort_err) = upload.abort().await {
+							eprintln!("could not abort upload of blob {hash}: {abort_err}");
+						}
+						
+						return Err(err);
+					},
+				};
+				
+				upload.wait_for_capacity(MAX_CONCURRENCY).await?;
+				upload.put(bytes);
+			}
+			
+			upload.finish().await?;
+			
+			Ok(())
+		}.boxed()
+	}
+	
+	fn get<'a>(&'a self, hash: &'a str) -> BoxFuture<'a, Result<Blob, io::Error>> {
+		async move {
+			let is_compressed = self.is_compressed(hash).await?;
+			let result = self.store.get(&Self::path(hash, is_compressed)).await?;
+			
+			Ok(Blob {
+				size: result.meta.size as u64,
+				stream: Box::pin(result.into_stream().map_err(io::Error::from)),
+				is_compressed,
+			})
+		}.boxed()
+	}
+	
+	fn get_range<'a>(&'a self, hash: &'a str, start: u64, end: u64) -> BoxFuture<'a, Result<BlobStream, io::Error>> {
+		async move {
+			if self.is_compressed(hash).await? {
+				let result = self.store.get(&Self::path(hash, true)).await?;
+				return decompress_range(Box::pin(result.into_stream().map_err(io::Error::from)), start, end).await;
+			}
+			
+			let options = GetOptions {
+				range: Some(GetRange::Bounded(start as usize..end as usize)),
+				..Default::default()
+			};
+			
+			let result = self.store.get_opts(&Self::path(hash, false), options).await?;
+			Ok(Box::pin(result.into_stream().map_err(io::Error::from)) as BlobStream)
+		}.boxed()
+	}
+	
+	fn exists<'a>(&'a self, hash: &'a str) -> BoxFuture<'a, Result<bool, io::Error>> {
+		async move {
+			Ok(self.object_exists(&Self::path(hash, true)).await?
+				|| self.object_exists(&Self::path(hash, false)).await?)
+		}.boxed()
+	}
+	
+	fn delete<'a>(&'a self, hash: &'a str) -> BoxFuture<'a, Result<(), io::Error>> {
+		async move {
+			for is_compressed in [true, false] {
+				match self.store.delete(&Self::path(hash, is_compressed)).await {
+					Ok(()) | Err(object_store::Error::NotFound { .. }) => (),
+					Err(err) => return Err(err.into()),
+				}
+			}
+			
+			Ok(())
+		}.boxed()
+	}
+	
+	/// Moves the blob to the `quarantine` prefix
+	fn quarantine<'a>(&'a self, hash: &'a str) -> BoxFuture<'a, Result<(), io::Error>> {
+		async move {
+			let path = Self::path(hash, self.is_compressed(hash).await?);
+			let name = path.filename().expect("blob paths should have a file name");
+			
+			self.store.rename(&path, &Path::from(format!("{QUARANTINE_PREFIX}/{name}"))).await?;
+			Ok(())
+		}.boxed()
+	}
+	
+	fn list(&self) -> BoxFuture<'_, Result<Vec<String>, io::Error>> {
+		async move {
+			let prefix = Path::from(PREFIX);
+			let mut objects = self.store.list(Some(&prefix));
+			let mut hashes = Vec::new();
+			
+			while let Some(object) = objects.next().await {
+				let object = object?;
+				
+				if let Some((hash, _)) = object.location.filename().and_then(parse_blob_name) {
+					hashes.push(hash.to_owned());
+				}
+			}
+			
+			Ok(hashes)
+		}.boxed()
+	}
+	
+	fn capacity(&self) -> Result<Option<(u64, u64)>, io::Error> {
+		Ok(None)
+	}
+}
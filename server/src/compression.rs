@@ -0,0 +1,48 @@
+use std::{io, path::Path};
+
+use async_compression::tokio::{bufread::ZstdDecoder, write::ZstdEncoder};
+use futures::TryStreamExt as _;
+use tokio::{fs::File, io::{AsyncReadExt, AsyncWriteExt}};
+use tokio_util::io::{ReaderStream, StreamReader};
+
+use crate::{blob_store::BlobStream, stream::HashStream};
+
+/// Smaller blobs are always stored uncompressed as there is hardly anything to save
+pub const MIN_COMPRESSION_SIZE: u64 = 128;
+
+/// Writes a compressed copy of the file at `source` to `destination` and returns the compressed size
+pub async fn compress_file(source: &Path, destination: &mut File) -> Result<u64, io::Error> {
+	let mut source = File::open(source).await?;
+	let mut encoder = ZstdEncoder::new(&mut *destination);
+	
+	tokio::io::copy(&mut source, &mut encoder).await?;
+	encoder.shutdown().await?;
+	
+	Ok(destination.metadata().await?.len())
+}
+
+/// Returns the uncompressed bytes from `start` up to `end` (exclusive) of a compressed blob
+pub async fn decompress_range(stream: BlobStream, start: u64, end: u64) -> Result<BlobStream, io::Error> {
+	let mut decoder = ZstdDecoder::new(StreamReader::new(stream));
+	
+	// zstd frames can't be seeked, so everything before the range needs to be decompressed
+	let skipped = tokio::io::copy(&mut (&mut decoder).take(start), &mut tokio::io::sink()).await?;
+	if skipped < start {
+		return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "compressed blob is shorter than expected"));
+	}
+	
+	Ok(Box::pin(ReaderStream::new(decoder.take(end - start))))
+}
+
+/// Returns the hash of the uncompressed content of a stored blob
+pub async fn content_hash(stream: BlobStream, is_compressed: bool) -> Result<blake3::Hash, io::Error> {
+	let stream = match is_compressed {
+		true => decompress_range(stream, 0, u64::MAX).await?,
+		false => stream,
+	};
+	
+	let mut stream = HashStream::new(stream);
+	while stream.try_next().await?.is_some() {}
+	
+	Ok(stream.hash())
+}
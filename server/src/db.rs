@@ -1,54 +1,102 @@
-use diesel::{connection::SimpleConnection, dsl::{AsSelect, SqlTypeOf}, prelude::*, sqlite::Sqlite};
+use diesel::{connection::SimpleConnection, dsl::{AsSelect, SqlTypeOf}, pg::{Pg, PgConnection}, prelude::*, sqlite::{Sqlite, SqliteConnection}};
 use diesel::result::Error as DieselError;
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 use fye_shared::NodeID;
+use serde::{Deserialize, Serialize};
 
 mod schema;
 use schema::*;
 
-pub fn establish_connection(url: &str, run_migrations: bool) -> Result<SqliteConnection, diesel::r2d2::Error> {
-	let mut conn = SqliteConnection::establish(url).map_err(diesel::r2d2::Error::ConnectionError)?;
-	
-	const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
+/// A connection to either of the supported databases
+/// 
+/// Queries are written once against the common subset of both,
+/// only migrations and connection setup differ between them.
+#[derive(diesel::MultiConnection)]
+pub enum AnyConnection {
+	Sqlite(SqliteConnection),
+	Postgres(PgConnection),
+}
+
+pub type Backend = <AnyConnection as diesel::Connection>::Backend;
+
+/// PostgreSQL is used for `postgres://` and `postgresql://` urls, anything else is treated as the path to a SQLite database
+pub fn is_postgres_url(url: &str) -> bool {
+	url.starts_with("postgres://") || url.starts_with("postgresql://")
+}
+
+pub fn establish_connection(url: &str, run_migrations: bool) -> Result<AnyConnection, diesel::r2d2::Error> {
+	let mut conn = if is_postgres_url(url) {
+		AnyConnection::Postgres(PgConnection::establish(url).map_err(diesel::r2d2::Error::ConnectionError)?)
+	} else {
+		AnyConnection::Sqlite(SqliteConnection::establish(url).map_err(diesel::r2d2::Error::ConnectionError)?)
+	};
 	
 	if run_migrations {
-		conn.run_pending_migrations(MIGRATIONS).unwrap(); // TODO: what to do about this error?
+		self::run_migrations(&mut conn).unwrap(); // TODO: what to do about this error?
 	}
 	
-	conn.batch_execute("
-		PRAGMA foreign_keys = ON;
-	").map_err(|err| diesel::r2d2::Error::ConnectionError(diesel::ConnectionError::CouldntSetupConfiguration(err)))?;
+	if let AnyConnection::Sqlite(conn) = &mut conn {
+		conn.batch_execute("
+			PRAGMA foreign_keys = ON;
+		").map_err(|err| diesel::r2d2::Error::ConnectionError(diesel::ConnectionError::CouldntSetupConfiguration(err)))?;
+	}
 	
 	Ok(conn)
 }
 
+/// Arbitrary key of the advisory lock held while running migrations on PostgreSQL
+const MIGRATION_LOCK_KEY: i64 = 0x0066_7965;
+
+pub fn run_migrations(conn: &mut AnyConnection) -> diesel::migration::Result<()> {
+	const SQLITE_MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+	const POSTGRES_MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations_postgres");
+	
+	match conn {
+		AnyConnection::Sqlite(conn) => {
+			conn.run_pending_migrations(SQLITE_MIGRATIONS)?;
+		},
+		AnyConnection::Postgres(conn) => {
+			// multiple servers may share the database and start at the same time
+			conn.batch_execute(&format!("SELECT pg_advisory_lock({MIGRATION_LOCK_KEY})"))?;
+			let result = conn.run_pending_migrations(POSTGRES_MIGRATIONS).map(|_| ());
+			conn.batch_execute(&format!("SELECT pg_advisory_unlock({MIGRATION_LOCK_KEY})"))?;
+			
+			result?;
+		},
+	}
+	
+	Ok(())
+}
+
 #[derive(Queryable, Selectable, Debug)]
 #[diesel(table_name = node_id)]
-#[diesel(check_for_backend(Sqlite))]
+#[diesel(check_for_backend(Sqlite, Pg))]
 pub struct CurrentNodeID {
 	pub current_id: i64,
 }
 
-#[derive(Queryable, Selectable, Insertable, Debug)]
+#[derive(Queryable, Selectable, Insertable, Serialize, Deserialize, Debug)]
 #[diesel(table_name = directories)]
-#[diesel(check_for_backend(Sqlite))]
+#[diesel(check_for_backend(Sqlite, Pg))]
 pub struct Directory {
 	pub id: i64,
 	pub parent: i64,
+	/// Incremented whenever an entry is added or removed, exported so clients can still revalidate against an imported copy
+	pub version: i64,
 }
 
-#[derive(Queryable, Selectable, Insertable, Debug)]
+#[derive(Queryable, Selectable, Insertable, Serialize, Deserialize, Debug)]
 #[diesel(table_name = files)]
-#[diesel(check_for_backend(Sqlite))]
+#[diesel(check_for_backend(Sqlite, Pg))]
 pub struct File {
 	pub id: i64,
 	pub size: i64,
 	pub hash: String,
 }
 
-#[derive(Queryable, Selectable, Debug)]
+#[derive(Queryable, Selectable, Serialize, Deserialize, Debug)]
 #[diesel(table_name = directory_entries)]
-#[diesel(check_for_backend(Sqlite))]
+#[diesel(check_for_backend(Sqlite, Pg))]
 pub struct DirectoryEntry {
 	pub parent: i64,
 	pub name: String,
@@ -58,7 +106,7 @@ pub struct DirectoryEntry {
 
 #[derive(Insertable, Debug)]
 #[diesel(table_name = directory_entries)]
-#[diesel(check_for_backend(Sqlite))]
+#[diesel(check_for_backend(Sqlite, Pg))]
 pub struct NewDirectoryEntry<'a> {
 	pub parent: i64,
 	pub name: &'a str,
@@ -66,23 +114,118 @@ pub struct NewDirectoryEntry<'a> {
 	pub file: Option<i64>,
 }
 
-// TODO: allowed whilst Directory::children is marked allow(unused)
-#[allow(unused)]
+#[derive(Queryable, Selectable, Insertable, Serialize, Deserialize, Debug)]
+#[diesel(table_name = quotas)]
+#[diesel(check_for_backend(Sqlite, Pg))]
+pub struct Quota {
+	pub directory: i64,
+	pub used_bytes: i64,
+	pub used_nodes: i64,
+	pub max_bytes: Option<i64>,
+	pub max_nodes: Option<i64>,
+}
+
+#[derive(Queryable, Selectable, Insertable, Serialize, Deserialize, Debug)]
+#[diesel(table_name = volume_key)]
+#[diesel(check_for_backend(Sqlite, Pg))]
+pub struct VolumeKey {
+	pub id: i32,
+	pub wrapped_key: Vec<u8>,
+}
+
+#[derive(Queryable, Selectable, Insertable, Debug)]
+#[diesel(table_name = delegations)]
+#[diesel(check_for_backend(Sqlite, Pg))]
+pub struct Delegation {
+	pub file: i64,
+	pub client: i64,
+	pub writable: bool,
+	/// Unix time in seconds when the client was asked to return the delegation
+	pub recalled_at: Option<i64>,
+}
+
+#[derive(Queryable, Selectable, Insertable, Debug)]
+#[diesel(table_name = lock_leases)]
+#[diesel(check_for_backend(Sqlite, Pg))]
+pub struct LockLease {
+	pub client: i64,
+	/// Unix time in seconds
+	pub expires_at: i64,
+}
+
+#[derive(Queryable, Selectable, Insertable, Clone, Debug)]
+#[diesel(table_name = file_locks)]
+#[diesel(check_for_backend(Sqlite, Pg))]
+pub struct Lock {
+	pub file: i64,
+	pub client: i64,
+	pub owner: i64,
+	pub exclusive: bool,
+	pub range_start: i64,
+	pub range_end: Option<i64>,
+	pub pid: i64,
+}
+
+#[derive(Queryable, Selectable, Insertable, Debug)]
+#[diesel(table_name = transactions)]
+#[diesel(check_for_backend(Sqlite, Pg))]
+pub struct OpenTransaction {
+	pub id: i64,
+	/// Unix time in seconds
+	pub opened_at: i64,
+}
+
+#[derive(Queryable, Selectable, Insertable, Debug)]
+#[diesel(table_name = staged_contents)]
+#[diesel(check_for_backend(Sqlite, Pg))]
+pub struct StagedContent {
+	pub transaction_id: i64,
+	pub file: i64,
+	pub prev_hash: String,
+	pub hash: String,
+	pub size: i64,
+}
+
+#[derive(Queryable, Selectable, Insertable, Debug)]
+#[diesel(table_name = staged_operations)]
+#[diesel(check_for_backend(Sqlite, Pg))]
+pub struct StagedOperation {
+	pub transaction_id: i64,
+	pub position: i64,
+	/// [`fye_shared::BatchOperation`] encoded with postcard
+	pub operation: Vec<u8>,
+}
+
 pub struct DirectoryChild {
-	pub parent: i64,
 	pub name: String,
 	pub data: EntryKind,
 }
 
-// TODO: allowed whilst Directory::children is marked allow(unused)
-#[allow(unused)]
 pub enum EntryKind {
 	File(File),
 	Directory(Directory),
 }
 
+impl CurrentNodeID {
+	pub fn get() -> node_id::BoxedQuery<'static, Backend, SqlTypeOf<AsSelect<Self, Backend>>> {
+		node_id::table
+			.select(CurrentNodeID::as_select())
+			.into_boxed()
+	}
+	
+	pub fn set(conn: &mut AnyConnection, new_id: i64) -> Result<(), DieselError> {
+		use schema::node_id::dsl::*;
+		
+		diesel::update(node_id)
+			.set(current_id.eq(new_id))
+			.execute(conn)?;
+		
+		Ok(())
+	}
+}
+
 impl Directory {
-	pub fn get(node_id: NodeID) -> directories::BoxedQuery<'static, Sqlite, SqlTypeOf<AsSelect<Self, Sqlite>>> {
+	pub fn get(node_id: NodeID) -> directories::BoxedQuery<'static, Backend, SqlTypeOf<AsSelect<Self, Backend>>> {
 		use schema::directories::dsl::*;
 		
 		directories.filter(id.eq(node_id.0 as i64))
@@ -90,7 +233,13 @@ impl Directory {
 			.into_boxed()
 	}
 	
-	pub fn exists(conn: &mut SqliteConnection, node_id: NodeID) -> Result<bool, DieselError> {
+	pub fn all() -> directories::BoxedQuery<'static, Backend, SqlTypeOf<AsSelect<Self, Backend>>> {
+		directories::table
+			.select(Directory::as_select())
+			.into_boxed()
+	}
+	
+	pub fn exists(conn: &mut AnyConnection, node_id: NodeID) -> Result<bool, DieselError> {
 		match Self::get(node_id).first(conn) {
 			Ok(_) => Ok(true),
 			Err(DieselError::NotFound) => Ok(false),
@@ -98,7 +247,7 @@ impl Directory {
 		}
 	}
 	
-	pub fn entries(&self) -> directory_entries::BoxedQuery<'static, Sqlite, SqlTypeOf<AsSelect<DirectoryEntry, Sqlite>>> {
+	pub fn entries(&self) -> directory_entries::BoxedQuery<'static, Backend, SqlTypeOf<AsSelect<DirectoryEntry, Backend>>> {
 		use schema::directory_entries::dsl::*;
 		
 		directory_entries.filter(parent.eq(self.id))
@@ -106,35 +255,46 @@ impl Directory {
 			.into_boxed()
 	}
 	
-	// TODO: actually not needed for now, keep it around just in case
-	#[allow(unused)]
-	pub fn children(&self, conn: &mut SqliteConnection) -> Result<impl Iterator<Item = DirectoryChild>, DieselError> {
-		// why does rust-analyzer need a type annotation to know what type this is?
-		let entries: Vec<(DirectoryEntry, Option<Directory>, Option<File>)> = directory_entries::table
-			.left_join(directories::table.on(directory_entries::parent.eq(directories::id)))
+	/// Returns the children ordered by name, starting after the entry called `after` if given
+	pub fn children(&self, conn: &mut AnyConnection, after: Option<&str>, limit: Option<i64>) -> Result<impl Iterator<Item = DirectoryChild>, DieselError> {
+		let mut query = directory_entries::table
+			.left_join(directories::table.on(directory_entries::directory.eq(directories::id.nullable())))
 			.left_join(files::table)
 			.filter(directory_entries::parent.eq(self.id))
+			// entries for non-existent nodes are skipped here so they don't count towards the limit, fsck removes them when repairing
+			.filter(directories::id.is_not_null().or(files::id.is_not_null()))
 			.select((DirectoryEntry::as_select(), Option::<Directory>::as_select(), Option::<File>::as_select()))
+			.order(directory_entries::name.asc())
+			.into_boxed();
+		
+		if let Some(after) = after {
+			query = query.filter(directory_entries::name.gt(after));
+		}
+		
+		if let Some(limit) = limit {
+			query = query.limit(limit);
+		}
+		
+		// why does rust-analyzer need a type annotation to know what type this is?
+		let entries: Vec<(DirectoryEntry, Option<Directory>, Option<File>)> = query
 			.load::<(DirectoryEntry, Option<Directory>, Option<File>)>(conn)?;
 		
 		Ok(entries.into_iter()
-			.filter_map(|entry| match entry {
-				(entry, Some(directory), None) => Some(DirectoryChild {
-					parent: entry.parent,
+			.map(|entry| match entry {
+				(entry, Some(directory), None) => DirectoryChild {
 					name: entry.name,
 					data: EntryKind::Directory(directory),
-				}),
-				(entry, None, Some(file)) => Some(DirectoryChild {
-					parent: entry.parent,
+				},
+				(entry, None, Some(file)) => DirectoryChild {
 					name: entry.name,
 					data: EntryKind::File(file),
-				}),
-				(_, None, None) => None, // TODO: entry for non-existent nodes are skipped, should they be removed?
+				},
+				(_, None, None) => panic!("should be impossible due to the filter in the query"),
 				(_, Some(_), Some(_)) => panic!("should be impossible due to the check on the directory_entries table"),
 			}))
 	}
 	
-	pub fn insert(&self, conn: &mut SqliteConnection) -> Result<(), DieselError> {
+	pub fn insert(&self, conn: &mut AnyConnection) -> Result<(), DieselError> {
 		let inserted_rows = diesel::insert_into(directories::table)
 			.values(self)
 			.execute(conn)?;
@@ -143,7 +303,43 @@ impl Directory {
 		Ok(())
 	}
 	
-	pub fn delete(conn: &mut SqliteConnection, node_id: NodeID) -> Result<bool, DieselError> {
+	/// Also bumps the version, as the parent is part of the directory's info
+	pub fn set_parent(conn: &mut AnyConnection, node_id: NodeID, new_parent: NodeID) -> Result<bool, DieselError> {
+		use schema::directories::dsl::*;
+		
+		let rows_updated = diesel::update(directories)
+			.filter(id.eq(node_id.0 as i64))
+			.set((parent.eq(new_parent.0 as i64), version.eq(version + 1)))
+			.execute(conn)?;
+		
+		Ok(rows_updated > 0)
+	}
+	
+	/// Has to be called for every change of the entries of the directory
+	pub fn bump_version(conn: &mut AnyConnection, node_id: NodeID) -> Result<(), DieselError> {
+		use schema::directories::dsl::*;
+		
+		diesel::update(directories)
+			.filter(id.eq(node_id.0 as i64))
+			.set(version.eq(version + 1))
+			.execute(conn)?;
+		
+		Ok(())
+	}
+	
+	/// Overwrites the version, for restoring it when importing
+	pub fn set_version(conn: &mut AnyConnection, node_id: NodeID, new_version: i64) -> Result<(), DieselError> {
+		use schema::directories::dsl::*;
+		
+		diesel::update(directories)
+			.filter(id.eq(node_id.0 as i64))
+			.set(version.eq(new_version))
+			.execute(conn)?;
+		
+		Ok(())
+	}
+	
+	pub fn delete(conn: &mut AnyConnection, node_id: NodeID) -> Result<bool, DieselError> {
 		use schema::directories::dsl::*;
 		
 		let deleted_rows = diesel::delete(directories.filter(id.eq(node_id.0 as i64)))
@@ -155,7 +351,7 @@ impl Directory {
 }
 
 impl File {
-	pub fn get(node_id: NodeID) -> files::BoxedQuery<'static, Sqlite, SqlTypeOf<AsSelect<Self, Sqlite>>> {
+	pub fn get(node_id: NodeID) -> files::BoxedQuery<'static, Backend, SqlTypeOf<AsSelect<Self, Backend>>> {
 		use schema::files::dsl::*;
 		
 		files.filter(id.eq(node_id.0 as i64))
@@ -163,7 +359,13 @@ impl File {
 			.into_boxed()
 	}
 	
-	pub fn exists(conn: &mut SqliteConnection, node_id: NodeID) -> Result<bool, DieselError> {
+	pub fn all() -> files::BoxedQuery<'static, Backend, SqlTypeOf<AsSelect<Self, Backend>>> {
+		files::table
+			.select(File::as_select())
+			.into_boxed()
+	}
+	
+	pub fn exists(conn: &mut AnyConnection, node_id: NodeID) -> Result<bool, DieselError> {
 		match Self::get(node_id).first(conn) {
 			Ok(_) => Ok(true),
 			Err(DieselError::NotFound) => Ok(false),
@@ -171,7 +373,7 @@ impl File {
 		}
 	}
 	
-	pub fn has_hash(conn: &mut SqliteConnection, node_id: NodeID, expected_hash: &str) -> Result<bool, DieselError> {
+	pub fn has_hash(conn: &mut AnyConnection, node_id: NodeID, expected_hash: &str) -> Result<bool, DieselError> {
 		use schema::files::dsl::*;
 		
 		let result = files.filter(id.eq(node_id.0 as i64).and(hash.eq(expected_hash)))
@@ -185,7 +387,17 @@ impl File {
 		}
 	}
 	
-	pub fn update_content(conn: &mut SqliteConnection, node_id: NodeID, prev_hash: &str, new_hash: &str, new_size: u64) -> Result<bool, DieselError> {
+	/// Returns the size of the content with the given hash if any file has that content
+	pub fn size_of_content(conn: &mut AnyConnection, content_hash: &str) -> Result<Option<i64>, DieselError> {
+		use schema::files::dsl::*;
+		
+		files.filter(hash.eq(content_hash))
+			.select(size)
+			.first(conn)
+			.optional()
+	}
+	
+	pub fn update_content(conn: &mut AnyConnection, node_id: NodeID, prev_hash: &str, new_hash: &str, new_size: u64) -> Result<bool, DieselError> {
 		use schema::files::dsl::*;
 		
 		let rows_updated = diesel::update(files)
@@ -199,7 +411,7 @@ impl File {
 		Ok(rows_updated > 0)
 	}
 	
-	pub fn insert(&self, conn: &mut SqliteConnection) -> Result<(), DieselError> {
+	pub fn insert(&self, conn: &mut AnyConnection) -> Result<(), DieselError> {
 		let inserted_rows = diesel::insert_into(files::table)
 			.values(self)
 			.execute(conn)?;
@@ -208,7 +420,7 @@ impl File {
 		Ok(())
 	}
 	
-	pub fn delete(conn: &mut SqliteConnection, node_id: NodeID) -> Result<bool, DieselError> {
+	pub fn delete(conn: &mut AnyConnection, node_id: NodeID) -> Result<bool, DieselError> {
 		use schema::files::dsl::*;
 		
 		let deleted_rows = diesel::delete(files.filter(id.eq(node_id.0 as i64)))
@@ -220,26 +432,553 @@ impl File {
 }
 
 impl DirectoryEntry {
-	pub fn get(parent_id: NodeID, entry_name: &str) -> directory_entries::BoxedQuery<'_, Sqlite, SqlTypeOf<AsSelect<DirectoryEntry, Sqlite>>> {
+	pub fn get(parent_id: NodeID, entry_name: &str) -> directory_entries::BoxedQuery<'_, Backend, SqlTypeOf<AsSelect<DirectoryEntry, Backend>>> {
 		use schema::directory_entries::dsl::*;
 		
 		directory_entries.filter(parent.eq(parent_id.0 as i64).and(name.eq(entry_name)))
 			.select(DirectoryEntry::as_select())
 			.into_boxed()
 	}
+	
+	pub fn all() -> directory_entries::BoxedQuery<'static, Backend, SqlTypeOf<AsSelect<DirectoryEntry, Backend>>> {
+		directory_entries::table
+			.select(DirectoryEntry::as_select())
+			.into_boxed()
+	}
+	
+	pub fn delete(conn: &mut AnyConnection, parent_id: NodeID, entry_name: &str) -> Result<bool, DieselError> {
+		use schema::directory_entries::dsl::*;
+		
+		let deleted_rows = diesel::delete(directory_entries.filter(parent.eq(parent_id.0 as i64).and(name.eq(entry_name))))
+			.execute(conn)?;
+		assert!(deleted_rows <= 1);
+		
+		if deleted_rows == 1 {
+			Directory::bump_version(conn, parent_id)?;
+		}
+		
+		Ok(deleted_rows == 1)
+	}
 }
 
 impl<'a> NewDirectoryEntry<'a> {
-	pub fn insert(&self, conn: &mut SqliteConnection) -> Result<(), DieselError> {
+	pub fn insert(&self, conn: &mut AnyConnection) -> Result<(), DieselError> {
 		let inserted_rows = diesel::insert_into(directory_entries::table)
 			.values(self)
 			.execute(conn)?;
 		assert_eq!(inserted_rows, 1);
 		
+		Directory::bump_version(conn, NodeID(self.parent as u64))
+	}
+}
+
+impl Quota {
+	/// A quota for a newly created top level directory without any limits
+	pub fn new(directory: NodeID) -> Self {
+		Self {
+			directory: directory.0 as i64,
+			used_bytes: 0,
+			used_nodes: 1, // the directory itself
+			max_bytes: None,
+			max_nodes: None,
+		}
+	}
+	
+	pub fn get(directory_id: NodeID) -> quotas::BoxedQuery<'static, Backend, SqlTypeOf<AsSelect<Self, Backend>>> {
+		use schema::quotas::dsl::*;
+		
+		quotas.filter(directory.eq(directory_id.0 as i64))
+			.select(Quota::as_select())
+			.into_boxed()
+	}
+	
+	pub fn all() -> quotas::BoxedQuery<'static, Backend, SqlTypeOf<AsSelect<Self, Backend>>> {
+		quotas::table
+			.select(Quota::as_select())
+			.into_boxed()
+	}
+	
+	pub fn is_exceeded(&self) -> bool {
+		self.max_bytes.is_some_and(|max| self.used_bytes > max)
+			|| self.max_nodes.is_some_and(|max| self.used_nodes > max)
+	}
+	
+	/// Returns how many bytes can be added before the limit is reached, if there is a limit
+	pub fn remaining_bytes(&self) -> Option<u64> {
+		self.max_bytes.map(|max| max.saturating_sub(self.used_bytes).max(0) as u64)
+	}
+	
+	pub fn insert(&self, conn: &mut AnyConnection) -> Result<(), DieselError> {
+		let inserted_rows = diesel::insert_into(quotas::table)
+			.values(self)
+			.execute(conn)?;
+		assert_eq!(inserted_rows, 1);
+		
+		Ok(())
+	}
+	
+	/// Adds the given (possibly negative) amounts to the usage and returns the updated quota
+	pub fn add_usage(conn: &mut AnyConnection, directory_id: NodeID, bytes: i64, nodes: i64) -> Result<Self, DieselError> {
+		use schema::quotas::dsl::*;
+		
+		// RETURNING isn't supported for multiple backends, the transaction makes sure the same row is selected
+		conn.transaction(|conn| {
+			diesel::update(quotas)
+				.filter(directory.eq(directory_id.0 as i64))
+				.set((
+					used_bytes.eq(used_bytes + bytes),
+					used_nodes.eq(used_nodes + nodes),
+				))
+				.execute(conn)?;
+			
+			Quota::get(directory_id).first(conn)
+		})
+	}
+	
+	/// Overwrites the usage, for when it has been recomputed from the content of the directory
+	pub fn set_usage(conn: &mut AnyConnection, directory_id: NodeID, bytes: i64, nodes: i64) -> Result<(), DieselError> {
+		use schema::quotas::dsl::*;
+		
+		diesel::update(quotas)
+			.filter(directory.eq(directory_id.0 as i64))
+			.set((
+				used_bytes.eq(bytes),
+				used_nodes.eq(nodes),
+			))
+			.execute(conn)?;
+		
+		Ok(())
+	}
+	
+	/// For directories that stop being top level directories, as their usage is charged to the new one instead
+	pub fn delete(conn: &mut AnyConnection, directory_id: NodeID) -> Result<(), DieselError> {
+		use schema::quotas::dsl::*;
+		
+		diesel::delete(quotas.filter(directory.eq(directory_id.0 as i64)))
+			.execute(conn)?;
+		
+		Ok(())
+	}
+	
+	pub fn set_limits(conn: &mut AnyConnection, directory_id: NodeID, new_max_bytes: Option<u64>, new_max_nodes: Option<u64>) -> Result<Self, DieselError> {
+		use schema::quotas::dsl::*;
+		
+		conn.transaction(|conn| {
+			diesel::update(quotas)
+				.filter(directory.eq(directory_id.0 as i64))
+				.set((
+					max_bytes.eq(new_max_bytes.map(|max| max as i64)),
+					max_nodes.eq(new_max_nodes.map(|max| max as i64)),
+				))
+				.execute(conn)?;
+			
+			Quota::get(directory_id).first(conn)
+		})
+	}
+}
+
+impl VolumeKey {
+	pub fn new(wrapped_key: Vec<u8>) -> Self {
+		Self {
+			id: 0, // there is only a single row
+			wrapped_key,
+		}
+	}
+	
+	pub fn get() -> volume_key::BoxedQuery<'static, Backend, SqlTypeOf<AsSelect<Self, Backend>>> {
+		volume_key::table
+			.select(VolumeKey::as_select())
+			.into_boxed()
+	}
+	
+	pub fn insert(&self, conn: &mut AnyConnection) -> Result<(), DieselError> {
+		let inserted_rows = diesel::insert_into(volume_key::table)
+			.values(self)
+			.execute(conn)?;
+		assert_eq!(inserted_rows, 1);
+		
 		Ok(())
 	}
 }
 
+impl Delegation {
+	pub fn new(file: NodeID, client: u64, writable: bool) -> Self {
+		Self {
+			file: file.0 as i64,
+			client: client as i64,
+			writable,
+			recalled_at: None,
+		}
+	}
+	
+	pub fn of_file(file_id: NodeID) -> delegations::BoxedQuery<'static, Backend, SqlTypeOf<AsSelect<Self, Backend>>> {
+		use schema::delegations::dsl::*;
+		
+		delegations.filter(file.eq(file_id.0 as i64))
+			.select(Delegation::as_select())
+			.into_boxed()
+	}
+	
+	/// Delegations the client was asked to return
+	pub fn recalled(client_id: u64) -> delegations::BoxedQuery<'static, Backend, SqlTypeOf<AsSelect<Self, Backend>>> {
+		use schema::delegations::dsl::*;
+		
+		delegations.filter(client.eq(client_id as i64))
+			.filter(recalled_at.is_not_null())
+			.select(Delegation::as_select())
+			.into_boxed()
+	}
+	
+	/// Replaces a previous delegation of the same file to the same client
+	pub fn replace(&self, conn: &mut AnyConnection) -> Result<(), DieselError> {
+		Delegation::delete(conn, NodeID(self.file as u64), self.client as u64)?;
+		
+		let inserted_rows = diesel::insert_into(delegations::table)
+			.values(self)
+			.execute(conn)?;
+		assert_eq!(inserted_rows, 1);
+		
+		Ok(())
+	}
+	
+	/// Keeps the time of an earlier recall, so the delegation is still revoked in time
+	pub fn recall(conn: &mut AnyConnection, file_id: NodeID, client_id: u64, now: i64) -> Result<(), DieselError> {
+		use schema::delegations::dsl::*;
+		
+		diesel::update(delegations)
+			.filter(file.eq(file_id.0 as i64))
+			.filter(client.eq(client_id as i64))
+			.filter(recalled_at.is_null())
+			.set(recalled_at.eq(now))
+			.execute(conn)?;
+		
+		Ok(())
+	}
+	
+	/// Returns whether there was such a delegation
+	pub fn delete(conn: &mut AnyConnection, file_id: NodeID, client_id: u64) -> Result<bool, DieselError> {
+		use schema::delegations::dsl::*;
+		
+		let deleted_rows = diesel::delete(delegations.filter(file.eq(file_id.0 as i64).and(client.eq(client_id as i64))))
+			.execute(conn)?;
+		
+		Ok(deleted_rows != 0)
+	}
+}
+
+impl LockLease {
+	/// Returns whether the client has a lease that didn't expire yet
+	pub fn is_held(conn: &mut AnyConnection, client_id: u64, now: i64) -> Result<bool, DieselError> {
+		use schema::lock_leases::dsl::*;
+		
+		let count: i64 = lock_leases.filter(client.eq(client_id as i64))
+			.filter(expires_at.gt(now))
+			.count()
+			.get_result(conn)?;
+		
+		Ok(count != 0)
+	}
+	
+	/// Replaces a previous lease of the same client
+	pub fn replace(&self, conn: &mut AnyConnection) -> Result<(), DieselError> {
+		use schema::lock_leases::dsl::*;
+		
+		diesel::delete(lock_leases.filter(client.eq(self.client)))
+			.execute(conn)?;
+		
+		let inserted_rows = diesel::insert_into(lock_leases)
+			.values(self)
+			.execute(conn)?;
+		assert_eq!(inserted_rows, 1);
+		
+		Ok(())
+	}
+	
+	/// Deletes expired leases along with the locks of their clients
+	pub fn delete_expired(conn: &mut AnyConnection, now: i64) -> Result<(), DieselError> {
+		let held = lock_leases::table
+			.filter(lock_leases::expires_at.gt(now))
+			.select(lock_leases::client);
+		
+		diesel::delete(file_locks::table.filter(file_locks::client.ne_all(held)))
+			.execute(conn)?;
+		diesel::delete(lock_leases::table.filter(lock_leases::expires_at.le(now)))
+			.execute(conn)?;
+		
+		Ok(())
+	}
+}
+
+impl Lock {
+	/// Locks on the file of clients whose lease didn't expire yet
+	pub fn held_on(file_id: NodeID, now: i64) -> file_locks::BoxedQuery<'static, Backend, SqlTypeOf<AsSelect<Self, Backend>>> {
+		let held = lock_leases::table
+			.filter(lock_leases::expires_at.gt(now))
+			.select(lock_leases::client);
+		
+		file_locks::table
+			.filter(file_locks::file.eq(file_id.0 as i64))
+			.filter(file_locks::client.eq_any(held))
+			.select(Lock::as_select())
+			.into_boxed()
+	}
+	
+	pub fn of_owner(file_id: NodeID, client_id: u64, owner_id: u64) -> file_locks::BoxedQuery<'static, Backend, SqlTypeOf<AsSelect<Self, Backend>>> {
+		use schema::file_locks::dsl::*;
+		
+		file_locks.filter(file.eq(file_id.0 as i64))
+			.filter(client.eq(client_id as i64))
+			.filter(owner.eq(owner_id as i64))
+			.select(Lock::as_select())
+			.into_boxed()
+	}
+	
+	/// Makes concurrent transactions locking the same file wait until this one ends, by updating the file without changing it
+	pub fn wait_for_others(conn: &mut AnyConnection, file_id: NodeID) -> Result<(), DieselError> {
+		use schema::files::dsl::*;
+		
+		diesel::update(files.filter(id.eq(file_id.0 as i64)))
+			.set(size.eq(size))
+			.execute(conn)?;
+		
+		Ok(())
+	}
+	
+	pub fn insert(&self, conn: &mut AnyConnection) -> Result<(), DieselError> {
+		let inserted_rows = diesel::insert_into(file_locks::table)
+			.values(self)
+			.execute(conn)?;
+		assert_eq!(inserted_rows, 1);
+		
+		Ok(())
+	}
+	
+	pub fn delete(&self, conn: &mut AnyConnection) -> Result<(), DieselError> {
+		use schema::file_locks::dsl::*;
+		
+		diesel::delete(file_locks)
+			.filter(file.eq(self.file))
+			.filter(client.eq(self.client))
+			.filter(owner.eq(self.owner))
+			.filter(range_start.eq(self.range_start))
+			.execute(conn)?;
+		
+		Ok(())
+	}
+}
+
+impl OpenTransaction {
+	pub fn insert(&self, conn: &mut AnyConnection) -> Result<(), DieselError> {
+		let inserted_rows = diesel::insert_into(transactions::table)
+			.values(self)
+			.execute(conn)?;
+		assert_eq!(inserted_rows, 1);
+		
+		Ok(())
+	}
+	
+	/// Returns whether the transaction was opened after `opened_after`, concurrent requests changing it wait until the current transaction ends
+	/// 
+	/// Updates the transaction without changing it, which locks its row.
+	pub fn lock(conn: &mut AnyConnection, transaction: i64, opened_after: i64) -> Result<bool, DieselError> {
+		use schema::transactions::dsl::*;
+		
+		let updated_rows = diesel::update(transactions.filter(id.eq(transaction)).filter(opened_at.gt(opened_after)))
+			.set(opened_at.eq(opened_at))
+			.execute(conn)?;
+		
+		Ok(updated_rows != 0)
+	}
+	
+	/// Deletes the transaction along with everything staged in it, returns whether it existed
+	pub fn delete(conn: &mut AnyConnection, transaction: i64) -> Result<bool, DieselError> {
+		use schema::transactions::dsl::*;
+		
+		let deleted_rows = diesel::delete(transactions.filter(id.eq(transaction)))
+			.execute(conn)?;
+		
+		Ok(deleted_rows != 0)
+	}
+	
+	pub fn delete_expired(conn: &mut AnyConnection, opened_before: i64) -> Result<(), DieselError> {
+		use schema::transactions::dsl::*;
+		
+		diesel::delete(transactions.filter(opened_at.le(opened_before)))
+			.execute(conn)?;
+		
+		Ok(())
+	}
+}
+
+impl StagedContent {
+	pub fn of_transaction(transaction: i64) -> staged_contents::BoxedQuery<'static, Backend, SqlTypeOf<AsSelect<Self, Backend>>> {
+		use schema::staged_contents::dsl::*;
+		
+		staged_contents.filter(transaction_id.eq(transaction))
+			.select(StagedContent::as_select())
+			.into_boxed()
+	}
+	
+	pub fn all() -> staged_contents::BoxedQuery<'static, Backend, SqlTypeOf<AsSelect<Self, Backend>>> {
+		staged_contents::table
+			.select(StagedContent::as_select())
+			.into_boxed()
+	}
+	
+	/// Number of files with staged contents in the transaction besides `except`
+	pub fn count(conn: &mut AnyConnection, transaction: i64, except: NodeID) -> Result<i64, DieselError> {
+		use schema::staged_contents::dsl::*;
+		
+		staged_contents.filter(transaction_id.eq(transaction))
+			.filter(file.ne(except.0 as i64))
+			.count()
+			.get_result(conn)
+	}
+	
+	/// Replaces content of the same file staged earlier in the transaction
+	pub fn replace(&self, conn: &mut AnyConnection) -> Result<(), DieselError> {
+		use schema::staged_contents::dsl::*;
+		
+		diesel::delete(staged_contents.filter(transaction_id.eq(self.transaction_id)).filter(file.eq(self.file)))
+			.execute(conn)?;
+		
+		let inserted_rows = diesel::insert_into(staged_contents)
+			.values(self)
+			.execute(conn)?;
+		assert_eq!(inserted_rows, 1);
+		
+		Ok(())
+	}
+}
+
+impl StagedOperation {
+	/// Returns the operations in the order they were staged
+	pub fn of_transaction(transaction: i64) -> staged_operations::BoxedQuery<'static, Backend, SqlTypeOf<AsSelect<Self, Backend>>> {
+		use schema::staged_operations::dsl::*;
+		
+		staged_operations.filter(transaction_id.eq(transaction))
+			.order(position.asc())
+			.select(StagedOperation::as_select())
+			.into_boxed()
+	}
+	
+	pub fn count(conn: &mut AnyConnection, transaction: i64) -> Result<i64, DieselError> {
+		use schema::staged_operations::dsl::*;
+		
+		staged_operations.filter(transaction_id.eq(transaction))
+			.count()
+			.get_result(conn)
+	}
+	
+	pub fn insert(&self, conn: &mut AnyConnection) -> Result<(), DieselError> {
+		let inserted_rows = diesel::insert_into(staged_operations::table)
+			.values(self)
+			.execute(conn)?;
+		assert_eq!(inserted_rows, 1);
+		
+		Ok(())
+	}
+}
+
+#[derive(QueryableByName, Debug)]
+struct NodeRow {
+	#[diesel(sql_type = diesel::sql_types::BigInt)]
+	id: i64,
+}
+
+/// Returns the directory directly inside the root directory which contains the given directory,
+/// or the directory itself if it already is such a top level directory
+/// 
+/// Returns [`None`] for the root directory.
+// numbered parameters are understood by both databases, as long as they appear in order for SQLite
+pub fn top_level_directory(conn: &mut AnyConnection, directory: NodeID) -> Result<Option<NodeID>, DieselError> {
+	let row: Option<NodeRow> = diesel::sql_query("
+		WITH RECURSIVE ancestors(id, parent) AS (
+			SELECT id, parent FROM directories WHERE id = $1
+			UNION ALL
+			SELECT directories.id, directories.parent FROM directories
+				JOIN ancestors ON directories.id = ancestors.parent
+				WHERE ancestors.parent != ancestors.id
+		)
+		SELECT id FROM ancestors WHERE parent = $2 AND id != parent
+	")
+		.bind::<diesel::sql_types::BigInt, _>(directory.0 as i64)
+		.bind::<diesel::sql_types::BigInt, _>(NodeID::ROOT.0 as i64)
+		.get_result(conn)
+		.optional()?;
+	
+	Ok(row.map(|row| NodeID(row.id as u64)))
+}
+
+#[derive(QueryableByName, Debug)]
+struct UsageRow {
+	#[diesel(sql_type = diesel::sql_types::BigInt)]
+	bytes: i64,
+	#[diesel(sql_type = diesel::sql_types::BigInt)]
+	nodes: i64,
+}
+
+/// Returns the bytes and nodes used by the given directory and everything below it, as counted by quotas
+pub fn subtree_usage(conn: &mut AnyConnection, directory: NodeID) -> Result<(i64, i64), DieselError> {
+	let row: UsageRow = diesel::sql_query("
+		WITH RECURSIVE subtree(id) AS (
+			SELECT id FROM directories WHERE id = $1
+			UNION ALL
+			SELECT directory_entries.directory FROM directory_entries
+				JOIN subtree ON directory_entries.parent = subtree.id
+				WHERE directory_entries.directory IS NOT NULL
+		)
+		SELECT
+			CAST(COALESCE(SUM(files.size), 0) AS BIGINT) AS bytes,
+			CAST(COUNT(files.id) + (SELECT COUNT(*) FROM subtree) AS BIGINT) AS nodes
+		FROM directory_entries
+			JOIN subtree ON directory_entries.parent = subtree.id
+			JOIN files ON directory_entries.file = files.id
+	")
+		.bind::<diesel::sql_types::BigInt, _>(directory.0 as i64)
+		.get_result(conn)?;
+	
+	Ok((row.bytes, row.nodes))
+}
+
+/// Returns the directory which has an entry for the given file
+pub fn containing_directory(conn: &mut AnyConnection, file_id: NodeID) -> Result<Option<NodeID>, DieselError> {
+	use schema::directory_entries::dsl::*;
+	
+	let parent_id = directory_entries.filter(file.eq(file_id.0 as i64))
+		.select(parent)
+		.first::<i64>(conn)
+		.optional()?;
+	
+	Ok(parent_id.map(|parent_id| NodeID(parent_id as u64)))
+}
+
+#[derive(QueryableByName, Debug)]
+pub struct Stats {
+	#[diesel(sql_type = diesel::sql_types::BigInt)]
+	pub file_count: i64,
+	#[diesel(sql_type = diesel::sql_types::BigInt)]
+	pub directory_count: i64,
+	#[diesel(sql_type = diesel::sql_types::BigInt)]
+	pub logical_bytes: i64,
+	#[diesel(sql_type = diesel::sql_types::BigInt)]
+	pub stored_bytes: i64,
+}
+
+impl Stats {
+	pub fn get(conn: &mut AnyConnection) -> Result<Self, DieselError> {
+		// files with the same hash share the same blob, so they only count once towards stored_bytes
+		// sums are numeric in PostgreSQL, so they are cast back
+		diesel::sql_query("
+			SELECT
+				(SELECT COUNT(*) FROM files) AS file_count,
+				(SELECT COUNT(*) FROM directories) AS directory_count,
+				(SELECT CAST(COALESCE(SUM(size), 0) AS BigInt) FROM files) AS logical_bytes,
+				(SELECT CAST(COALESCE(SUM(size), 0) AS BigInt) FROM (SELECT DISTINCT hash, size FROM files) AS blobs) AS stored_bytes
+		").get_result(conn)
+	}
+}
+
 /// Returns the next available [`NodeID`] to use for inserting a new node into the database.
 /// 
 /// Needs to be used immediately or discarded. If held onto for a long while, it's possible that
@@ -247,16 +986,19 @@ impl<'a> NewDirectoryEntry<'a> {
 /// 
 /// # Warning
 /// Don't call this function from within a transaction
-pub fn next_available_id(conn: &mut SqliteConnection) -> Result<NodeID, DieselError> {
+pub fn next_available_id(conn: &mut AnyConnection) -> Result<NodeID, DieselError> {
 	use schema::node_id::dsl::*;
 	
 	// infinitely loops if all ids are used up, not likely to occur
 	loop {
-		let id = diesel::update(node_id)
-			.set(current_id.eq(current_id + 1)) // TODO: implement overflow
-			.returning(CurrentNodeID::as_returning())
-			.get_result(conn)?
-			.current_id;
+		// RETURNING isn't supported for multiple backends, the update locks the row until the transaction ends
+		let id = conn.transaction(|conn| {
+			diesel::update(node_id)
+				.set(current_id.eq(current_id + 1)) // TODO: implement overflow
+				.execute(conn)?;
+			
+			node_id.select(current_id).get_result::<i64>(conn)
+		})?;
 		let id = NodeID(id as u64);
 		
 		if !File::exists(conn, id)? && !Directory::exists(conn, id)? {
@@ -267,3 +1009,20 @@ pub fn next_available_id(conn: &mut SqliteConnection) -> Result<NodeID, DieselEr
 		}
 	}
 }
+
+/// Returns an id that was never handed out before, unlike node ids they don't wrap around
+/// 
+/// # Warning
+/// Don't call this function from within a transaction
+pub fn next_transaction_id(conn: &mut AnyConnection) -> Result<i64, DieselError> {
+	use schema::transaction_id::dsl::*;
+	
+	// like for node ids, the update locks the row until the transaction ends
+	conn.transaction(|conn| {
+		diesel::update(transaction_id)
+			.set(current_id.eq(current_id + 1))
+			.execute(conn)?;
+		
+		transaction_id.select(current_id).get_result::<i64>(conn)
+	})
+}
@@ -1,5 +1,37 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    /// Representation of the `delegations` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    delegations (file, client) {
+        /// The `file` column of the `delegations` table.
+        ///
+        /// Its SQL type is `BigInt`.
+        ///
+        /// (Automatically generated by Diesel.)
+        file -> BigInt,
+        /// The `client` column of the `delegations` table.
+        ///
+        /// Its SQL type is `BigInt`.
+        ///
+        /// (Automatically generated by Diesel.)
+        client -> BigInt,
+        /// The `writable` column of the `delegations` table.
+        ///
+        /// Its SQL type is `Bool`.
+        ///
+        /// (Automatically generated by Diesel.)
+        writable -> Bool,
+        /// The `recalled_at` column of the `delegations` table.
+        ///
+        /// Its SQL type is `Nullable<BigInt>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        recalled_at -> Nullable<BigInt>,
+    }
+}
+
 diesel::table! {
     /// Representation of the `directories` table.
     ///
@@ -17,6 +49,12 @@ diesel::table! {
         ///
         /// (Automatically generated by Diesel.)
         parent -> BigInt,
+        /// The `version` column of the `directories` table.
+        ///
+        /// Its SQL type is `BigInt`.
+        ///
+        /// (Automatically generated by Diesel.)
+        version -> BigInt,
     }
 }
 
@@ -52,6 +90,56 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    /// Representation of the `file_locks` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    file_locks (file, client, owner, range_start) {
+        /// The `file` column of the `file_locks` table.
+        ///
+        /// Its SQL type is `BigInt`.
+        ///
+        /// (Automatically generated by Diesel.)
+        file -> BigInt,
+        /// The `client` column of the `file_locks` table.
+        ///
+        /// Its SQL type is `BigInt`.
+        ///
+        /// (Automatically generated by Diesel.)
+        client -> BigInt,
+        /// The `owner` column of the `file_locks` table.
+        ///
+        /// Its SQL type is `BigInt`.
+        ///
+        /// (Automatically generated by Diesel.)
+        owner -> BigInt,
+        /// The `exclusive` column of the `file_locks` table.
+        ///
+        /// Its SQL type is `Bool`.
+        ///
+        /// (Automatically generated by Diesel.)
+        exclusive -> Bool,
+        /// The `range_start` column of the `file_locks` table.
+        ///
+        /// Its SQL type is `BigInt`.
+        ///
+        /// (Automatically generated by Diesel.)
+        range_start -> BigInt,
+        /// The `range_end` column of the `file_locks` table.
+        ///
+        /// Its SQL type is `Nullable<BigInt>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        range_end -> Nullable<BigInt>,
+        /// The `pid` column of the `file_locks` table.
+        ///
+        /// Its SQL type is `BigInt`.
+        ///
+        /// (Automatically generated by Diesel.)
+        pid -> BigInt,
+    }
+}
+
 diesel::table! {
     /// Representation of the `files` table.
     ///
@@ -78,6 +166,26 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    /// Representation of the `lock_leases` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    lock_leases (client) {
+        /// The `client` column of the `lock_leases` table.
+        ///
+        /// Its SQL type is `BigInt`.
+        ///
+        /// (Automatically generated by Diesel.)
+        client -> BigInt,
+        /// The `expires_at` column of the `lock_leases` table.
+        ///
+        /// Its SQL type is `BigInt`.
+        ///
+        /// (Automatically generated by Diesel.)
+        expires_at -> BigInt,
+    }
+}
+
 diesel::table! {
     /// Representation of the `node_id` table.
     ///
@@ -92,11 +200,182 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    /// Representation of the `quotas` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    quotas (directory) {
+        /// The `directory` column of the `quotas` table.
+        ///
+        /// Its SQL type is `BigInt`.
+        ///
+        /// (Automatically generated by Diesel.)
+        directory -> BigInt,
+        /// The `used_bytes` column of the `quotas` table.
+        ///
+        /// Its SQL type is `BigInt`.
+        ///
+        /// (Automatically generated by Diesel.)
+        used_bytes -> BigInt,
+        /// The `used_nodes` column of the `quotas` table.
+        ///
+        /// Its SQL type is `BigInt`.
+        ///
+        /// (Automatically generated by Diesel.)
+        used_nodes -> BigInt,
+        /// The `max_bytes` column of the `quotas` table.
+        ///
+        /// Its SQL type is `Nullable<BigInt>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        max_bytes -> Nullable<BigInt>,
+        /// The `max_nodes` column of the `quotas` table.
+        ///
+        /// Its SQL type is `Nullable<BigInt>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        max_nodes -> Nullable<BigInt>,
+    }
+}
+
+diesel::table! {
+    /// Representation of the `staged_contents` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    staged_contents (transaction_id, file) {
+        /// The `transaction_id` column of the `staged_contents` table.
+        ///
+        /// Its SQL type is `BigInt`.
+        ///
+        /// (Automatically generated by Diesel.)
+        transaction_id -> BigInt,
+        /// The `file` column of the `staged_contents` table.
+        ///
+        /// Its SQL type is `BigInt`.
+        ///
+        /// (Automatically generated by Diesel.)
+        file -> BigInt,
+        /// The `prev_hash` column of the `staged_contents` table.
+        ///
+        /// Its SQL type is `Text`.
+        ///
+        /// (Automatically generated by Diesel.)
+        prev_hash -> Text,
+        /// The `hash` column of the `staged_contents` table.
+        ///
+        /// Its SQL type is `Text`.
+        ///
+        /// (Automatically generated by Diesel.)
+        hash -> Text,
+        /// The `size` column of the `staged_contents` table.
+        ///
+        /// Its SQL type is `BigInt`.
+        ///
+        /// (Automatically generated by Diesel.)
+        size -> BigInt,
+    }
+}
+
+diesel::table! {
+    /// Representation of the `staged_operations` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    staged_operations (transaction_id, position) {
+        /// The `transaction_id` column of the `staged_operations` table.
+        ///
+        /// Its SQL type is `BigInt`.
+        ///
+        /// (Automatically generated by Diesel.)
+        transaction_id -> BigInt,
+        /// The `position` column of the `staged_operations` table.
+        ///
+        /// Its SQL type is `BigInt`.
+        ///
+        /// (Automatically generated by Diesel.)
+        position -> BigInt,
+        /// The `operation` column of the `staged_operations` table.
+        ///
+        /// Its SQL type is `Binary`.
+        ///
+        /// (Automatically generated by Diesel.)
+        operation -> Binary,
+    }
+}
+
+diesel::table! {
+    /// Representation of the `transaction_id` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    transaction_id (current_id) {
+        /// The `current_id` column of the `transaction_id` table.
+        ///
+        /// Its SQL type is `BigInt`.
+        ///
+        /// (Automatically generated by Diesel.)
+        current_id -> BigInt,
+    }
+}
+
+diesel::table! {
+    /// Representation of the `transactions` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    transactions (id) {
+        /// The `id` column of the `transactions` table.
+        ///
+        /// Its SQL type is `BigInt`.
+        ///
+        /// (Automatically generated by Diesel.)
+        id -> BigInt,
+        /// The `opened_at` column of the `transactions` table.
+        ///
+        /// Its SQL type is `BigInt`.
+        ///
+        /// (Automatically generated by Diesel.)
+        opened_at -> BigInt,
+    }
+}
+
+diesel::table! {
+    /// Representation of the `volume_key` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    volume_key (id) {
+        /// The `id` column of the `volume_key` table.
+        ///
+        /// Its SQL type is `Integer`.
+        ///
+        /// (Automatically generated by Diesel.)
+        id -> Integer,
+        /// The `wrapped_key` column of the `volume_key` table.
+        ///
+        /// Its SQL type is `Binary`.
+        ///
+        /// (Automatically generated by Diesel.)
+        wrapped_key -> Binary,
+    }
+}
+
+diesel::joinable!(delegations -> files (file));
 diesel::joinable!(directory_entries -> files (file));
+diesel::joinable!(file_locks -> files (file));
+diesel::joinable!(quotas -> directories (directory));
+diesel::joinable!(staged_contents -> files (file));
+diesel::joinable!(staged_contents -> transactions (transaction_id));
+diesel::joinable!(staged_operations -> transactions (transaction_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    delegations,
     directories,
     directory_entries,
+    file_locks,
     files,
+    lock_leases,
     node_id,
+    quotas,
+    staged_contents,
+    staged_operations,
+    transaction_id,
+    transactions,
+    volume_key,
 );
@@ -1,9 +1,11 @@
 use std::{backtrace::{Backtrace, BacktraceStatus}, fmt::{Debug, Display, Formatter}};
 
 use axum::{http::StatusCode, response::{IntoResponse, Response}};
-use diesel::{connection::{AnsiTransactionManager, TransactionManager}, result::Error as DieselError, Connection, SqliteConnection};
+use diesel::{connection::TransactionManager, result::Error as DieselError, Connection as _};
 
-use crate::extractors::{Header, Location};
+use crate::{db, extractors::{ContentRange, Header, Location}};
+
+type DbTransactionManager = <db::AnyConnection as diesel::Connection>::TransactionManager;
 
 #[derive(PartialEq, Debug)]
 pub enum Error {
@@ -17,6 +19,15 @@ pub enum Error {
 	DirectoryNotEmpty,
 	Modified,
 	NotModified,
+	QuotaExceeded,
+	/// The uploaded content doesn't match the declared hash or size
+	ContentMismatch,
+	/// Another owner holds a conflicting lock
+	Locked,
+	/// A directory would be moved into itself or its own subdirectories
+	InvalidMove,
+	/// Contains the size of the content
+	RangeNotSatisfiable(u64),
 }
 
 pub struct InternalError {
@@ -94,6 +105,11 @@ impl IntoResponse for Error {
 			DirectoryNotEmpty => (StatusCode::CONFLICT, "Directory Not Empty").into_response(),
 			Modified => StatusCode::PRECONDITION_FAILED.into_response(),
 			NotModified => StatusCode::NOT_MODIFIED.into_response(),
+			QuotaExceeded => (StatusCode::INSUFFICIENT_STORAGE, "Quota Exceeded").into_response(),
+			ContentMismatch => (StatusCode::UNPROCESSABLE_ENTITY, "Content Mismatch").into_response(),
+			Locked => StatusCode::LOCKED.into_response(),
+			InvalidMove => (StatusCode::CONFLICT, "Invalid Move").into_response(),
+			RangeNotSatisfiable(size) => (StatusCode::RANGE_NOT_SATISFIABLE, Header::<ContentRange>((None, size))).into_response(),
 			Internal(internal_error) => {
 				eprintln!("{internal_error}");
 				StatusCode::INTERNAL_SERVER_ERROR.into_response()
@@ -106,9 +122,9 @@ impl IntoResponse for Error {
 /// that the error type implements [`From`]<[`diesel::result::Error`]>.
 /// 
 /// Converts all errors arising from the transaction itself to a [`Error::DbError`].
-pub fn transaction<T, C>(conn: &mut SqliteConnection, callback: C) -> Result<T, Error>
+pub fn transaction<T, C>(conn: &mut db::AnyConnection, callback: C) -> Result<T, Error>
 where
-	C: FnOnce(&mut SqliteConnection) -> Result<T, Error>,
+	C: FnOnce(&mut db::AnyConnection) -> Result<T, Error>,
 {
 	let result = conn.transaction(|conn| -> Result<T, TransactionError> {
 		Ok(callback(conn)?)
@@ -122,18 +138,18 @@ where
 }
 
 // TODO: not cancel-safe
-pub async fn async_transaction<T, C>(conn: &mut SqliteConnection, callback: C) -> Result<T, Error>
+pub async fn async_transaction<T, C>(conn: &mut db::AnyConnection, callback: C) -> Result<T, Error>
 where
-	C: async FnOnce(&mut SqliteConnection) -> Result<T, Error>,
+	C: AsyncFnOnce(&mut db::AnyConnection) -> Result<T, Error>,
 {
-	AnsiTransactionManager::begin_transaction(conn).map_err(|err| Error::internal(err, "could not begin transaction"))?;
+	DbTransactionManager::begin_transaction(conn).map_err(|err| Error::internal(err, "could not begin transaction"))?;
 	
 	match callback(conn).await {
 		Ok(result) => {
-			AnsiTransactionManager::commit_transaction(conn).map_err(|err| Error::internal(err, "could not commit transaction"))?;
+			DbTransactionManager::commit_transaction(conn).map_err(|err| Error::internal(err, "could not commit transaction"))?;
 			Ok(result)
 		},
-		Err(error) => match AnsiTransactionManager::rollback_transaction(conn) {
+		Err(error) => match DbTransactionManager::rollback_transaction(conn) {
 			Ok(()) | Err(DieselError::BrokenTransactionManager) => Err(error),
 			Err(err) => Err(Error::internal(err, format!("could not rollback transaction after error: {error}"))),
 		}
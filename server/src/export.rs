@@ -0,0 +1,412 @@
+use std::{collections::{BTreeSet, HashMap, HashSet}, fs, io::{self, BufReader, BufWriter, Read as _}, path::Path, sync::Arc};
+
+use diesel::{connection::SimpleConnection as _, OptionalExtension as _, RunQueryDsl as _};
+use fye_shared::NodeID;
+use serde::{Deserialize, Serialize};
+use tokio::runtime::Handle;
+use tokio_util::io::{ReaderStream, StreamReader, SyncIoBridge};
+
+use crate::{blob_store::{parse_blob_name, BlobStore}, compression::content_hash, db, error::{transaction, Error}, hash::EMPTY_HASH, routes::upload_file::UploadFile};
+
+/// Path of the metadata within the archive, which is the first entry
+const METADATA_PATH: &str = "metadata.postcard";
+/// Directory within the archive containing the blobs, named the same way as in the blob stores
+const BLOBS_DIRECTORY: &str = "blobs";
+/// Needs to be incremented whenever the layout of [`Metadata`] changes
+const FORMAT_VERSION: u32 = 3;
+
+/// Everything stored in the database, independent of the database used
+#[derive(Serialize, Deserialize, Debug)]
+struct Metadata {
+	current_id: i64,
+	directories: Vec<db::Directory>,
+	files: Vec<db::File>,
+	entries: Vec<db::DirectoryEntry>,
+	quotas: Vec<db::Quota>,
+	volume_key: Option<db::VolumeKey>,
+	/// Blobs referenced by files that were missing from the store, so they aren't part of the archive
+	missing_blobs: Vec<String>,
+}
+
+impl Metadata {
+	/// Returns the hashes of all blobs referenced by files
+	fn blob_hashes(&self) -> BTreeSet<&str> {
+		self.files.iter()
+			.map(|file| file.hash.as_str())
+			.filter(|hash| *hash != EMPTY_HASH) // empty files don't have a blob
+			.collect()
+	}
+	
+	/// Returns the hashes of all blobs that are part of the archive
+	fn archived_blob_hashes(&self) -> impl Iterator<Item = &str> {
+		self.blob_hashes().into_iter()
+			.filter(|hash| !self.missing_blobs.iter().any(|missing| missing == hash))
+	}
+}
+
+/// Writes the metadata and all referenced blobs into a new tar archive at `path`, returning the hashes of missing blobs
+/// 
+/// The metadata is read within a single transaction, so the archive is consistent even while the server is running.
+/// Blobs never change once stored, so they don't need to be part of that transaction, but they may be missing,
+/// for example because fsck quarantined them. Missing blobs are left out and listed in the metadata instead,
+/// so the files referencing them are still imported, and fsck reports them the same way after importing.
+pub async fn export(conn: &mut db::AnyConnection, blobs: &dyn BlobStore, path: &Path) -> Result<Vec<String>, Error> {
+	let mut metadata = load_metadata(conn)?;
+	
+	let mut missing_blobs = Vec::new();
+	for hash in metadata.blob_hashes() {
+		if !blobs.exists(hash).await.map_err(|err| Error::internal(err, format!("failed checking blob {hash}")))? {
+			missing_blobs.push(hash.to_owned());
+		}
+	}
+	metadata.missing_blobs = missing_blobs;
+	
+	let file = fs::File::create_new(path).map_err(|err| Error::internal(err, "failed creating archive"))?;
+	let result = write_archive(&metadata, blobs, file).await;
+	
+	if result.is_err() {
+		if let Err(err) = fs::remove_file(path) {
+			eprintln!("could not clean up archive at {}: {err}", path.display());
+		}
+	}
+	
+	result.map(|()| metadata.missing_blobs)
+}
+
+/// Restores an archive written by [`export`] into a store that doesn't contain anything yet
+/// 
+/// Every blob is checked against its hash before it is stored. The metadata is only inserted once all
+/// referenced blobs except the ones that were already missing are stored, so a failed import leaves nothing behind except for unreferenced blobs.
+pub async fn import(conn: &mut db::AnyConnection, blobs: Arc<dyn BlobStore>, uploads: Arc<Path>, path: &Path) -> Result<(), Error> {
+	let stats = db::Stats::get(conn).map_err(|err| Error::internal(err, "failed querying stats"))?;
+	let volume_key = db::VolumeKey::get()
+		.first(conn).optional().map_err(|err| Error::internal(err, "failed querying volume key"))?;
+	
+	// only the root directory exists in an empty store
+	if stats.file_count != 0 || stats.directory_count != 1 || volume_key.is_some() {
+		return Err(Error::internal(io::Error::other("the store is not empty"), "refusing to import"));
+	}
+	
+	let file = fs::File::open(path).map_err(|err| Error::internal(err, "failed opening archive"))?;
+	
+	let (metadata, stored) = {
+		let blobs = blobs.clone();
+		tokio::task::spawn_blocking(move || read_archive(file, &*blobs, &uploads))
+			.await.expect("reading the archive should not panic")?
+	};
+	
+	for hash in metadata.archived_blob_hashes() {
+		let exists = stored.contains(hash)
+			|| blobs.exists(hash).await.map_err(|err| Error::internal(err, format!("failed checking blob {hash}")))?;
+		
+		if !exists {
+			return Err(invalid_archive(format!("blob {hash} is missing")));
+		}
+	}
+	
+	insert_metadata(conn, &metadata)
+}
+
+fn load_metadata(conn: &mut db::AnyConnection) -> Result<Metadata, Error> {
+	transaction(conn, |conn| {
+		// every statement would see a different snapshot otherwise
+		if let db::AnyConnection::Postgres(conn) = conn {
+			conn.batch_execute("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+				.map_err(|err| Error::internal(err, "failed setting isolation level"))?;
+		}
+		
+		Ok(Metadata {
+			current_id: db::CurrentNodeID::get()
+				.first(conn).map_err(|err| Error::internal(err, "failed loading current id"))?
+				.current_id,
+			directories: db::Directory::all()
+				.load(conn).map_err(|err| Error::internal(err, "failed loading directories"))?,
+			files: db::File::all()
+				.load(conn).map_err(|err| Error::internal(err, "failed loading files"))?,
+			entries: db::DirectoryEntry::all()
+				.load(conn).map_err(|err| Error::internal(err, "failed loading directory entries"))?,
+			quotas: db::Quota::all()
+				.load(conn).map_err(|err| Error::internal(err, "failed loading quotas"))?,
+			volume_key: db::VolumeKey::get()
+				.first(conn).optional().map_err(|err| Error::internal(err, "failed loading volume key"))?,
+			missing_blobs: Vec::new(),
+		})
+	})
+}
+
+async fn write_archive(metadata: &Metadata, blobs: &dyn BlobStore, file: fs::File) -> Result<(), Error> {
+	let mut encoded = postcard::to_stdvec(&FORMAT_VERSION).expect("should be serializable");
+	postcard::to_io(metadata, &mut encoded).map_err(|err| Error::internal(err, "failed encoding metadata"))?;
+	
+	let mut archive = tar::Builder::new(BufWriter::new(file));
+	archive.append_data(&mut entry_header(encoded.len() as u64), METADATA_PATH, &*encoded)
+		.map_err(|err| Error::internal(err, "failed writing metadata"))?;
+	
+	for hash in metadata.archived_blob_hashes() {
+		let blob = blobs.get(hash).await.map_err(|err| match err.kind() {
+			io::ErrorKind::NotFound => Error::internal(err, format!("blob {hash} was removed while exporting")),
+			_ => Error::internal(err, format!("failed reading blob {hash}")),
+		})?;
+		
+		let name = match blob.is_compressed {
+			true => format!("{BLOBS_DIRECTORY}/{hash}.zst"),
+			false => format!("{BLOBS_DIRECTORY}/{hash}"),
+		};
+		let mut header = entry_header(blob.size);
+		let reader = SyncIoBridge::new(StreamReader::new(blob.stream)).take(blob.size);
+		
+		// the bridge blocks on the stream, which isn't allowed within the runtime
+		archive = tokio::task::spawn_blocking(move || {
+			archive.append_data(&mut header, name, reader)?;
+			Ok::<_, io::Error>(archive)
+		}).await.expect("writing the archive should not panic")
+			.map_err(|err| Error::internal(err, format!("failed writing blob {hash}")))?;
+	}
+	
+	tokio::task::spawn_blocking(move || {
+		let file = archive.into_inner()?
+			.into_inner().map_err(io::IntoInnerError::into_error)?;
+		file.sync_all()
+	}).await.expect("writing the archive should not panic")
+		.map_err(|err| Error::internal(err, "failed finishing archive"))
+}
+
+fn entry_header(size: u64) -> tar::Header {
+	let mut header = tar::Header::new_ustar();
+	header.set_entry_type(tar::EntryType::Regular);
+	header.set_mode(0o644);
+	header.set_size(size);
+	
+	header
+}
+
+/// Stores all blobs in the archive and returns the metadata along with the hashes of the stored blobs
+/// 
+/// Blocks, so needs to run outside of the runtime.
+fn read_archive(file: fs::File, blobs: &dyn BlobStore, uploads: &Path) -> Result<(Metadata, HashSet<String>), Error> {
+	let runtime = Handle::current();
+	let mut archive = tar::Archive::new(BufReader::new(file));
+	
+	let mut metadata = None;
+	let mut stored = HashSet::new();
+	
+	for entry in archive.entries().map_err(|err| Error::internal(err, "failed reading archive"))? {
+		let mut entry = entry.map_err(|err| Error::internal(err, "failed reading archive entry"))?;
+		let entry_path = entry.path().map_err(|err| Error::internal(err, "failed reading archive entry"))?.into_owned();
+		
+		if entry_path == Path::new(METADATA_PATH) {
+			let mut encoded = Vec::new();
+			entry.read_to_end(&mut encoded).map_err(|err| Error::internal(err, "failed reading metadata"))?;
+			metadata = Some(decode_metadata(&encoded)?);
+			continue;
+		}
+		
+		let Some((hash, is_compressed)) = entry_path.strip_prefix(BLOBS_DIRECTORY).ok()
+			.and_then(Path::to_str)
+			.and_then(parse_blob_name)
+		else {
+			return Err(invalid_archive(format!("unexpected entry {}", entry_path.display())));
+		};
+		
+		let upload = runtime.block_on(UploadFile::new(uploads.join(format!("import-{hash}"))))
+			.map_err(|err| Error::internal(err, "failed creating upload file"))?;
+		
+		let mut destination = fs::OpenOptions::new().write(true).truncate(true).open(upload.path())
+			.map_err(|err| Error::internal(err, "failed opening upload file"))?;
+		io::copy(&mut entry, &mut destination).map_err(|err| Error::internal(err, format!("failed extracting blob {hash}")))?;
+		destination.sync_all().map_err(|err| Error::internal(err, format!("failed extracting blob {hash}")))?;
+		
+		runtime.block_on(store_blob(blobs, upload, hash, is_compressed))?;
+		stored.insert(hash.to_owned());
+	}
+	
+	let metadata = metadata.ok_or_else(|| invalid_archive("metadata is missing"))?;
+	Ok((metadata, stored))
+}
+
+fn decode_metadata(encoded: &[u8]) -> Result<Metadata, Error> {
+	let (version, encoded): (u32, _) = postcard::take_from_bytes(encoded)
+		.map_err(|err| Error::internal(err, "failed decoding metadata"))?;
+	
+	if version != FORMAT_VERSION {
+		return Err(invalid_archive(format!("unsupported format version {version}")));
+	}
+	
+	postcard::from_bytes(encoded).map_err(|err| Error::internal(err, "failed decoding metadata"))
+}
+
+/// Stores the extracted blob after checking that its content matches the hash
+async fn store_blob(blobs: &dyn BlobStore, upload: UploadFile, hash: &str, is_compressed: bool) -> Result<(), Error> {
+	let file = tokio::fs::File::open(upload.path()).await.map_err(|err| Error::internal(err, "failed opening upload file"))?;
+	
+	// a blob that fails to decompress is as invalid as one with the wrong hash
+	let matches = match content_hash(Box::pin(ReaderStream::new(file)), is_compressed).await {
+		Ok(actual) => actual.to_hex().as_str() == hash,
+		Err(_) => false,
+	};
+	
+	if !matches {
+		return Err(invalid_archive(format!("content of blob {hash} doesn't match its hash")));
+	}
+	
+	blobs.put_file(hash, is_compressed, upload).await
+		.map_err(|err| Error::internal(err, format!("failed storing blob {hash}")))
+}
+
+fn insert_metadata(conn: &mut db::AnyConnection, metadata: &Metadata) -> Result<(), Error> {
+	let directories = parents_first(&metadata.directories)?;
+	
+	transaction(conn, |conn| {
+		for dir in directories {
+			dir.insert(conn).map_err(|err| Error::internal(err, "failed inserting directory"))?;
+		}
+		
+		for file in &metadata.files {
+			file.insert(conn).map_err(|err| Error::internal(err, "failed inserting file"))?;
+		}
+		
+		for entry in &metadata.entries {
+			let entry = db::NewDirectoryEntry {
+				parent: entry.parent,
+				name: &entry.name,
+				directory: entry.directory,
+				file: entry.file,
+			};
+			entry.insert(conn).map_err(|err| Error::internal(err, "failed inserting directory entry"))?;
+		}
+		
+		// inserting the entries bumped the versions, and the root directory wasn't inserted at all
+		for dir in &metadata.directories {
+			db::Directory::set_version(conn, NodeID(dir.id as u64), dir.version).map_err(|err| Error::internal(err, "failed updating directory version"))?;
+		}
+		
+		for quota in &metadata.quotas {
+			quota.insert(conn).map_err(|err| Error::internal(err, "failed inserting quota"))?;
+		}
+		
+		if let Some(volume_key) = &metadata.volume_key {
+			volume_key.insert(conn).map_err(|err| Error::internal(err, "failed inserting volume key"))?;
+		}
+		
+		db::CurrentNodeID::set(conn, metadata.current_id).map_err(|err| Error::internal(err, "failed updating current id"))
+	})
+}
+
+/// Orders the directories so that parents are inserted before their children,
+/// leaving out the root directory which always exists
+fn parents_first(directories: &[db::Directory]) -> Result<Vec<&db::Directory>, Error> {
+	let root = NodeID::ROOT.0 as i64;
+	
+	let mut children: HashMap<i64, Vec<&db::Directory>> = HashMap::new();
+	for dir in directories.iter().filter(|dir| dir.id != root) {
+		children.entry(dir.parent).or_default().push(dir);
+	}
+	
+	let mut sorted = Vec::with_capacity(directories.len());
+	let mut pending = vec![root];
+	
+	while let Some(id) = pending.pop() {
+		for child in children.remove(&id).unwrap_or_default() {
+			pending.push(child.id);
+			sorted.push(child);
+		}
+	}
+	
+	if !children.is_empty() {
+		return Err(invalid_archive("some directories don't reach the root directory"));
+	}
+	
+	Ok(sorted)
+}
+
+fn invalid_archive(message: impl Into<String>) -> Error {
+	Error::internal(io::Error::new(io::ErrorKind::InvalidData, message.into()), "invalid archive")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	use axum::extract::Path as UrlPath;
+	use axum_postcard::Postcard;
+	use fye_shared::Hash;
+	
+	use crate::{extractors::{BodyStream, Header, Location, OptHeader}, routes::{create_dir, create_file, DelegationRecalls, dir_info, file_data, write_file_data, write_lock::FileWriteLock}, testing::*};
+	
+	#[tokio::test(flavor = "multi_thread")]
+	async fn export_then_import() {
+		let mut db = TestDb::new();
+		let directories = TestDirectories::new();
+		
+		let (_, Header(location)) = create_dir(db.conn(), UrlPath(NodeID::ROOT), Postcard("dir".to_owned())).await.unwrap();
+		let Location::Directory(dir_id) = location else {panic!()};
+		
+		let (_, Header(location), _) = create_file(db.conn(), UrlPath(dir_id), Postcard("file".to_owned())).await.unwrap();
+		let Location::File(file_id) = location else {panic!()};
+		
+		let content = b"compressible ".repeat(100);
+		let stream = futures::stream::iter([Ok::<_, io::Error>(bytes::Bytes::from(content.clone()))]);
+		write_file_data(db.conn(), directories.dirs(), directories.blobs(), FileWriteLock::default(), DelegationRecalls::default(), OptHeader(None), UrlPath(file_id), Header(Hash(EMPTY_HASH.to_owned())), Header(hash_of(&content)), Header(content.len() as u64), BodyStream::from_stream(stream)).await.unwrap();
+		
+		let (Header(etag), _) = dir_info(db.conn(), UrlPath(dir_id), OptHeader(None)).await.unwrap();
+		
+		let archive = directories.files().with_file_name("export.tar");
+		assert_eq!(export(&mut db.conn(), &*directories.blobs(), &archive).await.unwrap(), Vec::<String>::new());
+		
+		// an existing archive isn't overwritten and a store that isn't empty isn't imported into
+		export(&mut db.conn(), &*directories.blobs(), &archive).await.unwrap_err();
+		import(&mut db.conn(), directories.blobs().0, directories.dirs().uploads, &archive).await.unwrap_err();
+		
+		let mut imported_db = TestDb::new();
+		let imported_directories = TestDirectories::new();
+		import(&mut imported_db.conn(), imported_directories.blobs().0, imported_directories.dirs().uploads, &archive).await.unwrap();
+		
+		let (Header(imported_etag), Postcard(dir)) = dir_info(imported_db.conn(), UrlPath(dir_id), OptHeader(None)).await.unwrap();
+		assert_eq!(dir.children["file"], file_id);
+		// the version doesn't start over, which would let an older listing match again after a few changes
+		assert_eq!(imported_etag, etag);
+		
+		let (_, _, _, _, _, body) = file_data(imported_db.conn(), imported_directories.blobs(), UrlPath(file_id), OptHeader(None), OptHeader(None), OptHeader(None), OptHeader(None)).await.unwrap();
+		assert_eq!(axum::body::to_bytes(body, usize::MAX).await.unwrap(), content);
+		
+		// new ids continue after the imported ones
+		let (_, Header(location)) = create_dir(imported_db.conn(), UrlPath(NodeID::ROOT), Postcard("new".to_owned())).await.unwrap();
+		let Location::Directory(new_id) = location else {panic!()};
+		assert!(new_id.0 > file_id.0);
+	}
+	
+	#[tokio::test(flavor = "multi_thread")]
+	async fn export_with_quarantined_blob() {
+		let mut db = TestDb::new();
+		let directories = TestDirectories::new();
+		
+		let mut write = async |name: &str, content: &[u8]| {
+			let (_, Header(location), _) = create_file(db.conn(), UrlPath(NodeID::ROOT), Postcard(name.to_owned())).await.unwrap();
+			let Location::File(id) = location else {panic!()};
+			
+			let stream = futures::stream::iter([Ok::<_, io::Error>(bytes::Bytes::from(content.to_owned()))]);
+			write_file_data(db.conn(), directories.dirs(), directories.blobs(), FileWriteLock::default(), DelegationRecalls::default(), OptHeader(None), UrlPath(id), Header(Hash(EMPTY_HASH.to_owned())), Header(hash_of(content)), Header(content.len() as u64), BodyStream::from_stream(stream)).await.unwrap();
+			id
+		};
+		
+		let good_id = write("good", b"good").await;
+		write("bad", b"bad").await;
+		
+		let bad_hash = hash_of(b"bad");
+		directories.blobs().quarantine(&bad_hash.0).await.unwrap();
+		
+		let archive = directories.files().with_file_name("export.tar");
+		assert_eq!(export(&mut db.conn(), &*directories.blobs(), &archive).await.unwrap(), [bad_hash.0.as_str()]);
+		
+		let mut imported_db = TestDb::new();
+		let imported_directories = TestDirectories::new();
+		import(&mut imported_db.conn(), imported_directories.blobs().0, imported_directories.dirs().uploads, &archive).await.unwrap();
+		
+		let (_, _, _, _, _, body) = file_data(imported_db.conn(), imported_directories.blobs(), UrlPath(good_id), OptHeader(None), OptHeader(None), OptHeader(None), OptHeader(None)).await.unwrap();
+		assert_eq!(axum::body::to_bytes(body, usize::MAX).await.unwrap(), &b"good"[..]);
+		
+		// the imported store is missing the same blob
+		let report = crate::fsck::check(&mut imported_db.conn(), &*imported_directories.blobs(), false).await.unwrap();
+		assert_eq!(report.missing_blobs, [bad_hash]);
+	}
+}
@@ -2,7 +2,7 @@ use std::{convert::Infallible, future::{self, Future}, io, marker::{PhantomData,
 
 use axum::{body::BodyDataStream, extract::{FromRequest, FromRequestParts, Request}, http::request::Parts};
 use bytes::Bytes;
-use diesel::{r2d2::R2D2Connection, SqliteConnection};
+use diesel::{connection::SimpleConnection, r2d2::R2D2Connection};
 use futures::{FutureExt, Stream};
 use pin_project::pin_project;
 use r2d2::{ManageConnection, Pool, PooledConnection};
@@ -12,7 +12,7 @@ use axum::{body::Body, BoxError};
 #[cfg(test)]
 use futures::TryStream;
 
-use crate::{db, error::Error, routes::write_lock::FileWriteLock};
+use crate::{blob_store::BlobStore, db, error::Error, routes::{write_lock::FileWriteLock, DelegationRecalls}};
 
 mod headers;
 pub use headers::*;
@@ -23,15 +23,21 @@ type BoxedFuture<'a, O> = Pin<Box<dyn Future<Output = O> + Send + 'a>>;
 pub struct AppState {
 	db_pool: Pool<ConnectionManager>,
 	directories: Directories,
+	blobs: Blobs,
 	file_write_lock: FileWriteLock,
+	delegation_recalls: DelegationRecalls,
+	storage_limit: StorageLimit,
 }
 
 impl AppState {
-	pub fn new(db_pool: Pool<ConnectionManager>, directories: Directories) -> Self {
+	pub fn new(db_pool: Pool<ConnectionManager>, directories: Directories, blobs: Blobs, storage_limit: StorageLimit) -> Self {
 		Self {
 			db_pool,
 			directories,
+			blobs,
 			file_write_lock: Default::default(),
+			delegation_recalls: Default::default(),
+			storage_limit,
 		}
 	}
 }
@@ -39,7 +45,6 @@ impl AppState {
 #[derive(Clone, Debug)]
 pub struct Directories {
 	pub uploads: Arc<Path>,
-	pub files: Arc<Path>,
 }
 
 impl FromRequestParts<AppState> for Directories {
@@ -54,6 +59,45 @@ impl FromRequestParts<AppState> for Directories {
 	}
 }
 
+#[derive(Clone, Debug)]
+pub struct Blobs(pub Arc<dyn BlobStore>);
+
+impl Deref for Blobs {
+	type Target = dyn BlobStore;
+	
+	fn deref(&self) -> &Self::Target {
+		&*self.0
+	}
+}
+
+impl FromRequestParts<AppState> for Blobs {
+	type Rejection = Infallible;
+	
+	fn from_request_parts<'p, 's, 'f>(_parts: &mut Parts, state: &'s AppState) -> BoxedFuture<'f, Result<Self, Self::Rejection>>
+	where
+		's: 'f,
+		'p: 'f,
+	{
+		future::ready(Ok(state.blobs.clone())).boxed()
+	}
+}
+
+/// Maximum number of bytes the server may use for file contents, if any
+#[derive(Clone, Copy, Debug)]
+pub struct StorageLimit(pub Option<u64>);
+
+impl FromRequestParts<AppState> for StorageLimit {
+	type Rejection = Infallible;
+	
+	fn from_request_parts<'p, 's, 'f>(_parts: &mut Parts, state: &'s AppState) -> BoxedFuture<'f, Result<Self, Self::Rejection>>
+	where
+		's: 'f,
+		'p: 'f,
+	{
+		future::ready(Ok(state.storage_limit)).boxed()
+	}
+}
+
 impl FromRequestParts<AppState> for FileWriteLock {
 	type Rejection = Infallible;
 	
@@ -66,6 +110,18 @@ impl FromRequestParts<AppState> for FileWriteLock {
 	}
 }
 
+impl FromRequestParts<AppState> for DelegationRecalls {
+	type Rejection = Infallible;
+	
+	fn from_request_parts<'p, 's, 'f>(_parts: &mut Parts, state: &'s AppState) -> BoxedFuture<'f, Result<Self, Self::Rejection>>
+	where
+		's: 'f,
+		'p: 'f,
+	{
+		future::ready(Ok(state.delegation_recalls.clone())).boxed()
+	}
+}
+
 #[derive(Debug)]
 pub struct ConnectionManager {
 	url: String,
@@ -82,7 +138,7 @@ impl ConnectionManager {
 }
 
 impl ManageConnection for ConnectionManager {
-	type Connection = SqliteConnection;
+	type Connection = db::AnyConnection;
 	type Error = diesel::r2d2::Error;
 	
 	fn connect(&self) -> Result<Self::Connection, Self::Error> {
@@ -98,11 +154,14 @@ impl ManageConnection for ConnectionManager {
 	}
 	
 	fn is_valid(&self, conn: &mut Self::Connection) -> Result<(), Self::Error> {
-		conn.ping().map_err(diesel::r2d2::Error::QueryError)
+		conn.batch_execute("SELECT 1").map_err(diesel::r2d2::Error::QueryError)
 	}
 	
 	fn has_broken(&self, conn: &mut Self::Connection) -> bool {
-		std::thread::panicking() || conn.is_broken()
+		std::thread::panicking() || match conn {
+			db::AnyConnection::Sqlite(conn) => conn.is_broken(),
+			db::AnyConnection::Postgres(conn) => conn.is_broken(),
+		}
 	}
 }
 
@@ -114,15 +173,17 @@ impl<'a> DbConnection<'a> {
 	}
 	
 	#[cfg(test)]
-	pub fn from_single(conn: &'a mut SqliteConnection) -> Self {
+	pub fn from_single(conn: &'a mut db::AnyConnection) -> Self {
 		Self(ConnectionKind::Single(conn))
 	}
 }
 
+// pooled connections are larger, but single ones only exist in tests
+#[allow(clippy::large_enum_variant)]
 enum ConnectionKind<'a> {
 	Pooled(PooledConnection<ConnectionManager>, PhantomData<&'a ()>),
 	#[cfg(test)]
-	Single(&'a mut SqliteConnection),
+	Single(&'a mut db::AnyConnection),
 }
 
 impl FromRequestParts<AppState> for DbConnection<'static> {
@@ -151,7 +212,7 @@ impl FromRequestParts<AppState> for DbConnection<'static> {
 }
 
 impl<'a> Deref for ConnectionKind<'a> {
-	type Target = SqliteConnection;
+	type Target = db::AnyConnection;
 	
 	fn deref(&self) -> &Self::Target {
 		match self {
@@ -173,7 +234,7 @@ impl<'a> DerefMut for ConnectionKind<'a> {
 }
 
 impl<'a> Deref for DbConnection<'a> {
-	type Target = SqliteConnection;
+	type Target = db::AnyConnection;
 	
 	fn deref(&self) -> &Self::Target {
 		self.0.deref()
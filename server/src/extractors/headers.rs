@@ -2,7 +2,7 @@ use std::{convert::Infallible, future};
 
 use axum::{extract::FromRequestParts, http::{header, request::Parts, HeaderName, HeaderValue}, response::{IntoResponse, IntoResponseParts, Response, ResponseParts}};
 use futures::FutureExt;
-use fye_shared::{Hash, NodeID};
+use fye_shared::{Hash, NodeID, CLIENT_ID_HEADER, CONTENT_HASH_HEADER, CONTENT_SIZE_HEADER};
 
 use crate::error::Error;
 
@@ -126,10 +126,154 @@ impl HeaderType for ETag {
 	}
 }
 
+/// ETag of file content, which is the hash of the content once it is decoded
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ContentETag {
+	Strong(Hash),
+	/// The content is sent encoded, so it is only semantically equivalent to content with the hash
+	Weak(Hash),
+}
+
+impl HeaderType for ContentETag {
+	type Data = Self;
+	
+	const HEADER_NAME: HeaderName = header::ETAG;
+	const MISSING_ERROR: Error = Error::HashMissing;
+	
+	fn parse(header_value: &HeaderValue) -> Result<Self::Data, Error> {
+		let hash = Hash::from_header(header_value).ok_or(Error::BadRequest)?;
+		
+		match header_value.as_bytes().starts_with(b"W/") {
+			true => Ok(Self::Weak(hash)),
+			false => Ok(Self::Strong(hash)),
+		}
+	}
+	
+	fn encode(data: Self::Data) -> HeaderValue {
+		match data {
+			Self::Strong(hash) => hash.to_header(),
+			Self::Weak(hash) => format!("W/\"{}\"", hash.0).parse().expect("should be a valid header value"),
+		}
+	}
+}
+
+/// ETag of the info of a node, which is the hash of files and the version of directories
+/// 
+/// Versions are prefixed with `v`, which never starts a hash.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum NodeETag {
+	File(Hash),
+	Directory(i64),
+}
+
+impl HeaderType for NodeETag {
+	type Data = Self;
+	
+	const HEADER_NAME: HeaderName = header::ETAG;
+	const MISSING_ERROR: Error = Error::HashMissing;
+	
+	fn parse(header_value: &HeaderValue) -> Result<Self::Data, Error> {
+		let tag = Hash::from_header(header_value).ok_or(Error::BadRequest)?;
+		
+		match tag.0.strip_prefix('v') {
+			Some(version) => version.parse().map(Self::Directory).map_err(|_| Error::BadRequest),
+			None => Ok(Self::File(tag)),
+		}
+	}
+	
+	fn encode(data: Self::Data) -> HeaderValue {
+		match data {
+			Self::File(hash) => hash.to_header(),
+			Self::Directory(version) => format!("\"v{version}\"").parse().expect("should be a valid header value"),
+		}
+	}
+}
+
+/// If-None-Match for the info of a node, see [`NodeETag`]
+#[derive(Debug)]
+pub struct IfNoneMatchNode;
+
+impl HeaderType for IfNoneMatchNode {
+	type Data = NodeETag;
+	
+	const HEADER_NAME: HeaderName = header::IF_NONE_MATCH;
+	const MISSING_ERROR: Error = Error::HashMissing;
+	
+	fn parse(header_value: &HeaderValue) -> Result<Self::Data, Error> {
+		NodeETag::parse(header_value)
+	}
+	
+	fn encode(data: Self::Data) -> HeaderValue {
+		NodeETag::encode(data)
+	}
+}
+
+/// Declared hash of the content being uploaded
+#[derive(Debug)]
+pub struct ContentHash;
+
+impl HeaderType for ContentHash {
+	type Data = Hash;
+	
+	const HEADER_NAME: HeaderName = HeaderName::from_static(CONTENT_HASH_HEADER);
+	const MISSING_ERROR: Error = Error::HashMissing;
+	
+	fn parse(header_value: &HeaderValue) -> Result<Self::Data, Error> {
+		Hash::from_header(header_value).ok_or(Error::BadRequest)
+	}
+	
+	fn encode(data: Self::Data) -> HeaderValue {
+		data.to_header()
+	}
+}
+
+/// Declared size of the content being uploaded, which isn't necessarily the size of the body
+#[derive(Debug)]
+pub struct ContentSize;
+
+impl HeaderType for ContentSize {
+	type Data = u64;
+	
+	const HEADER_NAME: HeaderName = HeaderName::from_static(CONTENT_SIZE_HEADER);
+	const MISSING_ERROR: Error = Error::BadRequest;
+	
+	fn parse(header_value: &HeaderValue) -> Result<Self::Data, Error> {
+		header_value.to_str().ok()
+			.and_then(|size| size.parse().ok())
+			.ok_or(Error::BadRequest)
+	}
+	
+	fn encode(data: Self::Data) -> HeaderValue {
+		data.into()
+	}
+}
+
+/// Client making a change, whose own delegations don't have to be recalled for it
+#[derive(Debug)]
+pub struct ClientId;
+
+impl HeaderType for ClientId {
+	type Data = u64;
+	
+	const HEADER_NAME: HeaderName = HeaderName::from_static(CLIENT_ID_HEADER);
+	const MISSING_ERROR: Error = Error::BadRequest;
+	
+	fn parse(header_value: &HeaderValue) -> Result<Self::Data, Error> {
+		header_value.to_str().ok()
+			.and_then(|id| id.parse().ok())
+			.ok_or(Error::BadRequest)
+	}
+	
+	fn encode(data: Self::Data) -> HeaderValue {
+		data.into()
+	}
+}
+
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub enum Location {
 	Directory(NodeID),
 	File(NodeID),
+	VolumeKey,
 }
 
 impl HeaderType for Location {
@@ -146,6 +290,237 @@ impl HeaderType for Location {
 		match data {
 			Self::Directory(id) => format!("/api/dir/{id}"),
 			Self::File(id) => format!("/api/file/{id}"),
+			Self::VolumeKey => "/api/volume-key".to_owned(),
 		}.parse().expect("should be a valid header value")
 	}
 }
+
+/// A single byte range, requests for multiple ranges aren't supported
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ByteRange {
+	/// Inclusive on both ends
+	FromTo(u64, u64),
+	From(u64),
+	/// The given number of bytes at the end
+	Last(u64),
+}
+
+impl ByteRange {
+	/// Returns the start and (exclusive) end within content of the given size,
+	/// or [`None`] if the range is not satisfiable
+	pub fn resolve(self, size: u64) -> Option<(u64, u64)> {
+		let (start, end) = match self {
+			Self::FromTo(start, end) => (start, end.saturating_add(1).min(size)),
+			Self::From(start) => (start, size),
+			Self::Last(0) => return None,
+			Self::Last(length) => (size.saturating_sub(length), size),
+		};
+		
+		(start < end).then_some((start, end))
+	}
+}
+
+#[derive(Debug)]
+pub struct Range;
+
+impl HeaderType for Range {
+	type Data = ByteRange;
+	
+	const HEADER_NAME: HeaderName = header::RANGE;
+	const MISSING_ERROR: Error = Error::BadRequest;
+	
+	fn parse(header_value: &HeaderValue) -> Result<Self::Data, Error> {
+		let range = header_value.to_str().ok()
+			.and_then(|value| value.strip_prefix("bytes="))
+			.and_then(|range| range.split_once('-'))
+			.ok_or(Error::BadRequest)?;
+		
+		let parse = |number: &str| number.trim().parse::<u64>().map_err(|_| Error::BadRequest);
+		
+		match range {
+			("", length) => Ok(ByteRange::Last(parse(length)?)),
+			(start, "") => Ok(ByteRange::From(parse(start)?)),
+			(start, end) => match (parse(start)?, parse(end)?) {
+				(start, end) if start <= end => Ok(ByteRange::FromTo(start, end)),
+				_ => Err(Error::BadRequest),
+			},
+		}
+	}
+	
+	fn encode(data: Self::Data) -> HeaderValue {
+		match data {
+			ByteRange::FromTo(start, end) => format!("bytes={start}-{end}"),
+			ByteRange::From(start) => format!("bytes={start}-"),
+			ByteRange::Last(length) => format!("bytes=-{length}"),
+		}.parse().expect("should be a valid header value")
+	}
+}
+
+#[derive(Debug)]
+pub struct ContentRange;
+
+impl HeaderType for ContentRange {
+	/// Start and exclusive end of the range, if it was satisfiable, and the total size
+	type Data = (Option<(u64, u64)>, u64);
+	
+	const HEADER_NAME: HeaderName = header::CONTENT_RANGE;
+	const MISSING_ERROR: Error = Error::BadRequest;
+	
+	fn parse(header_value: &HeaderValue) -> Result<Self::Data, Error> {
+		let (range, size) = header_value.to_str().ok()
+			.and_then(|value| value.strip_prefix("bytes "))
+			.and_then(|value| value.split_once('/'))
+			.ok_or(Error::BadRequest)?;
+		
+		let parse = |number: &str| number.parse::<u64>().map_err(|_| Error::BadRequest);
+		let size = parse(size)?;
+		
+		if range == "*" {
+			return Ok((None, size));
+		}
+		
+		let (start, last) = range.split_once('-').ok_or(Error::BadRequest)?;
+		
+		match (parse(start)?, parse(last)?) {
+			(start, last) if start <= last && last < size => Ok((Some((start, last + 1)), size)),
+			_ => Err(Error::BadRequest),
+		}
+	}
+	
+	fn encode((range, size): Self::Data) -> HeaderValue {
+		match range {
+			Some((start, end)) => format!("bytes {start}-{}/{size}", end - 1),
+			None => format!("bytes */{size}"),
+		}.parse().expect("should be a valid header value")
+	}
+}
+
+/// Request header the response depends on besides the URL
+#[derive(Debug)]
+pub struct Vary;
+
+impl HeaderType for Vary {
+	type Data = HeaderName;
+	
+	const HEADER_NAME: HeaderName = header::VARY;
+	const MISSING_ERROR: Error = Error::BadRequest;
+	
+	fn parse(header_value: &HeaderValue) -> Result<Self::Data, Error> {
+		HeaderName::from_bytes(header_value.as_bytes()).map_err(|_| Error::BadRequest)
+	}
+	
+	fn encode(data: Self::Data) -> HeaderValue {
+		data.into()
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ContentCoding {
+	Zstd,
+}
+
+#[derive(Debug)]
+pub struct AcceptEncoding;
+
+impl HeaderType for AcceptEncoding {
+	/// Only the supported codings which are acceptable to the client
+	type Data = Vec<ContentCoding>;
+	
+	const HEADER_NAME: HeaderName = header::ACCEPT_ENCODING;
+	const MISSING_ERROR: Error = Error::BadRequest;
+	
+	fn parse(header_value: &HeaderValue) -> Result<Self::Data, Error> {
+		let value = header_value.to_str().map_err(|_| Error::BadRequest)?;
+		
+		let codings = value.split(',')
+			.filter_map(|item| {
+				let mut parts = item.split(';').map(str::trim);
+				let coding = parts.next()?;
+				
+				// a quality of 0 means the coding is not acceptable
+				if parts.any(|param| param.strip_prefix("q=").and_then(|quality| quality.parse::<f32>().ok()) == Some(0.0)) {
+					return None;
+				}
+				
+				coding.eq_ignore_ascii_case("zstd").then_some(ContentCoding::Zstd)
+			})
+			.collect();
+		
+		Ok(codings)
+	}
+	
+	fn encode(data: Self::Data) -> HeaderValue {
+		data.iter()
+			.map(|coding| match coding {
+				ContentCoding::Zstd => "zstd",
+			})
+			.collect::<Vec<_>>().join(", ")
+			.parse().expect("should be a valid header value")
+	}
+}
+
+#[derive(Debug)]
+pub struct ContentEncoding;
+
+impl HeaderType for ContentEncoding {
+	type Data = ContentCoding;
+	
+	const HEADER_NAME: HeaderName = header::CONTENT_ENCODING;
+	const MISSING_ERROR: Error = Error::BadRequest;
+	
+	fn parse(header_value: &HeaderValue) -> Result<Self::Data, Error> {
+		match header_value.as_bytes() {
+			b"zstd" => Ok(ContentCoding::Zstd),
+			_ => Err(Error::BadRequest),
+		}
+	}
+	
+	fn encode(data: Self::Data) -> HeaderValue {
+		match data {
+			ContentCoding::Zstd => HeaderValue::from_static("zstd"),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	#[test]
+	fn content_range() {
+		for data in [(Some((0, 1)), 1), (Some((5, 10)), 10), (None, 10)] {
+			assert_eq!(ContentRange::parse(&ContentRange::encode(data)).unwrap(), data);
+		}
+		
+		for value in ["bytes 5-10/10", "bytes 6-5/10", "bytes 5/10", "bytes 0-1/", "bytes */", "items 0-1/10", "bytes -1/10"] {
+			assert_eq!(ContentRange::parse(&HeaderValue::from_static(value)).unwrap_err(), Error::BadRequest, "{value}");
+		}
+	}
+	
+	#[test]
+	fn content_etag() {
+		let hash = Hash("abc".to_owned());
+		
+		assert_eq!(ContentETag::encode(ContentETag::Strong(hash.clone())), "\"abc\"");
+		assert_eq!(ContentETag::encode(ContentETag::Weak(hash.clone())), "W/\"abc\"");
+		
+		for etag in [ContentETag::Strong(hash.clone()), ContentETag::Weak(hash.clone())] {
+			assert_eq!(ContentETag::parse(&ContentETag::encode(etag.clone())).unwrap(), etag);
+		}
+		
+		// clients compare the hash regardless of the encoding
+		assert_eq!(Hash::from_header(&HeaderValue::from_static("W/\"abc\"")), Some(hash));
+	}
+	
+	#[test]
+	fn node_etag() {
+		assert_eq!(NodeETag::encode(NodeETag::File(Hash("abc".to_owned()))), "\"abc\"");
+		assert_eq!(NodeETag::encode(NodeETag::Directory(3)), "\"v3\"");
+		
+		for etag in [NodeETag::File(Hash("abc".to_owned())), NodeETag::Directory(3)] {
+			assert_eq!(NodeETag::parse(&NodeETag::encode(etag.clone())).unwrap(), etag);
+		}
+		
+		assert_eq!(NodeETag::parse(&HeaderValue::from_static("\"vabc\"")), Err(Error::BadRequest));
+	}
+}
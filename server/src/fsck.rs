@@ -0,0 +1,297 @@
+use std::{collections::{HashMap, HashSet}, fmt::{Display, Formatter}, io};
+
+use diesel::{OptionalExtension as _, RunQueryDsl as _};
+use futures::TryStreamExt as _;
+use fye_shared::{FsckReport, Hash, NodeID};
+
+use crate::{blob_store::{BlobStore, BlobStream}, compression::content_hash, db, error::{transaction, Error}, hash::EMPTY_HASH};
+
+/// Name of the directory inside the root directory which orphaned nodes are moved into
+pub const LOST_AND_FOUND: &str = "lost+found";
+
+/// Checks that the metadata is consistent and that every stored blob matches its hash
+/// 
+/// If `repair` is set, corrupt blobs are quarantined, dangling entries are removed,
+/// orphaned nodes are moved into [`LOST_AND_FOUND`], named after their id,
+/// and the usage of every top level quota is recomputed from its content.
+/// Orphaned blobs are only reported as they may belong to an upload that is still in progress,
+/// directory cycles are only reported as well.
+/// [`FsckReport::repaired`] is only set if nothing that can't be repaired is left afterwards.
+/// 
+/// All metadata is loaded into memory and every blob is read in full, so this takes a while for larger volumes.
+pub async fn check(conn: &mut db::AnyConnection, blobs: &dyn BlobStore, repair: bool) -> Result<FsckReport, Error> {
+	let mut report = FsckReport::default();
+	
+	let directories: Vec<db::Directory> = db::Directory::all()
+		.load(conn).map_err(|err| Error::internal(err, "failed loading directories"))?;
+	let files: Vec<db::File> = db::File::all()
+		.load(conn).map_err(|err| Error::internal(err, "failed loading files"))?;
+	let entries: Vec<db::DirectoryEntry> = db::DirectoryEntry::all()
+		.load(conn).map_err(|err| Error::internal(err, "failed loading directory entries"))?;
+	
+	let mut parents: HashMap<i64, i64> = directories.iter().map(|dir| (dir.id, dir.parent)).collect();
+	let sizes: HashMap<i64, i64> = files.iter().map(|file| (file.id, file.size)).collect();
+	
+	let mut children: HashMap<i64, Vec<i64>> = HashMap::new();
+	
+	for entry in &entries {
+		let child = match (entry.directory, entry.file) {
+			(Some(id), None) if parents.contains_key(&id) => Some(id),
+			(None, Some(id)) if sizes.contains_key(&id) => Some(id),
+			_ => None,
+		};
+		
+		match child {
+			Some(child) if parents.contains_key(&entry.parent) => children.entry(entry.parent).or_default().push(child),
+			_ => report.dangling_entries.push((NodeID(entry.parent as u64), entry.name.clone())),
+		}
+	}
+	
+	report.directory_cycles = find_cycles(&parents);
+	
+	let has_entry: HashSet<i64> = children.values().flatten().copied().collect();
+	report.orphaned_nodes = parents.keys().chain(sizes.keys())
+		.filter(|id| **id != NodeID::ROOT.0 as i64 && !has_entry.contains(id))
+		.map(|id| NodeID(*id as u64))
+		.collect();
+	report.orphaned_nodes.sort_by_key(|id| id.0);
+	
+	let quotas: Vec<db::Quota> = db::Quota::all()
+		.load(conn).map_err(|err| Error::internal(err, "failed loading quotas"))?;
+	let top_level: HashSet<i64> = top_level_directories(&children, &parents).collect();
+	
+	report.wrong_quotas = quotas.iter()
+		.filter(|quota| top_level.contains(&quota.directory))
+		.filter(|quota| (quota.used_bytes, quota.used_nodes) != subtree_usage(quota.directory, &children, &sizes))
+		.map(|quota| NodeID(quota.directory as u64))
+		.collect();
+	report.wrong_quotas.sort_by_key(|id| id.0);
+	
+	let mut stored: Vec<String> = blobs.list().await.map_err(|err| Error::internal(err, "failed listing blobs"))?;
+	stored.sort();
+	
+	let referenced: HashSet<&str> = files.iter()
+		.map(|file| file.hash.as_str())
+		.filter(|hash| *hash != EMPTY_HASH) // empty files don't have a blob
+		.collect();
+	
+	let stored_set: HashSet<&str> = stored.iter().map(String::as_str).collect();
+	report.missing_blobs = referenced.difference(&stored_set)
+		.map(|hash| Hash(hash.to_string()))
+		.collect();
+	report.missing_blobs.sort_by(|a, b| a.0.cmp(&b.0));
+	
+	// contents staged in open transactions are only referenced by files once the transaction is committed
+	let staged: Vec<db::StagedContent> = db::StagedContent::all()
+		.load(conn).map_err(|err| Error::internal(err, "failed loading staged contents"))?;
+	let staged: HashSet<&str> = staged.iter().map(|content| content.hash.as_str()).collect();
+	
+	for hash in &stored {
+		if !referenced.contains(hash.as_str()) && !staged.contains(hash.as_str()) {
+			report.orphaned_blobs.push(Hash(hash.clone()));
+		}
+		
+		if !blob_matches(blobs, hash).await.map_err(|err| Error::internal(err, format!("failed reading blob {hash}")))? {
+			report.corrupt_blobs.push(Hash(hash.clone()));
+		}
+	}
+	
+	if !repair {
+		return Ok(report);
+	}
+	
+	for hash in &report.corrupt_blobs {
+		blobs.quarantine(&hash.0).await.map_err(|err| Error::internal(err, format!("failed quarantining blob {}", hash.0)))?;
+	}
+	
+	let lost_and_found = match report.orphaned_nodes.is_empty() {
+		true => None,
+		false => Some(lost_and_found(conn)?),
+	};
+	
+	transaction(conn, |conn| {
+		for (parent, name) in &report.dangling_entries {
+			db::DirectoryEntry::delete(conn, *parent, name).map_err(|err| Error::internal(err, "failed deleting dangling entry"))?;
+		}
+		
+		if let Some(lost_and_found) = lost_and_found {
+			let lost_and_found_children = children.entry(lost_and_found.0 as i64).or_default();
+			
+			for orphan in &report.orphaned_nodes {
+				let id = orphan.0 as i64;
+				let is_directory = parents.contains_key(&id);
+				
+				if is_directory {
+					db::Directory::set_parent(conn, *orphan, lost_and_found).map_err(|err| Error::internal(err, "failed updating parent"))?;
+					parents.insert(id, lost_and_found.0 as i64);
+				}
+				
+				let name = id.to_string();
+				let entry = db::NewDirectoryEntry {
+					parent: lost_and_found.0 as i64,
+					name: &name,
+					directory: is_directory.then_some(id),
+					file: (!is_directory).then_some(id),
+				};
+				entry.insert(conn).map_err(|err| Error::internal(err, "failed inserting directory entry"))?;
+				
+				lost_and_found_children.push(id);
+			}
+			
+			// lost+found doesn't have any limits unless they are set afterwards
+			let root_children = children.entry(NodeID::ROOT.0 as i64).or_default();
+			if !root_children.contains(&(lost_and_found.0 as i64)) {
+				root_children.push(lost_and_found.0 as i64);
+			}
+			parents.insert(lost_and_found.0 as i64, NodeID::ROOT.0 as i64);
+		}
+		
+		// dangling entries and orphans were counted towards the quota of the top level directory they were in,
+		// recomputing the usage from the repaired tree takes care of both
+		for directory in top_level_directories(&children, &parents) {
+			let (bytes, nodes) = subtree_usage(directory, &children, &sizes);
+			db::Quota::set_usage(conn, NodeID(directory as u64), bytes, nodes).map_err(|err| Error::internal(err, "failed updating quota"))?;
+		}
+		
+		Ok(())
+	})?;
+	
+	// quarantined blobs are missing now unless nothing referenced them, then they were orphaned as well
+	let repaired_any = !report.dangling_entries.is_empty() || !report.orphaned_nodes.is_empty() || !report.wrong_quotas.is_empty();
+	report.repaired = repaired_any
+		&& report.missing_blobs.is_empty()
+		&& report.corrupt_blobs.is_empty()
+		&& report.orphaned_blobs.is_empty()
+		&& report.directory_cycles.is_empty();
+	Ok(report)
+}
+
+/// Marks errors from reading a blob, as opposed to errors from decompressing it
+#[derive(Debug)]
+struct ReadFailed(io::Error);
+
+impl Display for ReadFailed {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}", self.0)
+	}
+}
+
+impl std::error::Error for ReadFailed {}
+
+/// Returns whether the uncompressed content of the blob has the given hash, blobs that fail to decompress don't match
+async fn blob_matches(blobs: &dyn BlobStore, hash: &str) -> Result<bool, io::Error> {
+	let blob = blobs.get(hash).await?;
+	let stream: BlobStream = Box::pin(blob.stream.map_err(|err| io::Error::other(ReadFailed(err))));
+	
+	match content_hash(stream, blob.is_compressed).await {
+		Ok(actual) => Ok(actual.to_hex().as_str() == hash),
+		Err(err) => match err.into_inner().map(|inner| inner.downcast::<ReadFailed>()) {
+			Some(Ok(read_failed)) => Err(read_failed.0),
+			_ => Ok(false),
+		},
+	}
+}
+
+/// Returns the directories which don't reach the root directory when following their parents,
+/// either because they are part of a cycle, lead into one, or have a parent that doesn't exist
+fn find_cycles(parents: &HashMap<i64, i64>) -> Vec<NodeID> {
+	let mut reaches_root = HashMap::from([(NodeID::ROOT.0 as i64, true)]);
+	
+	for &start in parents.keys() {
+		let mut path = Vec::new();
+		let mut current = start;
+		
+		let result = loop {
+			if let Some(&result) = reaches_root.get(&current) {
+				break result;
+			}
+			
+			if path.contains(&current) {
+				break false;
+			}
+			
+			path.push(current);
+			
+			match parents.get(&current) {
+				Some(&parent) => current = parent,
+				None => break false,
+			}
+		};
+		
+		for id in path {
+			reaches_root.insert(id, result);
+		}
+	}
+	
+	let mut cycles: Vec<NodeID> = reaches_root.into_iter()
+		.filter(|(_, reaches_root)| !reaches_root)
+		.map(|(id, _)| NodeID(id as u64))
+		.collect();
+	cycles.sort_by_key(|id| id.0);
+	
+	cycles
+}
+
+/// Returns the directories inside the root directory, which are the ones that have a quota
+fn top_level_directories<'a>(children: &'a HashMap<i64, Vec<i64>>, parents: &'a HashMap<i64, i64>) -> impl Iterator<Item = i64> + 'a {
+	children.get(&(NodeID::ROOT.0 as i64)).into_iter().flatten()
+		.copied()
+		.filter(|id| parents.contains_key(id))
+}
+
+/// Returns the bytes and nodes of the node and everything inside it
+fn subtree_usage(id: i64, children: &HashMap<i64, Vec<i64>>, sizes: &HashMap<i64, i64>) -> (i64, i64) {
+	let mut visited = HashSet::new();
+	let mut pending = vec![id];
+	let (mut bytes, mut nodes) = (0, 0);
+	
+	while let Some(id) = pending.pop() {
+		if !visited.insert(id) {
+			continue;
+		}
+		
+		bytes += sizes.get(&id).copied().unwrap_or(0);
+		nodes += 1;
+		pending.extend(children.get(&id).into_iter().flatten());
+	}
+	
+	(bytes, nodes)
+}
+
+/// Returns the [`LOST_AND_FOUND`] directory, creating it if it doesn't exist yet
+/// 
+/// # Warning
+/// Don't call this function from within a transaction
+fn lost_and_found(conn: &mut db::AnyConnection) -> Result<NodeID, Error> {
+	let entry = db::DirectoryEntry::get(NodeID::ROOT, LOST_AND_FOUND)
+		.first(conn).optional().map_err(|err| Error::internal(err, "failed looking up directory entry"))?;
+	
+	match entry {
+		Some(db::DirectoryEntry { directory: Some(id), .. }) => return Ok(NodeID(id as u64)),
+		Some(_) => return Err(Error::NotADirectory),
+		None => (),
+	}
+	
+	let id = db::next_available_id(conn).map_err(|err| Error::internal(err, "failed generating next id"))?;
+	
+	transaction(conn, |conn| {
+		let dir = db::Directory {
+			id: id.0 as i64,
+			parent: NodeID::ROOT.0 as i64,
+			version: 0,
+		};
+		dir.insert(conn).map_err(|err| Error::internal(err, "failed inserting new node"))?;
+		
+		let entry = db::NewDirectoryEntry {
+			parent: NodeID::ROOT.0 as i64,
+			name: LOST_AND_FOUND,
+			directory: Some(id.0 as i64),
+			file: None,
+		};
+		entry.insert(conn).map_err(|err| Error::internal(err, "failed inserting directory entry"))?;
+		
+		db::Quota::new(id).insert(conn).map_err(|err| Error::internal(err, "failed inserting new quota"))?;
+		
+		Ok(id)
+	})
+}
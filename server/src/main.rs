@@ -1,12 +1,14 @@
-#![feature(async_closure)]
 #![forbid(unsafe_code)]
 #![deny(non_snake_case)]
 
-use std::{any::Any, ops::Deref, path::PathBuf};
+use std::{any::Any, net::SocketAddr, ops::Deref, path::{Path, PathBuf}, sync::Arc};
 
-use axum::{http::StatusCode, response::{IntoResponse, Response}, routing::{get, post}, Router};
+use axum::{http::StatusCode, response::{IntoResponse, Response}, routing::{delete, get, post, put}, Router};
 use diesel::r2d2::Pool;
-use extractors::{AppState, ConnectionManager, Directories};
+use blob_store::{BlobStore, LocalBlobStore, MemoryBlobStore, S3BlobStore};
+use extractors::{AppState, Blobs, ConnectionManager, Directories, StorageLimit};
+use axum_server::tls_rustls::RustlsConfig;
+use tls::TlsPaths;
 use tokio::net::TcpListener;
 use tower_http::catch_panic::CatchPanicLayer;
 
@@ -14,42 +16,190 @@ mod testing;
 mod hash;
 mod db;
 mod stream;
+mod compression;
+mod blob_store;
+mod fsck;
+mod export;
 mod extractors;
 mod error;
 mod routes;
+mod tls;
 
 #[tokio::main]
-#[expect(clippy::needless_return)]
 async fn main() {
-	let db_manager = ConnectionManager::new("dev_data/fye.db".to_owned());
+	// a postgres:// url allows running multiple servers with the same database
+	let db_url = std::env::var("FYE_DATABASE_URL").unwrap_or_else(|_| "dev_data/fye.db".to_owned());
+	let db_manager = ConnectionManager::new(db_url);
 	let db_pool = Pool::builder()
 		.test_on_check_out(true)
 		.build(db_manager).unwrap();
 	
 	let directories = Directories {
 		uploads: PathBuf::from("dev_data/uploads").into(),
-		files: PathBuf::from("dev_data/files").into(),
 	};
 	
 	std::fs::create_dir_all(&directories.uploads).unwrap();
-	std::fs::create_dir_all(&directories.files).unwrap();
 	
-	let app_state = AppState::new(db_pool, directories);
+	let blobs: Arc<dyn BlobStore> = match std::env::var("FYE_BLOB_STORE").as_deref() {
+		Err(_) | Ok("local") => {
+			let files = PathBuf::from("dev_data/files");
+			std::fs::create_dir_all(&files).unwrap();
+			Arc::new(LocalBlobStore::new(files.into()))
+		},
+		Ok("s3") => Arc::new(S3BlobStore::from_env().unwrap()),
+		Ok("memory") => Arc::new(MemoryBlobStore::default()),
+		Ok(other) => panic!("unknown FYE_BLOB_STORE {other}, should be local, s3 or memory"),
+	};
+	
+	let args: Vec<String> = std::env::args().skip(1).collect();
+	match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+		[] => (),
+		["fsck"] => return run_fsck(&db_pool, &*blobs, false).await,
+		["fsck", "--repair"] => return run_fsck(&db_pool, &*blobs, true).await,
+		["export", path] => return run_export(&db_pool, &*blobs, Path::new(path)).await,
+		["import", path] => return run_import(&db_pool, blobs, directories.uploads, Path::new(path)).await,
+		["quota", id, max_bytes, max_nodes] => return run_set_quota(&db_pool, id, max_bytes, max_nodes),
+		_ => {
+			eprintln!("Usage: fye-server [fsck [--repair] | export <archive> | import <archive> | quota <node id> <max bytes|none> <max nodes|none>]");
+			std::process::exit(2);
+		},
+	}
+	
+	let storage_limit = std::env::var("FYE_STORAGE_LIMIT").ok()
+		.map(|limit| limit.parse().expect("FYE_STORAGE_LIMIT should be a number of bytes"));
+	
+	let app_state = AppState::new(db_pool, directories, Blobs(blobs), StorageLimit(storage_limit));
 	
-	let app = Router::new()
+	let tls_paths = TlsPaths::from_env();
+	
+	let mut app = Router::new()
 		.route("/api/node/:id", get(routes::node_info))
+		.route("/api/resolve", get(routes::resolve_path))
+		.route("/api/stats", get(routes::storage_stats))
 		.route("/api/dir/:id", get(routes::dir_info))
+		.route("/api/dir/:id/listing", get(routes::dir_listing))
 		.route("/api/dir/:id/new-dir", post(routes::create_dir))
 		.route("/api/dir/:id/new-file", post(routes::create_file))
 		.route("/api/dir/:id/delete-dir", post(routes::delete_dir))
 		.route("/api/dir/:id/delete-file", post(routes::delete_file))
+		.route("/api/batch", post(routes::batch))
+		.route("/api/transaction", post(routes::open_transaction))
+		.route("/api/transaction/:tx", delete(routes::abort_transaction))
+		.route("/api/transaction/:tx/file/:id", put(routes::stage_file_data))
+		.route("/api/transaction/:tx/operations", post(routes::stage_operations))
+		.route("/api/transaction/:tx/commit", post(routes::commit_transaction))
+		.route("/api/node/:id/quota", get(routes::quota_info))
+		.route("/api/volume-key", get(routes::volume_key).post(routes::create_volume_key))
 		.route("/api/file/:id", get(routes::file_info))
 		.route("/api/file/:id/data", get(routes::file_data).put(routes::write_file_data))
+		.route("/api/file/:id/lock", post(routes::lock_file))
+		.route("/api/file/:id/lock/test", post(routes::test_lock))
+		.route("/api/file/:id/unlock", post(routes::unlock_file))
+		.route("/api/lock-lease/:client", put(routes::renew_lock_lease))
+		.route("/api/file/:id/delegation", post(routes::request_delegation))
+		.route("/api/file/:id/delegation/:client", delete(routes::return_delegation))
+		.route("/api/delegation-recalls/:client", get(routes::delegation_recalls));
+	
+	// only available when every client has to present a certificate signed by the client CA
+	if tls_paths.as_ref().is_some_and(|paths| paths.client_ca.is_some()) {
+		app = app.route("/api/admin/fsck", post(routes::fsck));
+	}
+	
+	let app = app
 		.layer(CatchPanicLayer::custom(handle_panic))
 		.with_state(app_state);
 	
-	let listener = TcpListener::bind("0.0.0.0:3000").await.unwrap();
-	axum::serve(listener, app).await.unwrap();
+	let address = SocketAddr::from(([0, 0, 0, 0], 3000));
+	
+	if let Some(tls_paths) = tls_paths {
+		let tls_config = RustlsConfig::from_config(Arc::new(tls::load_config(&tls_paths).unwrap()));
+		tls::reload_on_change(tls_config.clone(), tls_paths);
+		
+		axum_server::bind_rustls(address, tls_config)
+			.serve(app.into_make_service()).await.unwrap();
+	} else {
+		let listener = TcpListener::bind(address).await.unwrap();
+		axum::serve(listener, app).await.unwrap();
+	}
+}
+
+/// Prints the report and exits with an error if there are problems that weren't repaired
+async fn run_fsck(db_pool: &Pool<ConnectionManager>, blobs: &dyn BlobStore, repair: bool) {
+	let mut conn = db_pool.get().unwrap();
+	
+	match fsck::check(&mut conn, blobs, repair).await {
+		Ok(report) => {
+			println!("{report:#?}");
+			
+			if !report.is_clean() && !report.repaired {
+				std::process::exit(1);
+			}
+		},
+		Err(err) => {
+			eprintln!("Checking failed: {err}");
+			std::process::exit(1);
+		},
+	}
+}
+
+/// Sets the quota limits of the top level directory containing the node, `none` removes a limit
+fn run_set_quota(db_pool: &Pool<ConnectionManager>, id: &str, max_bytes: &str, max_nodes: &str) {
+	fn parse_limit(limit: &str) -> Option<u64> {
+		match limit {
+			"none" => None,
+			limit => Some(limit.parse().unwrap_or_else(|_| {
+				eprintln!("Invalid limit {limit}, should be a number or none");
+				std::process::exit(2);
+			})),
+		}
+	}
+	
+	let Ok(id) = id.parse() else {
+		eprintln!("Invalid node id {id}");
+		std::process::exit(2);
+	};
+	
+	let limits = fye_shared::QuotaLimits {
+		max_bytes: parse_limit(max_bytes),
+		max_nodes: parse_limit(max_nodes),
+	};
+	
+	let mut conn = db_pool.get().unwrap();
+	
+	match routes::set_quota_limits(&mut conn, fye_shared::NodeID(id), limits) {
+		Ok(usage) => println!("{usage:#?}"),
+		Err(err) => {
+			eprintln!("Setting quota failed: {err}");
+			std::process::exit(1);
+		},
+	}
+}
+
+async fn run_export(db_pool: &Pool<ConnectionManager>, blobs: &dyn BlobStore, path: &Path) {
+	let mut conn = db_pool.get().unwrap();
+	
+	match export::export(&mut conn, blobs, path).await {
+		Ok(missing_blobs) => {
+			// the archive is still usable, the files just can't be read like before
+			for hash in missing_blobs {
+				eprintln!("Blob {hash} is missing and was left out");
+			}
+		},
+		Err(err) => {
+			eprintln!("Exporting failed: {err}");
+			std::process::exit(1);
+		},
+	}
+}
+
+/// Only imports into an empty store, which is what a freshly created data directory contains
+async fn run_import(db_pool: &Pool<ConnectionManager>, blobs: Arc<dyn BlobStore>, uploads: Arc<Path>, path: &Path) {
+	let mut conn = db_pool.get().unwrap();
+	
+	if let Err(err) = export::import(&mut conn, blobs, uploads, path).await {
+		eprintln!("Importing failed: {err}");
+		std::process::exit(1);
+	}
 }
 
 fn handle_panic(panic: Box<dyn Any + Send>) -> Response {
@@ -2,27 +2,52 @@ mod info;
 mod files;
 mod create;
 mod delete;
+mod rename;
+mod stats;
+mod quota;
+mod volume_key;
+mod batch;
+mod transaction;
+mod locks;
+mod delegations;
+mod fsck;
 
 pub use info::*;
 pub use files::*;
 pub use create::*;
 pub use delete::*;
+use rename::*;
+pub use stats::*;
+pub use quota::*;
+pub use volume_key::*;
+pub use batch::*;
+pub use transaction::*;
+pub use locks::*;
+pub use delegations::*;
+pub use fsck::*;
 
-use axum::{body::Body, extract::Path, http::StatusCode};
+use axum::{body::Body, extract::{Path, Query}, http::{header, StatusCode}};
 use axum_postcard::Postcard;
-use diesel::{result::DatabaseErrorKind, RunQueryDsl as _, OptionalExtension as _, SqliteConnection};
+use diesel::{result::DatabaseErrorKind, Connection as _, RunQueryDsl as _, OptionalExtension as _};
 use diesel::result::Error as DieselError;
-use fye_shared::{NodeInfo, DirectoryInfo, FileInfo, NodeID, Hash};
-use tokio::fs::File;
-use tokio_util::io::ReaderStream;
+use serde::Deserialize;
+use fye_shared::{NodeInfo, DirectoryInfo, DirectoryListing, EntryAttributes, EntryInfo, FileInfo, NodeID, Hash, QuotaLimits, QuotaUsage, ResolvedNode, StorageStats};
+use fye_shared::{BatchError, BatchOperation, BatchOutcome, BatchRequest, BatchResult, MAX_BATCH_SIZE, TransactionID};
+use fye_shared::{FileLock, LockOwner, UnlockRequest, LOCK_LEASE_SECS};
+use fye_shared::{DelegationKind, DelegationRequest};
+use fye_shared::FsckReport;
+use tokio::io::AsyncWriteExt as _;
 
-use crate::{db, error::{transaction, async_transaction, Error}, hash::EMPTY_HASH, stream::{stream_to_file, HashStream}};
+use crate::{db, error::{transaction, async_transaction, Error}, hash::EMPTY_HASH, stream::{stream_to_file, HashStream, LimitExceeded, LimitStream}};
+use crate::compression::{compress_file, decompress_range, MIN_COMPRESSION_SIZE};
 use crate::extractors::*;
 
 #[cfg(test)]
 mod tests {
 	use super::*;
 	use crate::testing::*;
+	use write_lock::FileWriteLock;
+	use fye_shared::LockKind;
 	
 	use std::error::Error as _;
 	use std::io;
@@ -35,25 +60,35 @@ mod tests {
 		let mut db = TestDb::new();
 		let directories = TestDirectories::new();
 		
-		let Err(err) = node_info(db.conn(), Path(NodeID(2))).await else {panic!()};
+		let Err(err) = node_info(db.conn(), Path(NodeID(2)), OptHeader(None)).await else {panic!()};
 		assert_eq!(err, Error::NotFound);
 		
-		let Err(err) = dir_info(db.conn(), Path(NodeID(2))).await else {panic!()};
+		let Err(err) = dir_info(db.conn(), Path(NodeID(2)), OptHeader(None)).await else {panic!()};
 		assert_eq!(err, Error::NotFound);
 		
 		let Err(err) = file_info(db.conn(), Path(NodeID(2))).await else {panic!()};
 		assert_eq!(err, Error::NotFound);
 		
-		let Err(err) = file_data(db.conn(), directories.dirs(), Path(NodeID(2)), OptHeader(None), OptHeader(None)).await else {panic!()};
+		let Err(err) = file_data(db.conn(), directories.blobs(), Path(NodeID(2)), OptHeader(None), OptHeader(None), OptHeader(None), OptHeader(None)).await else {panic!()};
 		assert_eq!(err, Error::NotFound);
 		
-		let Err(err) = write_file_data(db.conn(), directories.dirs(), Path(NodeID(2)), Header(Hash(EMPTY_HASH.to_owned())), BodyStream::empty()).await else {panic!()};
+		let Err(err) = write_file_data(db.conn(), directories.dirs(), directories.blobs(), FileWriteLock::default(), DelegationRecalls::default(), OptHeader(None), Path(NodeID(2)), Header(Hash(EMPTY_HASH.to_owned())), Header(Hash(EMPTY_HASH.to_owned())), Header(0), BodyStream::empty()).await else {panic!()};
+		assert_eq!(err, Error::NotFound);
+		
+		let lock = FileLock {
+			owner: LockOwner { client: 1, owner: 1 },
+			kind: LockKind::Exclusive,
+			start: 0,
+			end: None,
+			pid: 0,
+		};
+		let Err(err) = lock_file(db.conn(), Path(NodeID(2)), Postcard(lock)).await else {panic!()};
 		assert_eq!(err, Error::NotFound);
 		
 		let Err(err) = delete_dir(db.conn(), Path(NodeID(2)), Postcard("something".to_owned())).await else {panic!()};
 		assert_eq!(err, Error::NotFound);
 		
-		let Err(err) = delete_file(db.conn(), Path(NodeID(2)), Postcard("something".to_owned())).await else {panic!()};
+		let Err(err) = delete_file(db.conn(), DelegationRecalls::default(), OptHeader(None), Path(NodeID(2)), Postcard("something".to_owned())).await else {panic!()};
 		assert_eq!(err, Error::NotFound);
 	}
 	
@@ -65,7 +100,7 @@ mod tests {
 		assert_eq!(err, Error::NotFound);
 		
 		
-		let Err(err) = delete_file(db.conn(), Path(ROOT), Postcard("doesn't exist".to_owned())).await else {panic!()};
+		let Err(err) = delete_file(db.conn(), DelegationRecalls::default(), OptHeader(None), Path(ROOT), Postcard("doesn't exist".to_owned())).await else {panic!()};
 		assert_eq!(err, Error::NotFound);
 	}
 	
@@ -77,7 +112,7 @@ mod tests {
 		assert_eq!(status, StatusCode::CREATED);
 		let Location::Directory(id) = location else {panic!()};
 		
-		let Postcard(parent) = dir_info(db.conn(), Path(ROOT)).await.unwrap();
+		let (_, Postcard(parent)) = dir_info(db.conn(), Path(ROOT), OptHeader(None)).await.unwrap();
 		assert_eq!(parent.parent, ROOT);
 		assert_eq!(parent.children.len(), 1);
 		
@@ -85,14 +120,14 @@ mod tests {
 		assert_eq!(child_name, "directory");
 		assert_eq!(child_id, &id);
 		
-		let Postcard(parent_node) = node_info(db.conn(), Path(ROOT)).await.unwrap();
+		let (_, Postcard(parent_node)) = node_info(db.conn(), Path(ROOT), OptHeader(None)).await.unwrap();
 		assert_eq!(parent_node, NodeInfo::Directory(parent));
 		
-		let Postcard(dir) = dir_info(db.conn(), Path(id)).await.unwrap();
+		let (_, Postcard(dir)) = dir_info(db.conn(), Path(id), OptHeader(None)).await.unwrap();
 		assert_eq!(dir.parent, ROOT);
 		assert!(dir.children.is_empty());
 		
-		let Postcard(node) = node_info(db.conn(), Path(id)).await.unwrap();
+		let (_, Postcard(node)) = node_info(db.conn(), Path(id), OptHeader(None)).await.unwrap();
 		assert_eq!(node, NodeInfo::Directory(dir));
 		
 		let Err(err) = file_info(db.conn(), Path(id)).await else {panic!()}; // Postcard doesn't implement Debug so unwrap_err doesn't work
@@ -109,7 +144,7 @@ mod tests {
 		
 		let Location::File(id) = location else {panic!()};
 		
-		let Postcard(parent) = dir_info(db.conn(), Path(ROOT)).await.unwrap();
+		let (_, Postcard(parent)) = dir_info(db.conn(), Path(ROOT), OptHeader(None)).await.unwrap();
 		assert_eq!(parent.parent, ROOT);
 		assert_eq!(parent.children.len(), 1);
 		
@@ -117,7 +152,7 @@ mod tests {
 		assert_eq!(child_name, "file");
 		assert_eq!(child_id, &id);
 		
-		let Postcard(parent_node) = node_info(db.conn(), Path(ROOT)).await.unwrap();
+		let (_, Postcard(parent_node)) = node_info(db.conn(), Path(ROOT), OptHeader(None)).await.unwrap();
 		assert_eq!(parent_node, NodeInfo::Directory(parent));
 		
 		let Postcard(file) = file_info(db.conn(), Path(id)).await.unwrap();
@@ -126,10 +161,172 @@ mod tests {
 			hash: Hash(EMPTY_HASH.to_owned()),
 		});
 		
-		let Postcard(node) = node_info(db.conn(), Path(id)).await.unwrap();
+		let (_, Postcard(node)) = node_info(db.conn(), Path(id), OptHeader(None)).await.unwrap();
 		assert_eq!(node, NodeInfo::File(file));
 		
-		let Err(err) = dir_info(db.conn(), Path(id)).await else {panic!()};
+		let Err(err) = dir_info(db.conn(), Path(id), OptHeader(None)).await else {panic!()};
+		assert_eq!(err, Error::NotADirectory);
+	}
+	
+	#[tokio::test]
+	async fn listing_includes_attributes() {
+		let mut db = TestDb::new();
+		
+		let (_, Header(location)) = create_dir(db.conn(), Path(ROOT), Postcard("directory".to_owned())).await.unwrap();
+		let Location::Directory(dir_id) = location else {panic!()};
+		
+		let (_, Header(location), _) = create_file(db.conn(), Path(ROOT), Postcard("file".to_owned())).await.unwrap();
+		let Location::File(file_id) = location else {panic!()};
+		
+		let Postcard(listing) = dir_listing(db.conn(), Path(ROOT), Query(Default::default())).await.unwrap();
+		assert_eq!(listing.parent, ROOT);
+		assert_eq!(listing.children.len(), 2);
+		assert!(listing.is_complete());
+		
+		assert_eq!(listing.children.get("directory"), Some(&EntryInfo {
+			id: dir_id,
+			attributes: EntryAttributes::Directory,
+		}));
+		
+		assert_eq!(listing.children.get("file"), Some(&EntryInfo {
+			id: file_id,
+			attributes: EntryAttributes::File(FileInfo {
+				size: 0,
+				hash: Hash(EMPTY_HASH.to_owned()),
+			}),
+		}));
+		
+		let (_, Postcard(dir)) = dir_info(db.conn(), Path(ROOT), OptHeader(None)).await.unwrap();
+		assert_eq!(listing.to_dir_info(), dir);
+		
+		let Err(err) = dir_listing(db.conn(), Path(file_id), Query(Default::default())).await else {panic!()};
+		assert_eq!(err, Error::NotADirectory);
+		
+		let Err(err) = dir_listing(db.conn(), Path(NodeID(1000)), Query(Default::default())).await else {panic!()};
+		assert_eq!(err, Error::NotFound);
+	}
+	
+	#[tokio::test]
+	async fn paginated_listing() {
+		let mut db = TestDb::new();
+		
+		for name in ["e", "b", "d", "a", "c"] {
+			create_file(db.conn(), Path(ROOT), Postcard(name.to_owned())).await.unwrap();
+		}
+		
+		let mut names = Vec::new();
+		let mut after = None;
+		
+		loop {
+			let query = ListingQuery {
+				after: after.take(),
+				limit: Some(2),
+			};
+			
+			let Postcard(page) = dir_listing(db.conn(), Path(ROOT), Query(query)).await.unwrap();
+			assert!(page.children.len() <= 2);
+			names.extend(page.children.into_keys());
+			
+			match page.continuation {
+				Some(continuation) => after = Some(continuation),
+				None => break,
+			}
+		}
+		
+		assert_eq!(names, ["a", "b", "c", "d", "e"]);
+		
+		// an exactly full last page doesn't need another request
+		let query = ListingQuery {
+			after: Some("a".to_owned()),
+			limit: Some(4),
+		};
+		
+		let Postcard(page) = dir_listing(db.conn(), Path(ROOT), Query(query)).await.unwrap();
+		assert_eq!(page.children.len(), 4);
+		assert!(page.is_complete());
+		
+		let query = ListingQuery {
+			after: None,
+			limit: Some(0),
+		};
+		
+		let Err(err) = dir_listing(db.conn(), Path(ROOT), Query(query)).await else {panic!()};
+		assert_eq!(err, Error::BadRequest);
+	}
+	
+	#[tokio::test]
+	async fn paginated_listing_skips_dangling_entries() {
+		let mut db = TestDb::new();
+		
+		let mut ids = Vec::new();
+		for name in ["a", "b", "c", "d"] {
+			let (_, Header(location), _) = create_file(db.conn(), Path(ROOT), Postcard(name.to_owned())).await.unwrap();
+			let Location::File(id) = location else {panic!()};
+			ids.push(id);
+		}
+		
+		db.disable_entry_foreign_keys();
+		assert!(db::File::delete(&mut db.conn(), ids[1]).unwrap());
+		assert!(db::File::delete(&mut db.conn(), ids[2]).unwrap());
+		
+		// the dangling entries don't take up room on the first page
+		let query = ListingQuery {
+			after: None,
+			limit: Some(2),
+		};
+		
+		let Postcard(page) = dir_listing(db.conn(), Path(ROOT), Query(query)).await.unwrap();
+		assert_eq!(page.children.into_keys().collect::<Vec<_>>(), ["a", "d"]);
+		assert!(page.continuation.is_none());
+		
+		let query = ListingQuery {
+			after: None,
+			limit: Some(1),
+		};
+		
+		let Postcard(page) = dir_listing(db.conn(), Path(ROOT), Query(query)).await.unwrap();
+		assert_eq!(page.children.into_keys().collect::<Vec<_>>(), ["a"]);
+		assert_eq!(page.continuation.as_deref(), Some("a"));
+	}
+	
+	#[tokio::test]
+	async fn resolve_nested_path() {
+		let mut db = TestDb::new();
+		
+		let (_, Header(location)) = create_dir(db.conn(), Path(ROOT), Postcard("a".to_owned())).await.unwrap();
+		let Location::Directory(a_id) = location else {panic!()};
+		
+		let (_, Header(location)) = create_dir(db.conn(), Path(a_id), Postcard("b".to_owned())).await.unwrap();
+		let Location::Directory(b_id) = location else {panic!()};
+		
+		let (_, Header(location), _) = create_file(db.conn(), Path(b_id), Postcard("c.txt".to_owned())).await.unwrap();
+		let Location::File(file_id) = location else {panic!()};
+		
+		async fn resolve(db: &mut TestDb, path: &str) -> Result<Postcard<Vec<ResolvedNode>>, Error> {
+			resolve_path(db.conn(), Query(ResolveQuery {
+				path: path.to_owned(),
+			})).await
+		}
+		
+		let Postcard(nodes) = resolve(&mut db, "/a/b/c.txt").await.unwrap();
+		let ids: Vec<_> = nodes.iter().map(|node| node.id).collect();
+		assert_eq!(ids, [ROOT, a_id, b_id, file_id]);
+		
+		let (_, Postcard(file_node)) = node_info(db.conn(), Path(file_id), OptHeader(None)).await.unwrap();
+		assert_eq!(nodes[3].info, file_node);
+		
+		let Postcard(nodes) = resolve(&mut db, "a//./b/../b/").await.unwrap();
+		let ids: Vec<_> = nodes.iter().map(|node| node.id).collect();
+		assert_eq!(ids, [ROOT, a_id, b_id]);
+		
+		let Postcard(nodes) = resolve(&mut db, "/../").await.unwrap();
+		assert_eq!(nodes.len(), 1);
+		assert_eq!(nodes[0].id, ROOT);
+		
+		let Err(err) = resolve(&mut db, "/a/missing/c.txt").await else {panic!()};
+		assert_eq!(err, Error::NotFound);
+		
+		let Err(err) = resolve(&mut db, "/a/b/c.txt/d").await else {panic!()};
 		assert_eq!(err, Error::NotADirectory);
 	}
 	
@@ -143,13 +340,13 @@ mod tests {
 		let status = delete_dir(db.conn(), Path(ROOT), Postcard("deleted".to_owned())).await.unwrap();
 		assert_eq!(status, StatusCode::NO_CONTENT);
 		
-		let Postcard(parent) = dir_info(db.conn(), Path(ROOT)).await.unwrap();
+		let (_, Postcard(parent)) = dir_info(db.conn(), Path(ROOT), OptHeader(None)).await.unwrap();
 		assert!(parent.children.is_empty());
 		
-		let Err(err) = node_info(db.conn(), Path(id)).await else {panic!()};
+		let Err(err) = node_info(db.conn(), Path(id), OptHeader(None)).await else {panic!()};
 		assert_eq!(err, Error::NotFound);
 		
-		let Err(err) = dir_info(db.conn(), Path(id)).await else {panic!()};
+		let Err(err) = dir_info(db.conn(), Path(id), OptHeader(None)).await else {panic!()};
 		assert_eq!(err, Error::NotFound);
 		
 		let Err(err) = file_info(db.conn(), Path(id)).await else {panic!()};
@@ -163,16 +360,16 @@ mod tests {
 		let (_, Header(location), _) = create_file(db.conn(), Path(ROOT), Postcard("deleted".to_owned())).await.unwrap();
 		let Location::File(id) = location else {panic!()};
 		
-		let status = delete_file(db.conn(), Path(ROOT), Postcard("deleted".to_owned())).await.unwrap();
+		let status = delete_file(db.conn(), DelegationRecalls::default(), OptHeader(None), Path(ROOT), Postcard("deleted".to_owned())).await.unwrap();
 		assert_eq!(status, StatusCode::NO_CONTENT);
 		
-		let Postcard(parent) = dir_info(db.conn(), Path(ROOT)).await.unwrap();
+		let (_, Postcard(parent)) = dir_info(db.conn(), Path(ROOT), OptHeader(None)).await.unwrap();
 		assert!(parent.children.is_empty());
 		
-		let Err(err) = node_info(db.conn(), Path(id)).await else {panic!()};
+		let Err(err) = node_info(db.conn(), Path(id), OptHeader(None)).await else {panic!()};
 		assert_eq!(err, Error::NotFound);
 		
-		let Err(err) = dir_info(db.conn(), Path(id)).await else {panic!()};
+		let Err(err) = dir_info(db.conn(), Path(id), OptHeader(None)).await else {panic!()};
 		assert_eq!(err, Error::NotFound);
 		
 		let Err(err) = file_info(db.conn(), Path(id)).await else {panic!()};
@@ -189,13 +386,13 @@ mod tests {
 		let (_, Header(location), _) = create_file(db.conn(), Path(ROOT), Postcard("file".to_owned())).await.unwrap();
 		let Location::File(file_id) = location else {panic!()};
 		
-		let Err(err) = delete_file(db.conn(), Path(ROOT), Postcard("directory".to_owned())).await else {panic!()};
+		let Err(err) = delete_file(db.conn(), DelegationRecalls::default(), OptHeader(None), Path(ROOT), Postcard("directory".to_owned())).await else {panic!()};
 		assert_eq!(err, Error::NotAFile);
 		
 		let Err(err) = delete_dir(db.conn(), Path(ROOT), Postcard("file".to_owned())).await else {panic!()};
 		assert_eq!(err, Error::NotADirectory);
 		
-		let Postcard(dir) = dir_info(db.conn(), Path(dir_id)).await.unwrap();
+		let (_, Postcard(dir)) = dir_info(db.conn(), Path(dir_id), OptHeader(None)).await.unwrap();
 		assert_eq!(dir.parent, ROOT);
 		assert!(dir.children.is_empty());
 		
@@ -205,12 +402,40 @@ mod tests {
 			hash: Hash(EMPTY_HASH.to_owned()),
 		});
 		
-		let Postcard(parent) = dir_info(db.conn(), Path(ROOT)).await.unwrap();
+		let (_, Postcard(parent)) = dir_info(db.conn(), Path(ROOT), OptHeader(None)).await.unwrap();
 		assert_eq!(parent.children.len(), 2);
 		assert_eq!(parent.children.get("directory"), Some(&dir_id));
 		assert_eq!(parent.children.get("file"), Some(&file_id));
 	}
 	
+	#[tokio::test]
+	async fn directory_revalidation() {
+		let mut db = TestDb::new();
+		
+		let (Header(etag), _) = dir_info(db.conn(), Path(ROOT), OptHeader(None)).await.unwrap();
+		
+		let Err(err) = dir_info(db.conn(), Path(ROOT), OptHeader(Some(etag.clone()))).await else {panic!()};
+		assert_eq!(err, Error::NotModified);
+		
+		let Err(err) = node_info(db.conn(), Path(ROOT), OptHeader(Some(etag.clone()))).await else {panic!()};
+		assert_eq!(err, Error::NotModified);
+		
+		create_file(db.conn(), Path(ROOT), Postcard("file".to_owned())).await.unwrap();
+		
+		let (Header(created_etag), Postcard(dir)) = dir_info(db.conn(), Path(ROOT), OptHeader(Some(etag.clone()))).await.unwrap();
+		assert_ne!(created_etag, etag);
+		assert_eq!(dir.children.len(), 1);
+		
+		let (Header(node_etag), _) = node_info(db.conn(), Path(ROOT), OptHeader(Some(etag))).await.unwrap();
+		assert_eq!(node_etag, created_etag);
+		
+		delete_file(db.conn(), DelegationRecalls::default(), OptHeader(None), Path(ROOT), Postcard("file".to_owned())).await.unwrap();
+		
+		// versions only ever increase, so an empty directory doesn't get its old tag back
+		let (Header(deleted_etag), _) = dir_info(db.conn(), Path(ROOT), OptHeader(Some(created_etag.clone()))).await.unwrap();
+		assert_ne!(deleted_etag, created_etag);
+	}
+	
 	#[tokio::test]
 	async fn already_exists() {
 		let mut db = TestDb::new();
@@ -232,6 +457,306 @@ mod tests {
 		assert_eq!(err, Error::AlreadyExists(file_location));
 	}
 	
+	#[tokio::test]
+	async fn batch_continues_after_failure() {
+		let mut db = TestDb::new();
+		let directories = TestDirectories::new();
+		
+		let request = BatchRequest {
+			atomic: false,
+			operations: vec![
+				BatchOperation::CreateDir { parent: ROOT, name: "dir".to_owned() },
+				BatchOperation::CreateDir { parent: ROOT, name: "dir".to_owned() },
+				BatchOperation::CreateFile { parent: ROOT, name: "file".to_owned() },
+				BatchOperation::DeleteDir { parent: ROOT, name: "file".to_owned() },
+				BatchOperation::DeleteFile { parent: ROOT, name: "file".to_owned() },
+			],
+		};
+		
+		let Postcard(results) = batch(db.conn(), directories.dirs(), directories.blobs(), DelegationRecalls::default(), OptHeader(None), Postcard(request)).await.unwrap();
+		assert_eq!(results.len(), 5);
+		
+		let Ok(BatchOutcome::CreatedDir(dir_id)) = results[0] else {panic!()};
+		assert_eq!(results[1], Err(BatchError::AlreadyExists));
+		let Ok(BatchOutcome::CreatedFile(_, ref hash)) = results[2] else {panic!()};
+		assert_eq!(hash, &Hash(EMPTY_HASH.to_owned()));
+		assert_eq!(results[3], Err(BatchError::NotADirectory));
+		assert_eq!(results[4], Ok(BatchOutcome::Deleted));
+		
+		let (_, Postcard(root)) = dir_info(db.conn(), Path(ROOT), OptHeader(None)).await.unwrap();
+		assert_eq!(root.children.len(), 1);
+		assert_eq!(root.children.get("dir"), Some(&dir_id));
+	}
+	
+	#[tokio::test]
+	async fn atomic_batch_rolls_back() {
+		let mut db = TestDb::new();
+		let directories = TestDirectories::new();
+		
+		create_file(db.conn(), Path(ROOT), Postcard("file".to_owned())).await.unwrap();
+		
+		let request = BatchRequest {
+			atomic: true,
+			operations: vec![
+				BatchOperation::CreateDir { parent: ROOT, name: "dir".to_owned() },
+				BatchOperation::DeleteFile { parent: ROOT, name: "file".to_owned() },
+				BatchOperation::CreateFile { parent: ROOT, name: "dir".to_owned() },
+				BatchOperation::CreateFile { parent: ROOT, name: "other".to_owned() },
+			],
+		};
+		
+		let Postcard(results) = batch(db.conn(), directories.dirs(), directories.blobs(), DelegationRecalls::default(), OptHeader(None), Postcard(request)).await.unwrap();
+		assert_eq!(results, vec![
+			Err(BatchError::Aborted),
+			Err(BatchError::Aborted),
+			Err(BatchError::AlreadyExists),
+			Err(BatchError::Aborted),
+		]);
+		
+		let (_, Postcard(root)) = dir_info(db.conn(), Path(ROOT), OptHeader(None)).await.unwrap();
+		assert_eq!(root.children.len(), 1);
+		assert!(root.children.contains_key("file"));
+	}
+	
+	#[tokio::test]
+	async fn batch_renames() {
+		let mut db = TestDb::new();
+		let directories = TestDirectories::new();
+		
+		let (_, Header(location)) = create_dir(db.conn(), Path(ROOT), Postcard("a".to_owned())).await.unwrap();
+		let Location::Directory(a_id) = location else {panic!()};
+		let (_, Header(location)) = create_dir(db.conn(), Path(ROOT), Postcard("b".to_owned())).await.unwrap();
+		let Location::Directory(b_id) = location else {panic!()};
+		let (_, Header(location)) = create_dir(db.conn(), Path(a_id), Postcard("sub".to_owned())).await.unwrap();
+		let Location::Directory(sub_id) = location else {panic!()};
+		
+		let (_, Header(location), Header(hash)) = create_file(db.conn(), Path(a_id), Postcard("f".to_owned())).await.unwrap();
+		let Location::File(f_id) = location else {panic!()};
+		let stream = bytes_stream_from(&[b"Hello"]);
+		write_file_data(db.conn(), directories.dirs(), directories.blobs(), FileWriteLock::default(), DelegationRecalls::default(), OptHeader(None), Path(f_id), Header(hash), Header(hash_of(b"Hello")), Header(5), BodyStream::from_stream(stream)).await.unwrap();
+		
+		create_file(db.conn(), Path(sub_id), Postcard("g".to_owned())).await.unwrap();
+		create_file(db.conn(), Path(b_id), Postcard("g2".to_owned())).await.unwrap();
+		
+		let rename = |parent, name: &str, new_parent, new_name: &str, replace| BatchOperation::Rename {
+			parent,
+			name: name.to_owned(),
+			new_parent,
+			new_name: new_name.to_owned(),
+			replace,
+		};
+		
+		let request = BatchRequest {
+			atomic: false,
+			operations: vec![
+				rename(a_id, "sub", b_id, "sub", false),
+				rename(ROOT, "b", sub_id, "b", false),
+				rename(sub_id, "g", b_id, "g2", false),
+				rename(sub_id, "g", b_id, "g2", true),
+				rename(a_id, "f", ROOT, "f", false),
+				rename(ROOT, "f", b_id, "sub", true),
+				// top level directories lose their quota
+				rename(ROOT, "a", b_id, "a", false),
+				// and new ones get one
+				rename(b_id, "sub", ROOT, "sub", false),
+			],
+		};
+		
+		let Postcard(results) = batch(db.conn(), directories.dirs(), directories.blobs(), DelegationRecalls::default(), OptHeader(None), Postcard(request)).await.unwrap();
+		assert_eq!(results, vec![
+			Ok(BatchOutcome::Renamed),
+			Err(BatchError::InvalidMove),
+			Err(BatchError::AlreadyExists),
+			Ok(BatchOutcome::Renamed),
+			Ok(BatchOutcome::Renamed),
+			Err(BatchError::NotAFile),
+			Ok(BatchOutcome::Renamed),
+			Ok(BatchOutcome::Renamed),
+		]);
+		
+		let (_, Postcard(root)) = dir_info(db.conn(), Path(ROOT), OptHeader(None)).await.unwrap();
+		assert_eq!(root.children.keys().collect::<Vec<_>>(), vec!["b", "f", "sub"]);
+		let (_, Postcard(b)) = dir_info(db.conn(), Path(b_id), OptHeader(None)).await.unwrap();
+		assert_eq!(b.children.keys().collect::<Vec<_>>(), vec!["a", "g2"]);
+		let (_, Postcard(a)) = dir_info(db.conn(), Path(a_id), OptHeader(None)).await.unwrap();
+		assert_eq!(a.parent, b_id);
+		let (_, Postcard(sub)) = dir_info(db.conn(), Path(sub_id), OptHeader(None)).await.unwrap();
+		assert_eq!(sub.parent, ROOT);
+		assert!(sub.children.is_empty());
+		
+		let Postcard(usage) = quota_info(db.conn(), Path(a_id)).await.unwrap();
+		assert_eq!((usage.directory, usage.used_bytes, usage.used_nodes), (b_id, 0, 3));
+		let Postcard(usage) = quota_info(db.conn(), Path(sub_id)).await.unwrap();
+		assert_eq!((usage.directory, usage.used_bytes, usage.used_nodes), (sub_id, 0, 1));
+		
+		let report = crate::fsck::check(&mut db.conn(), &*directories.blobs(), false).await.unwrap();
+		assert!(report.is_clean(), "{report:?}");
+	}
+	
+	#[tokio::test]
+	async fn batch_sets_sizes() {
+		let mut db = TestDb::new();
+		let directories = TestDirectories::new();
+		
+		let (_, Header(location)) = create_dir(db.conn(), Path(ROOT), Postcard("top".to_owned())).await.unwrap();
+		let Location::Directory(top_id) = location else {panic!()};
+		set_quota_limits(&mut db.conn(), top_id, QuotaLimits { max_bytes: Some(10), max_nodes: None }).unwrap();
+		
+		let (_, Header(location), Header(empty_hash)) = create_file(db.conn(), Path(top_id), Postcard("file".to_owned())).await.unwrap();
+		let Location::File(id) = location else {panic!()};
+		let stream = bytes_stream_from(&[b"Hello"]);
+		write_file_data(db.conn(), directories.dirs(), directories.blobs(), FileWriteLock::default(), DelegationRecalls::default(), OptHeader(None), Path(id), Header(empty_hash.clone()), Header(hash_of(b"Hello")), Header(5), BodyStream::from_stream(stream)).await.unwrap();
+		let hash = hash_of(b"Hello");
+		
+		let set_size = |expected_hash: &Hash, size| BatchRequest {
+			atomic: false,
+			operations: vec![BatchOperation::SetSize { file: id, expected_hash: expected_hash.clone(), size }],
+		};
+		
+		let Postcard(results) = batch(db.conn(), directories.dirs(), directories.blobs(), DelegationRecalls::default(), OptHeader(None), Postcard(set_size(&empty_hash, 2))).await.unwrap();
+		assert_eq!(results, vec![Err(BatchError::Modified)]);
+		let Postcard(results) = batch(db.conn(), directories.dirs(), directories.blobs(), DelegationRecalls::default(), OptHeader(None), Postcard(set_size(&hash, 11))).await.unwrap();
+		assert_eq!(results, vec![Err(BatchError::QuotaExceeded)]);
+		
+		let Postcard(results) = batch(db.conn(), directories.dirs(), directories.blobs(), DelegationRecalls::default(), OptHeader(None), Postcard(set_size(&hash, 2))).await.unwrap();
+		let hash = hash_of(b"He");
+		assert_eq!(results, vec![Ok(BatchOutcome::Resized(hash.clone()))]);
+		
+		let Postcard(results) = batch(db.conn(), directories.dirs(), directories.blobs(), DelegationRecalls::default(), OptHeader(None), Postcard(set_size(&hash, 4))).await.unwrap();
+		let hash = hash_of(b"He\0\0");
+		assert_eq!(results, vec![Ok(BatchOutcome::Resized(hash.clone()))]);
+		
+		let (_, Header(etag), _, _, _, body) = file_data(db.conn(), directories.blobs(), Path(id), OptHeader(None), OptHeader(None), OptHeader(None), OptHeader(None)).await.unwrap();
+		assert_eq!(etag, ContentETag::Strong(hash.clone()));
+		assert_eq!(axum::body::to_bytes(body, usize::MAX).await.unwrap(), &b"He\0\0"[..]);
+		
+		let Postcard(usage) = quota_info(db.conn(), Path(id)).await.unwrap();
+		assert_eq!(usage.used_bytes, 4);
+		
+		// resizing the same file twice in a request isn't supported
+		let mut request = set_size(&hash, 0);
+		request.operations.push(BatchOperation::SetSize { file: id, expected_hash: hash_of(b""), size: 1 });
+		let Err(err) = batch(db.conn(), directories.dirs(), directories.blobs(), DelegationRecalls::default(), OptHeader(None), Postcard(request)).await else {panic!()};
+		assert_eq!(err, Error::BadRequest);
+	}
+	
+	#[tokio::test]
+	async fn moving_changes_etag() {
+		let mut db = TestDb::new();
+		let directories = TestDirectories::new();
+		
+		let (_, Header(location)) = create_dir(db.conn(), Path(ROOT), Postcard("a".to_owned())).await.unwrap();
+		let Location::Directory(a_id) = location else {panic!()};
+		let (_, Header(location)) = create_dir(db.conn(), Path(ROOT), Postcard("b".to_owned())).await.unwrap();
+		let Location::Directory(b_id) = location else {panic!()};
+		
+		let (Header(etag), _) = dir_info(db.conn(), Path(b_id), OptHeader(None)).await.unwrap();
+		
+		let request = BatchRequest {
+			atomic: false,
+			operations: vec![BatchOperation::Rename {
+				parent: ROOT,
+				name: "b".to_owned(),
+				new_parent: a_id,
+				new_name: "b".to_owned(),
+				replace: false,
+			}],
+		};
+		batch(db.conn(), directories.dirs(), directories.blobs(), DelegationRecalls::default(), OptHeader(None), Postcard(request)).await.unwrap();
+		
+		// the entries are the same, but the parent is not
+		let (Header(moved_etag), Postcard(b)) = dir_info(db.conn(), Path(b_id), OptHeader(Some(etag.clone()))).await.unwrap();
+		assert_ne!(moved_etag, etag);
+		assert_eq!(b.parent, a_id);
+	}
+	
+	#[tokio::test]
+	async fn conflicting_delegations_recalled() {
+		let mut db = TestDb::new();
+		let recalls = DelegationRecalls::default();
+		
+		let (_, Header(location), _) = create_file(db.conn(), Path(ROOT), Postcard("file".to_owned())).await.unwrap();
+		let Location::File(id) = location else {panic!()};
+		
+		let request = |client, kind| Postcard(DelegationRequest { client, kind });
+		
+		// read delegations are shared
+		request_delegation(db.conn(), FileWriteLock::default(), recalls.clone(), Path(id), request(1, DelegationKind::Read)).await.unwrap();
+		request_delegation(db.conn(), FileWriteLock::default(), recalls.clone(), Path(id), request(2, DelegationKind::Read)).await.unwrap();
+		
+		let err = request_delegation(db.conn(), FileWriteLock::default(), recalls.clone(), Path(id), request(3, DelegationKind::Write)).await.unwrap_err();
+		assert_eq!(err, Error::Locked);
+		
+		let Postcard(recalled) = delegation_recalls(db.conn(), recalls.clone(), Path(1)).await.unwrap();
+		assert_eq!(recalled, vec![id]);
+		return_delegation(db.conn(), Path((id, 1))).await.unwrap();
+		
+		// still waiting for the second one to be returned
+		let err = request_delegation(db.conn(), FileWriteLock::default(), recalls.clone(), Path(id), request(3, DelegationKind::Write)).await.unwrap_err();
+		assert_eq!(err, Error::Locked);
+		
+		// a recalled delegation can't be renewed
+		let err = request_delegation(db.conn(), FileWriteLock::default(), recalls.clone(), Path(id), request(2, DelegationKind::Read)).await.unwrap_err();
+		assert_eq!(err, Error::Locked);
+		
+		return_delegation(db.conn(), Path((id, 2))).await.unwrap();
+		request_delegation(db.conn(), FileWriteLock::default(), recalls.clone(), Path(id), request(3, DelegationKind::Write)).await.unwrap();
+		
+		let err = request_delegation(db.conn(), FileWriteLock::default(), recalls.clone(), Path(id), request(1, DelegationKind::Read)).await.unwrap_err();
+		assert_eq!(err, Error::Locked);
+		
+		let Postcard(recalled) = delegation_recalls(db.conn(), recalls, Path(3)).await.unwrap();
+		assert_eq!(recalled, vec![id]);
+	}
+	
+	#[tokio::test]
+	async fn changes_recall_delegations() {
+		let mut db = TestDb::new();
+		let directories = TestDirectories::new();
+		let recalls = DelegationRecalls::default();
+		
+		let (_, Header(location), _) = create_file(db.conn(), Path(ROOT), Postcard("file".to_owned())).await.unwrap();
+		let Location::File(id) = location else {panic!()};
+		
+		let request = |client, kind| Postcard(DelegationRequest { client, kind });
+		request_delegation(db.conn(), FileWriteLock::default(), recalls.clone(), Path(id), request(1, DelegationKind::Read)).await.unwrap();
+		
+		// the holder of the delegation can still change the file
+		let stream = bytes_stream_from(&[b"Hello"]);
+		write_file_data(db.conn(), directories.dirs(), directories.blobs(), FileWriteLock::default(), recalls.clone(), OptHeader(Some(1)), Path(id), Header(Hash(EMPTY_HASH.to_owned())), Header(hash_of(b"Hello")), Header(5), BodyStream::from_stream(stream)).await.unwrap();
+		let recalled: Vec<db::Delegation> = db::Delegation::recalled(1).load(&mut *db.conn()).unwrap();
+		assert!(recalled.is_empty());
+		
+		let stream = bytes_stream_from(&[b"World"]);
+		let err = write_file_data(db.conn(), directories.dirs(), directories.blobs(), FileWriteLock::default(), recalls.clone(), OptHeader(Some(2)), Path(id), Header(hash_of(b"Hello")), Header(hash_of(b"World")), Header(5), BodyStream::from_stream(stream)).await.unwrap_err();
+		assert_eq!(err, Error::Locked);
+		let Postcard(recalled) = delegation_recalls(db.conn(), recalls.clone(), Path(1)).await.unwrap();
+		assert_eq!(recalled, vec![id]);
+		
+		// stays locked until the delegation is returned, whichever way the file is changed
+		let Postcard(tx) = open_transaction(db.conn()).await.unwrap();
+		let stream = bytes_stream_from(&[b"World"]);
+		stage_file_data(db.conn(), directories.dirs(), directories.blobs(), Path((tx, id)), Header(hash_of(b"Hello")), Header(hash_of(b"World")), Header(5), BodyStream::from_stream(stream)).await.unwrap();
+		let Err(err) = commit_transaction(db.conn(), directories.dirs(), directories.blobs(), FileWriteLock::default(), recalls.clone(), OptHeader(None), Path(tx)).await else {panic!()};
+		assert_eq!(err, Error::Locked);
+		
+		let request = BatchRequest {
+			atomic: false,
+			operations: vec![BatchOperation::DeleteFile { parent: ROOT, name: "file".to_owned() }],
+		};
+		let Postcard(results) = batch(db.conn(), directories.dirs(), directories.blobs(), recalls.clone(), OptHeader(Some(2)), Postcard(request)).await.unwrap();
+		assert_eq!(results, vec![Err(BatchError::Locked)]);
+		
+		let Err(err) = delete_file(db.conn(), recalls.clone(), OptHeader(Some(2)), Path(ROOT), Postcard("file".to_owned())).await else {panic!()};
+		assert_eq!(err, Error::Locked);
+		
+		let Postcard(info) = file_info(db.conn(), Path(id)).await.unwrap();
+		assert_eq!(info.hash, hash_of(b"Hello"));
+		
+		return_delegation(db.conn(), Path((id, 1))).await.unwrap();
+		delete_file(db.conn(), recalls, OptHeader(Some(2)), Path(ROOT), Postcard("file".to_owned())).await.unwrap();
+	}
+	
 	#[tokio::test]
 	async fn read_empty_file() {
 		let mut db = TestDb::new();
@@ -240,13 +765,198 @@ mod tests {
 		let (_, Header(location), _) = create_file(db.conn(), Path(ROOT), Postcard("file".to_owned())).await.unwrap();
 		let Location::File(id) = location else {panic!()};
 		
-		let (Header(hash), body) = file_data(db.conn(), directories.dirs(), Path(id), OptHeader(None), OptHeader(None)).await.unwrap();
-		assert_eq!(hash, Hash(EMPTY_HASH.to_owned()));
+		let (_, Header(etag), _, _, _, body) = file_data(db.conn(), directories.blobs(), Path(id), OptHeader(None), OptHeader(None), OptHeader(None), OptHeader(None)).await.unwrap();
+		assert_eq!(etag, ContentETag::Strong(Hash(EMPTY_HASH.to_owned())));
 		
 		let mut stream = body.into_data_stream();
 		assert!(stream.next().await.is_none());
 	}
 	
+	#[tokio::test]
+	async fn storage_stats_deduplicate() {
+		let mut db = TestDb::new();
+		let directories = TestDirectories::new();
+		
+		for name in ["first", "second"] {
+			let (_, Header(location), _) = create_file(db.conn(), Path(ROOT), Postcard(name.to_owned())).await.unwrap();
+			let Location::File(id) = location else {panic!()};
+			
+			let stream = bytes_stream_from(&[b"same content"]);
+			write_file_data(db.conn(), directories.dirs(), directories.blobs(), FileWriteLock::default(), DelegationRecalls::default(), OptHeader(None), Path(id), Header(Hash(EMPTY_HASH.to_owned())), Header(hash_of(b"same content")), Header(12), BodyStream::from_stream(stream)).await.unwrap();
+		}
+		
+		create_dir(db.conn(), Path(ROOT), Postcard("directory".to_owned())).await.unwrap();
+		
+		let Postcard(stats) = storage_stats(db.conn(), directories.blobs(), StorageLimit(None)).await.unwrap();
+		assert_eq!(stats.file_count, 2);
+		assert_eq!(stats.directory_count, 2); // including the root directory
+		assert_eq!(stats.logical_bytes, 2 * b"same content".len() as u64);
+		assert_eq!(stats.stored_bytes, b"same content".len() as u64);
+		
+		let Postcard(stats) = storage_stats(db.conn(), directories.blobs(), StorageLimit(Some(20))).await.unwrap();
+		assert_eq!(stats.total_bytes, 20);
+		assert_eq!(stats.available_bytes, 20 - b"same content".len() as u64);
+	}
+	
+	#[tokio::test]
+	async fn quota_enforced() {
+		let mut db = TestDb::new();
+		let directories = TestDirectories::new();
+		
+		let (_, Header(location)) = create_dir(db.conn(), Path(ROOT), Postcard("limited".to_owned())).await.unwrap();
+		let Location::Directory(top_id) = location else {panic!()};
+		
+		let (_, Header(location)) = create_dir(db.conn(), Path(top_id), Postcard("nested".to_owned())).await.unwrap();
+		let Location::Directory(nested_id) = location else {panic!()};
+		
+		let limits = QuotaLimits {
+			max_bytes: Some(10),
+			max_nodes: Some(4),
+		};
+		
+		let usage = set_quota_limits(&mut db.conn(), nested_id, limits.clone()).unwrap();
+		assert_eq!(usage, QuotaUsage {
+			directory: top_id,
+			used_bytes: 0,
+			used_nodes: 2,
+			limits,
+		});
+		
+		let (_, Header(location), Header(hash)) = create_file(db.conn(), Path(nested_id), Postcard("file".to_owned())).await.unwrap();
+		let Location::File(file_id) = location else {panic!()};
+		
+		let stream = bytes_stream_from(&[b"Hello"]);
+		write_file_data(db.conn(), directories.dirs(), directories.blobs(), FileWriteLock::default(), DelegationRecalls::default(), OptHeader(None), Path(file_id), Header(hash), Header(hash_of(b"Hello")), Header(5), BodyStream::from_stream(stream)).await.unwrap();
+		
+		let Postcard(usage) = quota_info(db.conn(), Path(file_id)).await.unwrap();
+		assert_eq!(usage.used_bytes, 5);
+		assert_eq!(usage.used_nodes, 3);
+		
+		// replacing the content frees up its previous size
+		let hash = Hash(blake3::hash(b"Hello").to_hex().to_string());
+		let stream = bytes_stream_from(&[b"Hello", b"World"]);
+		write_file_data(db.conn(), directories.dirs(), directories.blobs(), FileWriteLock::default(), DelegationRecalls::default(), OptHeader(None), Path(file_id), Header(hash), Header(hash_of(b"HelloWorld")), Header(10), BodyStream::from_stream(stream)).await.unwrap();
+		
+		let hash = Hash(blake3::hash(b"HelloWorld").to_hex().to_string());
+		let stream = bytes_stream_from(&[b"Hello", b"World", b"!"]);
+		let err = write_file_data(db.conn(), directories.dirs(), directories.blobs(), FileWriteLock::default(), DelegationRecalls::default(), OptHeader(None), Path(file_id), Header(hash.clone()), Header(hash_of(b"HelloWorld!")), Header(11), BodyStream::from_stream(stream)).await.unwrap_err();
+		assert_eq!(err, Error::QuotaExceeded);
+		
+		let Postcard(file) = file_info(db.conn(), Path(file_id)).await.unwrap();
+		assert_eq!(file, FileInfo {
+			size: 10,
+			hash,
+		});
+		
+		create_file(db.conn(), Path(top_id), Postcard("second".to_owned())).await.unwrap();
+		
+		let err = create_dir(db.conn(), Path(nested_id), Postcard("too many".to_owned())).await.unwrap_err();
+		assert_eq!(err, Error::QuotaExceeded);
+		
+		// failed creation is rolled back
+		let (_, Postcard(dir)) = dir_info(db.conn(), Path(nested_id), OptHeader(None)).await.unwrap();
+		assert_eq!(dir.children.len(), 1);
+		
+		delete_file(db.conn(), DelegationRecalls::default(), OptHeader(None), Path(nested_id), Postcard("file".to_owned())).await.unwrap();
+		
+		let Postcard(usage) = quota_info(db.conn(), Path(top_id)).await.unwrap();
+		assert_eq!(usage.used_bytes, 0);
+		assert_eq!(usage.used_nodes, 3);
+		
+		let Err(err) = quota_info(db.conn(), Path(ROOT)).await else {panic!()};
+		assert_eq!(err, Error::NotFound);
+	}
+	
+	#[tokio::test]
+	async fn volume_key_created_once() {
+		let mut db = TestDb::new();
+		
+		let Err(err) = volume_key(db.conn()).await else {panic!()};
+		assert_eq!(err, Error::NotFound);
+		
+		let (status, Header(location)) = create_volume_key(db.conn(), Postcard(b"first".to_vec())).await.unwrap();
+		assert_eq!(status, StatusCode::CREATED);
+		assert_eq!(location, Location::VolumeKey);
+		
+		let err = create_volume_key(db.conn(), Postcard(b"second".to_vec())).await.unwrap_err();
+		assert_eq!(err, Error::AlreadyExists(Location::VolumeKey));
+		
+		let Postcard(key) = volume_key(db.conn()).await.unwrap();
+		assert_eq!(key, b"first");
+	}
+	
+	#[tokio::test]
+	async fn compressed_range_reads() {
+		let mut db = TestDb::new();
+		let directories = TestDirectories::new();
+		
+		let (_, Header(location), _) = create_file(db.conn(), Path(ROOT), Postcard("file".to_owned())).await.unwrap();
+		let Location::File(id) = location else {panic!()};
+		
+		let content = b"compressible ".repeat(100);
+		let stream = futures::stream::iter([Ok::<_, io::Error>(bytes::Bytes::from(content.clone()))]);
+		write_file_data(db.conn(), directories.dirs(), directories.blobs(), FileWriteLock::default(), DelegationRecalls::default(), OptHeader(None), Path(id), Header(Hash(EMPTY_HASH.to_owned())), Header(hash_of(&content)), Header(content.len() as u64), BodyStream::from_stream(stream)).await.unwrap();
+		
+		let hash = blake3::hash(&content).to_hex();
+		assert!(directories.files().join(format!("{hash}.zst")).exists());
+		assert!(!directories.files().join(hash.as_str()).exists());
+		
+		// the hash is the one of the uncompressed content
+		let (status, Header(etag), Header(vary), _, encoding, body) = file_data(db.conn(), directories.blobs(), Path(id), OptHeader(None), OptHeader(None), OptHeader(None), OptHeader(None)).await.unwrap();
+		assert_eq!(status, StatusCode::OK);
+		assert_eq!(etag, ContentETag::Strong(Hash(hash.to_string())));
+		assert_eq!(vary, header::ACCEPT_ENCODING);
+		assert!(encoding.is_none());
+		assert_eq!(axum::body::to_bytes(body, usize::MAX).await.unwrap(), content);
+		
+		let accept_zstd = OptHeader(Some(vec![ContentCoding::Zstd]));
+		let (_, Header(etag), _, _, encoding, body) = file_data(db.conn(), directories.blobs(), Path(id), OptHeader(None), OptHeader(None), OptHeader(None), accept_zstd).await.unwrap();
+		assert_eq!(etag, ContentETag::Weak(Hash(hash.to_string())));
+		assert_eq!(encoding.map(|Header(coding)| coding), Some(ContentCoding::Zstd));
+		assert!(axum::body::to_bytes(body, usize::MAX).await.unwrap().len() < content.len());
+		
+		// ranges are always decompressed
+		let range = OptHeader(Some(ByteRange::FromTo(600, 612)));
+		let accept_zstd = OptHeader(Some(vec![ContentCoding::Zstd]));
+		let (status, Header(etag), Header(vary), content_range, encoding, body) = file_data(db.conn(), directories.blobs(), Path(id), OptHeader(None), OptHeader(None), range, accept_zstd).await.unwrap();
+		assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+		assert_eq!(etag, ContentETag::Strong(Hash(hash.to_string())));
+		assert_eq!(vary, header::ACCEPT_ENCODING);
+		assert_eq!(content_range.map(|Header(range)| range), Some((Some((600, 613)), 1300)));
+		assert!(encoding.is_none());
+		assert_eq!(axum::body::to_bytes(body, usize::MAX).await.unwrap(), &content[600..613]);
+		
+		let range = OptHeader(Some(ByteRange::From(1300)));
+		let err = file_data(db.conn(), directories.blobs(), Path(id), OptHeader(None), OptHeader(None), range, OptHeader(None)).await.unwrap_err();
+		assert_eq!(err, Error::RangeNotSatisfiable(1300));
+	}
+	
+	#[tokio::test]
+	async fn uncompressed_range_reads() {
+		let mut db = TestDb::new();
+		let directories = TestDirectories::new();
+		
+		let (_, Header(location), _) = create_file(db.conn(), Path(ROOT), Postcard("file".to_owned())).await.unwrap();
+		let Location::File(id) = location else {panic!()};
+		
+		// too small to be compressed
+		let stream = bytes_stream_from(&[b"Hello", b"World"]);
+		write_file_data(db.conn(), directories.dirs(), directories.blobs(), FileWriteLock::default(), DelegationRecalls::default(), OptHeader(None), Path(id), Header(Hash(EMPTY_HASH.to_owned())), Header(hash_of(b"HelloWorld")), Header(10), BodyStream::from_stream(stream)).await.unwrap();
+		
+		let hash = blake3::hash(b"HelloWorld").to_hex();
+		assert!(directories.files().join(hash.as_str()).exists());
+		
+		let range = OptHeader(Some(ByteRange::Last(5)));
+		let (status, _, _, content_range, _, body) = file_data(db.conn(), directories.blobs(), Path(id), OptHeader(None), OptHeader(None), range, OptHeader(None)).await.unwrap();
+		assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+		assert_eq!(content_range.map(|Header(range)| range), Some((Some((5, 10)), 10)));
+		assert_eq!(axum::body::to_bytes(body, usize::MAX).await.unwrap(), &b"World"[..]);
+		
+		let range = OptHeader(Some(ByteRange::FromTo(2, 100)));
+		let (_, _, _, _, _, body) = file_data(db.conn(), directories.blobs(), Path(id), OptHeader(None), OptHeader(None), range, OptHeader(None)).await.unwrap();
+		assert_eq!(axum::body::to_bytes(body, usize::MAX).await.unwrap(), &b"lloWorld"[..]);
+	}
+	
 	#[tokio::test]
 	async fn upload_failed_partial() {
 		let mut db = TestDb::new();
@@ -258,7 +968,7 @@ mod tests {
 		// should be repeatable
 		for _ in 0..2 {
 			let stream = PartialBody::new(b"Partial content".into());
-			let err = write_file_data(db.conn(), directories.dirs(), Path(id), Header(Hash(EMPTY_HASH.to_owned())), BodyStream::from_stream(stream)).await.unwrap_err();
+			let err = write_file_data(db.conn(), directories.dirs(), directories.blobs(), FileWriteLock::default(), DelegationRecalls::default(), OptHeader(None), Path(id), Header(Hash(EMPTY_HASH.to_owned())), Header(hash_of(b"Partial content")), Header(15), BodyStream::from_stream(stream)).await.unwrap_err();
 			// TODO: maybe the route should return a different error
 			assert!(matches!(err, Error::Internal(_)));
 			let err = err.source().unwrap().downcast_ref::<io::Error>().unwrap();
@@ -267,17 +977,306 @@ mod tests {
 		}
 		
 		// file data is empty
-		let (Header(hash), body) = file_data(db.conn(), directories.dirs(), Path(id), OptHeader(None), OptHeader(None)).await.unwrap();
-		assert_eq!(hash, Hash(EMPTY_HASH.to_owned()));
+		let (_, Header(etag), _, _, _, body) = file_data(db.conn(), directories.blobs(), Path(id), OptHeader(None), OptHeader(None), OptHeader(None), OptHeader(None)).await.unwrap();
+		assert_eq!(etag, ContentETag::Strong(Hash(EMPTY_HASH.to_owned())));
 		
 		let mut stream = body.into_data_stream();
 		assert!(stream.next().await.is_none());
 		
 		// directories are empty
-		let dirs = directories.dirs();
-		// TODO: race condition?
-		assert!(dirs.uploads.read_dir().unwrap().next().is_none());
-		assert!(dirs.files.read_dir().unwrap().next().is_none());
+		assert!(directories.uploads_removed().await);
+		assert!(directories.files().read_dir().unwrap().next().is_none());
+	}
+	
+	#[tokio::test]
+	async fn declared_content_mismatch() {
+		let mut db = TestDb::new();
+		let directories = TestDirectories::new();
+		
+		let (_, Header(location), _) = create_file(db.conn(), Path(ROOT), Postcard("file".to_owned())).await.unwrap();
+		let Location::File(id) = location else {panic!()};
+		
+		// cut off, but the body ended without an error
+		let stream = bytes_stream_from(&[b"Hello"]);
+		let err = write_file_data(db.conn(), directories.dirs(), directories.blobs(), FileWriteLock::default(), DelegationRecalls::default(), OptHeader(None), Path(id), Header(Hash(EMPTY_HASH.to_owned())), Header(hash_of(b"HelloWorld")), Header(10), BodyStream::from_stream(stream)).await.unwrap_err();
+		assert_eq!(err, Error::ContentMismatch);
+		
+		let stream = bytes_stream_from(&[b"Hello"]);
+		let err = write_file_data(db.conn(), directories.dirs(), directories.blobs(), FileWriteLock::default(), DelegationRecalls::default(), OptHeader(None), Path(id), Header(Hash(EMPTY_HASH.to_owned())), Header(hash_of(b"Hello")), Header(4), BodyStream::from_stream(stream)).await.unwrap_err();
+		assert_eq!(err, Error::ContentMismatch);
+		
+		let Postcard(file) = file_info(db.conn(), Path(id)).await.unwrap();
+		assert_eq!(file.hash, Hash(EMPTY_HASH.to_owned()));
+		
+		assert!(directories.uploads_removed().await);
+		assert!(directories.files().read_dir().unwrap().next().is_none());
+	}
+	
+	#[tokio::test]
+	async fn upload_by_reference() {
+		let mut db = TestDb::new();
+		let directories = TestDirectories::new();
+		
+		let (_, Header(location), _) = create_file(db.conn(), Path(ROOT), Postcard("first".to_owned())).await.unwrap();
+		let Location::File(first_id) = location else {panic!()};
+		let (_, Header(location), _) = create_file(db.conn(), Path(ROOT), Postcard("second".to_owned())).await.unwrap();
+		let Location::File(second_id) = location else {panic!()};
+		
+		// not stored yet, so an empty body doesn't match
+		let err = write_file_data(db.conn(), directories.dirs(), directories.blobs(), FileWriteLock::default(), DelegationRecalls::default(), OptHeader(None), Path(second_id), Header(Hash(EMPTY_HASH.to_owned())), Header(hash_of(b"HelloWorld")), Header(10), BodyStream::empty()).await.unwrap_err();
+		assert_eq!(err, Error::ContentMismatch);
+		
+		let stream = bytes_stream_from(&[b"HelloWorld"]);
+		write_file_data(db.conn(), directories.dirs(), directories.blobs(), FileWriteLock::default(), DelegationRecalls::default(), OptHeader(None), Path(first_id), Header(Hash(EMPTY_HASH.to_owned())), Header(hash_of(b"HelloWorld")), Header(10), BodyStream::from_stream(stream)).await.unwrap();
+		
+		// the body isn't read at all
+		let stream = PartialBody::new(b"ignored".into());
+		write_file_data(db.conn(), directories.dirs(), directories.blobs(), FileWriteLock::default(), DelegationRecalls::default(), OptHeader(None), Path(second_id), Header(Hash(EMPTY_HASH.to_owned())), Header(hash_of(b"HelloWorld")), Header(10), BodyStream::from_stream(stream)).await.unwrap();
+		
+		let (_, Header(etag), _, _, _, body) = file_data(db.conn(), directories.blobs(), Path(second_id), OptHeader(None), OptHeader(None), OptHeader(None), OptHeader(None)).await.unwrap();
+		assert_eq!(etag, ContentETag::Strong(hash_of(b"HelloWorld")));
+		assert_eq!(axum::body::to_bytes(body, usize::MAX).await.unwrap(), &b"HelloWorld"[..]);
+		
+		// the size needs to match as well
+		let (_, Header(location), _) = create_file(db.conn(), Path(ROOT), Postcard("third".to_owned())).await.unwrap();
+		let Location::File(third_id) = location else {panic!()};
+		let err = write_file_data(db.conn(), directories.dirs(), directories.blobs(), FileWriteLock::default(), DelegationRecalls::default(), OptHeader(None), Path(third_id), Header(Hash(EMPTY_HASH.to_owned())), Header(hash_of(b"HelloWorld")), Header(11), BodyStream::empty()).await.unwrap_err();
+		assert_eq!(err, Error::ContentMismatch);
+	}
+	
+	#[tokio::test]
+	async fn upload_by_reference_of_quarantined_blob() {
+		let mut db = TestDb::new();
+		let directories = TestDirectories::new();
+		
+		let (_, Header(location), _) = create_file(db.conn(), Path(ROOT), Postcard("first".to_owned())).await.unwrap();
+		let Location::File(first_id) = location else {panic!()};
+		let (_, Header(location), _) = create_file(db.conn(), Path(ROOT), Postcard("second".to_owned())).await.unwrap();
+		let Location::File(second_id) = location else {panic!()};
+		
+		let stream = bytes_stream_from(&[b"HelloWorld"]);
+		write_file_data(db.conn(), directories.dirs(), directories.blobs(), FileWriteLock::default(), DelegationRecalls::default(), OptHeader(None), Path(first_id), Header(Hash(EMPTY_HASH.to_owned())), Header(hash_of(b"HelloWorld")), Header(10), BodyStream::from_stream(stream)).await.unwrap();
+		directories.blobs().quarantine(&hash_of(b"HelloWorld").0).await.unwrap();
+		
+		// the first file still refers to the content, but it has to be uploaded again
+		let err = write_file_data(db.conn(), directories.dirs(), directories.blobs(), FileWriteLock::default(), DelegationRecalls::default(), OptHeader(None), Path(second_id), Header(Hash(EMPTY_HASH.to_owned())), Header(hash_of(b"HelloWorld")), Header(10), BodyStream::empty()).await.unwrap_err();
+		assert_eq!(err, Error::ContentMismatch);
+		
+		let stream = bytes_stream_from(&[b"HelloWorld"]);
+		write_file_data(db.conn(), directories.dirs(), directories.blobs(), FileWriteLock::default(), DelegationRecalls::default(), OptHeader(None), Path(second_id), Header(Hash(EMPTY_HASH.to_owned())), Header(hash_of(b"HelloWorld")), Header(10), BodyStream::from_stream(stream)).await.unwrap();
+		
+		let (_, _, _, _, _, body) = file_data(db.conn(), directories.blobs(), Path(first_id), OptHeader(None), OptHeader(None), OptHeader(None), OptHeader(None)).await.unwrap();
+		assert_eq!(axum::body::to_bytes(body, usize::MAX).await.unwrap(), &b"HelloWorld"[..]);
+	}
+	
+	#[tokio::test]
+	async fn transaction_commits_together() {
+		let mut db = TestDb::new();
+		let directories = TestDirectories::new();
+		
+		let (_, Header(location), _) = create_file(db.conn(), Path(ROOT), Postcard("manifest".to_owned())).await.unwrap();
+		let Location::File(manifest_id) = location else {panic!()};
+		let (_, Header(location), _) = create_file(db.conn(), Path(ROOT), Postcard("data".to_owned())).await.unwrap();
+		let Location::File(data_id) = location else {panic!()};
+		
+		let Postcard(tx) = open_transaction(db.conn()).await.unwrap();
+		
+		let stream = bytes_stream_from(&[b"Hello"]);
+		stage_file_data(db.conn(), directories.dirs(), directories.blobs(), Path((tx, manifest_id)), Header(Hash(EMPTY_HASH.to_owned())), Header(hash_of(b"Hello")), Header(5), BodyStream::from_stream(stream)).await.unwrap();
+		let stream = bytes_stream_from(&[b"World"]);
+		stage_file_data(db.conn(), directories.dirs(), directories.blobs(), Path((tx, data_id)), Header(Hash(EMPTY_HASH.to_owned())), Header(hash_of(b"World")), Header(5), BodyStream::from_stream(stream)).await.unwrap();
+		stage_operations(db.conn(), Path(tx), Postcard(vec![BatchOperation::CreateDir { parent: ROOT, name: "dir".to_owned() }])).await.unwrap();
+		
+		// nothing is visible before committing
+		let Postcard(info) = file_info(db.conn(), Path(manifest_id)).await.unwrap();
+		assert_eq!(info.hash, Hash(EMPTY_HASH.to_owned()));
+		let (_, Postcard(root)) = dir_info(db.conn(), Path(ROOT), OptHeader(None)).await.unwrap();
+		assert!(!root.children.contains_key("dir"));
+		
+		// staged contents are already stored, but not orphaned
+		let report = crate::fsck::check(&mut db.conn(), &*directories.blobs(), false).await.unwrap();
+		assert!(report.is_clean(), "{report:?}");
+		
+		let Postcard(outcomes) = commit_transaction(db.conn(), directories.dirs(), directories.blobs(), FileWriteLock::default(), DelegationRecalls::default(), OptHeader(None), Path(tx)).await.unwrap();
+		let [BatchOutcome::CreatedDir(dir_id)] = outcomes[..] else {panic!()};
+		
+		let Postcard(info) = file_info(db.conn(), Path(manifest_id)).await.unwrap();
+		assert_eq!(info.hash, hash_of(b"Hello"));
+		let (_, _, _, _, _, body) = file_data(db.conn(), directories.blobs(), Path(data_id), OptHeader(None), OptHeader(None), OptHeader(None), OptHeader(None)).await.unwrap();
+		assert_eq!(axum::body::to_bytes(body, usize::MAX).await.unwrap(), &b"World"[..]);
+		let (_, Postcard(root)) = dir_info(db.conn(), Path(ROOT), OptHeader(None)).await.unwrap();
+		assert_eq!(root.children.get("dir"), Some(&dir_id));
+		
+		// committed transactions are closed
+		let Err(err) = commit_transaction(db.conn(), directories.dirs(), directories.blobs(), FileWriteLock::default(), DelegationRecalls::default(), OptHeader(None), Path(tx)).await else {panic!()};
+		assert_eq!(err, Error::NotFound);
+		let err = abort_transaction(db.conn(), Path(tx)).await.unwrap_err();
+		assert_eq!(err, Error::NotFound);
+		
+		// dropping timed out transactions, like opening one after the timeout does, closes them
+		let Postcard(expired) = open_transaction(db.conn()).await.unwrap();
+		db::OpenTransaction::delete_expired(&mut db.conn(), i64::MAX).unwrap();
+		let err = stage_operations(db.conn(), Path(expired), Postcard(Vec::new())).await.unwrap_err();
+		assert_eq!(err, Error::NotFound);
+	}
+	
+	#[tokio::test]
+	async fn transaction_precondition_failed() {
+		let mut db = TestDb::new();
+		let directories = TestDirectories::new();
+		
+		let (_, Header(location), _) = create_file(db.conn(), Path(ROOT), Postcard("manifest".to_owned())).await.unwrap();
+		let Location::File(manifest_id) = location else {panic!()};
+		let (_, Header(location), _) = create_file(db.conn(), Path(ROOT), Postcard("data".to_owned())).await.unwrap();
+		let Location::File(data_id) = location else {panic!()};
+		
+		let Postcard(tx) = open_transaction(db.conn()).await.unwrap();
+		
+		let stream = bytes_stream_from(&[b"Hello"]);
+		stage_file_data(db.conn(), directories.dirs(), directories.blobs(), Path((tx, manifest_id)), Header(Hash(EMPTY_HASH.to_owned())), Header(hash_of(b"Hello")), Header(5), BodyStream::from_stream(stream)).await.unwrap();
+		let stream = bytes_stream_from(&[b"World"]);
+		stage_file_data(db.conn(), directories.dirs(), directories.blobs(), Path((tx, data_id)), Header(Hash(EMPTY_HASH.to_owned())), Header(hash_of(b"World")), Header(5), BodyStream::from_stream(stream)).await.unwrap();
+		stage_operations(db.conn(), Path(tx), Postcard(vec![BatchOperation::CreateDir { parent: ROOT, name: "dir".to_owned() }])).await.unwrap();
+		
+		// written by someone else after staging
+		let stream = bytes_stream_from(&[b"Other"]);
+		write_file_data(db.conn(), directories.dirs(), directories.blobs(), FileWriteLock::default(), DelegationRecalls::default(), OptHeader(None), Path(data_id), Header(Hash(EMPTY_HASH.to_owned())), Header(hash_of(b"Other")), Header(5), BodyStream::from_stream(stream)).await.unwrap();
+		
+		let Err(err) = commit_transaction(db.conn(), directories.dirs(), directories.blobs(), FileWriteLock::default(), DelegationRecalls::default(), OptHeader(None), Path(tx)).await else {panic!()};
+		assert_eq!(err, Error::Modified);
+		
+		let Postcard(info) = file_info(db.conn(), Path(manifest_id)).await.unwrap();
+		assert_eq!(info.hash, Hash(EMPTY_HASH.to_owned()));
+		let Postcard(info) = file_info(db.conn(), Path(data_id)).await.unwrap();
+		assert_eq!(info.hash, hash_of(b"Other"));
+		let (_, Postcard(root)) = dir_info(db.conn(), Path(ROOT), OptHeader(None)).await.unwrap();
+		assert!(!root.children.contains_key("dir"));
+	}
+	
+	#[tokio::test]
+	async fn transaction_staged_files_limited() {
+		let mut db = TestDb::new();
+		let directories = TestDirectories::new();
+		
+		let Postcard(tx) = open_transaction(db.conn()).await.unwrap();
+		
+		let mut ids = Vec::new();
+		for i in 0..=MAX_STAGED_FILES {
+			let (_, Header(location), _) = create_file(db.conn(), Path(ROOT), Postcard(format!("file{i}"))).await.unwrap();
+			let Location::File(id) = location else {panic!()};
+			ids.push(id);
+		}
+		
+		for &id in &ids[..MAX_STAGED_FILES] {
+			stage_file_data(db.conn(), directories.dirs(), directories.blobs(), Path((tx, id)), Header(Hash(EMPTY_HASH.to_owned())), Header(Hash(EMPTY_HASH.to_owned())), Header(0), BodyStream::empty()).await.unwrap();
+		}
+		
+		let err = stage_file_data(db.conn(), directories.dirs(), directories.blobs(), Path((tx, ids[MAX_STAGED_FILES])), Header(Hash(EMPTY_HASH.to_owned())), Header(Hash(EMPTY_HASH.to_owned())), Header(0), BodyStream::empty()).await.unwrap_err();
+		assert_eq!(err, Error::BadRequest);
+		
+		// replacing staged content is still allowed
+		let stream = bytes_stream_from(&[b"Hello"]);
+		stage_file_data(db.conn(), directories.dirs(), directories.blobs(), Path((tx, ids[0])), Header(Hash(EMPTY_HASH.to_owned())), Header(hash_of(b"Hello")), Header(5), BodyStream::from_stream(stream)).await.unwrap();
+	}
+	
+	#[tokio::test]
+	async fn fsck_repairs() {
+		let mut db = TestDb::new();
+		let directories = TestDirectories::new();
+		
+		let (_, Header(location), _) = create_file(db.conn(), Path(ROOT), Postcard("good".to_owned())).await.unwrap();
+		let Location::File(good_id) = location else {panic!()};
+		let stream = bytes_stream_from(&[b"HelloWorld"]);
+		write_file_data(db.conn(), directories.dirs(), directories.blobs(), FileWriteLock::default(), DelegationRecalls::default(), OptHeader(None), Path(good_id), Header(Hash(EMPTY_HASH.to_owned())), Header(hash_of(b"HelloWorld")), Header(10), BodyStream::from_stream(stream)).await.unwrap();
+		
+		let (_, Header(location), _) = create_file(db.conn(), Path(ROOT), Postcard("bad".to_owned())).await.unwrap();
+		let Location::File(bad_id) = location else {panic!()};
+		let stream = bytes_stream_from(&[b"corrupted"]);
+		write_file_data(db.conn(), directories.dirs(), directories.blobs(), FileWriteLock::default(), DelegationRecalls::default(), OptHeader(None), Path(bad_id), Header(Hash(EMPTY_HASH.to_owned())), Header(hash_of(b"corrupted")), Header(9), BodyStream::from_stream(stream)).await.unwrap();
+		
+		let bad_hash = blake3::hash(b"corrupted").to_hex();
+		std::fs::write(directories.files().join(bad_hash.as_str()), b"tampered").unwrap();
+		
+		let orphan_hash = blake3::hash(b"orphan").to_hex();
+		let stream = futures::stream::iter([Ok(bytes::Bytes::from_static(b"orphan"))]);
+		directories.blobs().put_stream(&orphan_hash, false, Box::pin(stream)).await.unwrap();
+		
+		let (_, Header(location)) = create_dir(db.conn(), Path(ROOT), Postcard("top".to_owned())).await.unwrap();
+		let Location::Directory(top_id) = location else {panic!()};
+		let (_, Header(location)) = create_dir(db.conn(), Path(top_id), Postcard("dir".to_owned())).await.unwrap();
+		let Location::Directory(dir_id) = location else {panic!()};
+		create_file(db.conn(), Path(dir_id), Postcard("inner".to_owned())).await.unwrap();
+		assert!(db::DirectoryEntry::delete(&mut db.conn(), top_id, "dir").unwrap());
+		
+		let (_, Header(location), _) = create_file(db.conn(), Path(top_id), Postcard("gone".to_owned())).await.unwrap();
+		let Location::File(gone_id) = location else {panic!()};
+		let stream = bytes_stream_from(&[b"Hello"]);
+		write_file_data(db.conn(), directories.dirs(), directories.blobs(), FileWriteLock::default(), DelegationRecalls::default(), OptHeader(None), Path(gone_id), Header(Hash(EMPTY_HASH.to_owned())), Header(hash_of(b"Hello")), Header(5), BodyStream::from_stream(stream)).await.unwrap();
+		// the entry is deleted along with the file, but top is still charged for it
+		assert!(db::File::delete(&mut db.conn(), gone_id).unwrap());
+		
+		let gone_hash = blake3::hash(b"Hello").to_hex();
+		let Postcard(report) = fsck(db.conn(), directories.blobs(), Query(FsckQuery { repair: false })).await.unwrap();
+		let mut orphaned_blobs = vec![Hash(orphan_hash.to_string()), Hash(gone_hash.to_string())];
+		orphaned_blobs.sort_by(|a, b| a.0.cmp(&b.0));
+		assert_eq!(report, FsckReport {
+			missing_blobs: vec![],
+			corrupt_blobs: vec![Hash(bad_hash.to_string())],
+			orphaned_blobs,
+			dangling_entries: vec![],
+			directory_cycles: vec![],
+			orphaned_nodes: vec![dir_id],
+			wrong_quotas: vec![top_id],
+			repaired: false,
+		});
+		
+		// the corrupt blob can't be replaced and orphaned blobs are only reported
+		let Postcard(report) = fsck(db.conn(), directories.blobs(), Query(FsckQuery { repair: true })).await.unwrap();
+		assert!(!report.repaired);
+		
+		// the corrupt blob is gone, so it is now missing
+		let report = crate::fsck::check(&mut db.conn(), &*directories.blobs(), false).await.unwrap();
+		assert_eq!(report.missing_blobs, vec![Hash(bad_hash.to_string())]);
+		assert!(report.corrupt_blobs.is_empty());
+		assert!(report.dangling_entries.is_empty());
+		assert!(report.orphaned_nodes.is_empty());
+		assert!(report.wrong_quotas.is_empty());
+		assert!(directories.files().join("quarantine").join(bad_hash.as_str()).exists());
+		
+		// nothing left that can be repaired
+		let report = crate::fsck::check(&mut db.conn(), &*directories.blobs(), true).await.unwrap();
+		assert!(!report.repaired);
+		
+		let (_, Postcard(root)) = dir_info(db.conn(), Path(ROOT), OptHeader(None)).await.unwrap();
+		let lost_and_found = root.children[crate::fsck::LOST_AND_FOUND];
+		
+		let (_, Postcard(lost_and_found)) = dir_info(db.conn(), Path(lost_and_found), OptHeader(None)).await.unwrap();
+		assert_eq!(lost_and_found.children.get(&dir_id.0.to_string()), Some(&dir_id));
+		
+		let (_, Postcard(dir)) = dir_info(db.conn(), Path(dir_id), OptHeader(None)).await.unwrap();
+		assert_eq!(dir.parent, root.children[crate::fsck::LOST_AND_FOUND]);
+		
+		// lost+found is charged for itself, the directory and the file inside it
+		let Postcard(quota) = quota_info(db.conn(), Path(dir_id)).await.unwrap();
+		assert_eq!((quota.used_bytes, quota.used_nodes), (0, 3));
+		
+		// top is no longer charged for the directory, its file or the deleted file
+		let Postcard(quota) = quota_info(db.conn(), Path(top_id)).await.unwrap();
+		assert_eq!((quota.used_bytes, quota.used_nodes), (0, 1));
+		
+		// the volume is only repaired once nothing is left that can't be repaired
+		assert!(db::File::delete(&mut db.conn(), bad_id).unwrap());
+		for hash in [orphan_hash, gone_hash] {
+			directories.blobs().delete(&hash).await.unwrap();
+		}
+		db::Quota::set_usage(&mut db.conn(), top_id, 100, 100).unwrap();
+		
+		let Postcard(report) = fsck(db.conn(), directories.blobs(), Query(FsckQuery { repair: true })).await.unwrap();
+		assert_eq!(report.wrong_quotas, vec![top_id]);
+		assert!(report.repaired);
+		
+		let Postcard(report) = fsck(db.conn(), directories.blobs(), Query(FsckQuery { repair: false })).await.unwrap();
+		assert!(report.is_clean());
 	}
 	
 	// TODO: add more test cases
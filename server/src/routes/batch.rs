@@ -0,0 +1,178 @@
+use std::{collections::HashMap, io};
+
+use super::*;
+
+/// Contents resized for [`BatchOperation::SetSize`] operations by [`resize_contents`], keyed by their file, expected hash and size
+pub(super) type ResizedContents = HashMap<(NodeID, Hash, u64), ResizedContent>;
+
+/// Receives the resized contents before any operation is applied, as that can't happen inside the database transaction
+/// 
+/// Operations that can't be prepared are left out and fail once they are applied.
+pub(super) async fn resize_contents(
+	conn: &mut db::AnyConnection,
+	directories: &Directories,
+	blobs: &Blobs,
+	operations: &[BatchOperation]
+) -> Result<ResizedContents, Error> {
+	let mut contents = ResizedContents::new();
+	
+	for operation in operations {
+		let BatchOperation::SetSize { file, expected_hash, size } = operation else { continue };
+		
+		if contents.keys().any(|(id, _, _)| id == file) {
+			return Err(Error::BadRequest);
+		}
+		
+		match resize_content(conn, directories, blobs, *file, expected_hash, *size).await {
+			Ok(content) => {
+				contents.insert((*file, expected_hash.clone(), *size), content);
+			},
+			Err(Error::Internal(err)) => eprintln!("{err}"),
+			Err(_) => {},
+		}
+	}
+	
+	Ok(contents)
+}
+
+/// Applies the operation and stores the content it resized, needs to run inside a transaction
+pub(super) async fn apply_resizing_operation(
+	conn: &mut db::AnyConnection,
+	blobs: &Blobs,
+	conflicts: &mut DelegationConflicts,
+	resized: &mut ResizedContents,
+	operation: &BatchOperation
+) -> Result<BatchOutcome, Error> {
+	let outcome = apply_operation(conn, conflicts, resized, operation)?;
+	
+	if let BatchOperation::SetSize { file, expected_hash, size } = operation {
+		if let Some(content) = resized.remove(&(*file, expected_hash.clone(), *size)) {
+			content.store(blobs).await?;
+		}
+	}
+	
+	Ok(outcome)
+}
+
+fn apply_operation(
+	conn: &mut db::AnyConnection,
+	conflicts: &mut DelegationConflicts,
+	resized: &ResizedContents,
+	operation: &BatchOperation
+) -> Result<BatchOutcome, Error> {
+	match operation {
+		BatchOperation::CreateDir { parent, name } => Ok(BatchOutcome::CreatedDir(create_dir_entry(conn, *parent, name)?)),
+		BatchOperation::CreateFile { parent, name } => {
+			let id = create_file_entry(conn, *parent, name)?;
+			Ok(BatchOutcome::CreatedFile(id, Hash(EMPTY_HASH.to_owned())))
+		},
+		BatchOperation::DeleteDir { parent, name } => {
+			delete_dir_entry(conn, *parent, name)?;
+			Ok(BatchOutcome::Deleted)
+		},
+		BatchOperation::DeleteFile { parent, name } => {
+			delete_file_entry(conn, conflicts, *parent, name)?;
+			Ok(BatchOutcome::Deleted)
+		},
+		BatchOperation::Rename { parent, name, new_parent, new_name, replace } => {
+			rename_entry(conn, conflicts, *parent, name, *new_parent, new_name, *replace)?;
+			Ok(BatchOutcome::Renamed)
+		},
+		BatchOperation::SetSize { file, expected_hash, size } => {
+			let content = resized.get(&(*file, expected_hash.clone(), *size));
+			
+			// also checked if the content couldn't be resized, so the operation fails the same way as preparing it did
+			replace_content(conn, *file, expected_hash, content.map_or(expected_hash, |content| &content.hash), *size)?;
+			
+			let Some(content) = content else {
+				return Err(Error::internal(io::Error::other("content was not resized"), "could not set file size"));
+			};
+			
+			conflicts.check(conn, *file)?;
+			
+			Ok(BatchOutcome::Resized(content.hash.clone()))
+		},
+	}
+}
+
+fn batch_error(err: Error) -> BatchError {
+	match err {
+		Error::NotFound => BatchError::NotFound,
+		Error::NotAFile => BatchError::NotAFile,
+		Error::NotADirectory => BatchError::NotADirectory,
+		Error::AlreadyExists(_) => BatchError::AlreadyExists,
+		Error::DirectoryNotEmpty => BatchError::DirectoryNotEmpty,
+		Error::QuotaExceeded => BatchError::QuotaExceeded,
+		Error::Locked => BatchError::Locked,
+		Error::InvalidMove => BatchError::InvalidMove,
+		Error::Modified => BatchError::Modified,
+		err => {
+			eprintln!("{err}");
+			BatchError::ServerError
+		},
+	}
+}
+
+/// Applies many changes of the directory tree in a single request, e.g. when a whole archive is extracted
+/// 
+/// Failed operations don't stop the remaining ones unless the batch is atomic,
+/// in which case the first failure rolls back all operations before it and skips all after it.
+pub async fn batch(
+	mut conn: DbConnection<'_>,
+	directories: Directories,
+	blobs: Blobs,
+	recalls: DelegationRecalls,
+	OptHeader(client): OptHeader<ClientId>,
+	Postcard(request): Postcard<BatchRequest>
+) -> Result<Postcard<Vec<BatchResult>>, Error> {
+	if request.operations.len() > MAX_BATCH_SIZE {
+		return Err(Error::BadRequest);
+	}
+	
+	let mut conflicts = DelegationConflicts::new(client);
+	let mut resized = resize_contents(&mut conn, &directories, &blobs, &request.operations).await?;
+	
+	if !request.atomic {
+		let mut results = Vec::with_capacity(request.operations.len());
+		
+		for operation in &request.operations {
+			let result = async_transaction(&mut conn, async |conn| {
+				apply_resizing_operation(conn, &blobs, &mut conflicts, &mut resized, operation).await
+			}).await;
+			
+			results.push(result.map_err(batch_error));
+		}
+		
+		conflicts.recall(&mut conn, &recalls)?;
+		return Ok(Postcard(results));
+	}
+	
+	// number of operations that were applied before the transaction failed, if it did
+	let mut applied = 0;
+	
+	let result = async_transaction(&mut conn, async |conn| {
+		let mut outcomes = Vec::with_capacity(request.operations.len());
+		
+		for operation in &request.operations {
+			outcomes.push(apply_resizing_operation(conn, &blobs, &mut conflicts, &mut resized, operation).await?);
+			applied += 1;
+		}
+		
+		Ok(outcomes)
+	}).await;
+	
+	conflicts.recall(&mut conn, &recalls)?;
+	
+	let results = match result {
+		Ok(outcomes) => outcomes.into_iter().map(Ok).collect(),
+		// committing failed after every operation was applied
+		Err(err) if applied == request.operations.len() => return Err(err),
+		Err(err) => {
+			let mut results = vec![Err(BatchError::Aborted); request.operations.len()];
+			results[applied] = Err(batch_error(err));
+			results
+		},
+	};
+	
+	Ok(Postcard(results))
+}
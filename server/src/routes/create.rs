@@ -1,6 +1,6 @@
 use super::*;
 
-fn get_entry_url(conn: &mut SqliteConnection, parent_id: NodeID, name: &str) -> Result<Location, Error> {
+fn get_entry_url(conn: &mut db::AnyConnection, parent_id: NodeID, name: &str) -> Result<Location, Error> {
 	// why does rust-analyzer need a type annotation to know what type this is?
 	let entry: db::DirectoryEntry = db::DirectoryEntry::get(parent_id, name)
 		.first(conn).map_err(|err| Error::internal(err, "failed looking up directory entry"))?;
@@ -12,81 +12,100 @@ fn get_entry_url(conn: &mut SqliteConnection, parent_id: NodeID, name: &str) ->
 	})
 }
 
+/// Has to be called within a transaction
+pub(super) fn create_dir_entry(conn: &mut db::AnyConnection, parent_id: NodeID, name: &str) -> Result<NodeID, Error> {
+	let id = db::next_available_id(conn).map_err(|err| Error::internal(err, "failed generating next id"))?;
+	
+	let dir = db::Directory {
+		id: id.0 as i64,
+		parent: parent_id.0 as i64,
+		version: 0,
+	};
+	
+	dir.insert(conn).map_err(|err| Error::internal(err, "failed inserting new node"))?;
+	
+	let dir_entry = db::NewDirectoryEntry {
+		parent: parent_id.0 as i64,
+		name,
+		directory: Some(id.0 as i64),
+		file: None,
+	};
+	
+	// in a savepoint, as PostgreSQL doesn't allow looking up the existing entry in a failed transaction
+	conn.transaction(|conn| dir_entry.insert(conn)).map_err(|err| match err {
+		// foreign key violation because parent doesn't exist in directories
+		DieselError::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => Error::NotFound,
+		// unique violation because entry already exists
+		DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => match get_entry_url(conn, parent_id, name) {
+			Ok(url) => Error::AlreadyExists(url),
+			Err(err) => err,
+		},
+		err => Error::internal(err, "failed inserting new directory entry"),
+	})?;
+	
+	if parent_id == NodeID::ROOT {
+		db::Quota::new(id).insert(conn).map_err(|err| Error::internal(err, "failed inserting new quota"))?;
+	} else {
+		let top_level_directory = top_level_directory(conn, parent_id)?;
+		charge_quota(conn, top_level_directory, 0, 1)?;
+	}
+	
+	Ok(id)
+}
+
 pub async fn create_dir(
 	mut conn: DbConnection<'_>,
 	Path(parent_id): Path<NodeID>,
 	Postcard(name): Postcard<String>
 ) -> Result<(StatusCode, Header<Location>), Error> {
-	let id = transaction(&mut conn, |conn| {
-		let id = db::next_available_id(conn).map_err(|err| Error::internal(err, "failed generating next id"))?;
-		
-		let dir = db::Directory {
-			id: id.0 as i64,
-			parent: parent_id.0 as i64,
-		};
-		
-		dir.insert(conn).map_err(|err| Error::internal(err, "failed inserting new node"))?;
-		
-		let dir_entry = db::NewDirectoryEntry {
-			parent: parent_id.0 as i64,
-			name: &name,
-			directory: Some(id.0 as i64),
-			file: None,
-		};
-		
-		dir_entry.insert(conn).map_err(|err| match err {
-			// foreign key violation because parent doesn't exist in directories
-			DieselError::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => Error::NotFound,
-			// unique violation because entry already exists
-			DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => match get_entry_url(conn, parent_id, &name) {
-				Ok(url) => Error::AlreadyExists(url),
-				Err(err) => err,
-			},
-			err => Error::internal(err, "failed inserting new directory entry"),
-		})?;
-		
-		Ok(id)
-	})?;
+	let id = transaction(&mut conn, |conn| create_dir_entry(conn, parent_id, &name))?;
 	
 	Ok((StatusCode::CREATED, Header(Location::Directory(id))))
 }
 
+/// Has to be called within a transaction, new files are empty
+pub(super) fn create_file_entry(conn: &mut db::AnyConnection, parent_id: NodeID, name: &str) -> Result<NodeID, Error> {
+	let id = db::next_available_id(conn).map_err(|err| Error::internal(err, "failed generating next id"))?;
+	
+	let file = db::File {
+		id: id.0 as i64,
+		size: 0,
+		hash: EMPTY_HASH.to_owned(), // TODO: avoid allocation
+	};
+	
+	file.insert(conn).map_err(|err| Error::internal(err, "failed inserting new node"))?;
+	
+	let dir_entry = db::NewDirectoryEntry {
+		parent: parent_id.0 as i64,
+		name,
+		directory: None,
+		file: Some(id.0 as i64),
+	};
+	
+	// in a savepoint, as PostgreSQL doesn't allow looking up the existing entry in a failed transaction
+	conn.transaction(|conn| dir_entry.insert(conn)).map_err(|err| match err {
+		// foreign key violation because parent doesn't exist in directories
+		DieselError::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => Error::NotFound,
+		// unique violation because entry already exists
+		DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => match get_entry_url(conn, parent_id, name) {
+			Ok(url) => Error::AlreadyExists(url),
+			Err(err) => err,
+		},
+		err => Error::internal(err, "failed inserting new directory entry"),
+	})?;
+	
+	let top_level_directory = top_level_directory(conn, parent_id)?;
+	charge_quota(conn, top_level_directory, 0, 1)?;
+	
+	Ok(id)
+}
+
 pub async fn create_file(
 	mut conn: DbConnection<'_>,
 	Path(parent_id): Path<NodeID>,
 	Postcard(name): Postcard<String>
 ) -> Result<(StatusCode, Header<Location>, Header<ETag>), Error> {
-	let id = transaction(&mut conn, |conn| {
-		let id = db::next_available_id(conn).map_err(|err| Error::internal(err, "failed generating next id"))?;
-		
-		let file = db::File {
-			id: id.0 as i64,
-			size: 0,
-			hash: EMPTY_HASH.to_owned(), // TODO: avoid allocation
-		};
-		
-		file.insert(conn).map_err(|err| Error::internal(err, "failed inserting new node"))?;
-		
-		let dir_entry = db::NewDirectoryEntry {
-			parent: parent_id.0 as i64,
-			name: &name,
-			directory: None,
-			file: Some(id.0 as i64),
-		};
-		
-		dir_entry.insert(conn).map_err(|err| match err {
-			// foreign key violation because parent doesn't exist in directories
-			DieselError::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => Error::NotFound,
-			// unique violation because entry already exists
-			DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => match get_entry_url(conn, parent_id, &name) {
-				Ok(url) => Error::AlreadyExists(url),
-				Err(err) => err,
-			},
-			err => Error::internal(err, "failed inserting new directory entry"),
-		})?;
-		
-		Ok(id)
-	})?;
+	let id = transaction(&mut conn, |conn| create_file_entry(conn, parent_id, &name))?;
 	
 	let hash = Hash(EMPTY_HASH.to_owned()); // TODO: avoid unnecessary allocation
 	
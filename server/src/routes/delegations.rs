@@ -0,0 +1,197 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::sync::Notify;
+
+use super::*;
+use super::write_lock::FileWriteLock;
+
+/// Seconds a client has to return a recalled delegation before it is revoked, in case the client is gone
+const RECALL_TIMEOUT_SECS: i64 = 60;
+/// How long clients asking for recalls are kept waiting if there are none
+const RECALL_WAIT: Duration = Duration::from_secs(30);
+
+/// Wakes up the clients of this server that are waiting for recalls, the recalls themselves are stored in the database
+/// 
+/// Clients of other servers sharing the database only notice recalls once they ask again.
+#[derive(Clone, Default, Debug)]
+pub struct DelegationRecalls {
+	recalled: Arc<Notify>,
+}
+
+/// Files changed by a client which other clients hold delegations of, which have to be recalled before the change is made
+/// 
+/// Changes are checked within their transaction, which is rolled back on conflicts, so the recalls are made afterwards.
+#[derive(Debug)]
+pub(super) struct DelegationConflicts {
+	client: Option<u64>,
+	files: Vec<NodeID>,
+}
+
+impl DelegationConflicts {
+	/// Delegations of the given client never conflict with its own changes, it has to write back its changes before returning them
+	pub fn new(client: Option<u64>) -> Self {
+		Self {
+			client,
+			files: Vec::new(),
+		}
+	}
+	
+	/// Fails with [`Error::Locked`] if other clients hold delegations of the file, which are recalled by [`Self::recall`]
+	/// 
+	/// Recalled delegations that weren't returned in time are revoked instead.
+	pub fn check(&mut self, conn: &mut db::AnyConnection, id: NodeID) -> Result<(), Error> {
+		let delegations: Vec<db::Delegation> = db::Delegation::of_file(id)
+			.load(conn).map_err(|err| Error::internal(err, "failed looking up delegations"))?;
+		
+		let now = unix_time();
+		let mut is_conflicting = false;
+		
+		for delegation in delegations {
+			if Some(delegation.client as u64) == self.client {
+				continue;
+			}
+			
+			match delegation.recalled_at {
+				Some(recalled_at) if now - recalled_at >= RECALL_TIMEOUT_SECS => {
+					db::Delegation::delete(conn, id, delegation.client as u64)
+						.map_err(|err| Error::internal(err, "failed revoking delegation"))?;
+				},
+				_ => is_conflicting = true,
+			}
+		}
+		
+		if is_conflicting {
+			self.files.push(id);
+			return Err(Error::Locked);
+		}
+		
+		Ok(())
+	}
+	
+	/// Recalls the delegations of all files that failed [`Self::check`], needs to be called outside of the transaction
+	pub fn recall(self, conn: &mut db::AnyConnection, recalls: &DelegationRecalls) -> Result<(), Error> {
+		if self.files.is_empty() {
+			return Ok(());
+		}
+		
+		let now = unix_time();
+		
+		transaction(conn, |conn| {
+			for id in self.files {
+				let delegations: Vec<db::Delegation> = db::Delegation::of_file(id)
+					.load(conn).map_err(|err| Error::internal(err, "failed looking up delegations"))?;
+				
+				for delegation in delegations {
+					if Some(delegation.client as u64) != self.client {
+						db::Delegation::recall(conn, id, delegation.client as u64, now)
+							.map_err(|err| Error::internal(err, "failed recalling delegation"))?;
+					}
+				}
+			}
+			
+			Ok(())
+		})?;
+		
+		recalls.recalled.notify_waiters();
+		Ok(())
+	}
+}
+
+/// Grants the delegation unless other clients hold conflicting ones, which are recalled instead
+/// 
+/// Fails with [`Error::Locked`] until all conflicting delegations were returned or revoked, so clients have to ask again.
+/// That includes a recalled delegation of the same client, which has to be returned first.
+pub async fn request_delegation(
+	mut conn: DbConnection<'_>,
+	file_write_lock: FileWriteLock,
+	recalls: DelegationRecalls,
+	Path(id): Path<NodeID>,
+	Postcard(request): Postcard<DelegationRequest>
+) -> Result<StatusCode, Error> {
+	// decided one at a time per file, like writes
+	let _guard = file_write_lock.lock(id).await;
+	
+	get_file_info(&mut conn, id)?;
+	
+	let now = unix_time();
+	let writable = request.kind == DelegationKind::Write;
+	
+	let (is_granted, did_recall) = transaction(&mut conn, |conn| {
+		let delegations: Vec<db::Delegation> = db::Delegation::of_file(id)
+			.load(conn).map_err(|err| Error::internal(err, "failed looking up delegations"))?;
+		
+		let mut is_granted = true;
+		let mut did_recall = false;
+		
+		for delegation in delegations {
+			let is_own = delegation.client == request.client as i64;
+			
+			// read delegations of different clients don't conflict
+			if !is_own && !writable && !delegation.writable {
+				continue;
+			}
+			
+			match delegation.recalled_at {
+				Some(recalled_at) if now - recalled_at >= RECALL_TIMEOUT_SECS => {
+					db::Delegation::delete(conn, id, delegation.client as u64)
+						.map_err(|err| Error::internal(err, "failed revoking delegation"))?;
+				},
+				Some(_) => is_granted = false,
+				None if is_own => (),
+				None => {
+					db::Delegation::recall(conn, id, delegation.client as u64, now)
+						.map_err(|err| Error::internal(err, "failed recalling delegation"))?;
+					
+					is_granted = false;
+					did_recall = true;
+				},
+			}
+		}
+		
+		if is_granted {
+			db::Delegation::new(id, request.client, writable).replace(conn)
+				.map_err(|err| Error::internal(err, "failed granting delegation"))?;
+		}
+		
+		Ok((is_granted, did_recall))
+	})?;
+	
+	if did_recall {
+		recalls.recalled.notify_waiters();
+	}
+	
+	match is_granted {
+		true => Ok(StatusCode::NO_CONTENT),
+		false => Err(Error::Locked),
+	}
+}
+
+/// Called by clients once they were asked to, or when they stop caching the file
+pub async fn return_delegation(mut conn: DbConnection<'_>, Path((id, client)): Path<(NodeID, u64)>) -> Result<StatusCode, Error> {
+	db::Delegation::delete(&mut conn, id, client)
+		.map_err(|err| Error::internal(err, "failed returning delegation"))?;
+	
+	Ok(StatusCode::NO_CONTENT)
+}
+
+/// Returns the files whose delegations the client has to return, waiting for a recall if there are none
+/// 
+/// Returns an empty list after a while or once anything was recalled, the client then asks again.
+pub async fn delegation_recalls(mut conn: DbConnection<'_>, recalls: DelegationRecalls, Path(client): Path<u64>) -> Result<Postcard<Vec<NodeID>>, Error> {
+	// created before looking up recalls, so recalls in between still wake it up
+	let recalled = recalls.recalled.notified();
+	
+	let delegations: Vec<db::Delegation> = db::Delegation::recalled(client)
+		.load(&mut *conn).map_err(|err| Error::internal(err, "failed looking up recalled delegations"))?;
+	
+	if !delegations.is_empty() {
+		return Ok(Postcard(delegations.into_iter().map(|delegation| NodeID(delegation.file as u64)).collect()));
+	}
+	
+	// not holding on to a connection of the pool while waiting
+	drop(conn);
+	
+	let _ = tokio::time::timeout(RECALL_WAIT, recalled).await;
+	
+	Ok(Postcard(Vec::new()))
+}
@@ -1,67 +1,97 @@
 use super::*;
 
+/// Has to be called within a transaction
+pub(super) fn delete_dir_entry(conn: &mut db::AnyConnection, parent_id: NodeID, name: &str) -> Result<(), Error> {
+	// TODO: this should be possible with one sql query
+	// why does rust-analyzer need a type annotation to know what type this is?
+	let entry: db::DirectoryEntry = db::DirectoryEntry::get(parent_id, name)
+		.first(conn).map_err(|err| match err {
+			DieselError::NotFound => {
+				match db::File::exists(conn, parent_id) {
+					Err(err) => Error::internal(err, "failed looking up node"),
+					Ok(true) => Error::NotADirectory,
+					Ok(false) => Error::NotFound,
+				}
+			},
+			err => Error::internal(err, "failed looking up node"),
+		})?;
+	
+	let id = match (entry.directory, entry.file) {
+		(Some(id), None) => id,
+		(None, Some(_)) => return Err(Error::NotADirectory),
+		_ => panic!("should be impossible due to the check on the directory_entries table"),
+	};
+	
+	match db::Directory::delete(conn, NodeID(id as u64)) {
+		// foreign key violation because directory_entries.parent has foreign key on directory meaning the directory is not empty
+		Err(DieselError::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _)) => return Err(Error::DirectoryNotEmpty),
+		Err(err) => return Err(Error::internal(err, "failed deleting node")),
+		Ok(false) => panic!("should be impossible as the foreign key constraint on the directory_entries table means the directory must exist"),
+		Ok(true) => (),
+	}
+	
+	// the entry is deleted along with the node
+	db::Directory::bump_version(conn, parent_id).map_err(|err| Error::internal(err, "failed updating directory version"))?;
+	
+	// the quota of a top level directory is deleted along with it
+	let top_level_directory = top_level_directory(conn, parent_id)?;
+	charge_quota(conn, top_level_directory, 0, -1)
+}
+
 pub async fn delete_dir(mut conn: DbConnection<'_>, Path(parent_id): Path<NodeID>, Postcard(name): Postcard<String>) -> Result<StatusCode, Error> {
-	transaction(&mut conn, |conn| {
-		// TODO: this should be possible with one sql query
-		// why does rust-analyzer need a type annotation to know what type this is?
-		let entry: db::DirectoryEntry = db::DirectoryEntry::get(parent_id, &name)
-			.first(conn).map_err(|err| match err {
-				DieselError::NotFound => {
-					match db::File::exists(conn, parent_id) {
-						Err(err) => Error::internal(err, "failed looking up node"),
-						Ok(true) => Error::NotADirectory,
-						Ok(false) => Error::NotFound,
-					}
-				},
-				err => Error::internal(err, "failed looking up node"),
-			})?;
-		
-		let id = match (entry.directory, entry.file) {
-			(Some(id), None) => id,
-			(None, Some(_)) => return Err(Error::NotADirectory),
-			_ => panic!("should be impossible due to the check on the directory_entries table"),
-		};
-		
-		match db::Directory::delete(conn, NodeID(id as u64)) {
-			// foreign key violation because directory_entries.parent has foreign key on directory meaning the directory is not empty
-			Err(DieselError::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _)) => Err(Error::DirectoryNotEmpty),
-			Err(err) => Err(Error::internal(err, "failed deleting node")),
-			Ok(false) => panic!("should be impossible as the foreign key constraint on the directory_entries table means the directory must exist"),
-			Ok(true) => Ok(()),
-		}
-	})?;
+	transaction(&mut conn, |conn| delete_dir_entry(conn, parent_id, &name))?;
 	
 	Ok(StatusCode::NO_CONTENT)
 }
 
-pub async fn delete_file(mut conn: DbConnection<'_>, Path(parent_id): Path<NodeID>, Postcard(name): Postcard<String>) -> Result<StatusCode, Error> {
-	transaction(&mut conn, |conn| {
-		// TODO: this should be possible with one sql query
-		// why does rust-analyzer need a type annotation to know what type this is?
-		let entry: db::DirectoryEntry = db::DirectoryEntry::get(parent_id, &name)
-			.first(conn).map_err(|err| match err {
-				DieselError::NotFound => {
-					match db::File::exists(conn, parent_id) {
-						Err(err) => Error::internal(err, "failed looking up node"),
-						Ok(true) => Error::NotADirectory,
-						Ok(false) => Error::NotFound,
-					}
-				},
-				err => Error::internal(err, "failed looking up node"),
-			})?;
-		
-		let id = match (entry.directory, entry.file) {
-			(None, Some(id)) => id,
-			(Some(_), None) => return Err(Error::NotAFile),
-			_ => panic!("should be impossible due to the check on the directory_entries table"),
-		};
-		
-		if db::File::delete(conn, NodeID(id as u64)).map_err(|err| Error::internal(err, "failed deleting node"))? {
-			Ok(())
-		} else {
-			panic!("should be impossible as the foreign key constraint on the directory_entries table means the file must exist");
-		}
-	})?;
+/// Has to be called within a transaction, fails with [`Error::Locked`] if other clients hold delegations of the file
+pub(super) fn delete_file_entry(conn: &mut db::AnyConnection, conflicts: &mut DelegationConflicts, parent_id: NodeID, name: &str) -> Result<(), Error> {
+	// TODO: this should be possible with one sql query
+	// why does rust-analyzer need a type annotation to know what type this is?
+	let entry: db::DirectoryEntry = db::DirectoryEntry::get(parent_id, name)
+		.first(conn).map_err(|err| match err {
+			DieselError::NotFound => {
+				match db::File::exists(conn, parent_id) {
+					Err(err) => Error::internal(err, "failed looking up node"),
+					Ok(true) => Error::NotADirectory,
+					Ok(false) => Error::NotFound,
+				}
+			},
+			err => Error::internal(err, "failed looking up node"),
+		})?;
+	
+	let id = match (entry.directory, entry.file) {
+		(None, Some(id)) => id,
+		(Some(_), None) => return Err(Error::NotAFile),
+		_ => panic!("should be impossible due to the check on the directory_entries table"),
+	};
+	
+	conflicts.check(conn, NodeID(id as u64))?;
+	
+	let file: db::File = db::File::get(NodeID(id as u64))
+		.first(conn).map_err(|err| Error::internal(err, "failed looking up node"))?;
+	
+	if !db::File::delete(conn, NodeID(id as u64)).map_err(|err| Error::internal(err, "failed deleting node"))? {
+		panic!("should be impossible as the foreign key constraint on the directory_entries table means the file must exist");
+	}
+	
+	db::Directory::bump_version(conn, parent_id).map_err(|err| Error::internal(err, "failed updating directory version"))?;
+	
+	let top_level_directory = top_level_directory(conn, parent_id)?;
+	charge_quota(conn, top_level_directory, -file.size, -1)
+}
+
+pub async fn delete_file(
+	mut conn: DbConnection<'_>,
+	recalls: DelegationRecalls,
+	OptHeader(client): OptHeader<ClientId>,
+	Path(parent_id): Path<NodeID>,
+	Postcard(name): Postcard<String>
+) -> Result<StatusCode, Error> {
+	let mut conflicts = DelegationConflicts::new(client);
+	let result = transaction(&mut conn, |conn| delete_file_entry(conn, &mut conflicts, parent_id, &name));
+	conflicts.recall(&mut conn, &recalls)?;
+	result?;
 	
 	Ok(StatusCode::NO_CONTENT)
 }
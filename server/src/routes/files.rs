@@ -1,11 +1,17 @@
+use std::{io, sync::atomic::{AtomicU64, Ordering}};
+
+use bytes::Bytes;
+use futures::{Stream, StreamExt as _, TryStreamExt as _};
+
 use super::*;
+use crate::blob_store::BlobStream;
 
-mod upload_file;
+pub mod upload_file;
 use upload_file::*;
 pub mod write_lock;
 use write_lock::*;
 
-fn get_file_info(conn: &mut SqliteConnection, id: NodeID) -> Result<db::File, Error> {
+pub(super) fn get_file_info(conn: &mut db::AnyConnection, id: NodeID) -> Result<db::File, Error> {
 	db::File::get(id)
 		.first(conn).map_err(|err| match err {
 			DieselError::NotFound => {
@@ -28,13 +34,18 @@ pub async fn file_info(mut conn: DbConnection<'_>, Path(id): Path<NodeID>) -> Re
 	}))
 }
 
+/// Compressed blobs are sent as is if the client accepts zstd, unless only a range was requested
+/// 
+/// The ETag is weak for encoded content, as it is the hash of the decoded content.
 pub async fn file_data(
 	mut conn: DbConnection<'_>,
-	directories: Directories,
+	blobs: Blobs,
 	Path(id): Path<NodeID>,
 	OptHeader(if_match): OptHeader<IfMatch>,
-	OptHeader(none_match): OptHeader<IfNoneMatch>
-) -> Result<(Header<ETag>, Body), Error> {
+	OptHeader(none_match): OptHeader<IfNoneMatch>,
+	OptHeader(range): OptHeader<Range>,
+	OptHeader(accept_encoding): OptHeader<AcceptEncoding>
+) -> Result<(StatusCode, Header<ContentETag>, Header<Vary>, Option<Header<ContentRange>>, Option<Header<ContentEncoding>>, Body), Error> {
 	let file_info = get_file_info(&mut conn, id)?;
 	
 	let hash = Hash(file_info.hash.clone()); // TODO: avoid clone
@@ -47,57 +58,345 @@ pub async fn file_data(
 		return Err(Error::NotModified);
 	}
 	
-	let body = if file_info.hash == EMPTY_HASH {
-		Body::empty()
-	} else {
-		let file = File::open(directories.files.join(&file_info.hash)).await.map_err(|err| Error::internal(err, "could not open requested file"))?;
-		let stream = ReaderStream::new(file);
-		Body::from_stream(stream)
+	let size = file_info.size as u64;
+	let (start, end) = match range {
+		Some(range) => range.resolve(size).ok_or(Error::RangeNotSatisfiable(size))?,
+		None => (0, size),
 	};
 	
-	Ok((Header(hash), body))
+	let (status, content_range) = match range {
+		Some(_) => (StatusCode::PARTIAL_CONTENT, Some(Header((Some((start, end)), size)))),
+		None => (StatusCode::OK, None),
+	};
+	
+	if file_info.hash == EMPTY_HASH {
+		return Ok((status, Header(ContentETag::Strong(hash)), Header(header::ACCEPT_ENCODING), content_range, None, Body::empty()));
+	}
+	
+	let accepts_zstd = accept_encoding.is_some_and(|codings| codings.contains(&ContentCoding::Zstd));
+	
+	let stream = match range {
+		Some(_) => blobs.get_range(&file_info.hash, start, end).await
+			.map_err(|err| Error::internal(err, "could not read requested file"))?,
+		None => {
+			let blob = blobs.get(&file_info.hash).await
+				.map_err(|err| Error::internal(err, "could not open requested file"))?;
+			
+			match (blob.is_compressed, accepts_zstd) {
+				(true, true) => return Ok((status, Header(ContentETag::Weak(hash)), Header(header::ACCEPT_ENCODING), None, Some(Header(ContentCoding::Zstd)), Body::from_stream(blob.stream))),
+				(true, false) => decompress_range(blob.stream, start, end).await
+					.map_err(|err| Error::internal(err, "could not decompress requested file"))?,
+				(false, _) => blob.stream,
+			}
+		},
+	};
+	
+	Ok((status, Header(ContentETag::Strong(hash)), Header(header::ACCEPT_ENCODING), content_range, None, Body::from_stream(stream)))
 }
 
+/// Replaces the content of a file, which needs to match the declared hash and size
+/// 
+/// If content with the declared hash is already stored, the body isn't read at all,
+/// so clients can upload by reference by sending an empty body first.
+/// Fails with [`Error::Locked`] before reading the body while other clients hold delegations of the file.
+#[expect(clippy::too_many_arguments)]
 pub async fn write_file_data(
 	mut conn: DbConnection<'_>,
 	directories: Directories,
+	blobs: Blobs,
 	file_write_lock: FileWriteLock,
+	recalls: DelegationRecalls,
+	OptHeader(client): OptHeader<ClientId>,
 	Path(id): Path<NodeID>,
 	Header(prev_hash): Header<IfMatch>,
+	Header(content_hash): Header<ContentHash>,
+	Header(content_size): Header<ContentSize>,
 	body_stream: BodyStream
 ) -> Result<StatusCode, Error> {
+	// delegations are only granted while holding the lock as well, so none can be granted until the content is stored
 	let _guard = file_write_lock.lock(id).await;
 	
-	let file_info = get_file_info(&mut conn, id)?;
+	let mut conflicts = DelegationConflicts::new(client);
+	let result = transaction(&mut conn, |conn| conflicts.check(conn, id));
+	conflicts.recall(&mut conn, &recalls)?;
+	result?;
+	
+	let content = receive_content(&mut conn, &directories, &blobs, id, prev_hash, content_hash, content_size, body_stream, &id.to_string()).await?;
+	async_transaction(&mut conn, async |conn| store_content(conn, &blobs, content).await).await?;
+	
+	Ok(StatusCode::NO_CONTENT)
+}
+
+/// New content for a file that was checked against the quota but isn't stored yet
+#[derive(Debug)]
+pub(super) struct ReceivedContent {
+	id: NodeID,
+	prev_hash: Hash,
+	prev_size: i64,
+	top_level_directory: Option<NodeID>,
+	content_hash: Hash,
+	content_size: u64,
+	/// `None` if the content is already stored
+	upload: Option<(UploadFile, bool)>,
+}
+
+impl ReceivedContent {
+	/// Stores the content right away, it only replaces the content of the file once the transaction is committed
+	pub(super) async fn stage(self, blobs: &Blobs, transaction_id: TransactionID) -> Result<db::StagedContent, Error> {
+		if let Some((file, is_compressed)) = self.upload {
+			blobs.put_file(&self.content_hash.0, is_compressed, file).await
+				.map_err(|err| Error::internal(err, "could not store staged file"))?;
+		}
+		
+		Ok(db::StagedContent {
+			transaction_id: transaction_id.0 as i64,
+			file: self.id.0 as i64,
+			prev_hash: self.prev_hash.0,
+			hash: self.content_hash.0,
+			size: self.content_size as i64,
+		})
+	}
+}
+
+/// Checks the file still has the expected hash and receives the body unless the content is already stored
+/// 
+/// The upload is named `upload_name` within the uploads directory, which has to be unique among concurrent uploads.
+#[expect(clippy::too_many_arguments)]
+pub(super) async fn receive_content(
+	conn: &mut db::AnyConnection,
+	directories: &Directories,
+	blobs: &Blobs,
+	id: NodeID,
+	prev_hash: Hash,
+	content_hash: Hash,
+	content_size: u64,
+	body_stream: impl Stream<Item = Result<Bytes, io::Error>> + Unpin,
+	upload_name: &str
+) -> Result<ReceivedContent, Error> {
+	let file_info = get_file_info(conn, id)?;
+	let prev_size = file_info.size;
 	
 	if prev_hash != Hash(file_info.hash) {
 		return Err(Error::Modified);
 	}
 	
-	let mut file = UploadFile::new(directories.uploads.join(id.0.to_string())).await
-		.map_err(|err| Error::internal(err, "could not open new file for upload"))?;
+	let (top_level_directory, size_limit) = content_size_limit(conn, id, prev_size)?;
 	
-	let mut hash_stream = HashStream::new(body_stream);
-	stream_to_file(&mut hash_stream, &mut file).await
-		.map_err(|err| Error::internal(err, "failed writing to file for upload"))?;
+	if size_limit.is_some_and(|limit| content_size > limit) {
+		return Err(Error::QuotaExceeded);
+	}
+	
+	// blobs without any file referencing them might still be in the middle of being stored, so they are uploaded again
+	let stored_size = match content_hash.0 == EMPTY_HASH {
+		true => Some(0),
+		false => db::File::size_of_content(conn, &content_hash.0).map_err(|err| Error::internal(err, "failed looking up content"))?,
+	};
 	
-	let hash = hash_stream.hash().to_hex();
-	let total_size = hash_stream.total_size();
+	let upload = match stored_size {
+		Some(size) if size as u64 != content_size => return Err(Error::ContentMismatch),
+		// files might still refer to a blob that fsck quarantined, it then has to be uploaded again
+		Some(_) if content_hash.0 == EMPTY_HASH || blob_exists(blobs, &content_hash).await? => None,
+		_ => Some(receive_upload(directories, upload_name, body_stream, size_limit, &content_hash, content_size).await?),
+	};
 	
-	async_transaction(&mut conn, async |conn| {
-		let found = db::File::update_content(conn, id, &prev_hash.0, &hash, total_size)
-			.map_err(|err| Error::internal(err, "failed updating node"))?;
-		
-		if !found {
-			return Err(Error::Modified);
+	Ok(ReceivedContent {
+		id,
+		prev_hash,
+		prev_size,
+		top_level_directory,
+		content_hash,
+		content_size,
+		upload,
+	})
+}
+
+fn file_top_level_directory(conn: &mut db::AnyConnection, id: NodeID) -> Result<Option<NodeID>, Error> {
+	match db::containing_directory(conn, id).map_err(|err| Error::internal(err, "failed looking up directory entry"))? {
+		Some(parent_id) => top_level_directory(conn, parent_id),
+		None => Ok(None),
+	}
+}
+
+/// Returns the top level directory containing the file and the largest content its quota allows for the file
+fn content_size_limit(conn: &mut db::AnyConnection, id: NodeID, prev_size: i64) -> Result<(Option<NodeID>, Option<u64>), Error> {
+	let top_level_directory = file_top_level_directory(conn, id)?;
+	
+	let quota = top_level_directory.map(|directory| db::Quota::get(directory).first::<db::Quota>(conn))
+		.transpose().map_err(|err| Error::internal(err, "failed looking up quota"))?;
+	
+	// the previous content gets replaced, so its size is available again
+	let size_limit = quota.and_then(|quota| quota.remaining_bytes())
+		.map(|remaining| remaining + prev_size as u64);
+	
+	Ok((top_level_directory, size_limit))
+}
+
+/// Content of a file resized ahead of applying [`BatchOperation::SetSize`], which happens inside a database transaction
+pub(super) struct ResizedContent {
+	pub(super) hash: Hash,
+	/// `None` if the content is already stored
+	upload: Option<(UploadFile, bool)>,
+}
+
+impl ResizedContent {
+	/// Stores the content once the file was updated, needs to run inside the same transaction
+	pub(super) async fn store(self, blobs: &Blobs) -> Result<(), Error> {
+		if let Some((file, is_compressed)) = self.upload {
+			blobs.put_file(&self.hash.0, is_compressed, file).await
+				.map_err(|err| Error::internal(err, "could not store resized content"))?;
 		}
 		
-		// part of the transaction, so updating the hash gets rolled back if the move fails
-		file.move_to(directories.files.join(hash.as_str())).await
-			.map_err(|err| Error::internal(err, "could not move uploaded file to files directory"))?;
-		
 		Ok(())
-	}).await?;
+	}
+}
+
+/// Receives the content of the file cut off or padded with zeros to `size` like an upload
+pub(super) async fn resize_content(
+	conn: &mut db::AnyConnection,
+	directories: &Directories,
+	blobs: &Blobs,
+	id: NodeID,
+	expected_hash: &Hash,
+	size: u64
+) -> Result<ResizedContent, Error> {
+	// resizing the same file concurrently still needs unique upload names
+	static RESIZES: AtomicU64 = AtomicU64::new(0);
 	
-	Ok(StatusCode::NO_CONTENT)
+	let file_info = get_file_info(conn, id)?;
+	let prev_size = file_info.size;
+	
+	if expected_hash.0 != file_info.hash {
+		return Err(Error::Modified);
+	}
+	
+	if size == prev_size as u64 {
+		return Ok(ResizedContent { hash: expected_hash.clone(), upload: None });
+	}
+	
+	// checked before reading anything, as the padding isn't limited by a request body
+	let (_, size_limit) = content_size_limit(conn, id, prev_size)?;
+	
+	if size_limit.is_some_and(|limit| size > limit) {
+		return Err(Error::QuotaExceeded);
+	}
+	
+	// read twice, as the content is only received if its hash isn't stored yet
+	let mut hash_stream = HashStream::new(resized_stream(blobs, expected_hash, prev_size as u64, size).await?);
+	while hash_stream.try_next().await.map_err(|err| Error::internal(err, "failed reading content to resize"))?.is_some() {}
+	let content_hash = Hash(hash_stream.hash().to_hex().to_string());
+	
+	let upload_name = format!("{id}.resize-{}", RESIZES.fetch_add(1, Ordering::Relaxed));
+	let stream = resized_stream(blobs, expected_hash, prev_size as u64, size).await?;
+	let content = receive_content(conn, directories, blobs, id, expected_hash.clone(), content_hash, size, stream, &upload_name).await?;
+	
+	Ok(ResizedContent { hash: content.content_hash, upload: content.upload })
+}
+
+/// Returns the first `size` bytes of the content, padded with zeros if it is shorter
+async fn resized_stream(blobs: &Blobs, hash: &Hash, prev_size: u64, size: u64) -> Result<BlobStream, Error> {
+	static ZEROS: [u8; 64 * 1024] = [0; 64 * 1024];
+	
+	let kept = prev_size.min(size);
+	let content = match kept {
+		0 => Box::pin(futures::stream::empty()),
+		kept => blobs.get_range(&hash.0, 0, kept).await
+			.map_err(|err| Error::internal(err, "could not read content to resize"))?,
+	};
+	
+	let padding = size - kept;
+	let chunks = padding.div_ceil(ZEROS.len() as u64);
+	let zeros = futures::stream::iter((0..chunks).map(move |index| {
+		let len = (padding - index * ZEROS.len() as u64).min(ZEROS.len() as u64);
+		Ok(Bytes::from_static(&ZEROS[..len as usize]))
+	}));
+	
+	Ok(Box::pin(content.chain(zeros)))
+}
+
+/// Replaces the content of the file with content that is already stored if it still has the expected hash, needs to run inside a transaction
+pub(super) fn replace_content(conn: &mut db::AnyConnection, id: NodeID, prev_hash: &Hash, content_hash: &Hash, content_size: u64) -> Result<(), Error> {
+	let file_info = get_file_info(conn, id)?;
+	
+	if prev_hash.0 != file_info.hash {
+		return Err(Error::Modified);
+	}
+	
+	let top_level_directory = file_top_level_directory(conn, id)?;
+	charge_quota(conn, top_level_directory, content_size as i64 - file_info.size, 0)?;
+	
+	db::File::update_content(conn, id, &prev_hash.0, &content_hash.0, content_size)
+		.map_err(|err| Error::internal(err, "failed updating file content"))?;
+	
+	Ok(())
+}
+
+/// Replaces the content of the file if it still has the expected hash, needs to run inside a transaction
+pub(super) async fn store_content(conn: &mut db::AnyConnection, blobs: &Blobs, content: ReceivedContent) -> Result<(), Error> {
+	let found = db::File::update_content(conn, content.id, &content.prev_hash.0, &content.content_hash.0, content.content_size)
+		.map_err(|err| Error::internal(err, "failed updating node"))?;
+	
+	if !found {
+		return Err(Error::Modified);
+	}
+	
+	// checked again as other files in the same directory might have been written in the meantime
+	charge_quota(conn, content.top_level_directory, content.content_size as i64 - content.prev_size, 0)?;
+	
+	// part of the transaction, so updating the hash gets rolled back if storing fails
+	if let Some((file, is_compressed)) = content.upload {
+		blobs.put_file(&content.content_hash.0, is_compressed, file).await
+			.map_err(|err| Error::internal(err, "could not store uploaded file"))?;
+	}
+	
+	Ok(())
+}
+
+async fn blob_exists(blobs: &Blobs, hash: &Hash) -> Result<bool, Error> {
+	blobs.exists(&hash.0).await
+		.map_err(|err| Error::internal(err, "failed looking up blob"))
+}
+
+/// Receives the body into the uploads directory and verifies it against the declared hash and size
+/// 
+/// Returns the file to store and whether it is compressed, which is only the case if that makes it smaller.
+async fn receive_upload(
+	directories: &Directories,
+	name: &str,
+	body_stream: impl Stream<Item = Result<Bytes, io::Error>> + Unpin,
+	size_limit: Option<u64>,
+	content_hash: &Hash,
+	content_size: u64
+) -> Result<(UploadFile, bool), Error> {
+	let mut file = UploadFile::new(directories.uploads.join(name)).await
+		.map_err(|err| Error::internal(err, "could not open new file for upload"))?;
+	
+	let mut hash_stream = HashStream::new(LimitStream::new(body_stream, size_limit));
+	stream_to_file(&mut hash_stream, &mut file).await
+		.map_err(|err| match LimitExceeded::is_cause_of(&err) {
+			true => Error::QuotaExceeded,
+			false => Error::internal(err, "failed writing to file for upload"),
+		})?;
+	
+	// a body that was cut off but ended cleanly is caught here
+	if hash_stream.hash().to_hex().as_str() != content_hash.0 || hash_stream.total_size() != content_size {
+		return Err(Error::ContentMismatch);
+	}
+	
+	file.flush().await.map_err(|err| Error::internal(err, "failed writing to file for upload"))?;
+	
+	if content_size < MIN_COMPRESSION_SIZE {
+		return Ok((file, false));
+	}
+	
+	let mut compressed_file = UploadFile::new(directories.uploads.join(format!("{name}.zst"))).await
+		.map_err(|err| Error::internal(err, "could not open new file for compression"))?;
+	
+	let compressed_size = compress_file(file.path(), &mut compressed_file).await
+		.map_err(|err| Error::internal(err, "failed compressing uploaded file"))?;
+	
+	// the hash stays the one of the uncompressed data
+	match compressed_size < content_size {
+		true => Ok((compressed_file, true)),
+		false => Ok((file, false)),
+	}
 }
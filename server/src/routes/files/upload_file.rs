@@ -2,6 +2,7 @@ use std::{io, ops::{Deref, DerefMut}, path::{Path, PathBuf}};
 
 use tokio::fs::{self, File, OpenOptions};
 
+#[derive(Debug)]
 pub struct UploadFile {
 	path: PathBuf,
 	file: File,
@@ -13,6 +14,7 @@ impl UploadFile {
 		let file = OpenOptions::new()
 			.write(true)
 			.create(true)
+			.truncate(true)
 			.open(&path).await?;
 		
 		Ok(Self {
@@ -22,6 +24,10 @@ impl UploadFile {
 		})
 	}
 	
+	pub fn path(&self) -> &Path {
+		&self.path
+	}
+	
 	pub async fn move_to(mut self, destination: impl AsRef<Path>) -> Result<(), io::Error> {
 		fs::rename(&self.path, destination).await?;
 		self.is_moved = true;
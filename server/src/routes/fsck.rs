@@ -0,0 +1,13 @@
+use super::*;
+
+#[derive(Deserialize, Default, Debug)]
+pub struct FsckQuery {
+	#[serde(default)]
+	pub repair: bool,
+}
+
+/// Checks the consistency of the metadata and blobs, see [`crate::fsck::check`]
+pub async fn fsck(mut conn: DbConnection<'_>, blobs: Blobs, Query(query): Query<FsckQuery>) -> Result<Postcard<FsckReport>, Error> {
+	let report = crate::fsck::check(&mut conn, &*blobs, query.repair).await?;
+	Ok(Postcard(report))
+}
@@ -1,20 +1,32 @@
 use super::*;
 
-pub async fn node_info(mut conn: DbConnection<'_>, Path(id): Path<NodeID>) -> Result<Postcard<NodeInfo>, Error> {
-	let conn = &mut *conn;
-	
+/// Returns the node along with its ETag, or [`Error::NotModified`] if it still has the ETag `none_match`
+fn get_node_info(conn: &mut db::AnyConnection, id: NodeID, none_match: Option<&NodeETag>) -> Result<(NodeETag, NodeInfo), Error> {
 	if let Some(file) = db::File::get(id)
 		.first(conn)
 		.optional().map_err(|err| Error::internal(err, "failed looking up node"))?
 	{
-		Ok(Postcard(NodeInfo::File(FileInfo {
+		let hash = Hash(file.hash);
+		let etag = NodeETag::File(hash.clone());
+		
+		if none_match.is_some_and(|expected| *expected == etag) {
+			return Err(Error::NotModified);
+		}
+		
+		Ok((etag, NodeInfo::File(FileInfo {
 			size: file.size as u64,
-			hash: Hash(file.hash),
+			hash,
 		})))
 	} else if let Some(dir) = db::Directory::get(id)
 		.first(conn)
 		.optional().map_err(|err| Error::internal(err, "failed looking up node"))?
 	{
+		let etag = NodeETag::Directory(dir.version);
+		
+		if none_match.is_some_and(|expected| *expected == etag) {
+			return Err(Error::NotModified);
+		}
+		
 		let children = dir.entries()
 			.load(conn).map_err(|err| Error::internal(err, "failed looking up directory entries"))?
 			.into_iter()
@@ -29,7 +41,7 @@ pub async fn node_info(mut conn: DbConnection<'_>, Path(id): Path<NodeID>) -> Re
 			})
 			.collect();
 		
-		Ok(Postcard(NodeInfo::Directory(DirectoryInfo {
+		Ok((etag, NodeInfo::Directory(DirectoryInfo {
 			parent: NodeID(dir.parent as u64),
 			children,
 		})))
@@ -38,10 +50,75 @@ pub async fn node_info(mut conn: DbConnection<'_>, Path(id): Path<NodeID>) -> Re
 	}
 }
 
-pub async fn dir_info(mut conn: DbConnection<'_>, Path(id): Path<NodeID>) -> Result<Postcard<DirectoryInfo>, Error> {
-	let conn = &mut *conn;
+/// The ETag is the hash of files and the version of directories
+pub async fn node_info(
+	mut conn: DbConnection<'_>,
+	Path(id): Path<NodeID>,
+	OptHeader(none_match): OptHeader<IfNoneMatchNode>
+) -> Result<(Header<NodeETag>, Postcard<NodeInfo>), Error> {
+	let (etag, info) = get_node_info(&mut conn, id, none_match.as_ref())?;
 	
-	let dir = db::Directory::get(id)
+	Ok((Header(etag), Postcard(info)))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ResolveQuery {
+	pub path: String,
+}
+
+/// Walks `path` starting from the root directory and returns every node along the way
+/// 
+/// Empty components and `.` are skipped, `..` goes back to the parent directory.
+pub async fn resolve_path(mut conn: DbConnection<'_>, Query(query): Query<ResolveQuery>) -> Result<Postcard<Vec<ResolvedNode>>, Error> {
+	let nodes = transaction(&mut conn, |conn| {
+		// only ids and whether they are directories are looked up while walking, so nodes that are left again with `..` aren't loaded
+		let mut path = vec![(NodeID::ROOT, true)];
+		
+		for component in query.path.split('/') {
+			match component {
+				"" | "." => continue,
+				".." => {
+					// the root directory is its own parent
+					if path.len() > 1 {
+						path.pop();
+					}
+					
+					continue;
+				},
+				_ => (),
+			}
+			
+			let &(parent, is_directory) = path.last().expect("root is never removed");
+			
+			if !is_directory {
+				return Err(Error::NotADirectory);
+			}
+			
+			let entry = db::DirectoryEntry::get(parent, component)
+				.first(conn)
+				.optional().map_err(|err| Error::internal(err, "failed looking up directory entry"))?
+				.ok_or(Error::NotFound)?;
+			
+			path.push(match (entry.directory, entry.file) {
+				(Some(id), None) => (NodeID(id as u64), true),
+				(None, Some(id)) => (NodeID(id as u64), false),
+				_ => panic!("should be impossible due to the check on the directory_entries table"),
+			});
+		}
+		
+		path.into_iter()
+			.map(|(id, _)| Ok(ResolvedNode {
+				id,
+				info: get_node_info(conn, id, None)?.1,
+			}))
+			.collect()
+	})?;
+	
+	Ok(Postcard(nodes))
+}
+
+fn get_dir_info(conn: &mut db::AnyConnection, id: NodeID) -> Result<db::Directory, Error> {
+	db::Directory::get(id)
 		.first(conn).map_err(|err| match err {
 			DieselError::NotFound => {
 				match db::File::exists(conn, id) {
@@ -51,7 +128,23 @@ pub async fn dir_info(mut conn: DbConnection<'_>, Path(id): Path<NodeID>) -> Res
 				}
 			},
 			err => Error::internal(err, "failed looking up node"), // TODO: what to do about unexpected error types?
-		})?;
+		})
+}
+
+/// The ETag changes whenever an entry is added or removed, so unchanged directories can be revalidated without listing them
+pub async fn dir_info(
+	mut conn: DbConnection<'_>,
+	Path(id): Path<NodeID>,
+	OptHeader(none_match): OptHeader<IfNoneMatchNode>
+) -> Result<(Header<NodeETag>, Postcard<DirectoryInfo>), Error> {
+	let conn = &mut *conn;
+	
+	let dir = get_dir_info(conn, id)?;
+	let etag = NodeETag::Directory(dir.version);
+	
+	if none_match.is_some_and(|expected| expected == etag) {
+		return Err(Error::NotModified);
+	}
 	
 	let children = dir.entries()
 		.load(conn).map_err(|err| Error::internal(err, "failed looking up directory entries"))?
@@ -67,8 +160,69 @@ pub async fn dir_info(mut conn: DbConnection<'_>, Path(id): Path<NodeID>) -> Res
 		})
 		.collect();
 	
-	Ok(Postcard(DirectoryInfo {
+	Ok((Header(etag), Postcard(DirectoryInfo {
 		parent: NodeID(dir.parent as u64),
 		children,
+	})))
+}
+
+pub const MAX_PAGE_SIZE: u32 = 1000;
+
+#[derive(Deserialize, Default, Debug)]
+pub struct ListingQuery {
+	/// continuation token from the previous page
+	pub after: Option<String>,
+	pub limit: Option<u32>,
+}
+
+pub async fn dir_listing(
+	mut conn: DbConnection<'_>,
+	Path(id): Path<NodeID>,
+	Query(query): Query<ListingQuery>
+) -> Result<Postcard<DirectoryListing>, Error> {
+	let conn = &mut *conn;
+	
+	let limit = match query.limit {
+		Some(0) => return Err(Error::BadRequest),
+		Some(limit) => limit.min(MAX_PAGE_SIZE),
+		None => MAX_PAGE_SIZE,
+	} as usize;
+	
+	let dir = get_dir_info(conn, id)?;
+	
+	// fetch one more than requested to know whether there are more pages
+	let mut children: Vec<_> = dir.children(conn, query.after.as_deref(), Some(limit as i64 + 1))
+		.map_err(|err| Error::internal(err, "failed looking up directory entries"))?
+		.map(|child| {
+			let entry = match child.data {
+				db::EntryKind::Directory(dir) => EntryInfo {
+					id: NodeID(dir.id as u64),
+					attributes: EntryAttributes::Directory,
+				},
+				db::EntryKind::File(file) => EntryInfo {
+					id: NodeID(file.id as u64),
+					attributes: EntryAttributes::File(FileInfo {
+						size: file.size as u64,
+						hash: Hash(file.hash),
+					}),
+				},
+			};
+			
+			(child.name, entry)
+		})
+		.collect();
+	
+	// the name of the last entry is used as the continuation token
+	let continuation = if children.len() > limit {
+		children.truncate(limit);
+		children.last().map(|(name, _)| name.clone())
+	} else {
+		None
+	};
+	
+	Ok(Postcard(DirectoryListing {
+		parent: NodeID(dir.parent as u64),
+		children: children.into_iter().collect(),
+		continuation,
 	}))
 }
@@ -0,0 +1,216 @@
+use fye_shared::LockKind;
+
+use super::*;
+
+// advisory locks are stored in the database along with the leases of their clients,
+// so they survive restarts and are shared by all servers using the same database
+const LOCK_LEASE: i64 = LOCK_LEASE_SECS as i64;
+
+fn lock_row(id: NodeID, lock: &FileLock) -> db::Lock {
+	db::Lock {
+		file: id.0 as i64,
+		client: lock.owner.client as i64,
+		owner: lock.owner.owner as i64,
+		exclusive: lock.kind == LockKind::Exclusive,
+		range_start: lock.start as i64,
+		range_end: lock.end.map(|end| end as i64),
+		pid: lock.pid as i64,
+	}
+}
+
+fn file_lock(row: db::Lock) -> FileLock {
+	FileLock {
+		owner: LockOwner {
+			client: row.client as u64,
+			owner: row.owner as u64,
+		},
+		kind: match row.exclusive {
+			true => LockKind::Exclusive,
+			false => LockKind::Shared,
+		},
+		start: row.range_start as u64,
+		end: row.range_end.map(|end| end as u64),
+		pid: row.pid as u32,
+	}
+}
+
+/// Returns whether the client still had a lease, otherwise its locks were already dropped
+/// 
+/// Also drops the locks of this client if its lease expired, so the new lease doesn't bring them back.
+fn renew(conn: &mut db::AnyConnection, client: u64, now: i64) -> Result<bool, Error> {
+	let had_lease = db::LockLease::is_held(conn, client, now)
+		.map_err(|err| Error::internal(err, "failed looking up lock lease"))?;
+	
+	db::LockLease::delete_expired(conn, now)
+		.map_err(|err| Error::internal(err, "failed dropping expired locks"))?;
+	
+	db::LockLease { client: client as i64, expires_at: now + LOCK_LEASE }.replace(conn)
+		.map_err(|err| Error::internal(err, "failed renewing lock lease"))?;
+	
+	Ok(had_lease)
+}
+
+fn conflicting(conn: &mut db::AnyConnection, id: NodeID, lock: &FileLock, now: i64) -> Result<Option<FileLock>, Error> {
+	let held: Vec<db::Lock> = db::Lock::held_on(id, now)
+		.load(conn).map_err(|err| Error::internal(err, "failed looking up locks"))?;
+	
+	Ok(held.into_iter().map(file_lock).find(|held| held.conflicts_with(lock)))
+}
+
+fn release(conn: &mut db::AnyConnection, id: NodeID, owner: LockOwner, start: u64, end: Option<u64>) -> Result<(), Error> {
+	let held: Vec<db::Lock> = db::Lock::of_owner(id, owner.client, owner.owner)
+		.load(conn).map_err(|err| Error::internal(err, "failed looking up locks"))?;
+	
+	for row in held {
+		let held = file_lock(row.clone());
+		
+		if !held.overlaps(start, end) {
+			continue;
+		}
+		
+		row.delete(conn).map_err(|err| Error::internal(err, "failed releasing lock"))?;
+		
+		let mut remaining = Vec::new();
+		
+		if held.start < start {
+			remaining.push(FileLock { end: Some(start), ..held.clone() });
+		}
+		
+		if let Some(end) = end {
+			if held.end.is_none_or(|held_end| held_end > end) {
+				remaining.push(FileLock { start: end, ..held.clone() });
+			}
+		}
+		
+		for lock in remaining {
+			lock_row(id, &lock).insert(conn).map_err(|err| Error::internal(err, "failed splitting lock"))?;
+		}
+	}
+	
+	Ok(())
+}
+
+/// Replaces the owner's locks within the range, so locks can be converted between shared and exclusive
+fn acquire(conn: &mut db::AnyConnection, id: NodeID, lock: FileLock, now: i64) -> Result<(), Error> {
+	transaction(conn, |conn| {
+		// otherwise conflicting locks could be acquired at the same time through other connections
+		db::Lock::wait_for_others(conn, id).map_err(|err| Error::internal(err, "failed locking file"))?;
+		
+		renew(conn, lock.owner.client, now)?;
+		
+		if conflicting(conn, id, &lock, now)?.is_some() {
+			return Err(Error::Locked);
+		}
+		
+		release(conn, id, lock.owner, lock.start, lock.end)?;
+		lock_row(id, &lock).insert(conn).map_err(|err| Error::internal(err, "failed acquiring lock"))
+	})
+}
+
+fn check_range(start: u64, end: Option<u64>) -> Result<(), Error> {
+	match end {
+		Some(end) if end <= start => Err(Error::BadRequest),
+		_ => Ok(()),
+	}
+}
+
+/// Fails without waiting if another owner holds a conflicting lock, and renews the lease of the client
+pub async fn lock_file(mut conn: DbConnection<'_>, Path(id): Path<NodeID>, Postcard(lock): Postcard<FileLock>) -> Result<StatusCode, Error> {
+	check_range(lock.start, lock.end)?;
+	get_file_info(&mut conn, id)?;
+	
+	acquire(&mut conn, id, lock, unix_time())?;
+	
+	Ok(StatusCode::NO_CONTENT)
+}
+
+/// Returns a lock of another owner that conflicts with the given one, if any
+pub async fn test_lock(mut conn: DbConnection<'_>, Path(id): Path<NodeID>, Postcard(lock): Postcard<FileLock>) -> Result<Postcard<Option<FileLock>>, Error> {
+	check_range(lock.start, lock.end)?;
+	
+	Ok(Postcard(conflicting(&mut conn, id, &lock, unix_time())?))
+}
+
+pub async fn unlock_file(mut conn: DbConnection<'_>, Path(id): Path<NodeID>, Postcard(request): Postcard<UnlockRequest>) -> Result<StatusCode, Error> {
+	check_range(request.start, request.end)?;
+	
+	transaction(&mut conn, |conn| release(conn, id, request.owner, request.start, request.end))?;
+	
+	Ok(StatusCode::NO_CONTENT)
+}
+
+/// Extends the lease of all locks of the client, fails if the lease already expired and the locks were dropped
+pub async fn renew_lock_lease(mut conn: DbConnection<'_>, Path(client): Path<u64>) -> Result<StatusCode, Error> {
+	match transaction(&mut conn, |conn| renew(conn, client, unix_time()))? {
+		true => Ok(StatusCode::NO_CONTENT),
+		false => Err(Error::NotFound),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::*;
+	
+	fn lock(client: u64, kind: LockKind, start: u64, end: Option<u64>) -> FileLock {
+		FileLock {
+			owner: LockOwner { client, owner: 1 },
+			kind,
+			start,
+			end,
+			pid: 0,
+		}
+	}
+	
+	fn create_file(db: &mut TestDb) -> NodeID {
+		transaction(&mut db.conn(), |conn| create_file_entry(conn, NodeID(1), "file")).unwrap()
+	}
+	
+	fn locks_of(db: &mut TestDb, id: NodeID, now: i64) -> Vec<FileLock> {
+		let held: Vec<db::Lock> = db::Lock::held_on(id, now).load(&mut *db.conn()).unwrap();
+		let mut locks: Vec<FileLock> = held.into_iter().map(file_lock).collect();
+		locks.sort_by_key(|lock| (lock.owner.client, lock.start));
+		locks
+	}
+	
+	#[test]
+	fn release_splits_locks() {
+		let mut db = TestDb::new();
+		let file = create_file(&mut db);
+		let now = unix_time();
+		
+		acquire(&mut db.conn(), file, lock(1, LockKind::Exclusive, 0, None), now).unwrap();
+		release(&mut db.conn(), file, LockOwner { client: 1, owner: 1 }, 10, Some(20)).unwrap();
+		
+		assert_eq!(locks_of(&mut db, file, now), vec![
+			lock(1, LockKind::Exclusive, 0, Some(10)),
+			lock(1, LockKind::Exclusive, 20, None),
+		]);
+		
+		// the released range is free for others
+		acquire(&mut db.conn(), file, lock(2, LockKind::Exclusive, 10, Some(20)), now).unwrap();
+		assert_eq!(acquire(&mut db.conn(), file, lock(2, LockKind::Shared, 5, Some(15)), now), Err(Error::Locked));
+	}
+	
+	#[test]
+	fn expired_leases_drop_locks() {
+		let mut db = TestDb::new();
+		let file = create_file(&mut db);
+		let now = unix_time();
+		
+		acquire(&mut db.conn(), file, lock(1, LockKind::Shared, 0, None), now).unwrap();
+		acquire(&mut db.conn(), file, lock(2, LockKind::Shared, 0, None), now).unwrap();
+		assert_eq!(acquire(&mut db.conn(), file, lock(3, LockKind::Exclusive, 0, Some(1)), now), Err(Error::Locked));
+		
+		let later = now + LOCK_LEASE / 2;
+		assert!(renew(&mut db.conn(), 1, later).unwrap());
+		
+		let expired = now + LOCK_LEASE;
+		assert_eq!(acquire(&mut db.conn(), file, lock(3, LockKind::Exclusive, 0, Some(1)), expired), Err(Error::Locked));
+		assert!(!renew(&mut db.conn(), 2, expired).unwrap());
+		
+		let after_renewal = later + LOCK_LEASE;
+		acquire(&mut db.conn(), file, lock(3, LockKind::Exclusive, 0, Some(1)), after_renewal).unwrap();
+		assert_eq!(locks_of(&mut db, file, after_renewal).len(), 1);
+	}
+}
@@ -0,0 +1,76 @@
+use super::*;
+
+/// Adds to the usage of the quota of the given top level directory, failing if that exceeds its limits
+/// 
+/// Needs to be called within a transaction so the change is rolled back on failure.
+/// Reducing the usage always succeeds, even if the quota was already exceeded before.
+pub(super) fn charge_quota(conn: &mut db::AnyConnection, top_level_directory: Option<NodeID>, bytes: i64, nodes: i64) -> Result<(), Error> {
+	let Some(top_level_directory) = top_level_directory else {
+		// nodes directly inside the root directory aren't limited
+		return Ok(());
+	};
+	
+	let quota = db::Quota::add_usage(conn, top_level_directory, bytes, nodes)
+		.map_err(|err| Error::internal(err, "failed updating quota"))?;
+	
+	if (bytes > 0 || nodes > 0) && quota.is_exceeded() {
+		return Err(Error::QuotaExceeded);
+	}
+	
+	Ok(())
+}
+
+pub(super) fn top_level_directory(conn: &mut db::AnyConnection, directory: NodeID) -> Result<Option<NodeID>, Error> {
+	db::top_level_directory(conn, directory).map_err(|err| Error::internal(err, "failed looking up top level directory"))
+}
+
+fn quota_usage(quota: db::Quota) -> QuotaUsage {
+	QuotaUsage {
+		directory: NodeID(quota.directory as u64),
+		used_bytes: quota.used_bytes as u64,
+		used_nodes: quota.used_nodes as u64,
+		limits: QuotaLimits {
+			max_bytes: quota.max_bytes.map(|max| max as u64),
+			max_nodes: quota.max_nodes.map(|max| max as u64),
+		},
+	}
+}
+
+fn get_quota_directory(conn: &mut db::AnyConnection, id: NodeID) -> Result<NodeID, Error> {
+	let directory = match db::File::exists(conn, id).map_err(|err| Error::internal(err, "failed looking up node"))? {
+		true => db::containing_directory(conn, id)
+			.map_err(|err| Error::internal(err, "failed looking up directory entry"))?
+			.ok_or(Error::NotFound)?,
+		false => id,
+	};
+	
+	// the root directory doesn't have a quota
+	top_level_directory(conn, directory)?.ok_or(Error::NotFound)
+}
+
+/// Returns the quota of the top level directory containing the given node
+pub async fn quota_info(mut conn: DbConnection<'_>, Path(id): Path<NodeID>) -> Result<Postcard<QuotaUsage>, Error> {
+	let quota = transaction(&mut conn, |conn| {
+		let directory = get_quota_directory(conn, id)?;
+		
+		db::Quota::get(directory)
+			.first(conn).map_err(|err| Error::internal(err, "failed looking up quota"))
+	})?;
+	
+	Ok(Postcard(quota_usage(quota)))
+}
+
+/// Sets the limits of the top level directory containing the given node
+/// 
+/// This isn't exposed through the API as clients could raise their own limits, the server command line uses it instead.
+/// New limits may be lower than the current usage, which prevents any further uploads until enough is deleted.
+pub fn set_quota_limits(conn: &mut db::AnyConnection, id: NodeID, limits: QuotaLimits) -> Result<QuotaUsage, Error> {
+	let quota = transaction(conn, |conn| {
+		let directory = get_quota_directory(conn, id)?;
+		
+		db::Quota::set_limits(conn, directory, limits.max_bytes, limits.max_nodes)
+			.map_err(|err| Error::internal(err, "failed updating quota"))
+	})?;
+	
+	Ok(quota_usage(quota))
+}
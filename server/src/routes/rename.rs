@@ -0,0 +1,138 @@
+use super::*;
+
+/// Fails with [`Error::NotFound`] or [`Error::NotADirectory`] unless the node is a directory
+fn check_directory(conn: &mut db::AnyConnection, id: NodeID) -> Result<(), Error> {
+	if db::Directory::exists(conn, id).map_err(|err| Error::internal(err, "failed looking up node"))? {
+		return Ok(());
+	}
+	
+	match db::File::exists(conn, id) {
+		Err(err) => Err(Error::internal(err, "failed looking up node")),
+		Ok(true) => Err(Error::NotADirectory),
+		Ok(false) => Err(Error::NotFound),
+	}
+}
+
+/// The top level directory whose quota a node in the given directory is charged to, which is its own if it is a top level directory itself
+fn quota_directory(conn: &mut db::AnyConnection, parent_id: NodeID, id: NodeID, is_directory: bool) -> Result<Option<NodeID>, Error> {
+	match parent_id == NodeID::ROOT {
+		true => Ok(is_directory.then_some(id)),
+		false => top_level_directory(conn, parent_id),
+	}
+}
+
+/// Has to be called within a transaction, fails with [`Error::Locked`] if a replaced file is delegated to other clients
+/// 
+/// The usage of the node is moved along with it to the quota of its new top level directory.
+/// Top level directories lose their quota when they are moved into another directory,
+/// and directories moved into the root directory get a new one without limits.
+pub(super) fn rename_entry(
+	conn: &mut db::AnyConnection,
+	conflicts: &mut DelegationConflicts,
+	parent_id: NodeID,
+	name: &str,
+	new_parent_id: NodeID,
+	new_name: &str,
+	replace: bool
+) -> Result<(), Error> {
+	check_directory(conn, parent_id)?;
+	check_directory(conn, new_parent_id)?;
+	
+	// why does rust-analyzer need a type annotation to know what type this is?
+	let entry: db::DirectoryEntry = db::DirectoryEntry::get(parent_id, name)
+		.first(conn).map_err(|err| match err {
+			DieselError::NotFound => Error::NotFound,
+			err => Error::internal(err, "failed looking up node"),
+		})?;
+	
+	if parent_id == new_parent_id && name == new_name {
+		return Ok(());
+	}
+	
+	let (id, is_directory) = match (entry.directory, entry.file) {
+		(Some(id), None) => (NodeID(id as u64), true),
+		(None, Some(id)) => (NodeID(id as u64), false),
+		_ => panic!("should be impossible due to the check on the directory_entries table"),
+	};
+	
+	if is_directory {
+		// the root directory is its own parent
+		let mut ancestor = new_parent_id;
+		
+		loop {
+			if ancestor == id {
+				return Err(Error::InvalidMove);
+			}
+			
+			if ancestor == NodeID::ROOT {
+				break;
+			}
+			
+			let directory: db::Directory = db::Directory::get(ancestor)
+				.first(conn).map_err(|err| Error::internal(err, "failed looking up parent directory"))?;
+			ancestor = NodeID(directory.parent as u64);
+		}
+	}
+	
+	let existing: Option<db::DirectoryEntry> = db::DirectoryEntry::get(new_parent_id, new_name)
+		.first(conn).optional().map_err(|err| Error::internal(err, "failed looking up node"))?;
+	
+	if let Some(existing) = existing {
+		let location = match (existing.directory, existing.file) {
+			(Some(id), None) => Location::Directory(NodeID(id as u64)),
+			(None, Some(id)) => Location::File(NodeID(id as u64)),
+			_ => panic!("should be impossible due to the check on the directory_entries table"),
+		};
+		
+		match (location, is_directory) {
+			(location, _) if !replace => return Err(Error::AlreadyExists(location)),
+			(Location::Directory(_), true) => delete_dir_entry(conn, new_parent_id, new_name)?,
+			(Location::File(_), false) => delete_file_entry(conn, conflicts, new_parent_id, new_name)?,
+			(Location::Directory(_), false) => return Err(Error::NotAFile),
+			_ => return Err(Error::NotADirectory),
+		}
+	}
+	
+	let (bytes, nodes) = match is_directory {
+		true => db::subtree_usage(conn, id).map_err(|err| Error::internal(err, "failed computing directory usage"))?,
+		false => {
+			let file: db::File = db::File::get(id)
+				.first(conn).map_err(|err| Error::internal(err, "failed looking up node"))?;
+			(file.size, 1)
+		},
+	};
+	
+	let old_quota = quota_directory(conn, parent_id, id, is_directory)?;
+	let new_quota = quota_directory(conn, new_parent_id, id, is_directory)?;
+	
+	db::DirectoryEntry::delete(conn, parent_id, name).map_err(|err| Error::internal(err, "failed deleting directory entry"))?;
+	
+	let new_entry = db::NewDirectoryEntry {
+		parent: new_parent_id.0 as i64,
+		name: new_name,
+		directory: entry.directory,
+		file: entry.file,
+	};
+	new_entry.insert(conn).map_err(|err| Error::internal(err, "failed inserting new directory entry"))?;
+	
+	if is_directory {
+		db::Directory::set_parent(conn, id, new_parent_id).map_err(|err| Error::internal(err, "failed updating parent directory"))?;
+	}
+	
+	if old_quota == new_quota {
+		return Ok(());
+	}
+	
+	match old_quota {
+		Some(directory) if directory == id => db::Quota::delete(conn, id).map_err(|err| Error::internal(err, "failed deleting quota"))?,
+		old_quota => charge_quota(conn, old_quota, -bytes, -nodes)?,
+	}
+	
+	match new_quota {
+		Some(directory) if directory == id => {
+			db::Quota::new(id).insert(conn).map_err(|err| Error::internal(err, "failed inserting new quota"))?;
+			db::Quota::set_usage(conn, id, bytes, nodes).map_err(|err| Error::internal(err, "failed updating quota"))
+		},
+		new_quota => charge_quota(conn, new_quota, bytes, nodes),
+	}
+}
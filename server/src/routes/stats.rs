@@ -0,0 +1,32 @@
+use super::*;
+
+pub async fn storage_stats(
+	mut conn: DbConnection<'_>,
+	blobs: Blobs,
+	StorageLimit(limit): StorageLimit
+) -> Result<Postcard<StorageStats>, Error> {
+	let stats = db::Stats::get(&mut conn).map_err(|err| Error::internal(err, "failed querying storage stats"))?;
+	let stored_bytes = stats.stored_bytes as u64;
+	
+	let capacity = blobs.capacity().map_err(|err| Error::internal(err, "failed querying storage capacity"))?;
+	
+	let (total_bytes, available_bytes) = match (limit, capacity) {
+		(Some(limit), Some((total, available))) => (
+			limit.min(total),
+			limit.saturating_sub(stored_bytes).min(available),
+		),
+		(Some(limit), None) => (limit, limit.saturating_sub(stored_bytes)),
+		(None, Some(capacity)) => capacity,
+		// e.g. object storage, which is practically unlimited
+		(None, None) => (u64::MAX, u64::MAX - stored_bytes),
+	};
+	
+	Ok(Postcard(StorageStats {
+		stored_bytes,
+		logical_bytes: stats.logical_bytes as u64,
+		file_count: stats.file_count as u64,
+		directory_count: stats.directory_count as u64,
+		total_bytes,
+		available_bytes,
+	}))
+}
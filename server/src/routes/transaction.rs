@@ -0,0 +1,205 @@
+use std::{sync::atomic::{AtomicU64, Ordering}, time::SystemTime};
+
+use super::*;
+use super::write_lock::FileWriteLock;
+
+/// Transactions that were neither committed nor aborted within this many seconds are dropped along with everything staged in them
+const TRANSACTION_TIMEOUT_SECS: i64 = 60 * 60;
+/// Limits the contents that are stored without being referenced by any file until the transaction is committed
+pub(super) const MAX_STAGED_FILES: usize = 100;
+
+pub(super) fn unix_time() -> i64 {
+	SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)
+		.expect("clock should be after 1970")
+		.as_secs() as i64
+}
+
+/// Fails with [`Error::NotFound`] unless the transaction is still open, and makes concurrent requests staging in it wait
+/// until the current database transaction ends
+fn lock_open_transaction(conn: &mut db::AnyConnection, transaction_id: TransactionID) -> Result<(), Error> {
+	let is_open = db::OpenTransaction::lock(conn, transaction_id.0 as i64, unix_time() - TRANSACTION_TIMEOUT_SECS)
+		.map_err(|err| Error::internal(err, "failed looking up transaction"))?;
+	
+	match is_open {
+		true => Ok(()),
+		false => Err(Error::NotFound),
+	}
+}
+
+/// Fails if too many other files were already staged
+fn check_staged_files(conn: &mut db::AnyConnection, transaction_id: TransactionID, id: NodeID) -> Result<(), Error> {
+	lock_open_transaction(conn, transaction_id)?;
+	
+	let staged_files = db::StagedContent::count(conn, transaction_id.0 as i64, id)
+		.map_err(|err| Error::internal(err, "failed counting staged files"))?;
+	
+	if staged_files as usize >= MAX_STAGED_FILES {
+		return Err(Error::BadRequest);
+	}
+	
+	Ok(())
+}
+
+/// Opens a transaction to stage file contents and directory changes in, which only become visible once it is committed
+/// 
+/// Transactions are stored in the database, so their requests may reach any server sharing it.
+/// Transactions that timed out are dropped here rather than by a background task on each server.
+pub async fn open_transaction(mut conn: DbConnection<'_>) -> Result<Postcard<TransactionID>, Error> {
+	let now = unix_time();
+	let id = db::next_transaction_id(&mut conn).map_err(|err| Error::internal(err, "failed getting next transaction id"))?;
+	
+	transaction(&mut conn, |conn| {
+		db::OpenTransaction::delete_expired(conn, now - TRANSACTION_TIMEOUT_SECS)
+			.map_err(|err| Error::internal(err, "failed dropping expired transactions"))?;
+		
+		db::OpenTransaction { id, opened_at: now }.insert(conn)
+			.map_err(|err| Error::internal(err, "failed opening transaction"))
+	})?;
+	
+	Ok(Postcard(TransactionID(id as u64)))
+}
+
+/// Stages new content for a file, with the same headers as writing it directly
+/// 
+/// The hash in `If-Match` is checked right away and again when committing.
+/// Fails if too many other files were already staged, staging a file again replaces its content instead.
+/// The content is stored before it is staged, but only replaces the content of the file once the transaction is committed.
+#[expect(clippy::too_many_arguments)]
+pub async fn stage_file_data(
+	mut conn: DbConnection<'_>,
+	directories: Directories,
+	blobs: Blobs,
+	Path((transaction_id, id)): Path<(TransactionID, NodeID)>,
+	Header(prev_hash): Header<IfMatch>,
+	Header(content_hash): Header<ContentHash>,
+	Header(content_size): Header<ContentSize>,
+	body_stream: BodyStream
+) -> Result<StatusCode, Error> {
+	// staging the same file concurrently still needs unique upload names
+	static UPLOADS: AtomicU64 = AtomicU64::new(0);
+	
+	// checked before receiving the body too, so it isn't received for nothing
+	check_staged_files(&mut conn, transaction_id, id)?;
+	
+	let upload_name = format!("{id}.transaction-{transaction_id}-{}", UPLOADS.fetch_add(1, Ordering::Relaxed));
+	let content = receive_content(&mut conn, &directories, &blobs, id, prev_hash, content_hash, content_size, body_stream, &upload_name).await?;
+	let staged = content.stage(&blobs, transaction_id).await?;
+	
+	transaction(&mut conn, |conn| {
+		check_staged_files(conn, transaction_id, id)?;
+		
+		staged.replace(conn)
+			.map_err(|err| Error::internal(err, "failed staging file"))
+	})?;
+	
+	Ok(StatusCode::NO_CONTENT)
+}
+
+/// Appends directory changes which are applied in order after all file contents when committing
+pub async fn stage_operations(
+	mut conn: DbConnection<'_>,
+	Path(transaction_id): Path<TransactionID>,
+	Postcard(operations): Postcard<Vec<BatchOperation>>
+) -> Result<StatusCode, Error> {
+	transaction(&mut conn, |conn| {
+		lock_open_transaction(conn, transaction_id)?;
+		
+		let staged = db::StagedOperation::count(conn, transaction_id.0 as i64)
+			.map_err(|err| Error::internal(err, "failed counting staged operations"))?;
+		
+		if staged as usize + operations.len() > MAX_BATCH_SIZE {
+			return Err(Error::BadRequest);
+		}
+		
+		for (position, operation) in (staged..).zip(&operations) {
+			let operation = postcard::to_allocvec(operation)
+				.map_err(|err| Error::internal(err, "failed encoding operation"))?;
+			
+			db::StagedOperation { transaction_id: transaction_id.0 as i64, position, operation }.insert(conn)
+				.map_err(|err| Error::internal(err, "failed staging operation"))?;
+		}
+		
+		Ok(())
+	})?;
+	
+	Ok(StatusCode::NO_CONTENT)
+}
+
+/// Makes all staged changes visible at once, returning the outcome of each staged operation
+/// 
+/// Nothing is changed if any file was modified since it was staged, any operation fails
+/// or other clients hold delegations of changed files, which are recalled then.
+/// The transaction is closed either way.
+pub async fn commit_transaction(
+	mut conn: DbConnection<'_>,
+	directories: Directories,
+	blobs: Blobs,
+	file_write_lock: FileWriteLock,
+	recalls: DelegationRecalls,
+	OptHeader(client): OptHeader<ClientId>,
+	Path(transaction_id): Path<TransactionID>
+) -> Result<Postcard<Vec<BatchOutcome>>, Error> {
+	// taken out of the database before applying anything, so the transaction is closed even if that fails
+	let (contents, operations) = transaction(&mut conn, |conn| {
+		lock_open_transaction(conn, transaction_id)?;
+		
+		let contents: Vec<db::StagedContent> = db::StagedContent::of_transaction(transaction_id.0 as i64)
+			.load(conn).map_err(|err| Error::internal(err, "failed loading staged files"))?;
+		let operations: Vec<db::StagedOperation> = db::StagedOperation::of_transaction(transaction_id.0 as i64)
+			.load(conn).map_err(|err| Error::internal(err, "failed loading staged operations"))?;
+		
+		db::OpenTransaction::delete(conn, transaction_id.0 as i64)
+			.map_err(|err| Error::internal(err, "failed closing transaction"))?;
+		
+		Ok((contents, operations))
+	})?;
+	
+	let operations = operations.iter()
+		.map(|operation| postcard::from_bytes::<BatchOperation>(&operation.operation))
+		.collect::<Result<Vec<_>, _>>()
+		.map_err(|err| Error::internal(err, "failed decoding staged operation"))?;
+	
+	// locked in a consistent order so concurrent commits can't deadlock
+	let mut ids: Vec<NodeID> = contents.iter().map(|content| NodeID(content.file as u64)).collect();
+	ids.sort_by_key(|id| id.0);
+	
+	let mut guards = Vec::with_capacity(ids.len());
+	for id in ids {
+		guards.push(file_write_lock.lock(id).await);
+	}
+	
+	let mut conflicts = DelegationConflicts::new(client);
+	let mut resized = resize_contents(&mut conn, &directories, &blobs, &operations).await?;
+	
+	let result = async_transaction(&mut conn, async |conn| {
+		for content in &contents {
+			let id = NodeID(content.file as u64);
+			
+			conflicts.check(conn, id)?;
+			replace_content(conn, id, &Hash(content.prev_hash.clone()), &Hash(content.hash.clone()), content.size as u64)?;
+		}
+		
+		let mut outcomes = Vec::with_capacity(operations.len());
+		for operation in &operations {
+			outcomes.push(apply_resizing_operation(conn, &blobs, &mut conflicts, &mut resized, operation).await?);
+		}
+		
+		Ok(outcomes)
+	}).await;
+	
+	conflicts.recall(&mut conn, &recalls)?;
+	let outcomes = result?;
+	
+	Ok(Postcard(outcomes))
+}
+
+/// Discards all staged changes
+pub async fn abort_transaction(mut conn: DbConnection<'_>, Path(transaction_id): Path<TransactionID>) -> Result<StatusCode, Error> {
+	let existed = db::OpenTransaction::delete(&mut conn, transaction_id.0 as i64)
+		.map_err(|err| Error::internal(err, "failed aborting transaction"))?;
+	
+	match existed {
+		true => Ok(StatusCode::NO_CONTENT),
+		false => Err(Error::NotFound),
+	}
+}
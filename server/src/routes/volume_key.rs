@@ -0,0 +1,30 @@
+use super::*;
+
+/// Returns the wrapped key of an encrypted volume
+/// 
+/// The server never sees the unwrapped key, so the contents are opaque to it.
+pub async fn volume_key(mut conn: DbConnection<'_>) -> Result<Postcard<Vec<u8>>, Error> {
+	let key: db::VolumeKey = db::VolumeKey::get()
+		.first(&mut *conn).map_err(|err| match err {
+			DieselError::NotFound => Error::NotFound,
+			err => Error::internal(err, "failed looking up volume key"),
+		})?;
+	
+	Ok(Postcard(key.wrapped_key))
+}
+
+/// Stores the wrapped key of an encrypted volume, which can only be done once
+/// 
+/// Files encrypted with the previous key would become unreadable if it was replaced,
+/// so two clients setting up encryption at the same time can't both succeed.
+pub async fn create_volume_key(
+	mut conn: DbConnection<'_>,
+	Postcard(wrapped_key): Postcard<Vec<u8>>
+) -> Result<(StatusCode, Header<Location>), Error> {
+	db::VolumeKey::new(wrapped_key).insert(&mut conn).map_err(|err| match err {
+		DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => Error::AlreadyExists(Location::VolumeKey),
+		err => Error::internal(err, "failed inserting volume key"),
+	})?;
+	
+	Ok((StatusCode::CREATED, Header(Location::VolumeKey)))
+}
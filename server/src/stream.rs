@@ -1,4 +1,4 @@
-use std::{future::Future, io, pin::Pin, task::{Context, Poll}};
+use std::{fmt::{self, Display, Formatter}, future::Future, io, pin::Pin, task::{Context, Poll}};
 
 use blake3::{Hash, Hasher};
 use bytes::Bytes;
@@ -125,6 +125,72 @@ impl<S: Stream<Item = Result<Bytes, io::Error>>> Stream for HashStream<S> {
 	}
 }
 
+/// Error returned by [`LimitStream`] once more bytes than allowed were received
+#[derive(Debug)]
+pub struct LimitExceeded;
+
+impl Display for LimitExceeded {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		write!(f, "stream exceeded its size limit")
+	}
+}
+
+impl std::error::Error for LimitExceeded {}
+
+impl LimitExceeded {
+	pub fn is_cause_of(err: &io::Error) -> bool {
+		err.get_ref().is_some_and(|inner| inner.is::<Self>())
+	}
+}
+
+/// Passes through the inner stream, but fails with [`LimitExceeded`] once more than `limit` bytes were received
+#[pin_project]
+pub struct LimitStream<S: Stream<Item = Result<Bytes, io::Error>>> {
+	#[pin]
+	inner: S,
+	remaining: Option<u64>,
+}
+
+impl<S: Stream<Item = Result<Bytes, io::Error>>> LimitStream<S> {
+	pub fn new(stream: S, limit: Option<u64>) -> Self {
+		Self {
+			inner: stream,
+			remaining: limit,
+		}
+	}
+}
+
+impl<S: Stream<Item = Result<Bytes, io::Error>>> Stream for LimitStream<S> {
+	type Item = Result<Bytes, io::Error>;
+	
+	fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+		let this = self.project();
+		let inner = this.inner;
+		let remaining = this.remaining;
+		
+		match inner.poll_next(cx) {
+			Poll::Ready(Some(Ok(bytes))) => {
+				let Some(remaining) = remaining else {
+					return Poll::Ready(Some(Ok(bytes)));
+				};
+				
+				match remaining.checked_sub(bytes.len() as u64) {
+					Some(new_remaining) => {
+						*remaining = new_remaining;
+						Poll::Ready(Some(Ok(bytes)))
+					},
+					None => Poll::Ready(Some(Err(io::Error::other(LimitExceeded)))),
+				}
+			},
+			result => result,
+		}
+	}
+	
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		self.inner.size_hint()
+	}
+}
+
 #[cfg(test)]
 mod tests {
 	use super::*;
@@ -172,4 +238,34 @@ mod tests {
 		assert_eq!(hash_stream.total_size(), b"Multiple chunks in this slice".len() as u64);
 		assert_eq!(hash_stream.hash(), blake3::hash(b"Multiple chunks in this slice"));
 	}
+	
+	#[tokio::test]
+	async fn limit_not_reached() {
+		let stream = bytes_stream_from(&[b"Hello ", b"world"]);
+		let mut limit_stream = pin!(LimitStream::new(stream, Some(11)));
+		
+		assert_eq!(limit_stream.next().await.unwrap().unwrap(), &b"Hello "[..]);
+		assert_eq!(limit_stream.next().await.unwrap().unwrap(), &b"world"[..]);
+		assert!(limit_stream.next().await.is_none());
+	}
+	
+	#[tokio::test]
+	async fn limit_exceeded() {
+		let stream = bytes_stream_from(&[b"Hello ", b"world"]);
+		let mut limit_stream = pin!(LimitStream::new(stream, Some(10)));
+		
+		assert_eq!(limit_stream.next().await.unwrap().unwrap(), &b"Hello "[..]);
+		let err = limit_stream.next().await.unwrap().unwrap_err();
+		assert!(LimitExceeded::is_cause_of(&err));
+	}
+	
+	#[tokio::test]
+	async fn no_limit() {
+		let stream = bytes_stream_from(&[b"Hello ", b"world"]);
+		let mut limit_stream = pin!(LimitStream::new(stream, None));
+		
+		assert_eq!(limit_stream.next().await.unwrap().unwrap(), &b"Hello "[..]);
+		assert_eq!(limit_stream.next().await.unwrap().unwrap(), &b"world"[..]);
+		assert!(limit_stream.next().await.is_none());
+	}
 }
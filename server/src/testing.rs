@@ -1,32 +1,84 @@
 #![cfg(test)]
 
-use std::{io, pin::Pin, task::{Context, Poll}};
+use std::{io, path::Path, pin::Pin, sync::{atomic::{AtomicU64, Ordering}, Arc}, task::{Context, Poll}, time::{Duration, SystemTime}};
 
 use bytes::Bytes;
-use diesel::SqliteConnection;
+use diesel::{connection::SimpleConnection, Connection as _, PgConnection};
 use futures::Stream;
+use fye_shared::Hash;
 use tempfile::TempDir;
 
-use crate::{db, extractors::{DbConnection, Directories}};
+use crate::{blob_store::LocalBlobStore, db, extractors::{Blobs, DbConnection, Directories}};
 
 pub struct TestDb {
-	conn: SqliteConnection,
+	conn: db::AnyConnection,
+	/// Schema created for this test within a shared PostgreSQL database
+	schema: Option<String>,
 }
 
 impl TestDb {
+	/// Uses an in-memory SQLite database, or PostgreSQL if `FYE_TEST_POSTGRES_URL` is set
+	/// 
+	/// The `server-postgres-tests` check of the flake runs all tests against PostgreSQL this way.
+	/// Each test gets its own schema within the PostgreSQL database, which is dropped afterwards.
 	pub fn new() -> Self {
+		let Ok(url) = std::env::var("FYE_TEST_POSTGRES_URL") else {
+			return Self {
+				conn: db::establish_connection(":memory:", true).unwrap(),
+				schema: None,
+			};
+		};
+		
+		static COUNTER: AtomicU64 = AtomicU64::new(0);
+		let timestamp = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_nanos();
+		let schema = format!("fye_test_{timestamp}_{}", COUNTER.fetch_add(1, Ordering::Relaxed));
+		
+		let mut conn = PgConnection::establish(&url).unwrap();
+		conn.batch_execute(&format!("CREATE SCHEMA {schema}; SET search_path TO {schema};")).unwrap();
+		
+		let mut conn = db::AnyConnection::Postgres(conn);
+		db::run_migrations(&mut conn).unwrap();
+		
 		Self {
-			conn: db::establish_connection(":memory:", true).unwrap(),
+			conn,
+			schema: Some(schema),
 		}
 	}
 	
 	pub fn conn(&mut self) -> DbConnection<'_> {
 		DbConnection::from_single(&mut self.conn)
 	}
+	
+	/// Stops deleting a node from deleting its entries, so tests can create dangling entries
+	pub fn disable_entry_foreign_keys(&mut self) {
+		let sql = match self.conn {
+			db::AnyConnection::Sqlite(_) => "PRAGMA foreign_keys = OFF;",
+			db::AnyConnection::Postgres(_) => "
+				ALTER TABLE directory_entries DROP CONSTRAINT directory_entries_directory_fkey;
+				ALTER TABLE directory_entries DROP CONSTRAINT directory_entries_file_fkey;
+			",
+		};
+		
+		self.conn.batch_execute(sql).unwrap();
+	}
+}
+
+impl Drop for TestDb {
+	fn drop(&mut self) {
+		if let Some(schema) = &self.schema {
+			if let Err(err) = self.conn.batch_execute(&format!("DROP SCHEMA {schema} CASCADE")) {
+				eprintln!("Failed dropping test schema {schema}: {err}");
+			}
+		}
+	}
+}
+
+pub fn hash_of(data: &[u8]) -> Hash {
+	Hash(blake3::hash(data).to_hex().to_string())
 }
 
 pub fn bytes_stream_from(chunks: &'static [&'static [u8]]) -> impl Stream<Item = Result<Bytes, io::Error>> {
-	let iter = chunks.into_iter()
+	let iter = chunks.iter()
 		.map(|chunk| Ok(Bytes::from(&chunk[..])));
 	
 	futures::stream::iter(iter)
@@ -35,6 +87,8 @@ pub fn bytes_stream_from(chunks: &'static [&'static [u8]]) -> impl Stream<Item =
 pub struct TestDirectories {
 	temp_dir: Option<TempDir>,
 	directories: Directories,
+	files: Arc<Path>,
+	blobs: Blobs,
 }
 
 impl TestDirectories {
@@ -42,21 +96,45 @@ impl TestDirectories {
 		let temp_dir = tempfile::tempdir().unwrap();
 		let directories = Directories {
 			uploads: temp_dir.path().join("uploads").into(),
-			files: temp_dir.path().join("files").into(),
 		};
+		let files: Arc<Path> = temp_dir.path().join("files").into();
 		
 		std::fs::create_dir_all(&directories.uploads).unwrap();
-		std::fs::create_dir_all(&directories.files).unwrap();
+		std::fs::create_dir_all(&files).unwrap();
 		
 		Self {
 			temp_dir: Some(temp_dir),
 			directories,
+			blobs: Blobs(Arc::new(LocalBlobStore::new(files.clone()))),
+			files,
 		}
 	}
 	
 	pub fn dirs(&self) -> Directories {
 		self.directories.clone()
 	}
+	
+	/// A local blob store within [`TestDirectories::files`]
+	pub fn blobs(&self) -> Blobs {
+		self.blobs.clone()
+	}
+	
+	pub fn files(&self) -> &Path {
+		&self.files
+	}
+	
+	/// Whether no uploads are left, waiting a bit as failed uploads are removed in the background
+	pub async fn uploads_removed(&self) -> bool {
+		for _ in 0..100 {
+			if self.directories.uploads.read_dir().unwrap().next().is_none() {
+				return true;
+			}
+			
+			tokio::time::sleep(Duration::from_millis(10)).await;
+		}
+		
+		false
+	}
 }
 
 impl Drop for TestDirectories {
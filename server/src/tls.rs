@@ -0,0 +1,233 @@
+use std::{fs::File, io::{self, BufReader}, path::{Path, PathBuf}, sync::Arc, time::{Duration, SystemTime}};
+
+use axum_server::tls_rustls::RustlsConfig;
+use rustls::{crypto::ring, pki_types::{CertificateDer, PrivateKeyDer}, server::WebPkiClientVerifier, RootCertStore, ServerConfig};
+
+const RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug)]
+pub struct TlsPaths {
+	pub cert: PathBuf,
+	pub key: PathBuf,
+	/// CA used to verify client certificates, clients need to authenticate if this is set
+	pub client_ca: Option<PathBuf>,
+}
+
+impl TlsPaths {
+	/// Reads the paths from `FYE_TLS_CERT`, `FYE_TLS_KEY` and `FYE_TLS_CLIENT_CA`
+	/// 
+	/// Returns [`None`] if TLS is not configured.
+	pub fn from_env() -> Option<Self> {
+		let cert = std::env::var_os("FYE_TLS_CERT")?;
+		let key = std::env::var_os("FYE_TLS_KEY").expect("FYE_TLS_KEY should be set when FYE_TLS_CERT is set");
+		
+		Some(Self {
+			cert: cert.into(),
+			key: key.into(),
+			client_ca: std::env::var_os("FYE_TLS_CLIENT_CA").map(Into::into),
+		})
+	}
+	
+	fn modified_times(&self) -> Vec<Option<SystemTime>> {
+		[Some(&self.cert), Some(&self.key), self.client_ca.as_ref()].into_iter()
+			.flatten()
+			.map(|path| std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok())
+			.collect()
+	}
+}
+
+fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, io::Error> {
+	let mut reader = BufReader::new(File::open(path)?);
+	let certs = rustls_pemfile::certs(&mut reader).collect::<Result<Vec<_>, _>>()?;
+	
+	if certs.is_empty() {
+		return Err(io::Error::new(io::ErrorKind::InvalidData, format!("no certificates found in {}", path.display())));
+	}
+	
+	Ok(certs)
+}
+
+fn read_key(path: &Path) -> Result<PrivateKeyDer<'static>, io::Error> {
+	let mut reader = BufReader::new(File::open(path)?);
+	
+	rustls_pemfile::private_key(&mut reader)?
+		.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("no private key found in {}", path.display())))
+}
+
+pub fn load_config(paths: &TlsPaths) -> Result<ServerConfig, io::Error> {
+	let provider = Arc::new(ring::default_provider());
+	
+	let builder = ServerConfig::builder_with_provider(provider.clone())
+		.with_safe_default_protocol_versions().map_err(io::Error::other)?;
+	
+	let builder = match &paths.client_ca {
+		Some(client_ca) => {
+			let mut roots = RootCertStore::empty();
+			
+			for cert in read_certs(client_ca)? {
+				roots.add(cert).map_err(io::Error::other)?;
+			}
+			
+			let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+				.build().map_err(io::Error::other)?;
+			
+			builder.with_client_cert_verifier(verifier)
+		},
+		None => builder.with_no_client_auth(),
+	};
+	
+	let mut config = builder.with_single_cert(read_certs(&paths.cert)?, read_key(&paths.key)?)
+		.map_err(io::Error::other)?;
+	
+	config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+	
+	Ok(config)
+}
+
+/// Periodically checks whether any of the files were modified and reloads the configuration if so
+/// 
+/// New connections use the reloaded configuration, existing ones are unaffected.
+pub fn reload_on_change(config: RustlsConfig, paths: TlsPaths) {
+	tokio::spawn(async move {
+		let mut last_modified = paths.modified_times();
+		let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+		
+		loop {
+			interval.tick().await;
+			
+			let modified = paths.modified_times();
+			if modified == last_modified {
+				continue;
+			}
+			
+			last_modified = modified;
+			
+			match load_config(&paths) {
+				Ok(new_config) => {
+					config.reload_from_config(Arc::new(new_config));
+					eprintln!("Reloaded TLS configuration");
+				},
+				Err(err) => eprintln!("Could not reload TLS configuration, keeping the previous one: {err}"),
+			}
+		}
+	});
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	use axum::{routing::get, Router};
+	use axum_server::Handle;
+	use rcgen::{BasicConstraints, CertificateParams, ExtendedKeyUsagePurpose, IsCa, KeyPair};
+	use tempfile::TempDir;
+	
+	struct TestCerts {
+		temp_dir: TempDir,
+		ca: String,
+		client_identity: String,
+	}
+	
+	impl TestCerts {
+		fn generate() -> Self {
+			let ca_key = KeyPair::generate().unwrap();
+			let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+			ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+			let ca_cert = ca_params.self_signed(&ca_key).unwrap();
+			
+			let server_key = KeyPair::generate().unwrap();
+			let mut server_params = CertificateParams::new(vec!["localhost".to_owned()]).unwrap();
+			server_params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+			let server_cert = server_params.signed_by(&server_key, &ca_cert, &ca_key).unwrap();
+			
+			let client_key = KeyPair::generate().unwrap();
+			let mut client_params = CertificateParams::new(vec!["client".to_owned()]).unwrap();
+			client_params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+			let client_cert = client_params.signed_by(&client_key, &ca_cert, &ca_key).unwrap();
+			
+			let temp_dir = tempfile::tempdir().unwrap();
+			std::fs::write(temp_dir.path().join("ca.pem"), ca_cert.pem()).unwrap();
+			std::fs::write(temp_dir.path().join("cert.pem"), server_cert.pem()).unwrap();
+			std::fs::write(temp_dir.path().join("key.pem"), server_key.serialize_pem()).unwrap();
+			
+			Self {
+				temp_dir,
+				ca: ca_cert.pem(),
+				client_identity: client_cert.pem() + &client_key.serialize_pem(),
+			}
+		}
+		
+		fn paths(&self, client_auth: bool) -> TlsPaths {
+			TlsPaths {
+				cert: self.temp_dir.path().join("cert.pem"),
+				key: self.temp_dir.path().join("key.pem"),
+				client_ca: client_auth.then(|| self.temp_dir.path().join("ca.pem")),
+			}
+		}
+	}
+	
+	async fn serve(config: ServerConfig) -> u16 {
+		let app = Router::new().route("/", get(|| async { "ok" }));
+		let handle = Handle::new();
+		
+		let server = axum_server::bind_rustls("127.0.0.1:0".parse().unwrap(), RustlsConfig::from_config(Arc::new(config)))
+			.handle(handle.clone())
+			.serve(app.into_make_service());
+		tokio::spawn(server);
+		
+		handle.listening().await.unwrap().port()
+	}
+	
+	fn client(certs: &TestCerts, identity: bool) -> reqwest::Client {
+		let mut builder = reqwest::Client::builder()
+			.use_rustls_tls()
+			.tls_built_in_root_certs(false)
+			.add_root_certificate(reqwest::Certificate::from_pem(certs.ca.as_bytes()).unwrap());
+		
+		if identity {
+			builder = builder.identity(reqwest::Identity::from_pem(certs.client_identity.as_bytes()).unwrap());
+		}
+		
+		builder.build().unwrap()
+	}
+	
+	#[test]
+	fn missing_key() {
+		let certs = TestCerts::generate();
+		let mut paths = certs.paths(false);
+		paths.key = certs.temp_dir.path().join("missing.pem");
+		
+		let err = load_config(&paths).unwrap_err();
+		assert_eq!(err.kind(), io::ErrorKind::NotFound);
+		
+		// a certificate is not a key
+		paths.key = paths.cert.clone();
+		let err = load_config(&paths).unwrap_err();
+		assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+	}
+	
+	#[tokio::test]
+	async fn serves_tls() {
+		let certs = TestCerts::generate();
+		let port = serve(load_config(&certs.paths(false)).unwrap()).await;
+		
+		let response = client(&certs, false).get(format!("https://localhost:{port}/")).send().await.unwrap();
+		assert_eq!(response.text().await.unwrap(), "ok");
+		
+		// plain http is rejected
+		let result = reqwest::get(format!("http://localhost:{port}/")).await;
+		assert!(result.is_err());
+	}
+	
+	#[tokio::test]
+	async fn requires_client_certificate() {
+		let certs = TestCerts::generate();
+		let port = serve(load_config(&certs.paths(true)).unwrap()).await;
+		
+		let result = client(&certs, false).get(format!("https://localhost:{port}/")).send().await;
+		assert!(result.is_err());
+		
+		let response = client(&certs, true).get(format!("https://localhost:{port}/")).send().await.unwrap();
+		assert_eq!(response.text().await.unwrap(), "ok");
+	}
+}
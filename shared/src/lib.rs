@@ -20,7 +20,7 @@ impl NodeID {
 			Self(id) => {
 				Self(id + 1)
 			},
-			
+		
 		}
 	}
 }
@@ -54,12 +54,14 @@ impl DirectoryInfo {
 	}
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Debug)]
 pub struct Hash(pub String);
 
 impl Hash {
+	/// Weak ETags are accepted as well, the server sends those for encoded content which has the hash once decoded
 	pub fn parse_header(header: &HeaderValue) -> Option<&str> {
 		let str = header.to_str().ok()?;
+		let str = str.strip_prefix("W/").unwrap_or(str);
 		
 		let str = str.strip_prefix('"')?
 			.strip_suffix('"')?;
@@ -77,6 +79,13 @@ impl Hash {
 	}
 }
 
+/// Header with the hash of the complete new content of a file, which the server verifies before storing it
+pub const CONTENT_HASH_HEADER: &str = "fye-content-hash";
+/// Header with the size of the complete new content of a file
+pub const CONTENT_SIZE_HEADER: &str = "fye-content-size";
+/// Header with the id a client uses for its locks and delegations, so its own delegations don't conflict with its changes
+pub const CLIENT_ID_HEADER: &str = "fye-client-id";
+
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
 pub struct FileInfo {
 	pub size: u64,
@@ -88,3 +97,253 @@ pub enum NodeInfo {
 	Directory(DirectoryInfo),
 	File(FileInfo),
 }
+
+/// A node along a path as returned when resolving it, starting with the root directory
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct ResolvedNode {
+	pub id: NodeID,
+	pub info: NodeInfo,
+}
+
+/// Attributes of a single child as returned in a [`DirectoryListing`].
+/// 
+/// Directories don't include their own children, those need to be requested separately.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub enum EntryAttributes {
+	Directory,
+	File(FileInfo),
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct EntryInfo {
+	pub id: NodeID,
+	pub attributes: EntryAttributes,
+}
+
+/// Like [`DirectoryInfo`], but with the attributes of every child included.
+/// 
+/// Listings are paginated, children are ordered by name and if there are more children after
+/// the last one included, `continuation` contains a token to request the next page with.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct DirectoryListing {
+	pub parent: NodeID,
+	pub children: BTreeMap<String, EntryInfo>,
+	pub continuation: Option<String>,
+}
+
+impl DirectoryListing {
+	pub fn is_complete(&self) -> bool {
+		self.continuation.is_none()
+	}
+	
+	/// Only contains all children of the directory if the listing [`is_complete`][`Self::is_complete`]
+	pub fn to_dir_info(&self) -> DirectoryInfo {
+		DirectoryInfo {
+			parent: self.parent,
+			children: self.children.iter()
+				.map(|(name, entry)| (name.clone(), entry.id))
+				.collect(),
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct StorageStats {
+	/// Bytes taken up by file contents on the server, identical contents are only counted once
+	pub stored_bytes: u64,
+	/// Sum of the sizes of all files
+	pub logical_bytes: u64,
+	pub file_count: u64,
+	pub directory_count: u64,
+	/// Capacity of the server, either the size of its disk or the configured limit
+	pub total_bytes: u64,
+	/// Bytes that can still be stored before the disk is full or the limit is reached
+	pub available_bytes: u64,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Default, Debug)]
+pub struct QuotaLimits {
+	pub max_bytes: Option<u64>,
+	pub max_nodes: Option<u64>,
+}
+
+/// Usage of a top level directory, counting every node inside it as well as the directory itself
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct QuotaUsage {
+	pub directory: NodeID,
+	pub used_bytes: u64,
+	pub used_nodes: u64,
+	pub limits: QuotaLimits,
+}
+
+/// Inconsistencies between the metadata and the stored blobs found by a consistency check
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Default, Debug)]
+pub struct FsckReport {
+	/// Blobs referenced by files which aren't stored
+	pub missing_blobs: Vec<Hash>,
+	/// Blobs whose content doesn't match their hash
+	pub corrupt_blobs: Vec<Hash>,
+	/// Blobs which aren't referenced by any file
+	pub orphaned_blobs: Vec<Hash>,
+	/// Entries pointing at nodes that don't exist, as the parent and name of the entry
+	pub dangling_entries: Vec<(NodeID, String)>,
+	/// Directories which never reach the root directory when following their parents
+	pub directory_cycles: Vec<NodeID>,
+	/// Nodes which aren't an entry of any directory
+	pub orphaned_nodes: Vec<NodeID>,
+	/// Top level directories whose quota usage doesn't match their content
+	pub wrong_quotas: Vec<NodeID>,
+	/// Whether dangling entries, orphaned nodes or wrong quotas were repaired and no other problems are left
+	pub repaired: bool,
+}
+
+impl FsckReport {
+	pub fn is_clean(&self) -> bool {
+		self.missing_blobs.is_empty()
+			&& self.corrupt_blobs.is_empty()
+			&& self.orphaned_blobs.is_empty()
+			&& self.dangling_entries.is_empty()
+			&& self.directory_cycles.is_empty()
+			&& self.orphaned_nodes.is_empty()
+			&& self.wrong_quotas.is_empty()
+	}
+}
+
+/// Maximum number of operations in a single [`BatchRequest`]
+pub const MAX_BATCH_SIZE: usize = 1000;
+
+/// A single change of the directory tree within a [`BatchRequest`], referring to entries by their parent and name
+/// 
+/// Nodes have no attributes besides their content, so [`BatchOperation::SetSize`] is the only operation setting attributes.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub enum BatchOperation {
+	CreateDir { parent: NodeID, name: String },
+	CreateFile { parent: NodeID, name: String },
+	DeleteDir { parent: NodeID, name: String },
+	DeleteFile { parent: NodeID, name: String },
+	/// Moves the entry to `new_parent` as `new_name`
+	/// 
+	/// An existing entry with the new name is replaced if `replace` is set, directories only by empty directories and files only by files.
+	Rename { parent: NodeID, name: String, new_parent: NodeID, new_name: String, replace: bool },
+	/// Cuts off the content of the file or pads it with zeros if it still has `expected_hash`, at most once per file in a request
+	/// 
+	/// Not usable for encrypted volumes, as the server can't resize encrypted content.
+	SetSize { file: NodeID, expected_hash: Hash, size: u64 },
+}
+
+/// Operations that are applied in order, with a [`BatchResult`] for each of them
+/// 
+/// Each operation is applied on its own unless the batch is atomic, then either all operations are applied or none.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct BatchRequest {
+	pub atomic: bool,
+	pub operations: Vec<BatchOperation>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub enum BatchOutcome {
+	CreatedDir(NodeID),
+	/// Contains the hash of the new, empty file
+	CreatedFile(NodeID, Hash),
+	Deleted,
+	Renamed,
+	/// Contains the hash of the resized content
+	Resized(Hash),
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub enum BatchError {
+	NotFound,
+	NotAFile,
+	NotADirectory,
+	AlreadyExists,
+	DirectoryNotEmpty,
+	QuotaExceeded,
+	/// Other clients hold delegations of the file, which are being recalled
+	Locked,
+	/// A directory can't be moved into itself or its own subdirectories
+	InvalidMove,
+	/// The file doesn't have the expected hash anymore
+	Modified,
+	ServerError,
+	/// Rolled back because another operation of an atomic batch failed
+	Aborted,
+}
+
+pub type BatchResult = Result<BatchOutcome, BatchError>;
+
+/// Identifies a transaction that was opened on the server and not committed or aborted yet
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub struct TransactionID(pub u64);
+
+impl Display for TransactionID {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		self.0.fmt(f)
+	}
+}
+
+/// Seconds a client's locks are held without the client renewing its lease
+pub const LOCK_LEASE_SECS: u64 = 30;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum LockKind {
+	Shared,
+	Exclusive,
+}
+
+/// Identifies who holds a lock, locks of the same owner never conflict with each other
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub struct LockOwner {
+	/// Chosen randomly by each client, which renews the lease of all its locks at once
+	pub client: u64,
+	/// Distinguishes owners within a client, e.g. processes or open files
+	pub owner: u64,
+}
+
+/// An advisory lock on the bytes from `start` up to `end`
+/// 
+/// Without an `end` the lock extends to the end of the file, however large it gets.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct FileLock {
+	pub owner: LockOwner,
+	pub kind: LockKind,
+	pub start: u64,
+	pub end: Option<u64>,
+	/// Process holding the lock, only reported to others testing for conflicting locks
+	pub pid: u32,
+}
+
+impl FileLock {
+	pub fn overlaps(&self, start: u64, end: Option<u64>) -> bool {
+		self.start < end.unwrap_or(u64::MAX) && start < self.end.unwrap_or(u64::MAX)
+	}
+	
+	pub fn conflicts_with(&self, other: &FileLock) -> bool {
+		self.owner != other.owner
+			&& (self.kind == LockKind::Exclusive || other.kind == LockKind::Exclusive)
+			&& self.overlaps(other.start, other.end)
+	}
+}
+
+/// Releases the bytes from `start` up to `end` of all locks of `owner`, splitting locks that extend beyond them
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct UnlockRequest {
+	pub owner: LockOwner,
+	pub start: u64,
+	pub end: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum DelegationKind {
+	/// Allows caching a file without revalidating it, conflicts with write delegations of other clients
+	Read,
+	/// Also allows delaying writes, conflicts with all delegations of other clients
+	Write,
+}
+
+/// Asks for a delegation of a file, identifying the client by the same id that owns its locks
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct DelegationRequest {
+	pub client: u64,
+	pub kind: DelegationKind,
+}